@@ -1,19 +1,106 @@
+use crate::adb::device::Device;
 use crate::error::{Result, ScrcpyError};
 use std::path::PathBuf;
 use std::process::Stdio;
 use tokio::process::Command;
 
+pub const MIN_BRIGHTNESS: i64 = 0;
+pub const MAX_BRIGHTNESS: i64 = 255;
+
+/// 屏幕亮度设置（首次调整亮度前保存，关闭时恢复）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrightnessState {
+    pub brightness: i64,
+    pub mode: Option<i64>,  // 0 = 手动，1 = 自动；None 表示设备没有该设置
+}
+
+/// 屏幕旋转设置（首次锁定方向前保存，关闭时恢复）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct RotationState {
+    pub locked: bool,                // accelerometer_rotation == 0：关闭自动旋转
+    pub user_rotation: Option<i64>,  // 锁定时的方向：0 竖屏、1 横屏、2 反向竖屏、3 反向横屏；None 表示设备没有该设置
+}
+
+/// `adb connect` 的最长等待时间（设备不可达时 adb 可能长时间没有输出）
+pub const ADB_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// user_rotation 的最大值（Surface.ROTATION_270）
+pub const MAX_USER_ROTATION: u8 = 3;
+
+/// 设备上的一个逻辑显示屏（`dumpsys display` 中的 `DisplayInfo`）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayInfo {
+    pub id: u32,
+    pub name: String,   // "Built-in Screen"、"HDMI Screen" 等
+    pub width: u32,     // 当前方向下的实际尺寸（`real W x H`）
+    pub height: u32,
+}
+
+/// adb 版本号（`adb version` 输出的 `Android Debug Bridge version 1.0.41`）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AdbVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl std::fmt::Display for AdbVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// 支持的最低 adb 版本：截图等二进制输出使用的 `exec-out` 从 1.0.32 开始提供
+pub const MIN_ADB_VERSION: AdbVersion = AdbVersion { major: 1, minor: 0, patch: 32 };
+
+#[derive(Clone)]
 pub struct AdbClient {
     pub adb_path: PathBuf,
+    version: Option<AdbVersion>,  // probe() 验证后的版本
 }
 
 impl AdbClient {
     pub fn new(adb_path: PathBuf) -> Self {
-        Self { adb_path }
+        Self { adb_path, version: None }
+    }
+
+    /// 启动时验证 adb：执行 `adb version`，确认是 adb 且版本足够新，并保存版本号
+    ///
+    /// 把之后各个命令中含糊的 "Failed to execute ADB" 提前变成明确的错误：
+    /// 文件不存在、不是 adb、版本过旧。
+    pub async fn probe(&mut self) -> Result<AdbVersion> {
+        let path = &self.adb_path;
+        let output = Command::new(path)
+            .arg("version")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .await
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => ScrcpyError::Adb(format!("adb not found at {:?}", path)),
+                _ => ScrcpyError::Adb(format!("failed to run adb at {:?}: {}", path, e)),
+            })?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let version = parse_adb_version(&stdout).ok_or_else(|| {
+            let first_line = stdout.lines().next().unwrap_or("").trim();
+            ScrcpyError::Adb(format!("{:?} is not adb (`version` printed {:?})", path, first_line))
+        })?;
+        if version < MIN_ADB_VERSION {
+            return Err(ScrcpyError::Adb(format!("adb {} is too old (need {} or newer)", version, MIN_ADB_VERSION)));
+        }
+        self.version = Some(version);
+        Ok(version)
     }
 
     /// 执行ADB命令
     pub async fn execute(&self, args: &[&str]) -> Result<String> {
+        let stdout = self.execute_bytes(args).await?;
+        Ok(String::from_utf8_lossy(&stdout).to_string())
+    }
+
+    /// 执行ADB命令，返回原始的标准输出（用于 `exec-out` 等二进制输出）
+    pub async fn execute_bytes(&self, args: &[&str]) -> Result<Vec<u8>> {
         let output = Command::new(&self.adb_path)
             .args(args)
             .stdout(Stdio::piped())
@@ -27,27 +114,64 @@ impl AdbClient {
             return Err(ScrcpyError::Adb(format!("ADB command failed: {}", stderr)));
         }
 
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        Ok(output.stdout)
     }
 
-    /// 获取已连接的设备列表
+    /// 获取已连接且可用（状态为 device）的设备列表
     pub async fn list_devices(&self) -> Result<Vec<String>> {
+        Ok(self
+            .list_all_devices()
+            .await?
+            .into_iter()
+            .filter(Device::is_ready)
+            .map(|device| device.id)
+            .collect())
+    }
+
+    /// 获取 `adb devices` 中的全部设备及其状态（包括 unauthorized、offline 等不可用的设备）
+    pub async fn list_all_devices(&self) -> Result<Vec<Device>> {
         let output = self.execute(&["devices"]).await?;
+        Ok(parse_device_list(&output))
+    }
+
+    /// 设备当前是否在线（出现在 `adb devices` 中且状态为 device）
+    pub async fn is_device_online(&self, device_id: &str) -> Result<bool> {
+        Ok(self.list_devices().await?.iter().any(|d| d == device_id))
+    }
+
+    /// 等待设备重新上线（`adb wait-for-device`），超时返回 Ok(false)
+    pub async fn wait_for_device(&self, device_id: &str, timeout: std::time::Duration) -> Result<bool> {
+        match tokio::time::timeout(timeout, self.execute(&["-s", device_id, "wait-for-device"])).await {
+            Ok(result) => result.map(|_| true),
+            Err(_) => Ok(false),
+        }
+    }
 
-        let devices: Vec<String> = output
-            .lines()
-            .skip(1) // 跳过 "List of devices attached"
-            .filter_map(|line| {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 && parts[1] == "device" {
-                    Some(parts[0].to_string())
-                } else {
-                    None
-                }
-            })
-            .collect();
+    /// 连接无线调试设备（`adb connect`），返回设备序列号
+    ///
+    /// `addr` 为 `host:port`，IPv6 使用 `[addr]:port`（`SocketAddr` 的格式）
+    ///
+    /// 超过 [`ADB_CONNECT_TIMEOUT`] 没有结果时返回错误，并让 adb server 放弃这次连接
+    pub async fn connect(&self, addr: &str) -> Result<String> {
+        let Ok(output) = tokio::time::timeout(ADB_CONNECT_TIMEOUT, self.execute(&["connect", addr])).await else {
+            let _ = self.disconnect(addr).await;
+            return Err(ScrcpyError::Adb(format!(
+                "adb connect {} timed out after {}s, is the device reachable?",
+                addr,
+                ADB_CONNECT_TIMEOUT.as_secs()
+            )));
+        };
+        parse_connect_output(addr, &output?)
+    }
 
-        Ok(devices)
+    /// 断开无线调试设备（`adb disconnect`）
+    pub async fn disconnect(&self, addr: &str) -> Result<()> {
+        // "disconnected 192.168.1.20:5555"；未连接时 adb 以非 0 退出（"no such device"）
+        let output = self.execute(&["disconnect", addr]).await?;
+        if !output.contains("disconnected") {
+            return Err(ScrcpyError::Adb(format!("adb disconnect {} failed: {}", addr, output.trim())));
+        }
+        Ok(())
     }
 
     /// 推送文件到设备
@@ -61,17 +185,463 @@ impl AdbClient {
         self.execute(&["-s", device_id, "shell", command]).await
     }
 
-    /// 端口转发
-    pub async fn forward(&self, device_id: &str, local_port: u16, remote: &str) -> Result<()> {
-        let local = format!("tcp:{}", local_port);
-        self.execute(&["-s", device_id, "forward", &local, remote]).await?;
+    /// 转发任意本地端点（如 `tcp:27183`、`localfilesystem:/tmp/x.sock`）
+    pub async fn forward_local(&self, device_id: &str, local: &str, remote: &str) -> Result<()> {
+        self.execute(&["-s", device_id, "forward", local, remote]).await?;
         Ok(())
     }
 
-    /// 移除端口转发
-    pub async fn forward_remove(&self, device_id: &str, local_port: u16) -> Result<()> {
-        let local = format!("tcp:{}", local_port);
-        self.execute(&["-s", device_id, "forward", "--remove", &local]).await?;
+    /// 移除任意本地端点的转发
+    pub async fn forward_remove_local(&self, device_id: &str, local: &str) -> Result<()> {
+        self.execute(&["-s", device_id, "forward", "--remove", local]).await?;
         Ok(())
     }
+
+    /// 在设备上截图并保存到 /sdcard/Pictures，返回保存路径
+    ///
+    /// 使用设备原生分辨率（不经过视频流缩放），文件名中的时间戳由设备生成。
+    pub async fn device_screenshot(&self, device_id: &str) -> Result<String> {
+        let output = self
+            .shell(
+                device_id,
+                "mkdir -p /sdcard/Pictures && p=/sdcard/Pictures/scrcpy_$(date +%Y%m%d_%H%M%S).png && screencap -p \"$p\" && echo \"$p\"",
+            )
+            .await?;
+        // 旧版 adb 不传递远程退出码：以最后一行是否为保存路径判断成功
+        match output.lines().last().map(str::trim) {
+            Some(path) if path.starts_with("/sdcard/Pictures/scrcpy_") => Ok(path.to_string()),
+            _ => Err(ScrcpyError::Adb(format!("screencap failed: {}", output.trim()))),
+        }
+    }
+
+    /// 截取设备屏幕，返回 PNG 数据（原生分辨率，不保存到设备）
+    pub async fn screencap_png(&self, device_id: &str) -> Result<Vec<u8>> {
+        let png = self.execute_bytes(&["-s", device_id, "exec-out", "screencap", "-p"]).await?;
+        if !png.starts_with(b"\x89PNG") {
+            let text = String::from_utf8_lossy(&png[..png.len().min(200)]).trim().to_string();
+            return Err(ScrcpyError::Adb(format!("screencap failed: {}", text)));
+        }
+        Ok(png)
+    }
+
+    /// 获取当前前台应用的包名
+    ///
+    /// 解析 `dumpsys activity activities` 中的 ResumedActivity 行，无法识别时返回 None
+    pub async fn foreground_package(&self, device_id: &str) -> Result<Option<String>> {
+        let output = self.shell(device_id, "dumpsys activity activities").await?;
+        Ok(parse_foreground_package(&output))
+    }
+
+    /// 显示屏当前的旋转方向（0-3，每级顺时针 90°）
+    ///
+    /// 解析 `dumpsys input` 的触控视口，比 `dumpsys display` 轻量，适合定期轮询；无法识别时返回 None
+    pub async fn display_rotation(&self, device_id: &str, display_id: u32) -> Result<Option<u8>> {
+        let output = self.shell(device_id, "dumpsys input").await?;
+        Ok(parse_input_rotation(&output, display_id))
+    }
+
+    /// 列出设备的逻辑显示屏（折叠屏内外屏、DeX、虚拟显示屏等），按 id 排序
+    pub async fn list_displays(&self, device_id: &str) -> Result<Vec<DisplayInfo>> {
+        let output = self.shell(device_id, "dumpsys display").await?;
+        Ok(parse_display_list(&output))
+    }
+
+    /// 设备的 Android SDK 版本（`ro.build.version.sdk`，如 Android 13 为 33）
+    pub async fn sdk_version(&self, device_id: &str) -> Result<u32> {
+        let output = self.shell(device_id, "getprop ro.build.version.sdk").await?;
+        output.trim().parse().map_err(|_| ScrcpyError::Adb(format!("unexpected SDK version '{}'", output.trim())))
+    }
+
+    /// 列出已启用的输入法 id（`ime list -s`），如 `com.google.android.inputmethod.latin/com.android.inputmethod.latin.LatinIME`
+    pub async fn list_imes(&self, device_id: &str) -> Result<Vec<String>> {
+        let output = self.shell(device_id, "ime list -s").await?;
+        Ok(parse_ime_list(&output))
+    }
+
+    /// 当前使用的输入法 id
+    pub async fn current_ime(&self, device_id: &str) -> Result<Option<String>> {
+        let output = self.shell(device_id, "settings get secure default_input_method").await?;
+        let id = output.trim();
+        Ok((!id.is_empty() && id != "null").then(|| id.to_string()))
+    }
+
+    /// 切换输入法（`ime set <id>`）
+    ///
+    /// id 必须是 `ime list -s` 中已启用的输入法，否则返回列出可用输入法的错误。
+    pub async fn set_ime(&self, device_id: &str, id: &str) -> Result<()> {
+        if !is_valid_ime_id(id) {
+            return Err(ScrcpyError::Adb(format!("invalid IME id '{}'", id)));
+        }
+        let imes = self.list_imes(device_id).await?;
+        if !imes.iter().any(|ime| ime == id) {
+            return Err(ScrcpyError::Adb(format!("unknown IME '{}' (enabled: {})", id, imes.join(", "))));
+        }
+        // 旧版 adb 不传递远程退出码：以输出判断是否成功
+        let output = self.shell(device_id, &ime_set_command(id)).await?;
+        if output.contains("selected") {
+            Ok(())
+        } else {
+            Err(ScrcpyError::Adb(format!("ime set failed: {}", output.trim())))
+        }
+    }
+
+    /// 读取当前屏幕亮度设置（`settings get system screen_brightness` / `screen_brightness_mode`）
+    ///
+    /// 设备不提供 screen_brightness（输出 `null`）时返回错误；没有 screen_brightness_mode 时 mode 为 None。
+    pub async fn get_brightness(&self, device_id: &str) -> Result<BrightnessState> {
+        let output = self.shell(device_id, "settings get system screen_brightness").await?;
+        let brightness = parse_setting_value(&output)
+            .ok_or_else(|| ScrcpyError::Adb(format!("device does not expose screen_brightness ({})", output.trim())))?;
+        let mode = parse_setting_value(&self.shell(device_id, "settings get system screen_brightness_mode").await?);
+        Ok(BrightnessState { brightness, mode })
+    }
+
+    /// 设置屏幕亮度（0 ~ 255），先关闭自动亮度，否则设置会被立即覆盖
+    pub async fn set_brightness(&self, device_id: &str, value: i64) -> Result<()> {
+        if !(MIN_BRIGHTNESS..=MAX_BRIGHTNESS).contains(&value) {
+            return Err(ScrcpyError::Adb(format!("brightness {} out of range {}-{}", value, MIN_BRIGHTNESS, MAX_BRIGHTNESS)));
+        }
+        let current = self.get_brightness(device_id).await?;
+        if current.mode.is_some() {
+            self.put_system_setting(device_id, "screen_brightness_mode", 0).await?;
+        }
+        self.put_system_setting(device_id, "screen_brightness", value).await
+    }
+
+    /// 恢复 get_brightness 读取的亮度设置（先写亮度，再恢复自动亮度模式）
+    pub async fn restore_brightness(&self, device_id: &str, state: BrightnessState) -> Result<()> {
+        self.put_system_setting(device_id, "screen_brightness", state.brightness).await?;
+        if let Some(mode) = state.mode {
+            self.put_system_setting(device_id, "screen_brightness_mode", mode).await?;
+        }
+        Ok(())
+    }
+
+    /// 读取自动旋转和锁定方向（`settings get system accelerometer_rotation` / `user_rotation`）
+    pub async fn get_rotation(&self, device_id: &str) -> Result<RotationState> {
+        let output = self.shell(device_id, "settings get system accelerometer_rotation").await?;
+        let accelerometer = parse_setting_value(&output)
+            .ok_or_else(|| ScrcpyError::Adb(format!("device does not expose accelerometer_rotation ({})", output.trim())))?;
+        let user_rotation = parse_setting_value(&self.shell(device_id, "settings get system user_rotation").await?);
+        Ok(RotationState { locked: accelerometer == 0, user_rotation })
+    }
+
+    /// 锁定屏幕方向（0 ~ 3）；None 恢复自动旋转
+    ///
+    /// 部分定制系统会拒绝写入（输出 SecurityException）或静默忽略，写入后读回确认，
+    /// 与请求不一致时返回错误，而不是让客户端以为方向已锁定。
+    pub async fn set_rotation_lock(&self, device_id: &str, rotation: Option<u8>) -> Result<RotationState> {
+        let expected = match rotation {
+            Some(rotation) if rotation > MAX_USER_ROTATION => {
+                return Err(ScrcpyError::Adb(format!("rotation {} out of range 0-{}", rotation, MAX_USER_ROTATION)));
+            }
+            Some(rotation) => {
+                // 先写方向再关闭自动旋转，避免关闭的瞬间转到旧的锁定方向
+                self.put_system_setting(device_id, "user_rotation", rotation as i64).await?;
+                self.put_system_setting(device_id, "accelerometer_rotation", 0).await?;
+                RotationState { locked: true, user_rotation: Some(rotation as i64) }
+            }
+            None => {
+                self.put_system_setting(device_id, "accelerometer_rotation", 1).await?;
+                RotationState { locked: false, user_rotation: None }
+            }
+        };
+        let actual = self.get_rotation(device_id).await?;
+        if actual.locked != expected.locked || (expected.locked && actual.user_rotation != expected.user_rotation) {
+            return Err(ScrcpyError::Adb("rotation settings are protected on this device".to_string()));
+        }
+        Ok(actual)
+    }
+
+    /// 恢复 get_rotation 读取的旋转设置
+    pub async fn restore_rotation(&self, device_id: &str, state: RotationState) -> Result<()> {
+        if let Some(rotation) = state.user_rotation {
+            self.put_system_setting(device_id, "user_rotation", rotation).await?;
+        }
+        self.put_system_setting(device_id, "accelerometer_rotation", if state.locked { 0 } else { 1 }).await
+    }
+
+    /// `settings put system <name> <value>`：成功时没有输出，失败时输出异常信息
+    async fn put_system_setting(&self, device_id: &str, name: &str, value: i64) -> Result<()> {
+        let output = self.shell(device_id, &format!("settings put system {} {}", name, value)).await?;
+        if output.trim().is_empty() {
+            Ok(())
+        } else {
+            Err(ScrcpyError::Adb(format!("settings put {} failed: {}", name, output.trim())))
+        }
+    }
+}
+
+/// 解析 `adb devices` 的输出：每行 `序列号<TAB>状态`
+///
+/// 状态可能包含空格（`no permissions (missing udev rules? ...)`），取序列号之后的全部内容
+fn parse_device_list(output: &str) -> Vec<Device> {
+    output
+        .lines()
+        .skip(1) // 跳过 "List of devices attached"
+        .filter_map(|line| {
+            let (serial, state) = line.trim().split_once(char::is_whitespace)?;
+            let state = state.trim();
+            (!state.is_empty()).then(|| Device::with_state(serial.to_string(), state.to_string()))
+        })
+        .collect()
+}
+
+/// 解析 `adb connect` 的输出，返回设备序列号
+///
+/// 成功："connected to 192.168.1.20:5555" / "already connected to ..."；
+/// 失败："failed to connect to ..."、"cannot connect to ..."、"failed to authenticate to ..."（需要在设备上授权）
+fn parse_connect_output(addr: &str, output: &str) -> Result<String> {
+    let output = output.trim();
+    let lower = output.to_ascii_lowercase();
+    if lower.contains("failed to authenticate") {
+        return Err(ScrcpyError::Adb(format!(
+            "adb connect {}: not authorized, accept the USB debugging prompt on the device and retry ({})",
+            addr, output
+        )));
+    }
+    let failed = ["failed", "cannot", "unable", "error"].iter().any(|word| lower.contains(word));
+    match lower.find("connected to ") {
+        Some(pos) if !failed => Ok(output[pos + "connected to ".len()..].trim().to_string()),
+        _ => Err(ScrcpyError::Adb(format!("adb connect {} failed: {}", addr, output))),
+    }
+}
+
+/// 解析 `ime list -s` 的输出（每行一个输入法 id）
+fn parse_ime_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| is_valid_ime_id(line))
+        .map(str::to_string)
+        .collect()
+}
+
+/// 解析 `adb version` 输出中的版本号
+fn parse_adb_version(output: &str) -> Option<AdbVersion> {
+    let version = output.lines().find_map(|line| line.trim().strip_prefix("Android Debug Bridge version "))?;
+    let mut parts = version.trim().split('.').map(|part| part.parse::<u32>().ok());
+    Some(AdbVersion { major: parts.next()??, minor: parts.next()??, patch: parts.next()?? })
+}
+
+/// 解析 `settings get` 输出的整数值（设置不存在时输出 `null`）
+fn parse_setting_value(output: &str) -> Option<i64> {
+    output.trim().parse().ok()
+}
+
+/// 输入法 id 格式：`包名/类名`，只允许包名/类名中的字符（id 会拼进 shell 命令）
+fn is_valid_ime_id(id: &str) -> bool {
+    match id.split_once('/') {
+        Some((package, class)) => {
+            let valid = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '$'));
+            valid(package) && valid(class)
+        }
+        None => false,
+    }
+}
+
+/// `ime set` 命令：内部类的 id 含 `$`（`com.foo/.Ime$Inner`），必须加单引号，否则被设备端 shell 展开
+///
+/// id 已经过 [`is_valid_ime_id`] 校验，不含单引号。
+fn ime_set_command(id: &str) -> String {
+    format!("ime set '{}'", id)
+}
+
+/// 从 `dumpsys activity activities` 输出中提取前台应用包名
+///
+/// 不同 Android 版本的格式：
+/// - `  mResumedActivity: ActivityRecord{c0ffee u0 com.example/.MainActivity t12}`
+/// - `  topResumedActivity=ActivityRecord{c0ffee u0 com.example/.MainActivity t12}`
+/// - `  ResumedActivity: ActivityRecord{c0ffee u0 com.example/.MainActivity t12}`
+fn parse_foreground_package(output: &str) -> Option<String> {
+    output
+        .lines()
+        .filter(|line| line.contains("ResumedActivity"))
+        .find_map(|line| {
+            let record = &line[line.find("ActivityRecord{")?..];
+            record
+                .split_whitespace()
+                .find(|token| token.contains('/'))
+                .and_then(|component| component.split('/').next())
+                .filter(|package| !package.is_empty())
+                .map(|package| package.to_string())
+        })
+}
+
+/// 从 `dumpsys input` 输出中提取显示屏的旋转方向
+///
+/// - Android 10+：`Viewport INTERNAL: displayId=0, uniqueId=local:0, port=0, orientation=1, logicalFrame=[...]`
+///   （外接屏和虚拟屏的视口是 `Viewport EXTERNAL` / `Viewport VIRTUAL`，按 displayId 匹配）
+/// - Android 9 及以下只有默认显示屏：触控设备的 `SurfaceOrientation: 1`
+fn parse_input_rotation(output: &str, display_id: u32) -> Option<u8> {
+    let display = format!("displayId={},", display_id);
+    let viewport = output
+        .lines()
+        .filter(|line| line.trim_start().starts_with("Viewport ") && line.contains(&display))
+        .find_map(|line| {
+            let value = &line[line.find("orientation=")? + "orientation=".len()..];
+            value.split(|c: char| !c.is_ascii_digit()).next()?.parse().ok()
+        });
+    if viewport.is_some() || display_id != 0 {
+        return viewport.filter(|rotation| *rotation < 4);
+    }
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("SurfaceOrientation:")?.trim().parse().ok())
+        .filter(|rotation: &u8| *rotation < 4)
+}
+
+/// 从 `dumpsys display` 输出中提取逻辑显示屏
+///
+/// 每个逻辑显示屏有 `mBaseDisplayInfo=DisplayInfo{"Built-in Screen", displayId 0, ..., real 1080 x 2400, ...}`，
+/// 之后的 `mOverrideDisplayInfo` 反映旋转后的当前尺寸，同一 id 以后出现的为准。
+fn parse_display_list(output: &str) -> Vec<DisplayInfo> {
+    let mut displays: Vec<DisplayInfo> = Vec::new();
+    for line in output.lines() {
+        let Some(start) = line.find("DisplayInfo{\"") else {
+            continue;
+        };
+        let info = &line[start + "DisplayInfo{\"".len()..];
+        let Some((name, fields)) = info.split_once('"') else {
+            continue;
+        };
+        let field = |key: &str| fields.split(", ").find_map(|field| field.trim().strip_prefix(key));
+        let Some(id) = field("displayId ").and_then(|id| id.parse().ok()) else {
+            continue;
+        };
+        let Some((width, height)) = field("real ")
+            .and_then(|size| size.split_once(" x "))
+            .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+        else {
+            continue;
+        };
+        let display = DisplayInfo { id, name: name.to_string(), width, height };
+        match displays.iter_mut().find(|d| d.id == id) {
+            Some(existing) => *existing = display,
+            None => displays.push(display),
+        }
+    }
+    displays.sort_by_key(|d| d.id);
+    displays
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_display_list() {
+        let output = r#"Display Devices: size=2
+  DisplayDeviceInfo{"Built-in Screen": uniqueId="local:4619827259835644672", 1080 x 2400, modeId 1}
+Logical Displays: size=2
+  Display 2:
+    mDisplayId=2
+    mBaseDisplayInfo=DisplayInfo{"Cover Screen", displayId 2, displayGroupId 0, FLAG_TRUSTED, real 720 x 748, largest app 748 x 748, rotation 0}
+  Display 0:
+    mDisplayId=0
+    mBaseDisplayInfo=DisplayInfo{"Built-in Screen", displayId 0, displayGroupId 0, FLAG_SECURE, real 1812 x 2176, largest app 2176 x 2176, rotation 0}
+    mOverrideDisplayInfo=DisplayInfo{"Built-in Screen", displayId 0, displayGroupId 0, FLAG_SECURE, real 2176 x 1812, largest app 2176 x 2176, rotation 1}
+"#;
+        assert_eq!(parse_display_list(output), vec![
+            DisplayInfo { id: 0, name: "Built-in Screen".to_string(), width: 2176, height: 1812 },
+            DisplayInfo { id: 2, name: "Cover Screen".to_string(), width: 720, height: 748 },
+        ]);
+        assert!(parse_display_list("Can't find service: display").is_empty());
+    }
+
+    #[test]
+    fn test_parse_foreground_package() {
+        let legacy = "  Stack #1:\n    mResumedActivity: ActivityRecord{4a3b u0 com.example.app/.MainActivity t42}\n";
+        assert_eq!(parse_foreground_package(legacy).as_deref(), Some("com.example.app"));
+
+        let modern = "  topResumedActivity=ActivityRecord{91f2 u0 org.demo/org.demo.ui.Home t7}\n";
+        assert_eq!(parse_foreground_package(modern).as_deref(), Some("org.demo"));
+
+        assert_eq!(parse_foreground_package("mResumedActivity: null"), None);
+        assert_eq!(parse_foreground_package(""), None);
+    }
+
+    #[test]
+    fn test_parse_input_rotation() {
+        let modern = "Input Reader State:\n  Viewports:\n    Viewport INTERNAL: displayId=0, uniqueId=local:0, port=0, orientation=1, logicalFrame=[0, 0, 2400, 1080], isActive=true\n    Viewport EXTERNAL: displayId=2, uniqueId=local:1, port=1, orientation=0, logicalFrame=[0, 0, 1920, 1080], isActive=true\n";
+        assert_eq!(parse_input_rotation(modern, 0), Some(1));
+        assert_eq!(parse_input_rotation(modern, 2), Some(0));
+        assert_eq!(parse_input_rotation(modern, 3), None);
+
+        let legacy = "  Device 4: sec_touchscreen\n    Touch Input Mapper (mode - direct):\n      SurfaceOrientation: 3\n";
+        assert_eq!(parse_input_rotation(legacy, 0), Some(3));
+        assert_eq!(parse_input_rotation(legacy, 1), None);
+        assert_eq!(parse_input_rotation("", 0), None);
+    }
+
+    #[test]
+    fn test_parse_ime_list() {
+        let output = "com.android.inputmethod.latin/.LatinIME\r\ncom.sohu.inputmethod.sogou/.SogouIME\n\n";
+        assert_eq!(
+            parse_ime_list(output),
+            vec!["com.android.inputmethod.latin/.LatinIME", "com.sohu.inputmethod.sogou/.SogouIME"]
+        );
+        assert!(is_valid_ime_id("com.example/com.example.Ime$Service"));
+        assert!(!is_valid_ime_id("com.example/.Ime; reboot"));
+        assert!(!is_valid_ime_id("com.example"));
+        assert!(!is_valid_ime_id("/.Ime"));
+        assert_eq!(ime_set_command("com.foo/.Ime$Inner"), "ime set 'com.foo/.Ime$Inner'");
+    }
+
+    #[test]
+    fn test_parse_adb_version() {
+        let output = "Android Debug Bridge version 1.0.41\r\nVersion 34.0.5-10900879\r\nInstalled as /usr/bin/adb\r\n";
+        let version = parse_adb_version(output).unwrap();
+        assert_eq!(version, AdbVersion { major: 1, minor: 0, patch: 41 });
+        assert_eq!(version.to_string(), "1.0.41");
+        assert!(version >= MIN_ADB_VERSION);
+        assert!(parse_adb_version("Android Debug Bridge version 1.0.31\n").unwrap() < MIN_ADB_VERSION);
+        assert_eq!(parse_adb_version("Python 3.12.1"), None);
+        assert_eq!(parse_adb_version("Android Debug Bridge version x.y"), None);
+    }
+
+    #[tokio::test]
+    async fn test_probe_missing_adb() {
+        let mut adb = AdbClient::new(PathBuf::from("/nonexistent/adb"));
+        let err = adb.probe().await.unwrap_err();
+        assert!(err.to_string().contains("adb not found at"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_device_list() {
+        let output = "List of devices attached\n\
+                      R58M123ABC\tdevice\n\
+                      emulator-5554\tunauthorized\n\
+                      192.168.1.20:5555\toffline\n\
+                      0123456789\tno permissions (missing udev rules? user is in the plugdev group); see [http://developer.android.com/tools/device.html]\n\
+                      \n";
+        let devices = parse_device_list(output);
+        let states: Vec<(&str, &str)> = devices.iter().map(|d| (d.id.as_str(), d.state.as_deref().unwrap())).collect();
+        assert_eq!(states[..3], [("R58M123ABC", "device"), ("emulator-5554", "unauthorized"), ("192.168.1.20:5555", "offline")]);
+        assert!(states[3].1.starts_with("no permissions (missing udev rules?"));
+        assert_eq!(devices.iter().filter(|d| d.is_ready()).count(), 1);
+        assert!(parse_device_list("List of devices attached\n\n").is_empty());
+    }
+
+    #[test]
+    fn test_parse_connect_output() {
+        let addr = "192.168.1.20:5555";
+        assert_eq!(parse_connect_output(addr, "connected to 192.168.1.20:5555\n").unwrap(), addr);
+        assert_eq!(parse_connect_output(addr, "already connected to 192.168.1.20:5555\n").unwrap(), addr);
+
+        let refused = parse_connect_output(addr, "failed to connect to '192.168.1.20:5555': Connection refused\n").unwrap_err();
+        assert!(refused.to_string().contains("Connection refused"), "{}", refused);
+        let unreachable = parse_connect_output(addr, "cannot connect to 192.168.1.20:5555: No route to host (113)").unwrap_err();
+        assert!(matches!(unreachable, ScrcpyError::Adb(_)));
+        let unauthorized = parse_connect_output(addr, "failed to authenticate to 192.168.1.20:5555").unwrap_err();
+        assert!(unauthorized.to_string().contains("not authorized"), "{}", unauthorized);
+        assert!(parse_connect_output(addr, "").is_err());
+    }
+
+    #[test]
+    fn test_parse_setting_value() {
+        assert_eq!(parse_setting_value("128\r\n"), Some(128));
+        assert_eq!(parse_setting_value("null\n"), None);
+        assert_eq!(parse_setting_value(""), None);
+    }
 }
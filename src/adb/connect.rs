@@ -0,0 +1,197 @@
+//! 无线 adb 连接 - 解析 `--connect` 地址（支持 IPv6 方括号），并在双栈网络上竞速选择可达地址
+//!
+//! 主机名同时解析出 A 和 AAAA 记录时，按 Happy Eyeballs（RFC 8305）的做法交替排列 IPv6/IPv4，
+//! 依次间隔 250ms 发起 TCP 探测，第一个连上的地址交给 `adb connect`，
+//! 这样一个过期的地址不会让启动卡在 TCP 超时上。
+
+use crate::error::{Result, ScrcpyError};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tracing::debug;
+
+/// adb 无线调试的默认端口
+pub const DEFAULT_ADB_PORT: u16 = 5555;
+/// 两次连接尝试之间的间隔（RFC 8305 推荐 250ms）
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// `--connect` 的目标地址
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ConnectTarget {
+    pub host: String,  // 主机名或 IP（IPv6 不含方括号，链路本地地址可带 `%zone`）
+    pub port: u16,
+}
+
+/// IPv6 地址，可带 `%zone` 后缀（`fe80::1%wlan0`、`fe80::1%3`），链路本地地址需要用它指定网卡
+fn is_ipv6_host(host: &str) -> bool {
+    let (addr, zone) = match host.split_once('%') {
+        Some((addr, zone)) => (addr, Some(zone)),
+        None => (host, None),
+    };
+    addr.parse::<Ipv6Addr>().is_ok()
+        && zone.is_none_or(|zone| !zone.is_empty() && zone.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+}
+
+/// 解析 `host`、`host:port`、`[v6]`、`[v6]:port` 或不带端口的裸 IPv6 地址（IPv6 可带 `%zone`）
+pub fn parse_connect_addr(s: &str) -> std::result::Result<ConnectTarget, String> {
+    let s = s.trim();
+    let invalid = || format!("invalid address '{}'", s);
+
+    let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+        // [v6] 或 [v6]:port
+        let (host, after) = rest.split_once(']').ok_or_else(invalid)?;
+        if !is_ipv6_host(host) {
+            return Err(format!("invalid IPv6 address '{}'", host));
+        }
+        let port = match after {
+            "" => None,
+            _ => Some(after.strip_prefix(':').ok_or_else(invalid)?),
+        };
+        (host, port)
+    } else if is_ipv6_host(s) {
+        // 不带方括号的 IPv6 地址：冒号都属于地址，不能按最后一个冒号拆分
+        (s, None)
+    } else {
+        match s.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => (host, Some(port)),
+            Some(_) => return Err(format!("IPv6 address with port must use brackets: '[addr]:port' ('{}')", s)),
+            None => (s, None),
+        }
+    };
+
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    let port = match port {
+        Some(port) => port.parse::<u16>().ok().filter(|p| *p != 0).ok_or_else(|| format!("invalid port '{}'", port))?,
+        None => DEFAULT_ADB_PORT,
+    };
+    Ok(ConnectTarget { host: host.to_string(), port })
+}
+
+/// 按 RFC 8305 交替排列地址：IPv6 优先，然后 IPv4、IPv6……
+pub fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(|a| a.is_ipv6());
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+    let mut ordered = Vec::new();
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// 依次（间隔 `delay`）对候选地址发起 TCP 连接，返回第一个连上的地址
+///
+/// 前一个尝试失败时立即开始下一个，不必等满间隔；全部失败或超过 `timeout` 时返回错误。
+pub async fn race_connect(candidates: &[SocketAddr], delay: Duration, timeout: Duration) -> Result<SocketAddr> {
+    let race = async {
+        let mut attempts = JoinSet::new();
+        let mut pending = candidates.iter().copied();
+        let mut last_error = None;
+
+        loop {
+            if let Some(addr) = pending.next() {
+                debug!("🔌 Trying {}", addr);
+                attempts.spawn(async move { (addr, TcpStream::connect(addr).await) });
+            } else if attempts.is_empty() {
+                return Err(last_error.unwrap_or_else(|| "no addresses to try".to_string()));
+            }
+
+            // 等待任一尝试完成，或到达下一次尝试的时间（失败后立即开始下一个尝试）
+            let next_attempt = tokio::time::sleep(delay);
+            tokio::select! {
+                Some(joined) = attempts.join_next() => match joined {
+                    Ok((addr, Ok(_))) => return Ok(addr),
+                    Ok((addr, Err(e))) => {
+                        debug!("❌ {} failed: {}", addr, e);
+                        last_error = Some(format!("{}: {}", addr, e));
+                    }
+                    Err(e) => last_error = Some(e.to_string()),
+                },
+                _ = next_attempt, if pending.len() > 0 => {}
+            }
+        }
+    };
+
+    match tokio::time::timeout(timeout, race).await {
+        Ok(Ok(addr)) => Ok(addr),
+        Ok(Err(e)) => Err(ScrcpyError::Adb(format!("No reachable address: {}", e))),
+        Err(_) => Err(ScrcpyError::Adb(format!("Timed out after {}s connecting to device", timeout.as_secs()))),
+    }
+}
+
+impl ConnectTarget {
+    /// 解析主机名并选出可达的地址（IP 字面量直接返回，不做探测）
+    ///
+    /// 带 `%zone` 的 IPv6 地址交给系统解析，由它把网卡名换成 scope id。
+    pub async fn resolve(&self, timeout: Duration) -> Result<SocketAddr> {
+        if let Ok(ip) = self.host.parse::<IpAddr>() {
+            return Ok(SocketAddr::new(ip, self.port));
+        }
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((self.host.as_str(), self.port))
+            .await
+            .map_err(|e| ScrcpyError::Adb(format!("Failed to resolve {}: {}", self.host, e)))?
+            .collect();
+        match addrs.as_slice() {
+            [] => Err(ScrcpyError::Adb(format!("{} has no addresses", self.host))),
+            [addr] => Ok(*addr),
+            _ => race_connect(&interleave_families(addrs), CONNECTION_ATTEMPT_DELAY, timeout).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(host: &str, port: u16) -> ConnectTarget {
+        ConnectTarget { host: host.to_string(), port }
+    }
+
+    #[test]
+    fn test_parse_connect_addr() {
+        assert_eq!(parse_connect_addr("192.168.1.20"), Ok(target("192.168.1.20", 5555)));
+        assert_eq!(parse_connect_addr("192.168.1.20:5037"), Ok(target("192.168.1.20", 5037)));
+        assert_eq!(parse_connect_addr("phone.lan:40000"), Ok(target("phone.lan", 40000)));
+        assert_eq!(parse_connect_addr("[fe80::1]:5555"), Ok(target("fe80::1", 5555)));
+        assert_eq!(parse_connect_addr("[2001:db8::7]"), Ok(target("2001:db8::7", 5555)));
+        assert_eq!(parse_connect_addr("2001:db8::7"), Ok(target("2001:db8::7", 5555)));
+        assert_eq!(parse_connect_addr("fe80::1%wlan0"), Ok(target("fe80::1%wlan0", 5555)));
+        assert_eq!(parse_connect_addr("[fe80::1%3]:40000"), Ok(target("fe80::1%3", 40000)));
+        assert!(parse_connect_addr("[fe80::1%]:5555").is_err());
+        assert!(parse_connect_addr("[fe80::1%wl an0]").is_err());
+
+        assert!(parse_connect_addr("2001:db8::7:99999").is_err());
+        assert!(parse_connect_addr("[2001:db8::7").is_err());
+        assert!(parse_connect_addr("[not-v6]:5555").is_err());
+        assert!(parse_connect_addr("[::1]5555").is_err());
+        assert!(parse_connect_addr("host:0").is_err());
+        assert!(parse_connect_addr(":5555").is_err());
+    }
+
+    #[test]
+    fn test_interleave_families() {
+        let addrs: Vec<SocketAddr> = ["10.0.0.1:5555", "10.0.0.2:5555", "[::1]:5555"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        let ordered: Vec<String> = interleave_families(addrs).iter().map(|a| a.to_string()).collect();
+        assert_eq!(ordered, vec!["[::1]:5555", "10.0.0.1:5555", "10.0.0.2:5555"]);
+    }
+
+    #[tokio::test]
+    async fn test_race_skips_dead_address() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let alive = listener.local_addr().unwrap();
+        // 先绑定再释放，得到一个（几乎一定）没有监听的端口
+        let dead = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+
+        let winner = race_connect(&[dead, alive], Duration::from_secs(5), Duration::from_secs(5)).await.unwrap();
+        assert_eq!(winner, alive);
+        assert!(race_connect(&[dead], Duration::from_millis(10), Duration::from_secs(5)).await.is_err());
+    }
+}
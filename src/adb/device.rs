@@ -6,6 +6,7 @@ pub struct Device {
     pub model: Option<String>,
     pub android_version: Option<String>,
     pub screen_size: Option<(u32, u32)>,
+    pub state: Option<String>,  // `adb devices` 中的状态：device、unauthorized、offline 等
 }
 
 impl Device {
@@ -15,20 +16,56 @@ impl Device {
             model: None,
             android_version: None,
             screen_size: None,
+            state: None,
         }
     }
 
-    pub fn with_info(
-        id: String,
-        model: String,
-        android_version: String,
-        screen_size: (u32, u32),
-    ) -> Self {
+    /// `adb devices` 中的一行设备
+    pub fn with_state(id: String, state: String) -> Self {
         Self {
-            id,
-            model: Some(model),
-            android_version: Some(android_version),
-            screen_size: Some(screen_size),
+            state: Some(state),
+            ..Self::new(id)
         }
     }
+
+    /// 是否可以使用（状态为 `device`；没有状态信息时视为可用）
+    pub fn is_ready(&self) -> bool {
+        self.state.as_deref().is_none_or(|state| state == "device")
+    }
+
+    /// 设备不可用时给用户的说明，例如 "Device X is unauthorized — accept the USB debugging prompt on the device"
+    pub fn state_hint(&self) -> Option<String> {
+        let state = self.state.as_deref().filter(|_| !self.is_ready())?;
+        let hint = match state {
+            "unauthorized" => "accept the USB debugging prompt on the device",
+            "authorizing" => "authorization is in progress, retry in a moment",
+            "offline" => "reconnect the device or restart adb (adb kill-server)",
+            "recovery" | "rescue" | "sideload" | "bootloader" => "the device is not booted into Android",
+            state if state.starts_with("no permissions") => "check the udev rules / USB permissions for this device",
+            _ => "the device is not ready",
+        };
+        Some(format!("Device {} is {} — {}", self.id, state, hint))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_hint() {
+        assert!(Device::new("abc".to_string()).is_ready());
+        let ready = Device::with_state("abc".to_string(), "device".to_string());
+        assert!(ready.is_ready());
+        assert_eq!(ready.state_hint(), None);
+
+        let unauthorized = Device::with_state("abc".to_string(), "unauthorized".to_string());
+        assert!(!unauthorized.is_ready());
+        assert_eq!(
+            unauthorized.state_hint().unwrap(),
+            "Device abc is unauthorized — accept the USB debugging prompt on the device"
+        );
+        let offline = Device::with_state("10.0.0.2:5555".to_string(), "offline".to_string());
+        assert!(offline.state_hint().unwrap().contains("adb kill-server"));
+    }
 }
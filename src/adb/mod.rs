@@ -1,5 +1,5 @@
 pub mod client;
+pub mod connect;
 pub mod device;
 
 pub use client::AdbClient;
-pub use device::Device;
@@ -0,0 +1,240 @@
+//! 配置文件模块 - 从 TOML 加载全局默认值和按设备序列号区分的覆盖项
+//!
+//! 优先级（高 → 低）：`[[device]]` 块 > 命令行参数 > 配置文件全局值 > 内置默认值
+//!
+//! 视频流参数（max_size、bit_rate、max_fps、codec）可以按设备覆盖；端口、监听方式、TLS 等进程级参数
+//! 只有全局值，由 main.rs 在解析命令行之后合并（命令行显式传入的值优先）。
+
+use crate::error::{Result, ScrcpyError};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// 目前 Web 端解码器只支持 H.264
+pub const SUPPORTED_CODECS: &[&str] = &["h264"];
+
+/// 单个设备的视频流参数
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct StreamSettings {
+    pub max_size: u32,
+    pub bit_rate: u32,
+    pub max_fps: u32,
+    pub codec: String,
+}
+
+/// 可选的视频流参数（配置文件全局值、命令行显式传入的值、设备块都使用这个结构）
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StreamOverrides {
+    pub max_size: Option<u32>,
+    pub bit_rate: Option<u32>,
+    pub max_fps: Option<u32>,
+    pub codec: Option<String>,
+}
+
+/// 按序列号匹配的设备块 `[[device]]`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub serial: String,
+    pub max_size: Option<u32>,
+    pub bit_rate: Option<u32>,
+    pub max_fps: Option<u32>,
+    pub codec: Option<String>,
+}
+
+/// 配置文件内容
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub max_size: Option<u32>,
+    pub bit_rate: Option<u32>,
+    pub max_fps: Option<u32>,
+    pub codec: Option<String>,
+    // 进程级参数，字段名与命令行参数一致（--ws-port → ws_port）
+    pub adb_path: Option<PathBuf>,
+    pub server_path: Option<PathBuf>,
+    pub serial: Option<String>,  // 对应 --device（`device` 是 [[device]] 块的名称）
+    pub ws_port: Option<u16>,
+    pub video_port: Option<u16>,
+    pub control_port: Option<u16>,
+    pub intra_refresh_period: Option<u32>,
+    pub log_level: Option<String>,
+    pub public: Option<bool>,
+    pub bind: Option<std::net::IpAddr>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub auth_token: Option<String>,
+    pub trust_proxy: Option<bool>,
+    pub audio: Option<bool>,
+    pub uhid: Option<bool>,
+    pub display_id: Option<u32>,
+    pub no_cleanup: Option<bool>,
+    #[serde(default, rename = "device")]
+    pub devices: Vec<DeviceConfig>,
+}
+
+impl StreamSettings {
+    /// 用 overrides 中已设置的字段覆盖当前值
+    fn apply(&mut self, overrides: &StreamOverrides) {
+        if let Some(max_size) = overrides.max_size {
+            self.max_size = max_size;
+        }
+        if let Some(bit_rate) = overrides.bit_rate {
+            self.bit_rate = bit_rate;
+        }
+        if let Some(max_fps) = overrides.max_fps {
+            self.max_fps = max_fps;
+        }
+        if let Some(codec) = &overrides.codec {
+            self.codec = codec.clone();
+        }
+    }
+}
+
+impl From<&DeviceConfig> for StreamOverrides {
+    fn from(device: &DeviceConfig) -> Self {
+        Self {
+            max_size: device.max_size,
+            bit_rate: device.bit_rate,
+            max_fps: device.max_fps,
+            codec: device.codec.clone(),
+        }
+    }
+}
+
+impl FileConfig {
+    /// 从 TOML 文件加载，未知字段或类型错误会给出带位置的错误信息
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| ScrcpyError::Config(format!("Failed to read {}: {}", path.display(), e)))?;
+        Self::parse(&content).map_err(|e| match e {
+            ScrcpyError::Config(msg) => ScrcpyError::Config(format!("{}: {}", path.display(), msg)),
+            other => other,
+        })
+    }
+
+    /// 解析 TOML 内容并校验
+    pub fn parse(content: &str) -> Result<Self> {
+        let config: Self = toml::from_str(content).map_err(|e| ScrcpyError::Config(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let codecs = std::iter::once(&self.codec)
+            .chain(self.devices.iter().map(|d| &d.codec))
+            .flatten();
+        for codec in codecs {
+            if !SUPPORTED_CODECS.contains(&codec.as_str()) {
+                return Err(ScrcpyError::Config(format!(
+                    "unsupported codec '{}' (supported: {})",
+                    codec,
+                    SUPPORTED_CODECS.join(", ")
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// 全局值（文件顶层字段）
+    fn global(&self) -> StreamOverrides {
+        StreamOverrides {
+            max_size: self.max_size,
+            bit_rate: self.bit_rate,
+            max_fps: self.max_fps,
+            codec: self.codec.clone(),
+        }
+    }
+
+    /// 查找序列号匹配的设备块
+    pub fn device(&self, serial: &str) -> Option<&DeviceConfig> {
+        self.devices.iter().find(|d| d.serial == serial)
+    }
+
+    /// 合并出某个设备最终使用的参数：设备块 > 命令行 > 文件全局值 > 默认值
+    pub fn resolve(&self, defaults: StreamSettings, cli: &StreamOverrides, serial: &str) -> StreamSettings {
+        let mut settings = defaults;
+        settings.apply(&self.global());
+        settings.apply(cli);
+        if let Some(device) = self.device(serial) {
+            settings.apply(&StreamOverrides::from(device));
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> StreamSettings {
+        StreamSettings { max_size: 1920, bit_rate: 4_000_000, max_fps: 60, codec: "h264".to_string() }
+    }
+
+    const CONFIG: &str = r#"
+        max_size = 1280
+        bit_rate = 8000000
+
+        [[device]]
+        serial = "fast-phone"
+        max_size = 1920
+        max_fps = 60
+
+        [[device]]
+        serial = "slow-phone"
+        max_size = 540
+        max_fps = 30
+    "#;
+
+    #[test]
+    fn test_resolve_precedence() {
+        let config = FileConfig::parse(CONFIG).unwrap();
+        let cli = StreamOverrides { max_size: Some(720), max_fps: Some(45), ..Default::default() };
+
+        // 设备块 > 命令行 > 文件全局值 > 默认值
+        let slow = config.resolve(defaults(), &cli, "slow-phone");
+        assert_eq!(slow, StreamSettings { max_size: 540, bit_rate: 8_000_000, max_fps: 30, codec: "h264".to_string() });
+
+        // 没有匹配的设备块：命令行覆盖文件全局值
+        let other = config.resolve(defaults(), &cli, "unknown");
+        assert_eq!(other, StreamSettings { max_size: 720, bit_rate: 8_000_000, max_fps: 45, codec: "h264".to_string() });
+
+        // 没有命令行参数：文件全局值覆盖默认值
+        let other = config.resolve(defaults(), &StreamOverrides::default(), "unknown");
+        assert_eq!(other, StreamSettings { max_size: 1280, bit_rate: 8_000_000, max_fps: 60, codec: "h264".to_string() });
+    }
+
+    #[test]
+    fn test_empty_config_keeps_defaults() {
+        let config = FileConfig::parse("").unwrap();
+        assert_eq!(config.resolve(defaults(), &StreamOverrides::default(), "any"), defaults());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(FileConfig::parse("max_sise = 1").is_err());
+        assert!(FileConfig::parse("max_size = \"big\"").is_err());
+        assert!(FileConfig::parse("[[device]]\nmax_size = 540").is_err());
+        assert!(FileConfig::parse("codec = \"vp9\"").is_err());
+        assert!(FileConfig::parse("ws_port = 70000").is_err());
+        assert!(FileConfig::parse("public = \"yes\"").is_err());
+
+        // 错误信息指出出错的字段
+        let e = FileConfig::parse("ws_prot = 8080").unwrap_err().to_string();
+        assert!(e.contains("ws_prot"), "{}", e);
+    }
+
+    #[test]
+    fn test_parse_process_settings() {
+        let config = FileConfig::parse(r#"
+            serial = "R58M12345"
+            ws_port = 9000
+            public = true
+            auth_token = "s3cret"
+            tls_cert = "certs/fullchain.pem"
+        "#).unwrap();
+        assert_eq!(config.serial.as_deref(), Some("R58M12345"));
+        assert_eq!((config.ws_port, config.public), (Some(9000), Some(true)));
+        assert_eq!(config.tls_cert, Some(PathBuf::from("certs/fullchain.pem")));
+        assert_eq!(config.log_level, None);
+    }
+}
@@ -5,8 +5,11 @@ pub enum ScrcpyError {
     #[error("ADB error: {0}")]
     Adb(String),
 
-    #[error("Device not found")]
-    DeviceNotFound,
+    #[error("Device {0} disconnected during startup")]
+    DeviceDisconnected(String),
+
+    #[error("Device {0} is already in use by another instance (pid {1}, lock file {2:?})")]
+    DeviceInUse(String, u32, std::path::PathBuf),
 
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
@@ -17,9 +20,18 @@ pub enum ScrcpyError {
     #[error("Video stream error: {0}")]
     VideoStream(String),
 
+    #[error("Audio stream error: {0}")]
+    AudioStream(String),
+
     #[error("Parse error: {0}")]
     Parse(String),
 
+    #[error("Config error: {0}")]
+    Config(String),
+
+    #[error("TLS error: {0}")]
+    Tls(String),
+
     #[error("No available port found in range {0}-{1}")]
     NoAvailablePort(u16, u16),
 }
@@ -1,4 +1,5 @@
 mod adb;
+mod config;
 mod error;
 mod scrcpy;
 mod utils;
@@ -7,23 +8,29 @@ mod ws;
 use adb::AdbClient;
 use error::{Result, ScrcpyError};
 use scrcpy::{ScrcpyServer, VideoStreamReader, ControlChannel};
+use utils::{FrameRateLimiter, FrameSinks, InstanceLock, NalDump, NalHistogram};
 use ws::WebSocketServer;
 use std::path::PathBuf;
-use tracing::{info, error, warn, debug, Level};
-use tracing_subscriber;
+use tracing::{info, error, warn, debug, trace, trace_span, Instrument, Level};
 use bytes::Bytes;
-use clap::Parser;
+use clap::{parser::ValueSource, CommandFactory, FromArgMatches, Parser};
 
 /// Rust-scrcpy: Android screen mirroring over ADB with WebSocket broadcasting
 ///
 /// Rust-scrcpy: 通过 ADB 实现 Android 屏幕镜像，并通过 WebSocket 广播到浏览器
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone, serde::Serialize)]
 #[command(name = "Rust-ws-scrcpy")]
 #[command(author = "zzzzyg")]
 #[command(version = "2.1.1")]
 #[command(about = "Stream Android device screen to web browsers via WebSocket", long_about = None)]
 #[command(help_template = "{name} {version}\nAuthor: {author}\n\n{about}\n\n{usage-heading} {usage}\n\n{all-args}")]
 struct Args {
+    /// TOML config file with global defaults and per-device [[device]] overrides
+    ///
+    /// TOML 配置文件：全局默认值 + 按序列号匹配的 [[device]] 覆盖项
+    #[arg(long)]
+    config: Option<PathBuf>,
+
     /// ADB executable path
     ///
     /// ADB 可执行文件路径
@@ -36,12 +43,24 @@ struct Args {
     #[arg(short, long, default_value = "../scrcpy-server/scrcpy-server-v3.3.4")]
     server_path: PathBuf,
 
-    /// Target device serial number (use first device if not specified)
+    /// Connect to a wireless device first: HOST[:PORT] or [IPv6][:PORT] (default port 5555)
+    ///
+    /// 先通过 `adb connect` 连接无线设备：HOST[:PORT] 或 [IPv6][:PORT]（默认端口 5555）
+    #[arg(long, value_name = "ADDR", value_parser = adb::connect::parse_connect_addr)]
+    connect: Option<adb::connect::ConnectTarget>,
+
+    /// Target device serial number (falls back to $ANDROID_SERIAL, then the first device)
     ///
-    /// 目标设备序列号（不指定则使用第一个设备）
+    /// 目标设备序列号（不指定则读取 $ANDROID_SERIAL，仍未设置则使用第一个设备）
     #[arg(short, long)]
     device: Option<String>,
 
+    /// Mirror every ready device at once on one port: pick a device at / or connect to /ws?device=SERIAL
+    ///
+    /// 同时镜像所有就绪的设备并共用一个端口：在 / 选择设备，或连接 /ws?device=SERIAL
+    #[arg(long, conflicts_with_all = ["device", "dump_nal", "replay_nal"])]
+    all_devices: bool,
+
     /// Maximum video resolution (width or height, whichever is larger)
     ///
     /// 最大视频分辨率（宽或高的最大值）
@@ -90,17 +109,363 @@ struct Args {
     #[arg(short = 'l', long, default_value = "info")]
     log_level: String,
 
+    /// Log level of the device-side scrcpy-server (verbose, debug, info, warn, error); defaults to match --log-level
+    ///
+    /// 设备端 scrcpy-server 的日志级别 (verbose, debug, info, warn, error)，默认与 --log-level 一致
+    #[arg(long, value_name = "LEVEL", value_parser = scrcpy::server::parse_server_log_level)]
+    server_log_level: Option<scrcpy::server::ServerLogLevel>,
+
     /// Listen on all network interfaces (0.0.0.0) for LAN access
     ///
     /// 监听所有网络接口（0.0.0.0），允许局域网访问
     #[arg(long)]
     public: bool,
+
+    /// Listen address, IPv4 or IPv6 (e.g. 192.168.1.5 or ::); default 127.0.0.1, or 0.0.0.0 with --public
+    ///
+    /// 监听地址，IPv4 或 IPv6（如 192.168.1.5 或 ::）；默认 127.0.0.1，--public 时为 0.0.0.0
+    #[arg(long, value_name = "ADDR", conflicts_with = "public")]
+    bind: Option<std::net::IpAddr>,
+
+    /// PEM certificate chain for serving HTTPS/WSS (requires --tls-key)
+    ///
+    /// HTTPS/WSS 使用的 PEM 证书链（需要同时指定 --tls-key）
+    #[arg(long, value_name = "PATH", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    ///
+    /// --tls-cert 对应的 PEM 私钥
+    #[arg(long, value_name = "PATH", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Require this token (?token= or Authorization: Bearer) on every route except /healthz
+    ///
+    /// 要求客户端携带该访问令牌（?token= 或 Authorization: Bearer），/healthz 除外
+    #[arg(long, value_name = "STR")]
+    auth_token: Option<String>,
+
+    /// Do not restore device state (show touches, stay awake, power mode) on exit
+    ///
+    /// 退出时不恢复设备状态（显示触摸、保持唤醒、电源模式）
+    #[arg(long)]
+    no_cleanup: bool,
+
+    /// Stop mirroring and exit after this many minutes, regardless of activity
+    ///
+    /// 会话最长时长（分钟），到时后无论是否有操作都会停止镜像并退出
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_session_minutes: Option<u64>,
+
+    /// Stop capture on the device after this many seconds, even if this host crashes (wraps the server in `timeout`)
+    ///
+    /// 设备端采集时长上限（秒），即使主机崩溃，设备上的 scrcpy-server 也会到期退出
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    device_time_limit: Option<u64>,
+
+    /// Cap the broadcast frame rate by dropping P-frames (SPS/PPS/IDR always pass)
+    ///
+    /// 限制广播帧率（丢弃 P 帧，SPS/PPS/IDR 总是发送），不影响编码帧率
+    #[arg(long)]
+    broadcast_fps: Option<u32>,
+
+    /// Only pass keyframes while no viewer or output is attached; full rate as soon as one connects
+    ///
+    /// 没有观看者和写入端时只发送关键帧以节省 CPU，有观看者连接时立即恢复全帧率
+    #[arg(long)]
+    scale_fps_to_clients: bool,
+
+    /// Once listening, print one JSON line with the WebSocket URL and actual ports to stdout (logs go to stderr)
+    ///
+    /// 开始监听后向 stdout 输出一行包含 WebSocket 地址和实际端口的 JSON，日志改为输出到 stderr
+    #[arg(long)]
+    print_endpoint: bool,
+
+    /// Touch pointer ids: indexed (browser ids, multi-touch) or generic (scrcpy's generic finger id, single finger only)
+    ///
+    /// 触摸指针 id：indexed 使用浏览器的 id（支持多点触控），generic 使用 scrcpy 的 generic finger id（仅单指）
+    #[arg(long, value_name = "MODE", default_value = "indexed", value_parser = scrcpy::control::parse_pointer_mode)]
+    pointer_mode: scrcpy::control::PointerMode,
+
+    /// Send each IDR to browsers as one message with the cached SPS/PPS in front (more reliable WebCodecs startup)
+    ///
+    /// 发给浏览器的每个 IDR 前拼接缓存的 SPS/PPS 作为一条消息，WebCodecs 启动更可靠
+    #[arg(long)]
+    inline_sps_on_idr: bool,
+
+    /// Forward video/control over Unix domain sockets in the temp dir instead of TCP
+    ///
+    /// 使用临时目录下的 Unix 域套接字转发视频流/控制流（代替 TCP 回环）
+    #[cfg(unix)]
+    #[arg(long)]
+    unix_socket: bool,
+
+    /// Only broadcast while this package is in the foreground (privacy mode)
+    ///
+    /// 仅当指定包名的应用在前台时才广播画面（隐私模式）
+    #[arg(long)]
+    only_app: Option<String>,
+
+    /// Foreground app poll interval in milliseconds (used with --only-app)
+    ///
+    /// 前台应用轮询间隔（毫秒，配合 --only-app 使用）
+    #[arg(long, default_value = "1000", value_parser = clap::value_parser!(u64).range(100..))]
+    only_app_poll_ms: u64,
+
+    /// Device rotation poll interval in milliseconds, pushes orientation changes the SPS doesn't reflect (0 = off)
+    ///
+    /// 设备旋转方向轮询间隔（毫秒），推送 SPS 反映不出的横竖屏变化（0 = 关闭）
+    #[arg(long, default_value = "1000", value_parser = parse_orientation_poll_ms)]
+    orientation_poll_ms: u64,
+
+    /// Times to wait for the device to reconnect if it drops during startup
+    ///
+    /// 启动阶段设备断开时，等待其重新连接的次数（0 = 不等待，直接退出）
+    #[arg(long, default_value = "0")]
+    startup_retries: u32,
+
+    /// Exit with an error after N consecutive failed reconnect cycles (0 = retry forever)
+    ///
+    /// 连续 N 次完整重连失败后以错误退出（0 = 无限重试），便于进程管理器告警或重启
+    #[arg(long, value_name = "N", default_value = "0")]
+    max_reconnects: u32,
+
+    /// Capture N frames, decode them with ffmpeg into --selftest-dir, check they are not all black, then exit
+    ///
+    /// 自检：采集 N 帧并用 ffmpeg 解码为 PNG 写入 --selftest-dir，检查画面不是全黑后退出（全黑时以错误退出）
+    #[cfg(feature = "selftest")]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..=1000))]
+    selftest_frames: Option<u64>,
+
+    /// Directory for selftest PNGs, the captured stream and selftest.json
+    ///
+    /// 自检输出目录（PNG、采集的码流和 selftest.json）
+    #[cfg(feature = "selftest")]
+    #[arg(long, value_name = "DIR", default_value = "selftest")]
+    selftest_dir: PathBuf,
+
+    /// ffmpeg executable used to decode selftest frames
+    ///
+    /// 自检解码使用的 ffmpeg 可执行文件
+    #[cfg(feature = "selftest")]
+    #[arg(long, value_name = "PATH", default_value = "ffmpeg")]
+    ffmpeg_path: PathBuf,
+
+    /// Seconds the web viewer keeps showing "reconnecting" during a server restart before reporting the stream as lost
+    ///
+    /// 重启 scrcpy server（调整分辨率、断流重连）时网页显示"重新连接中"的宽限秒数，超时后才提示画面中断
+    #[arg(long, value_name = "SECS", default_value_t = ws::reconnect::DEFAULT_RECONNECT_GRACE.as_secs())]
+    reconnect_grace: u64,
+
+    /// Letterbox the stream to a fixed aspect ratio in the web viewer (e.g. 16:9)
+    ///
+    /// 在网页中把画面加黑边到固定宽高比（如 16:9），用于多设备拼接墙
+    #[arg(long, value_name = "W:H", value_parser = parse_aspect)]
+    target_aspect: Option<(u32, u32)>,
+
+    /// Also write the raw H.264 stream to a file/FIFO, or `unix:PATH` socket (repeatable)
+    ///
+    /// 同时把原始 H.264 流写入文件/FIFO，或 `unix:路径` 的 Unix 套接字（可重复指定）
+    #[arg(long, value_name = "PATH")]
+    raw_output: Vec<String>,
+
+    /// Write a WebVTT timeline (<file>.vtt) of keyframes and control events next to each --raw-output file
+    ///
+    /// 在每个 --raw-output 录制文件旁写一个 WebVTT 时间线（<文件>.vtt），记录关键帧和控制事件的时间
+    #[arg(long, requires = "raw_output")]
+    record_timeline: bool,
+
+    /// Write every NAL unit read from the device (before any filtering) to a file for bug reports
+    ///
+    /// 把从设备读取的每个 NAL 单元（任何过滤之前）写入文件，用于提交 bug 时复现
+    #[arg(long, value_name = "FILE")]
+    dump_nal: Option<PathBuf>,
+
+    /// Replay a --dump-nal file through the WebSocket path instead of a device (no ADB needed), paced at --max-fps
+    ///
+    /// 不连接设备，把 --dump-nal 文件按 --max-fps 的节奏经 WebSocket 回放（不需要 ADB）
+    #[arg(long, value_name = "FILE", conflicts_with = "dump_nal")]
+    replay_nal: Option<PathBuf>,
+
+    /// Re-send SPS/PPS to a client before the next IDR after N broadcast lags within 10s (0 = off)
+    ///
+    /// 客户端 10 秒内积压 N 次后，在下一个 IDR 前单独给它重发 SPS/PPS（0 = 关闭，适合弱网下的 MSE 客户端）
+    #[arg(long, value_name = "N", default_value = "0")]
+    lag_resync_threshold: u32,
+
+    /// Max size of a single WebSocket message from a client; larger messages close the connection
+    ///
+    /// 客户端发来的单条 WebSocket 消息的最大字节数，超过时关闭该连接
+    #[arg(long, value_name = "BYTES", default_value_t = ws::server::DEFAULT_MAX_MESSAGE_BYTES, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1024..))]
+    max_message_bytes: usize,
+
+    /// Trust X-Forwarded-For for client addresses (only behind a trusted reverse proxy)
+    ///
+    /// 信任 X-Forwarded-For 作为客户端地址（仅在可信的反向代理之后使用）
+    #[arg(long)]
+    trust_proxy: bool,
+
+    /// Seconds without a video frame before /healthz reports unhealthy
+    ///
+    /// 超过该秒数未收到视频帧时 /healthz 返回 503
+    #[arg(long, default_value = "5", value_parser = clap::value_parser!(u64).range(1..))]
+    health_stale_secs: u64,
+
+    /// Main class launched by app_process (for testing forked server builds)
+    ///
+    /// app_process 启动的主类（用于测试自定义的 scrcpy-server 构建）
+    #[arg(long, default_value = scrcpy::server::DEFAULT_SERVER_CLASS, value_parser = scrcpy::server::parse_server_class)]
+    server_class: String,
+
+    /// Extra space-separated key=value args appended to the server command
+    ///
+    /// 追加到 server 启动命令的参数，空格分隔的 key=value（如 "log_level=debug video_codec=h265"）
+    #[arg(long, value_name = "ARGS", value_parser = validate_server_extra_args)]
+    server_extra_args: Option<String>,
+
+    /// Bytes read from the video socket per read call (clamped to 4096..=8388608)
+    ///
+    /// 每次从视频 socket 读取的字节数（限制在 4KB ~ 8MB 之间）
+    #[arg(long, default_value_t = scrcpy::video::DEFAULT_READ_BUFFER_BYTES)]
+    read_buffer_bytes: usize,
+
+    /// Serve /debug/config with the resolved settings (always on with --log-level debug/trace)
+    ///
+    /// 启用 /debug/config，返回实际生效的参数（日志级别为 debug/trace 时自动启用）
+    #[arg(long)]
+    enable_debug_endpoints: bool,
+
+    /// Log a NAL-type histogram, bitrate and fps every N seconds
+    ///
+    /// 每 N 秒输出一次 NAL 类型统计（IDR/P/SPS/PPS/SEI/AUD 数量）、字节数和帧率
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    verbose_frames: Option<u64>,
+
+    /// Send KEYCODE_WAKEUP when the video stalls because the device locked
+    ///
+    /// 长时间收不到视频帧（通常是设备锁屏）时发送 WAKEUP 唤醒屏幕
+    #[arg(long)]
+    wake_on_lock: bool,
+
+    /// What to do when a --raw-output sink cannot keep up: drop (P-frames until next IDR) or stop
+    ///
+    /// --raw-output 写入跟不上时的处理：drop 丢帧直到下一个 IDR，stop 停止该写入端
+    #[arg(long, value_name = "POLICY", default_value = "drop", value_parser = utils::frame_sinks::parse_overflow_policy)]
+    record_overflow: utils::frame_sinks::OverflowPolicy,
+
+    /// Start code length (3 or 4 bytes) for /raw and --raw-output; WebSocket clients always get 4
+    ///
+    /// /raw 和 --raw-output 输出的起始码长度（3 或 4 字节），WebSocket 客户端始终为 4 字节
+    #[arg(long, value_name = "3|4", default_value_t = 4, value_parser = clap::value_parser!(u8).range(3..=4))]
+    start_code: u8,
+
+    /// Capture device audio (Opus, Android 11+) and stream it on /audio
+    ///
+    /// 采集设备声音（Opus，需要 Android 11+），通过 /audio 推送给浏览器
+    #[arg(long)]
+    audio: bool,
+
+    /// Keep audio playing on the device while it is captured (scrcpy audio_dup, Android 13+)
+    ///
+    /// 采集音频时设备扬声器继续播放（scrcpy audio_dup，需要 Android 13+）
+    #[arg(long)]
+    audio_dup: bool,
+
+    /// Inject keyboard input through a virtual UHID keyboard (physical key positions, native modifiers and key repeat)
+    ///
+    /// 通过设备上的虚拟 UHID 键盘注入键盘输入（按物理键位，修饰键和长按由设备按真实键盘处理）
+    #[arg(long)]
+    uhid: bool,
+
+    /// Launch the server with frame headers and forward device PTS to viewers
+    ///
+    /// 以带帧头的方式启动 server，读取设备 PTS 并转发给浏览器
+    #[arg(long)]
+    frame_meta: bool,
+
+    /// Mirror another display (DeX, foldable cover screen, virtual display); see the startup log for ids
+    ///
+    /// 镜像其他显示屏（DeX、折叠屏外屏、虚拟显示屏），可用的 id 见启动日志
+    #[arg(long, value_name = "N")]
+    display_id: Option<u32>,
+
+    /// Mirror several displays of the device at once, each served at /device/<serial>/display/<id>/
+    ///
+    /// 同时镜像设备的多个显示屏（如折叠屏内外屏），每个显示屏的页面在 /device/<serial>/display/<id>/
+    #[arg(long, value_name = "N,N", value_delimiter = ',', num_args = 1.., conflicts_with_all = ["display_id", "display_size", "dump_nal", "replay_nal"])]
+    display_ids: Vec<u32>,
+
+    /// Size of the mirrored display for touch mapping, when it cannot be read from dumpsys display
+    ///
+    /// 所镜像显示屏的尺寸（用于触控坐标换算），无法从 dumpsys display 读取时指定
+    #[arg(long, value_name = "WxH", value_parser = parse_display_size)]
+    display_size: Option<(u32, u32)>,
+
+    /// Mirror only part of the screen: WxH:X:Y in the device's natural orientation (scrcpy crop)
+    ///
+    /// 只镜像屏幕的一部分：WxH:X:Y，坐标相对设备的自然方向（scrcpy 的 crop 参数）
+    #[arg(long, value_name = "WxH:X:Y", value_parser = scrcpy::server::parse_crop)]
+    crop: Option<scrcpy::server::Crop>,
+
+    /// Keep the video in one orientation when the device rotates: 0|natural, 1 (90° ccw), 2 (180°), 3 (90° cw) or initial
+    ///
+    /// 设备旋转时视频保持固定方向：0|natural（自然方向）、1（逆时针 90°）、2（180°）、3（顺时针 90°）或 initial（启动时的方向）
+    #[arg(long, value_name = "ORIENTATION", value_parser = scrcpy::server::parse_orientation_lock)]
+    lock_orientation: Option<scrcpy::server::OrientationLock>,
+
+    /// Don't power on the device screen when the server starts (scrcpy power_on=false)
+    ///
+    /// 启动时不点亮屏幕，熄屏的设备保持熄屏
+    #[arg(long)]
+    no_power_on: bool,
+
+    /// Start even if another instance holds the lock for this device
+    ///
+    /// 即使该设备已被另一个实例锁定也强制启动
+    #[arg(long)]
+    force: bool,
+
+    /// Request an H.264 profile from the encoder: baseline, main or high (baseline is required by Broadway.js)
+    ///
+    /// 请求编码器使用的 H.264 profile：baseline / main / high（Broadway.js 只支持 baseline）
+    #[arg(long, value_name = "PROFILE", value_parser = scrcpy::server::parse_codec_profile)]
+    codec_profile: Option<scrcpy::server::CodecProfile>,
+}
+
+impl Args {
+    /// WebSocket 监听地址：--bind > --public（0.0.0.0）> 127.0.0.1
+    fn bind_addr(&self) -> std::net::IpAddr {
+        match self.bind {
+            Some(addr) => addr,
+            None if self.public => std::net::Ipv4Addr::UNSPECIFIED.into(),
+            None => std::net::Ipv4Addr::LOCALHOST.into(),
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // 解析命令行参数（这会自动处理 --help 和 --version）
-    let args = Args::parse();
+    // 保留 ArgMatches 以区分“命令行显式传入”和“使用默认值”，用于和配置文件合并
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    // 加载配置文件（可选）并合并进程级参数，格式错误时在初始化日志之前退出
+    let file_config = match &args.config {
+        Some(path) => config::FileConfig::load(path),
+        None => Ok(config::FileConfig::default()),
+    }
+    .and_then(|file_config| {
+        apply_file_settings(&mut args, &file_config, |id| matches.value_source(id) == Some(ValueSource::CommandLine))?;
+        Ok(file_config)
+    });
+    let file_config = match file_config {
+        Ok(file_config) => file_config,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            return Err(e);
+        }
+    };
 
     // 根据参数设置日志级别
     let log_level = match args.log_level.to_lowercase().as_str() {
@@ -116,8 +481,22 @@ async fn main() -> Result<()> {
     };
 
     // 初始化日志
+    // debug 及以下级别时输出 span 关闭事件（含耗时），便于分析启动各阶段的时间
+    let span_events = if log_level >= Level::DEBUG {
+        tracing_subscriber::fmt::format::FmtSpan::CLOSE
+    } else {
+        tracing_subscriber::fmt::format::FmtSpan::NONE
+    };
+    // --print-endpoint：stdout 只留给握手行，日志改写到 stderr
+    let log_writer = if args.print_endpoint {
+        tracing_subscriber::fmt::writer::BoxMakeWriter::new(std::io::stderr)
+    } else {
+        tracing_subscriber::fmt::writer::BoxMakeWriter::new(std::io::stdout)
+    };
     tracing_subscriber::fmt()
         .with_max_level(log_level)
+        .with_span_events(span_events)
+        .with_writer(log_writer)
         .init();
 
     info!("🚀 Rust-Scrcpy starting...");
@@ -135,88 +514,1668 @@ async fn main() -> Result<()> {
     info!("   Control port: {}", args.control_port);
     info!("   IDR interval: {}s", args.intra_refresh_period);
     info!("   Log level: {}", args.log_level);
-    info!("   Public mode: {}", if args.public { "Yes (LAN accessible)" } else { "No (localhost only)" });
+    let server_log_level = args.server_log_level.unwrap_or(scrcpy::server::ServerLogLevel::from_level(log_level));
+    info!("   Server log level: {}", server_log_level.as_str());
+    let bind_addr = args.bind_addr();
+    info!("   Listen address: {}{}", bind_addr, if bind_addr.is_loopback() { " (localhost only)" } else { "" });
+    info!("   Cleanup on exit: {}", if args.no_cleanup { "No" } else { "Yes" });
+    if args.auth_token.is_some() {
+        info!("   Auth token: required (open /?token=<token>)");
+    }
+    if args.pointer_mode == scrcpy::control::PointerMode::Generic {
+        info!("   Pointer mode: generic (single finger, no multi-touch)");
+    }
+    if args.audio {
+        info!("   Audio: Opus on /audio");
+    }
+    if args.inline_sps_on_idr {
+        info!("   Inline SPS/PPS on IDR: enabled");
+    }
+    if args.uhid {
+        info!("   Keyboard: UHID");
+    }
+    if args.frame_meta {
+        info!("   Frame meta: device PTS forwarded to viewers");
+    }
+    if args.no_power_on {
+        info!("   Power on: No");
+    }
+    if let Some(fps) = args.broadcast_fps {
+        info!("   Broadcast FPS: {}", fps);
+    }
+    if args.scale_fps_to_clients {
+        info!("   Scale FPS to clients: Yes");
+    }
+    if let Some(minutes) = args.max_session_minutes {
+        info!("   Max session: {} min", minutes);
+    }
+    if let Some(secs) = args.device_time_limit {
+        info!("   Device time limit: {}s", secs);
+    }
+    if let Some(secs) = args.verbose_frames {
+        info!("   Frame stats: every {}s", secs);
+    }
+    let read_buffer_bytes = scrcpy::video::clamp_read_buffer_bytes(args.read_buffer_bytes);
+    if read_buffer_bytes != args.read_buffer_bytes {
+        warn!("⚠️  --read-buffer-bytes {} is out of range, using {}", args.read_buffer_bytes, read_buffer_bytes);
+        // 之后所有会话（包括重连）都使用限制后的值
+        args.read_buffer_bytes = read_buffer_bytes;
+    }
+    if args.server_class != scrcpy::server::DEFAULT_SERVER_CLASS {
+        info!("   Server class: {}", args.server_class);
+    }
+    if let Some(ref extra_args) = args.server_extra_args {
+        info!("   Server extra args: {}", extra_args);
+    }
+    if let Some((w, h)) = args.target_aspect {
+        info!("   Target aspect: {}:{}", w, h);
+    }
+    if let Some(crop) = args.crop {
+        info!("   Crop: {}x{} at ({}, {})", crop.width, crop.height, crop.x, crop.y);
+    }
+    if let Some(lock) = args.lock_orientation {
+        info!("   Orientation lock: {:?} (capture_orientation={})", lock, lock.capture_orientation());
+    }
+    if let Some(ref package) = args.only_app {
+        info!("   Only app: {} (poll every {}ms)", package, args.only_app_poll_ms);
+    }
+
+    // 校验内嵌的解码器脚本并输出哈希
+    ws::assets::verify_decoder_assets()?;
+
+    // --tls-cert / --tls-key：启动设备端 server 前加载证书，文件有误时尽早退出
+    let tls = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => {
+            let tls = ws::tls::load_tls_config(cert, key).await?;
+            info!("🔒 TLS enabled (certificate {:?})", cert);
+            Some(tls)
+        }
+        _ => None,
+    };
+
+    // --replay-nal：回放转储文件，不连接设备
+    if let Some(path) = args.replay_nal.clone() {
+        return run_replay(&args, &path, tls).await;
+    }
+
+    // --dump-nal：启动前打开转储文件，路径无效时尽早退出
+    let nal_dump = match &args.dump_nal {
+        Some(path) => Some(NalDump::create(path).await?),
+        None => None,
+    };
+
+    // 命令行显式传入的视频流参数，优先于配置文件的全局值
+    let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    let cli_overrides = config::StreamOverrides {
+        max_size: from_cli("max_size").then_some(args.max_size),
+        bit_rate: from_cli("bit_rate").then_some(args.bit_rate),
+        max_fps: from_cli("max_fps").then_some(args.max_fps),
+        codec: None,
+    };
 
     // 获取ADB路径
-    if !args.adb_path.exists() {
-        eprintln!("❌ ADB not found at: {:?}", args.adb_path);
-        eprintln!("Please specify correct ADB path with --adb-path option");
-        return Ok(());
+    // 验证 adb（存在、确实是 adb、版本足够新），之后的 ADB 命令不再逐个报含糊的错误
+    let mut adb = AdbClient::new(args.adb_path.clone());
+    match adb.probe().await {
+        Ok(version) => info!("🔧 adb {} at {:?}", version, args.adb_path),
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            eprintln!("Please specify correct ADB path with --adb-path option");
+            return Err(e);
+        }
     }
 
-    let adb = AdbClient::new(args.adb_path);
+    // --connect：先连接无线设备，未指定 --device 时使用该设备
+    if let Some(target) = args.connect.clone() {
+        info!("📶 Connecting to {}:{}...", target.host, target.port);
+        let connected = match target.resolve(WIRELESS_CONNECT_TIMEOUT).await {
+            Ok(addr) => adb.connect(&addr.to_string()).await,
+            Err(e) => Err(e),
+        };
+        match connected {
+            Ok(serial) => {
+                info!("✅ Connected to {}", serial);
+                args.device.get_or_insert(serial);
+            }
+            Err(e) => {
+                eprintln!("❌ {}", e);
+                return Ok(());
+            }
+        }
+    }
 
     // 列出已连接的设备
     info!("📱 Checking connected devices...");
-    let devices = adb.list_devices().await?;
+    let all_devices = adb.list_all_devices().await?;
+    // unauthorized、offline 等状态的设备不能使用，逐个说明原因而不是当作没有设备
+    for hint in all_devices.iter().filter_map(|device| device.state_hint()) {
+        warn!("⚠️  {}", hint);
+    }
+    let devices: Vec<String> = all_devices.iter().filter(|device| device.is_ready()).map(|device| device.id.clone()).collect();
 
     if devices.is_empty() {
-        eprintln!("❌ No devices connected");
-        eprintln!("Please connect an Android device via USB or WiFi");
+        if all_devices.is_empty() {
+            eprintln!("❌ No devices connected");
+            eprintln!("Please connect an Android device via USB or WiFi");
+        } else {
+            eprintln!("❌ No usable devices:");
+            for hint in all_devices.iter().filter_map(|device| device.state_hint()) {
+                eprintln!("   {}", hint);
+            }
+        }
         return Ok(());
     }
-
-    info!("✅ Found {} device(s):", devices.len());
-    for device in &devices {
-        info!("  - {}", device);
+
+    info!("✅ Found {} device(s):", devices.len());
+    for device in &devices {
+        info!("  - {}", device);
+    }
+
+    // --all-devices：每台就绪的设备一个会话，共用一个 WebSocket 端口
+    if args.all_devices {
+        return run_all_devices(args, adb, devices, &file_config, &cli_overrides, log_level, server_log_level, tls).await;
+    }
+
+    // 选择设备：--device > $ANDROID_SERIAL > 第一个设备（与 adb 的约定一致）
+    let requested_device = match args.device.clone() {
+        Some(device) => Some((device, "--device")),
+        None => android_serial_from_env().map(|device| (device, "ANDROID_SERIAL")),
+    };
+    let device_id = if let Some((device, source)) = requested_device {
+        if !devices.contains(&device) {
+            match all_devices.iter().find(|entry| entry.id == device).and_then(|entry| entry.state_hint()) {
+                Some(hint) => eprintln!("❌ {} (from {})", hint, source),
+                None => eprintln!("❌ Device {} (from {}) not found in connected devices", device, source),
+            }
+            return Ok(());
+        }
+        if source == "ANDROID_SERIAL" {
+            info!("📌 Using device from ANDROID_SERIAL");
+        }
+        device
+    } else {
+        devices[0].clone()
+    };
+    info!("🎯 Using device: {}", device_id);
+
+    // --display-ids：同一台设备的每个显示屏一个会话，同样由 hub 共用一个端口
+    if !args.display_ids.is_empty() {
+        return run_all_devices(args, adb, vec![device_id], &file_config, &cli_overrides, log_level, server_log_level, tls).await;
+    }
+
+    run_session(args, adb, device_id, &file_config, &cli_overrides, log_level, server_log_level, tls, nal_dump, None).await
+}
+
+/// --all-devices：为每台设备运行一个会话，WebSocket 端口由 [`ws::hub::DeviceHub`] 统一监听并按 `?device=` 分发
+///
+/// --display-ids 时每台设备的每个显示屏各一个会话，按 `/device/<serial>/display/<id>/` 分发。
+#[allow(clippy::too_many_arguments)]
+async fn run_all_devices(
+    args: Args,
+    adb: AdbClient,
+    devices: Vec<String>,
+    file_config: &config::FileConfig,
+    cli_overrides: &config::StreamOverrides,
+    log_level: Level,
+    server_log_level: scrcpy::server::ServerLogLevel,
+    tls: Option<axum_server::tls_rustls::RustlsConfig>,
+) -> Result<()> {
+    let hub = ws::hub::DeviceHub::new();
+    let listener = hub.bind(args.bind_addr(), args.ws_port, tls.is_some()).await?;
+    let actual_ws_port = listener.local_addr()?.port();
+    if actual_ws_port != args.ws_port {
+        info!("📌 WebSocket port {} was occupied, using port {} instead", args.ws_port, actual_ws_port);
+    }
+    if args.print_endpoint {
+        print_endpoint(args.bind_addr(), actual_ws_port, None, tls.is_some());
+    }
+    let auth_token = args.auth_token.as_deref().map(ws::auth::AuthToken::new);
+    let hub_server = hub.clone();
+    tokio::spawn(async move {
+        if let Err(e) = hub_server.serve(listener, tls, auth_token).await {
+            error!("WebSocket server error: {}", e);
+        }
+    });
+    // (设备, 显示屏)：未指定 --display-ids 时每台设备一个会话
+    let targets: Vec<(String, Option<u32>)> = devices
+        .iter()
+        .flat_map(|device_id| match args.display_ids.as_slice() {
+            [] => vec![(device_id.clone(), None)],
+            ids => ids.iter().map(|&id| (device_id.clone(), Some(id))).collect(),
+        })
+        .collect();
+    if args.display_ids.is_empty() {
+        info!("📱 Mirroring {} devices: pick one at / or connect to /ws?device=SERIAL", devices.len());
+    } else {
+        info!("🖥️  Mirroring {} displays: pick one at / or open /device/SERIAL/display/ID/", targets.len());
+    }
+
+    // 各会话在同一个任务中并发运行，日志带上设备序列号（和显示屏 id）
+    let sessions = targets.iter().map(|(device_id, display)| {
+        let mut args = args.clone();
+        let span = match display {
+            Some(id) => {
+                args.display_id = Some(*id);
+                tracing::info_span!("device", serial = %device_id, display = id)
+            }
+            None => tracing::info_span!("device", serial = %device_id),
+        };
+        run_session(args, adb.clone(), device_id.clone(), file_config, cli_overrides, log_level, server_log_level, None, None, Some(hub.clone()))
+            .instrument(span)
+    });
+    let mut first_error = None;
+    for ((device_id, display), result) in targets.iter().zip(futures_util::future::join_all(sessions).await) {
+        if let Err(e) = result {
+            match display {
+                Some(id) => error!("❌ Session for display {} of {} failed: {}", id, device_id, e),
+                None => error!("❌ Session for {} failed: {}", device_id, e),
+            }
+            first_error.get_or_insert(e);
+        }
+    }
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// 一台设备的镜像会话：启动 scrcpy-server 和 WebSocket 服务，转发视频和控制，直到退出
+///
+/// `hub` 为 Some 时（--all-devices）不单独监听端口，Router 注册到 hub 后由它分发请求。
+#[allow(clippy::too_many_arguments)]
+async fn run_session(
+    args: Args,
+    adb: AdbClient,
+    device_id: String,
+    file_config: &config::FileConfig,
+    cli_overrides: &config::StreamOverrides,
+    log_level: Level,
+    server_log_level: scrcpy::server::ServerLogLevel,
+    tls: Option<axum_server::tls_rustls::RustlsConfig>,
+    nal_dump: Option<NalDump>,
+    hub: Option<ws::hub::DeviceHub>,
+) -> Result<()> {
+    // --display-ids：本会话镜像的显示屏（run_all_devices 已把它写入 args.display_id）
+    let display_session = (!args.display_ids.is_empty()).then(|| args.display_id.unwrap_or(0));
+
+    // 设备实例锁：防止两个实例同时推送 server、抢占同一个抽象套接字（多显示屏会话按显示屏分别加锁）
+    let lock_name = match display_session {
+        Some(display) => format!("{}@display{}", device_id, display),
+        None => device_id.clone(),
+    };
+    let _instance_lock = match InstanceLock::acquire(&lock_name, args.force) {
+        Ok(lock) => lock,
+        Err(ScrcpyError::DeviceInUse(device, pid, path)) => {
+            eprintln!("❌ Device {} is already being mirrored by another instance (pid {})", device, pid);
+            eprintln!("Stop the other instance first, or use --force to start anyway (lock file: {:?})", path);
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+
+    // 获取设备信息（设备在此期间断开时给出明确提示，并可等待重连）
+    let mut retries_left = args.startup_retries;
+    let (model, android_version, device_width, device_height) = loop {
+        match gather_device_info(&adb, &device_id).await {
+            Ok(info) => break info,
+            Err(ScrcpyError::DeviceDisconnected(_)) if retries_left > 0 => {
+                retries_left -= 1;
+                warn!("⚠️  Device {} disconnected during startup, waiting for it to reconnect ({} retries left)...",
+                    device_id, retries_left);
+                if !adb.wait_for_device(&device_id, STARTUP_RECONNECT_TIMEOUT).await? {
+                    eprintln!("❌ Device {} did not reconnect within {}s", device_id, STARTUP_RECONNECT_TIMEOUT.as_secs());
+                    return Ok(());
+                }
+                info!("🔌 Device {} reconnected, retrying startup", device_id);
+            }
+            Err(ScrcpyError::DeviceDisconnected(_)) => {
+                eprintln!("❌ Device {} disconnected during startup", device_id);
+                eprintln!("Reconnect the device and try again, or use --startup-retries to wait for it");
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    info!("📱 Device Info:");
+    info!("  Model: {}", model.trim());
+    info!("  Android: {}", android_version.trim());
+    if device_width > 0 && device_height > 0 {
+        info!("  Physical Screen: {}x{}", device_width, device_height);
+    } else {
+        info!("  Physical Screen: unknown (from first SPS)");
+    }
+    // 多显示屏设备（折叠屏、DeX）：列出所有显示屏，--display-id 选择镜像哪一个
+    let displays = adb.list_displays(&device_id).await.unwrap_or_else(|e| {
+        debug!("Failed to list displays: {}", e);
+        Vec::new()
+    });
+    if displays.len() > 1 {
+        info!("🖥️  {} displays (mirroring display {}):", displays.len(), args.display_id.unwrap_or(0));
+        for info in &displays {
+            info!("  Display {}: {} {}x{}", info.id, info.name, info.width, info.height);
+        }
+    }
+    // wm size 只反映默认显示屏，其他显示屏的触控坐标按它自己的尺寸换算
+    let (device_width, device_height) = match (args.display_size, args.display_id) {
+        (Some((width, height)), _) => {
+            info!("📐 Display size {}x{} (from --display-size)", width, height);
+            (width, height)
+        }
+        (None, Some(id)) if id != 0 => match display_size(&displays, id) {
+            Ok((width, height)) => {
+                info!("📐 Display {} size {}x{} (from dumpsys display)", id, width, height);
+                (width, height)
+            }
+            Err(e) => {
+                eprintln!("❌ {}", e);
+                eprintln!("Check the display id, or pass its size with --display-size WxH");
+                return Ok(());
+            }
+        },
+        _ => (device_width, device_height),
+    };
+
+    // --crop：启动 server 之前检查裁剪区域是否在屏幕内（屏幕尺寸未知时交给 server 检查）
+    if let Some(crop) = args.crop.filter(|_| device_width > 0 && device_height > 0) {
+        if let Err(e) = crop.check_bounds((device_width, device_height)) {
+            eprintln!("❌ {}", e);
+            eprintln!("The crop rectangle is relative to the device's natural orientation");
+            return Ok(());
+        }
+    }
+
+    // 部署和启动scrcpy-server
+    if !args.server_path.exists() {
+        eprintln!("❌ scrcpy-server not found at: {:?}", args.server_path);
+        eprintln!("Please specify correct server path with --server-path option");
+        return Ok(());
+    }
+
+    // --only-app：后台轮询前台应用，目标应用在前台时为 true
+    let (app_visible_tx, mut app_visible_rx) = tokio::sync::watch::channel(args.only_app.is_none());
+    if let Some(package) = args.only_app.clone() {
+        tokio::spawn(watch_foreground_app(
+            adb.clone(),
+            device_id.clone(),
+            package,
+            tokio::time::Duration::from_millis(args.only_app_poll_ms),
+            app_visible_tx,
+        ));
+    }
+
+    // 后台轮询设备旋转方向（0-3）：--lock-orientation 时视频方向固定，不需要轮询
+    let (rotation_tx, mut rotation_rx) = tokio::sync::watch::channel(None);
+    let poll_rotation = args.orientation_poll_ms > 0 && args.lock_orientation.is_none() && device_width > 0 && device_height > 0;
+    if poll_rotation {
+        tokio::spawn(watch_device_rotation(
+            adb.clone(),
+            device_id.clone(),
+            args.display_id.unwrap_or(0),
+            tokio::time::Duration::from_millis(args.orientation_poll_ms),
+            rotation_tx,
+        ));
+    }
+    // 视频在自然方向下的宽高：--crop 时是裁剪区域，否则是整个屏幕
+    let natural_landscape = args.crop.map_or(device_width > device_height, |crop| crop.width > crop.height);
+
+    // 合并配置文件：[[device]] 块 > 命令行 > 文件全局值 > 默认值
+    let defaults = config::StreamSettings {
+        max_size: args.max_size,
+        bit_rate: args.bit_rate,
+        max_fps: args.max_fps,
+        codec: "h264".to_string(),
+    };
+    let stream_settings = file_config.resolve(defaults, cli_overrides, &device_id);
+    if file_config.device(&device_id).is_some() {
+        info!("📄 Applying [[device]] config for {}", device_id);
+    }
+    info!("📐 Stream settings: {}p, {} Mbps, {} fps, {}",
+        stream_settings.max_size, stream_settings.bit_rate / 1_000_000, stream_settings.max_fps, stream_settings.codec);
+
+    let mut server = ScrcpyServer::with_config(
+        adb.clone(),
+        device_id.clone(),
+        args.server_path.clone(),
+        stream_settings.max_size,
+        stream_settings.bit_rate,
+        stream_settings.max_fps,
+        args.video_port,
+        args.control_port,
+        args.intra_refresh_period,
+    )?;
+    server.set_cleanup(!args.no_cleanup);
+    server.set_server_class(args.server_class.clone());
+    server.set_codec_profile(args.codec_profile);
+    server.set_power_on(!args.no_power_on);
+    server.set_display_id(args.display_id);
+    // 同一台设备上的多个 server 用显示屏 id 作为 scid，各自监听 scrcpy_<scid>
+    server.set_scid(display_session);
+    server.set_crop(args.crop);
+    server.set_orientation_lock(args.lock_orientation);
+    server.set_frame_meta(args.frame_meta);
+    server.set_time_limit(args.device_time_limit.map(std::time::Duration::from_secs));
+    server.set_log_level(server_log_level);
+    server.set_audio(args.audio)?;
+    if args.audio_dup {
+        configure_audio_dup(&mut server, &adb, &device_id).await;
+    }
+    if let Some(ref extra_args) = args.server_extra_args {
+        // 已在参数解析阶段校验过
+        server.set_extra_args(scrcpy::server::parse_server_extra_args(extra_args).unwrap_or_default());
+    }
+    #[cfg(unix)]
+    if args.unix_socket {
+        info!("🔌 Using Unix domain sockets in {:?}", std::env::temp_dir());
+        server.set_unix_socket_dir(Some(std::env::temp_dir()));
+    }
+
+    // 部署服务器
+    if let Err(e) = server.deploy().await {
+        error!("Failed to deploy server: {}", e);
+        return Err(e);
+    }
+
+    // --audio：音频广播通道，每次（重新）连接后由转发任务写入，/audio 客户端订阅
+    let audio_sender = args.audio.then(|| tokio::sync::broadcast::channel(ws::audio::AUDIO_CHANNEL_CAPACITY).0);
+
+    // 启动服务器并连接视频流和控制流（编码器无法以请求的码率启动时自动降低码率重试）
+    let (mut reader, mut control_channel) = match start_with_bit_rate_fallback(&mut server, args.read_buffer_bytes, audio_sender.as_ref()).await {
+        Ok(streams) => streams,
+        Err(e) => {
+            error!("Failed to start server: {}", e);
+            let _ = server.stop().await;
+            return Err(e);
+        }
+    };
+
+    // --selftest-frames：采集、解码、检查后直接退出，不启动 WebSocket 服务
+    #[cfg(feature = "selftest")]
+    if let Some(frames) = args.selftest_frames {
+        let result = utils::selftest::run(&mut reader, frames as usize, &args.selftest_dir, &args.ffmpeg_path).await;
+        drop(reader);
+        drop(control_channel);
+        server.stop().await?;
+        return result.map(|_| ());
+    }
+
+    // 创建 IDR 请求通道
+    let (idr_request_tx, mut idr_request_rx) = tokio::sync::mpsc::channel::<()>(10);
+
+    // 创建控制事件队列：连续的 MOVE/滚动事件在入队时合并，DOWN/UP 始终按序保留
+    let (control_tx, mut control_rx) = scrcpy::control_queue::control_queue(100);
+
+    // 创建 WebSocket 服务器（自动寻找可用端口）
+    let mut ws_server = WebSocketServer::new(args.ws_port, idr_request_tx, control_tx, device_width, device_height, args.bind_addr())?;
+    ws_server.set_health_stale_after(std::time::Duration::from_secs(args.health_stale_secs));
+    ws_server.set_trust_proxy(args.trust_proxy);
+    ws_server.set_lag_resync_threshold(args.lag_resync_threshold);
+    ws_server.set_max_message_bytes(args.max_message_bytes);
+    ws_server.set_device(adb.clone(), device_id.clone(), args.display_id.unwrap_or(0));
+    if args.only_app.is_some() {
+        ws_server.set_privacy_gate(app_visible_rx.clone());
+    }
+    if let Some(ref audio_sender) = audio_sender {
+        ws_server.set_audio_sender(audio_sender.clone());
+    }
+    if let Some(tls) = tls {
+        ws_server.set_tls(tls);
+    }
+    // 多设备模式由 hub 统一检查令牌
+    if let Some(token) = args.auth_token.as_ref().filter(|_| hub.is_none()) {
+        ws_server.set_auth_token(token);
+    }
+    ws_server.set_hub_authenticated(hub.is_some() && args.auth_token.is_some());
+    let saved_brightness = ws_server.get_saved_brightness();
+    let saved_rotation = ws_server.get_saved_rotation();
+    let liveness = ws_server.get_liveness();
+    let stream_stats = ws_server.get_stream_stats();
+    tokio::spawn(watch_device_presence(adb.clone(), device_id.clone(), liveness.clone()));
+    let actual_ws_port = ws_server.get_actual_port();
+    if args.enable_debug_endpoints || matches!(log_level, Level::DEBUG | Level::TRACE) {
+        let mut resolved_args = serde_json::to_value(&args).unwrap_or_default();
+        redact_secrets(&mut resolved_args);
+        ws_server.set_debug_config(serde_json::json!({
+            "args": resolved_args,
+            "stream_settings": stream_settings,
+            "server_command": server.server_command(),
+            "ports": {
+                "ws": actual_ws_port,
+                "video": server.get_actual_video_port(),
+                "control": server.get_actual_control_port(),
+                "audio": server.get_actual_audio_port(),
+            },
+            "device": {
+                "serial": device_id,
+                "model": model.trim(),
+                "android_version": android_version.trim(),
+                "screen": [device_width, device_height],
+            },
+        }));
+        info!("🐞 Debug endpoint enabled: /debug/config");
+    }
+    let mut frame_sinks = FrameSinks::new(ws_server.get_sender());
+    frame_sinks.set_overflow_policy(args.record_overflow);
+    frame_sinks.set_start_code_len(args.start_code);
+    ws_server.set_start_code_len(args.start_code);
+    ws_server.set_sink_stats(frame_sinks.get_stats());
+    ws_server.set_server_log_stats(server.get_log_stats());
+    let raw_outputs: Vec<_> = args.raw_output.iter()
+        .filter_map(|target| add_raw_output(&mut frame_sinks, target).map(|writer| (target.as_str(), writer)))
+        .collect();
+    let mut timelines = create_timelines(&args, &raw_outputs).await;
+    let config_sender = ws_server.get_config_sender();
+    let video_config = ws_server.get_video_config();
+    video_config.write().await.target_aspect = args.target_aspect;
+    video_config.write().await.inline_sps_on_idr = args.inline_sps_on_idr;
+    video_config.write().await.uhid_keyboard = args.uhid;
+    video_config.write().await.frame_meta = args.frame_meta;
+    let shutdown_sender = ws_server.get_shutdown_sender();
+    let mut device_msg_task = spawn_device_message_reader(&mut control_channel, config_sender.clone());
+    // --uhid：启动时就创建键盘，设备端的输入法从一开始就识别到物理键盘
+    if args.uhid {
+        if let Err(e) = control_channel.create_uhid_keyboard().await {
+            warn!("⌨️  Failed to create UHID keyboard, it will be retried on the first key: {}", e);
+        }
+    }
+
+    // 显示实际使用的端口信息
+    if hub.is_none() && actual_ws_port != args.ws_port {
+        info!("📌 WebSocket port {} was occupied, using port {} instead", args.ws_port, actual_ws_port);
+    }
+
+    // raw_stream 模式：SPS/PPS 将在视频帧循环中从 NAL 流提取并缓存
+
+    // 在后台提供服务；运行中出错时通知主循环退出
+    let (ws_failed_tx, mut ws_failed_rx) = tokio::sync::oneshot::channel::<ScrcpyError>();
+    // oneshot 完成后不能再次 poll：发送端被丢弃（服务正常结束）时同样算完成；hub 模式从一开始就不监听
+    let mut ws_failed_done = hub.is_some();
+    if let Some(hub) = &hub {
+        // --all-devices / --display-ids：不单独监听，Router 注册到 hub。端口由 hub 的服务持有，
+        // 它出错时不会经过 ws_failed_tx（发送端一直留到会话结束，不会被使用），因此 ws_failed_done 一开始就是 true，主循环不 poll 接收端
+        hub.register(&device_id, display_session, model.trim(), ws_server.build_router()).await;
+    } else {
+        // 先绑定 WebSocket 端口：失败时停止设备端 server 再退出，不留下无法访问的会话
+        let listener = match ws_server.bind().await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("❌ WebSocket server failed to start: {}", e);
+                drop(reader);
+                drop(control_channel);
+                server.stop().await?;
+                return Err(e);
+            }
+        };
+        if args.print_endpoint {
+            let device_ports = match server.control_endpoint() {
+                scrcpy::stream::DeviceEndpoint::Tcp(control_port) => Some((server.get_actual_video_port(), control_port)),
+                #[cfg(unix)]
+                scrcpy::stream::DeviceEndpoint::Unix(_) => None,
+            };
+            print_endpoint(args.bind_addr(), actual_ws_port, device_ports, args.tls_cert.is_some());
+        }
+        tokio::spawn(async move {
+            if let Err(e) = ws_server.serve(listener).await {
+                error!("WebSocket server error: {}", e);
+                let _ = ws_failed_tx.send(e);
+            }
+        });
+    }
+    let mut fatal_error = None;  // 导致会话结束的错误（退出码非 0）
+
+    info!("📺 Starting to receive and broadcast video frames...");
+    info!("   Press Ctrl+C to stop");
+
+    let mut config_frame_count = 0;
+    let mut sps_cached = false;
+    let mut pps_cached = false;
+    let mut pending_idr_request = false;
+    let mut frame_limiter = FrameRateLimiter::new(args.broadcast_fps);
+    let mut app_visible = args.only_app.is_none();
+    let mut resume_needs_idr = false;
+    let mut config_after_restart = false;  // set_max_size / reconfigure 重启后，第一个 SPS 总是广播配置
+    let mut key_hold: Option<scrcpy::control::KeyHold> = None;  // 正在进行的长按（key_hold 事件）
+    let mut touch_gate = scrcpy::control::TouchGate::default();  // 旋转期间暂停触摸转发
+    let mut pointer_ids = scrcpy::control::PointerIdMapper::new(args.pointer_mode);  // --pointer-mode
+    let mut last_frame_at = tokio::time::Instant::now();
+    let mut device_locked = false;  // 视频长时间中断（锁屏），已通知客户端
+    // 控制流写入失败：scrcpy-server 只接受一次控制连接，重新连接端口得到的是没有 server 的空连接，只能完整重连
+    let mut control_lost = false;
+    let mut failed_reconnects = 0u32;  // 连续失败的完整重连次数（--max-reconnects）
+    let mut reconnect_grace = ws::reconnect::ReconnectGrace::new(std::time::Duration::from_secs(args.reconnect_grace));
+
+    // 会话时长限制：到期后通知客户端并退出
+    let session_deadline = args.max_session_minutes
+        .map(|minutes| tokio::time::Instant::now() + tokio::time::Duration::from_secs(minutes * 60));
+    let mut session_log_interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+    let mut health_interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
+
+    // --verbose-frames：每帧只计数，定时输出 NAL 类型统计
+    let frame_stats_period = tokio::time::Duration::from_secs(args.verbose_frames.unwrap_or(1));
+    let mut frame_stats_interval = tokio::time::interval_at(tokio::time::Instant::now() + frame_stats_period, frame_stats_period);
+    let mut frame_histogram = NalHistogram::default();
+    let mut frame_stats_since = tokio::time::Instant::now();
+
+    // Ctrl+C / SIGTERM：跳出主循环，执行下面的清理（恢复设备设置、移除端口转发、结束 server）
+    let shutdown_signal = shutdown_signal();
+    tokio::pin!(shutdown_signal);
+
+    // 持续接收并广播视频帧
+    loop {
+        tokio::select! {
+            _ = &mut shutdown_signal => {
+                info!("🛑 Received shutdown signal, stopping...");
+                let _ = shutdown_sender.send(Some("Server shutting down".to_string()));
+                break;
+            }
+
+            // 会话时长到期
+            _ = tokio::time::sleep_until(session_deadline.unwrap_or_else(tokio::time::Instant::now)), if session_deadline.is_some() => {
+                info!("⏰ Max session duration reached, stopping...");
+                let _ = config_sender.send("{\"type\":\"session_ending\"}".to_string());
+                let _ = shutdown_sender.send(Some("Max session duration reached".to_string()));
+                break;
+            }
+
+            // WebSocket 服务意外停止：没有客户端能访问，停止会话
+            result = &mut ws_failed_rx, if !ws_failed_done => {
+                ws_failed_done = true;
+                if let Ok(e) = result {
+                    fatal_error = Some(e);
+                    break;
+                }
+            }
+
+            // --only-app：目标应用进入/离开前台
+            Ok(()) = app_visible_rx.changed(), if args.only_app.is_some() => {
+                app_visible = *app_visible_rx.borrow_and_update();
+                if app_visible {
+                    info!("▶️  Target app is in foreground, resuming broadcast");
+                    // 暂停期间丢弃了参考帧，恢复后从下一个 IDR 开始发送
+                    resume_needs_idr = true;
+                } else {
+                    info!("⏸️  Target app left foreground, pausing broadcast");
+                }
+                let _ = config_sender.send(format!("{{\"type\":\"privacy\",\"paused\":{}}}", !app_visible));
+            }
+
+            // 设备旋转：编码器保持分辨率（加黑边）时 SPS 不变，由轮询结果推送横竖屏
+            Ok(()) = rotation_rx.changed(), if poll_rotation => {
+                let rotation = *rotation_rx.borrow_and_update();
+                if let Some(rotation) = rotation {
+                    let is_landscape = natural_landscape != (rotation % 2 == 1);
+                    let mut config = video_config.write().await;
+                    if config.is_landscape != is_landscape {
+                        info!("🔄 Device rotated ({}°), Landscape: {}", rotation as u32 * 90, is_landscape);
+                        config.is_landscape = is_landscape;
+                        let _ = config_sender.send(config.config_message());
+                    }
+                }
+            }
+
+            // 定期输出剩余时长：最后 5 分钟每分钟一次，其余每 10 分钟一次
+            _ = session_log_interval.tick(), if session_deadline.is_some() => {
+                if let Some(deadline) = session_deadline {
+                    let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+                    let remaining_minutes = remaining.as_secs().div_ceil(60);
+                    if remaining_minutes <= 5 || remaining_minutes % 10 == 0 {
+                        info!("⏳ Session ends in {} min", remaining_minutes);
+                    }
+                }
+            }
+
+            // 更新 server 进程存活状态（/healthz），检测锁屏导致的视频中断
+            _ = health_interval.tick() => {
+                liveness.set_server_alive(server.is_running());
+                if let Some(msg) = reconnect_grace.poll(std::time::Instant::now()) {
+                    let _ = config_sender.send(msg);
+                }
+                if !device_locked && !reconnect_grace.is_reconnecting() && last_frame_at.elapsed() >= DEVICE_LOCKED_FRAME_GAP {
+                    device_locked = true;
+                    warn!("🔒 No video for {}s, device is probably locked", last_frame_at.elapsed().as_secs());
+                    let _ = config_sender.send("{\"type\":\"device_locked\",\"locked\":true}".to_string());
+                    if args.wake_on_lock {
+                        info!("⏰ --wake-on-lock: sending WAKEUP");
+                        if let Err(e) = control_channel.send_syskey(scrcpy::control::SysKey::Wakeup).await {
+                            warn!("Failed to send WAKEUP: {}", e);
+                        }
+                    }
+                }
+            }
+
+            // --verbose-frames：输出并清零本周期的帧统计
+            _ = frame_stats_interval.tick(), if args.verbose_frames.is_some() => {
+                let now = tokio::time::Instant::now();
+                let summary = frame_histogram.take(now - frame_stats_since);
+                frame_stats_since = now;
+                info!("📊 Frames: {}", summary);
+            }
+
+            // 长按：按 Android 自动重复节奏发送重复的 DOWN，到时发送 UP
+            _ = tokio::time::sleep_until(key_hold.as_ref().map(|h| h.deadline()).unwrap_or_else(tokio::time::Instant::now)), if key_hold.is_some() => {
+                if let Some(hold) = key_hold.as_mut() {
+                    let (key, done) = hold.advance(tokio::time::Instant::now());
+                    if done {
+                        key_hold = None;
+                    }
+                    if let Err(e) = control_channel.send_key_event(&key).await {
+                        error!("Failed to send held key event: {}", e);
+                        key_hold = None;
+                    }
+                }
+            }
+
+            // 处理控制事件
+            Some(control_event) = control_rx.recv() => {
+                debug!("🎮 Received control event: {:?}", control_event);
+                // 等待完整重连期间丢弃控制事件（重连后单独补发半个手势反而会造成误操作）
+                if control_lost && !matches!(control_event, scrcpy::control::ControlEvent::SetMaxSize(_) | scrcpy::control::ControlEvent::Reconfigure(_)) {
+                    debug!("🎮 Control stream reconnecting, dropping event");
+                    continue;
+                }
+                for (_, timeline) in &mut timelines {
+                    timeline.on_control(&control_event, std::time::Instant::now());
+                }
+                let mut reconfigure: Option<scrcpy::control::ReconfigureEvent> = None;
+                let result = match control_event {
+                    scrcpy::control::ControlEvent::Touch(touch) => match pointer_ids.map(touch) {
+                        Some(touch) if touch_gate.allow(&touch, tokio::time::Instant::now()) => {
+                            control_channel.send_touch_event(&touch).await
+                        }
+                        Some(_) => {
+                            debug!("👆 Rotation in progress, dropping touch");
+                            Ok(())
+                        }
+                        None => Ok(()),
+                    },
+                    scrcpy::control::ControlEvent::Rotate => {
+                        touch_gate.pause(tokio::time::Instant::now());
+                        control_channel.rotate_device().await
+                    }
+                    scrcpy::control::ControlEvent::Key(key) => {
+                        control_channel.send_key_event(&key).await
+                    }
+                    scrcpy::control::ControlEvent::Text(text) => {
+                        control_channel.send_text(&text.text).await
+                    }
+                    scrcpy::control::ControlEvent::Clipboard(clip) => {
+                        control_channel.set_clipboard(&clip.text, clip.paste).await
+                    }
+                    scrcpy::control::ControlEvent::ImeAction(ime) => {
+                        control_channel.send_ime_action(ime.action).await
+                    }
+                    scrcpy::control::ControlEvent::SysKey(syskey) => {
+                        control_channel.send_syskey(syskey.key).await
+                    }
+                    scrcpy::control::ControlEvent::KeyHold(event) => {
+                        // 新的长按先抬起正在按住的键
+                        if let Some(previous) = key_hold.take() {
+                            let _ = control_channel.send_key_event(&previous.release()).await;
+                        }
+                        info!("⏬ Holding key {} for {} ms", event.keycode, event.duration_ms);
+                        let (hold, down) = scrcpy::control::KeyHold::start(&event, tokio::time::Instant::now());
+                        key_hold = Some(hold);
+                        control_channel.send_key_event(&down).await
+                    }
+                    scrcpy::control::ControlEvent::UhidMouseRel(event) => {
+                        control_channel.send_uhid_mouse_rel(&event).await
+                    }
+                    scrcpy::control::ControlEvent::UhidMouseClose => {
+                        control_channel.close_uhid_mouse().await
+                    }
+                    // 重启或重连后的新控制流上还没有键盘，第一个按键时重新创建
+                    scrcpy::control::ControlEvent::UhidKey(event) if args.uhid => {
+                        control_channel.send_uhid_key(&event).await
+                    }
+                    scrcpy::control::ControlEvent::UhidKeyReleaseAll => {
+                        control_channel.release_uhid_keys().await
+                    }
+                    scrcpy::control::ControlEvent::UhidKey(_) => {
+                        Err(ScrcpyError::Parse("UHID keyboard is disabled (start with --uhid)".to_string()))
+                    }
+                    // WebSocket 层已回复 unknown_type，不会进入队列
+                    scrcpy::control::ControlEvent::Unknown => Ok(()),
+                    scrcpy::control::ControlEvent::Scroll(scroll) => {
+                        control_channel.send_scroll_event(
+                            scroll.x, scroll.y,
+                            scroll.width, scroll.height,
+                            scroll.hscroll, scroll.vscroll
+                        ).await
+                    }
+                    // 重启编码器需要替换当前的视频流和控制流，在事件处理之后进行
+                    scrcpy::control::ControlEvent::SetMaxSize(size) => {
+                        reconfigure = Some(size.into());
+                        Ok(())
+                    }
+                    scrcpy::control::ControlEvent::Reconfigure(change) => {
+                        reconfigure = Some(change);
+                        Ok(())
+                    }
+                };
+                if let Err(e) = result {
+                    error!("Failed to send control event to device: {}", e);
+                    // 写入失败：scrcpy 不会再接受新的控制连接，由视频帧分支执行一次完整重连
+                    if matches!(e, ScrcpyError::Network(_)) && !control_lost {
+                        warn!("🎮 Control stream lost, restarting the session...");
+                        key_hold = None;
+                        control_lost = true;
+                        let _ = config_sender.send("{\"type\":\"control_status\",\"state\":\"reconnecting\"}".to_string());
+                    }
+                } else {
+                    debug!("✅ Control event sent successfully");
+                }
+
+                // set_max_size / reconfigure：scrcpy 无法在运行中修改编码参数，以新参数重启 server
+                if let Some(change) = reconfigure {
+                    info!("📐 Restarting encoder with bit_rate={:?} max_fps={:?} max_size={:?}", change.bit_rate, change.max_fps, change.max_size);
+
+                    // 旧连接留到新连接建立后再替换（与完整重连相同）：restart 会结束旧进程，旧分辨率下尚未读取的帧随之丢弃；
+                    // 重启失败时会话结束，旧连接仍由循环后的统一清理关闭
+                    if let Some(msg) = reconnect_grace.begin(std::time::Instant::now()) {
+                        let _ = config_sender.send(msg);
+                    }
+
+                    let previous = (server.get_bit_rate(), server.get_max_fps(), server.get_max_size());
+                    if let Some(bit_rate) = change.bit_rate {
+                        server.set_bit_rate(bit_rate);
+                    }
+                    if let Some(max_fps) = change.max_fps {
+                        server.set_max_fps(max_fps);
+                    }
+                    if let Some(max_size) = change.max_size {
+                        server.set_max_size(max_size);
+                    }
+                    // 新参数启动失败：通知客户端，恢复原参数重启，不结束会话
+                    (reader, control_channel) = match start_with_bit_rate_fallback(&mut server, args.read_buffer_bytes, audio_sender.as_ref()).await {
+                        Ok(streams) => streams,
+                        Err(e) => {
+                            error!("❌ Encoder failed with the new parameters, restoring previous ones: {}", e);
+                            let _ = config_sender.send(serde_json::json!({
+                                "type": "error",
+                                "reason": "reconfigure_failed",
+                                "message": e.to_string(),
+                            }).to_string());
+                            server.set_bit_rate(previous.0);
+                            server.set_max_fps(previous.1);
+                            server.set_max_size(previous.2);
+                            // 原参数也无法启动：结束会话，走循环后的统一清理（注销、恢复亮度、移除转发等）
+                            match start_with_bit_rate_fallback(&mut server, args.read_buffer_bytes, audio_sender.as_ref()).await {
+                                Ok(streams) => streams,
+                                Err(e) => {
+                                    error!("❌ Failed to restart with the previous parameters: {}", e);
+                                    fatal_error = Some(e);
+                                    break;
+                                }
+                            }
+                        }
+                    };
+                    last_frame_at = tokio::time::Instant::now();  // 重启期间没有视频帧，不是锁屏
+                    if let Some(task) = std::mem::replace(&mut device_msg_task, spawn_device_message_reader(&mut control_channel, config_sender.clone())) {
+                        task.abort();
+                    }
+                    // 重启后控制流也是新连接
+                    if std::mem::take(&mut control_lost) {
+                        let _ = config_sender.send("{\"type\":\"control_status\",\"state\":\"connected\"}".to_string());
+                    }
+
+                    // 清除旧分辨率的 SPS/PPS，等待新编码器输出后重新缓存并广播
+                    sps_cached = false;
+                    pps_cached = false;
+                    let mut config = video_config.write().await;
+                    config.sps = None;
+                    config.pps = None;
+                    drop(config);
+                    resume_needs_idr = true;  // 客户端只从新编码器的 IDR 开始解码
+                    config_after_restart = true;
+                }
+            }
+
+            // 处理IDR请求
+            Some(_) = idr_request_rx.recv() => {
+                debug!("🎬 Received IDR request from new client");
+                pending_idr_request = true;
+
+                // 立即重新发送缓存的SPS/PPS
+                if sps_cached {
+                    // 获取当前缓存的SPS并重新广播
+                    let config = video_config.read().await;
+                    if let Some(sps) = &config.sps {
+                        let _ = frame_sinks.broadcast().send(sps.clone().into());
+                    }
+                    if let Some(pps) = &config.pps {
+                        let _ = frame_sinks.broadcast().send(pps.clone().into());
+                    }
+                    drop(config);
+                }
+            }
+
+            // 处理视频帧（控制流丢失时不再读取，直接进入完整重连）
+            frame_result = async {
+                if control_lost {
+                    return Ok(Err(ScrcpyError::Network("control stream lost".to_string())));
+                }
+                tokio::time::timeout(tokio::time::Duration::from_secs(10), reader.read_frame(server.stream_frame_meta())).await
+            } => {
+                match frame_result {
+                    Ok(Ok(Some(frame))) => {
+                        liveness.mark_frame();
+                        last_frame_at = tokio::time::Instant::now();
+                        if device_locked {
+                            device_locked = false;
+                            info!("🔓 Video resumed");
+                            let _ = config_sender.send("{\"type\":\"device_locked\",\"locked\":false}".to_string());
+                        }
+                        if args.verbose_frames.is_some() {
+                            frame_histogram.record(frame.data[0] & 0x1F, frame.data.len());
+                        }
+                        if let Some(dump) = &nal_dump {
+                            dump.write(&frame.data);
+                        }
+                        // 每帧一个 trace 级别的 span，便于 tracing-flame 等工具分析耗时
+                        let frame_span = trace_span!(
+                            "frame",
+                            nal_type = frame.data[0] & 0x1F,
+                            bytes = frame.data.len(),
+                            width = tracing::field::Empty,
+                            height = tracing::field::Empty,
+                        );
+                        async {
+                            if frame.is_keyframe() {
+                                stream_stats.record_keyframe();
+
+                                // 如果收到IDR帧并且有pending请求，清除标志
+                                let nal_type = frame.data[0] & 0x1F;
+                                if nal_type == 5 && pending_idr_request {
+                                    debug!("✅ Got requested IDR frame");
+                                    pending_idr_request = false;
+                                }
+                            }
+
+                            if frame.frame_type == scrcpy::FrameType::Config {
+                                config_frame_count += 1;
+
+                                // 缓存 SPS/PPS
+                                let nal_type = frame.data[0] & 0x1F;
+                                if nal_type == 7 {
+                                    // SPS - 从中解析分辨率
+                                    let geometry_changed = cache_sps(&frame.data, &video_config, &config_sender).await;
+                                    if geometry_changed {
+                                        // 旋转后的新尺寸已生效，客户端收到配置后按新尺寸计算触摸坐标
+                                        touch_gate.resume();
+                                    }
+                                    // 重新配置后分辨率没变时 cache_sps 不会广播，仍通知客户端新编码器已就绪
+                                    if std::mem::take(&mut config_after_restart) && !geometry_changed {
+                                        let _ = config_sender.send(video_config.read().await.config_message());
+                                    }
+                                    if !sps_cached {
+                                        info!("✅ SPS cached ({} bytes)", frame.data.len() + 4);
+                                        sps_cached = true;
+                                    }
+
+                                } else if nal_type == 8 && !pps_cached {
+                                    // PPS
+                                    let mut nal_with_start_code = vec![0x00, 0x00, 0x00, 0x01];
+                                    nal_with_start_code.extend_from_slice(&frame.data);
+
+                                    let mut config = video_config.write().await;
+                                    config.pps = Some(Bytes::from(nal_with_start_code.clone()));
+                                    drop(config);
+
+                                    info!("✅ PPS cached ({} bytes)", nal_with_start_code.len());
+                                    pps_cached = true;
+                                }
+                            }
+
+                            // --only-app：目标应用不在前台时不广播任何画面
+                            if !app_visible {
+                                return;
+                            }
+                            if resume_needs_idr {
+                                match frame.data[0] & 0x1F {
+                                    5 => resume_needs_idr = false,
+                                    1 => return,
+                                    _ => {}
+                                }
+                            }
+
+                            // --scale-fps-to-clients：没有任何接收方时只放行关键帧，第一个观看者连接后的下一帧恢复
+                            if args.scale_fps_to_clients {
+                                let idle = frame_sinks.broadcast().receiver_count() == 0 && !frame_sinks.has_active_writers();
+                                if idle != frame_limiter.is_keyframes_only() {
+                                    frame_limiter.set_keyframes_only(idle);
+                                    if idle {
+                                        info!("💤 No viewers, broadcasting keyframes only");
+                                    } else {
+                                        info!("▶️ Viewer connected, resuming full frame rate");
+                                    }
+                                }
+                            }
+
+                            // 广播帧率限制：丢弃多余的 P 帧（优先丢弃非参考帧）
+                            if !frame_limiter.allow(frame.data[0], std::time::Instant::now()) {
+                                return;
+                            }
+
+                            // 构建完整的 NAL 单元（包含起始码）
+                            let mut nal_with_start_code = vec![0x00, 0x00, 0x00, 0x01];
+                            nal_with_start_code.extend_from_slice(&frame.data);
+
+                            // 分发给 WebSocket 客户端和所有写入端（非阻塞）
+                            frame_sinks.dispatch_with_pts(Bytes::from(nal_with_start_code), args.frame_meta.then_some(frame.pts));
+                            if let Some(msg) = reconnect_grace.on_frame(frame.data[0], std::time::Instant::now()) {
+                                let _ = config_sender.send(msg);
+                            }
+                            // 只记录写入端实际收下的帧，时间线的帧序号与录制文件保持一致
+                            for (writer, timeline) in &mut timelines {
+                                if frame_sinks.wrote_last(*writer) {
+                                    timeline.on_frame(frame.data[0], frame.pts, std::time::Instant::now());
+                                }
+                            }
+
+                            let frame_counter = stream_stats.record_frame();
+
+                            // 帧统计日志降为 trace 级别，避免影响性能
+                            if frame_counter % 60 == 0 {
+                                trace!(
+                                    "  Frames: {}, Keyframes: {}, Config: {}, Subscribers: {}",
+                                    reader.frame_count(),
+                                    stream_stats.keyframes(),
+                                    config_frame_count,
+                                    frame_sinks.broadcast().receiver_count()
+                                );
+                            }
+                        }
+                        .instrument(frame_span)
+                        .await;
+                    }
+                    Ok(ended) => {
+                        // --device-time-limit 到期：设备端 server 按时退出，正常结束会话
+                        if server.time_limit_reached() {
+                            info!("⏰ Device time limit reached, stopping...");
+                            let _ = config_sender.send("{\"type\":\"session_ending\"}".to_string());
+                            let _ = shutdown_sender.send(Some("Device time limit reached".to_string()));
+                            break;
+                        }
+                        // 视频流结束、出错或控制流丢失：重启 server 并重新连接（一次完整重连）
+                        match ended {
+                            Err(e) if control_lost => warn!("🎮 {}, reconnecting...", e),
+                            Err(e) => error!("Error reading frame: {}, reconnecting...", e),
+                            Ok(_) => warn!("Stream ended, reconnecting..."),
+                        }
+                        key_hold = None;
+                        if let Some(msg) = reconnect_grace.begin(std::time::Instant::now()) {
+                            let _ = config_sender.send(msg);
+                        }
+                        match reconnect_session(&mut server, args.read_buffer_bytes, audio_sender.as_ref()).await {
+                            Ok((new_reader, mut new_control)) => {
+                                if let Some(task) = std::mem::replace(&mut device_msg_task, spawn_device_message_reader(&mut new_control, config_sender.clone())) {
+                                    task.abort();
+                                }
+                                reader = new_reader;
+                                control_channel = new_control;
+                                failed_reconnects = 0;
+                                if std::mem::take(&mut control_lost) {
+                                    let _ = config_sender.send("{\"type\":\"control_status\",\"state\":\"connected\"}".to_string());
+                                }
+                                last_frame_at = tokio::time::Instant::now();
+
+                                // 新编码器会重新输出 SPS/PPS
+                                sps_cached = false;
+                                pps_cached = false;
+                                let mut config = video_config.write().await;
+                                config.sps = None;
+                                config.pps = None;
+                                drop(config);
+                                resume_needs_idr = true;
+                                info!("✅ Reconnected to device");
+                            }
+                            Err(e) => {
+                                failed_reconnects += 1;
+                                error!("❌ Reconnect attempt {} failed: {}", failed_reconnects, e);
+                                if let Some(e) = reconnect_limit_error(failed_reconnects, args.max_reconnects, &shutdown_sender) {
+                                    fatal_error = Some(e);
+                                    break;
+                                }
+                                let backoff = reconnect_backoff(failed_reconnects);
+                                info!("⏳ Retrying in {}s", backoff.as_secs());
+                                tokio::time::sleep(backoff).await;
+                            }
+                        }
+                        continue;
+                    }
+                    Err(_) => {
+                        warn!("Timeout waiting for frame, continuing...");
+                        continue;
+                    }
+                }
+            }
+        }
+    }
+
+    // 会话结束后 hub 不再把请求交给它
+    if let Some(hub) = &hub {
+        hub.unregister(&device_id, display_session).await;
+    }
+
+    // 先关闭视频流和控制流，让 scrcpy 检测到断开后自行退出并执行 cleanup
+    drop(reader);
+    drop(control_channel);
+
+    // 恢复客户端调整前的屏幕亮度
+    if let Some(state) = saved_brightness.lock().await.take() {
+        match adb.restore_brightness(&device_id, state).await {
+            Ok(()) => info!("🔆 Restored brightness to {}", state.brightness),
+            Err(e) => warn!("❌ Failed to restore brightness: {}", e),
+        }
+    }
+
+    // 恢复客户端锁定方向前的旋转设置
+    if let Some(state) = saved_rotation.lock().await.take() {
+        match adb.restore_rotation(&device_id, state).await {
+            Ok(()) => info!("🔄 Restored rotation settings (auto-rotate {})", if state.locked { "off" } else { "on" }),
+            Err(e) => warn!("❌ Failed to restore rotation settings: {}", e),
+        }
+    }
+
+    // 停止服务器
+    server.stop().await?;
+
+    if let Some(dump) = nal_dump {
+        dump.finish().await;
+    }
+    for (_, timeline) in timelines {
+        timeline.finish().await;
+    }
+
+    if let Some(e) = fatal_error {
+        return Err(e);
+    }
+    info!("👋 Shutting down...");
+    Ok(())
+}
+
+/// 等待 Ctrl+C（Unix 上还包括 systemd、docker stop 发送的 SIGTERM）
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(signal) => signal,
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            }
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+// 轮询前台应用，状态变化时通知主循环
+async fn watch_foreground_app(
+    adb: AdbClient,
+    device_id: String,
+    package: String,
+    interval: tokio::time::Duration,
+    visible_tx: tokio::sync::watch::Sender<bool>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    while !visible_tx.is_closed() {
+        ticker.tick().await;
+        match adb.foreground_package(&device_id).await {
+            Ok(Some(top)) => {
+                let visible = top == package;
+                visible_tx.send_if_modified(|current| {
+                    let changed = *current != visible;
+                    *current = visible;
+                    changed
+                });
+            }
+            Ok(None) => debug!("Could not determine foreground app"),
+            Err(e) => debug!("Failed to query foreground app: {}", e),
+        }
+    }
+}
+
+/// 轮询设备的旋转方向，同一读数连续出现两次才更新，快速连续旋转时不会逐次推送给客户端
+async fn watch_device_rotation(
+    adb: AdbClient,
+    device_id: String,
+    display_id: u32,
+    interval: tokio::time::Duration,
+    rotation_tx: tokio::sync::watch::Sender<Option<u8>>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    let mut debounce = StableReading::default();
+    while !rotation_tx.is_closed() {
+        ticker.tick().await;
+        match adb.display_rotation(&device_id, display_id).await {
+            Ok(Some(rotation)) => {
+                if let Some(rotation) = debounce.push(rotation) {
+                    rotation_tx.send_if_modified(|current| {
+                        let changed = *current != Some(rotation);
+                        *current = Some(rotation);
+                        changed
+                    });
+                }
+            }
+            Ok(None) => debug!("Could not determine display rotation"),
+            Err(e) => debug!("Failed to query display rotation: {}", e),
+        }
+    }
+}
+
+/// 去抖：连续两次读到相同的值才返回
+#[derive(Debug, Default)]
+struct StableReading<T> {
+    last: Option<T>,
+}
+
+impl<T: PartialEq + Copy> StableReading<T> {
+    fn push(&mut self, value: T) -> Option<T> {
+        let stable = self.last == Some(value);
+        self.last = Some(value);
+        stable.then_some(value)
+    }
+}
+
+/// 原始流写入端的队列长度（帧），写入跟不上时丢帧并等待下一个 IDR
+const RAW_OUTPUT_QUEUE_FRAMES: usize = 120;
+
+// 添加 --raw-output 写入端：普通路径写文件/FIFO，`unix:` 前缀连接 Unix 套接字；返回写入端编号
+fn add_raw_output(sinks: &mut FrameSinks, target: &str) -> Option<usize> {
+    info!("💾 Raw output: {}", target);
+    if let Some(path) = target.strip_prefix("unix:") {
+        #[cfg(unix)]
+        return Some(sinks.add_writer(target, RAW_OUTPUT_QUEUE_FRAMES, tokio::net::UnixStream::connect(PathBuf::from(path))));
+        #[cfg(not(unix))]
+        {
+            warn!("Unix socket output is not supported on this platform: {}", path);
+            None
+        }
+    } else {
+        Some(sinks.add_writer(target, RAW_OUTPUT_QUEUE_FRAMES, tokio::fs::File::create(PathBuf::from(target))))
+    }
+}
+
+/// --record-timeline：为每个文件类型的 --raw-output 创建时间线（Unix 套接字没有对应的文件），与写入端编号配对
+async fn create_timelines(args: &Args, raw_outputs: &[(&str, usize)]) -> Vec<(usize, utils::TimelineSidecar)> {
+    let mut timelines = Vec::new();
+    if !args.record_timeline {
+        return timelines;
+    }
+    for &(target, writer) in raw_outputs.iter().filter(|(target, _)| !target.starts_with("unix:")) {
+        let path = utils::TimelineSidecar::sidecar_path(std::path::Path::new(target));
+        match utils::TimelineSidecar::create(&path).await {
+            Ok(timeline) => timelines.push((writer, timeline)),
+            Err(e) => warn!("❌ Failed to create recording timeline {:?}: {}", path, e),
+        }
+    }
+    timelines
+}
+
+/// --audio-dup：设备低于 Android 13 时给出提示并忽略（采集音频时 scrcpy-server 会因此拒绝启动）
+async fn configure_audio_dup(server: &mut ScrcpyServer, adb: &AdbClient, device_id: &str) {
+    match adb.sdk_version(device_id).await {
+        Ok(sdk) if sdk < scrcpy::server::AUDIO_DUP_MIN_SDK => {
+            warn!("🔊 --audio-dup requires Android 13 (API {}), device is API {}; ignoring", scrcpy::server::AUDIO_DUP_MIN_SDK, sdk);
+        }
+        Ok(_) => {
+            info!("🔊 Audio duplication enabled (audio_source=playback audio_dup=true)");
+            // 不采集音频时 server 以 audio=false 启动，设备声音本来就不会被静音
+            if !server.audio_enabled() {
+                warn!("🔊 Audio capture is off, --audio-dup only takes effect together with --audio");
+            }
+            server.set_audio_dup(true);
+        }
+        Err(e) => warn!("🔊 Could not read the device SDK version, ignoring --audio-dup: {}", e),
+    }
+}
+
+/// 启动 server 并确认编码器开始输出
+///
+/// 部分设备的编码器不支持过高的码率：MediaCodec 配置失败后 scrcpy 关闭视频连接并退出。
+/// 此时把码率减半后重启，直到 [`scrcpy::server::MIN_FALLBACK_BIT_RATE`]；之后的重启沿用最终码率。
+async fn start_with_bit_rate_fallback(
+    server: &mut ScrcpyServer,
+    read_buffer_bytes: usize,
+    audio_sender: Option<&tokio::sync::broadcast::Sender<Bytes>>,
+) -> Result<(VideoStreamReader, ControlChannel)> {
+    // 运行中修改编码参数时重启已有的进程
+    if server.is_started() {
+        server.restart().await?;
+    } else {
+        server.start().await?;
+    }
+    loop {
+        let (mut reader, control_channel) = connect_streams(server, read_buffer_bytes, audio_sender).await?;
+        let bit_rate = server.get_bit_rate();
+        // 日志中的编码错误只用来说明失败原因：scrcpy 在编码出错后会自行降低分辨率重试，
+        // 之后视频流照常输出，这种情况不能当作启动失败
+        let logged_error = || server.get_log_stats().snapshot().encoder_error;
+        let failure = match tokio::time::timeout(ENCODER_START_TIMEOUT, reader.wait_for_data()).await {
+            Ok(Ok(true)) => None,
+            Ok(Ok(false)) => Some(logged_error().unwrap_or_else(|| "video stream closed before the first frame".to_string())),
+            Ok(Err(e)) => Some(logged_error().unwrap_or_else(|| e.to_string())),
+            // 锁屏时编码器不输出，没有错误日志就继续
+            Err(_) => logged_error(),
+        };
+        let Some(failure) = failure else {
+            if let Some(actual) = server.get_log_stats().snapshot().bit_rate.filter(|&actual| actual < bit_rate as u64) {
+                warn!("📉 Requested {} bps but the device reports {} bps", bit_rate, actual);
+            }
+            return Ok((reader, control_channel));
+        };
+
+        let Some(next) = scrcpy::server::downgraded_bit_rate(bit_rate) else {
+            return Err(ScrcpyError::VideoStream(format!("Encoder failed to start at {} bps: {}", bit_rate, failure)));
+        };
+        warn!("📉 Encoder failed at {} Mbps ({}), retrying at {} Mbps", bit_rate as f64 / 1e6, failure, next as f64 / 1e6);
+        drop(reader);
+        drop(control_channel);
+        server.set_bit_rate(next);
+        server.restart().await?;
+    }
+}
+
+/// 视频流断开后的一次完整重连：重启 server，重新连接视频流和控制流
+async fn reconnect_session(
+    server: &mut ScrcpyServer,
+    read_buffer_bytes: usize,
+    audio_sender: Option<&tokio::sync::broadcast::Sender<Bytes>>,
+) -> Result<(VideoStreamReader, ControlChannel)> {
+    server.restart().await?;
+    connect_streams(server, read_buffer_bytes, audio_sender).await
+}
+
+/// 第一次重连失败后的等待时间，之后每次翻倍
+const RECONNECT_INITIAL_BACKOFF: std::time::Duration = std::time::Duration::from_secs(1);
+/// 重连等待时间上限（设备长时间离线时不会频繁启动 adb 命令）
+const RECONNECT_MAX_BACKOFF: std::time::Duration = std::time::Duration::from_secs(16);
+
+/// 连续失败 `failed_reconnects` 次（从 1 开始）后，下一次完整重连前的等待时间
+fn reconnect_backoff(failed_reconnects: u32) -> std::time::Duration {
+    RECONNECT_INITIAL_BACKOFF
+        .saturating_mul(1u32 << failed_reconnects.saturating_sub(1).min(16))
+        .min(RECONNECT_MAX_BACKOFF)
+}
+
+/// 连续重连失败次数达到 `--max-reconnects` 时，以关闭原因通知客户端并返回致命错误
+fn reconnect_limit_error(
+    failed_reconnects: u32,
+    max_reconnects: u32,
+    shutdown_sender: &tokio::sync::watch::Sender<Option<String>>,
+) -> Option<ScrcpyError> {
+    if max_reconnects == 0 || failed_reconnects < max_reconnects {
+        return None;
+    }
+    let reason = format!("Device lost: {} reconnect attempts failed", failed_reconnects);
+    error!("💀 {}, giving up", reason);
+    let _ = shutdown_sender.send(Some(reason.clone()));
+    Some(ScrcpyError::Network(reason))
+}
+
+/// 缓存 SPS，并在分辨率、方向或 profile 变化时广播配置（主循环和 --replay-nal 共用）
+///
+/// 返回分辨率或方向是否变化
+async fn cache_sps(
+    sps: &Bytes,
+    video_config: &tokio::sync::RwLock<ws::server::VideoConfig>,
+    config_sender: &tokio::sync::broadcast::Sender<String>,
+) -> bool {
+    let mut nal_with_start_code = vec![0x00, 0x00, 0x00, 0x01];
+    nal_with_start_code.extend_from_slice(sps);
+
+    let mut config = video_config.write().await;
+    config.sps = Some(Bytes::from(nal_with_start_code));
+
+    // 解析 SPS 获取分辨率，检测横竖屏变化
+    let mut should_broadcast = false;
+    let mut geometry_changed = false;
+    if let Some((width, height)) = parse_sps_resolution(sps) {
+        tracing::Span::current().record("width", width).record("height", height);
+        let new_is_landscape = width > height;
+        let resolution_changed = config.width != width || config.height != height;
+
+        // 分辨率不变时横竖屏以设备轮询为准，否则周期性重复的 SPS 会把轮询的结果改回去
+        if resolution_changed {
+            config.width = width;
+            config.height = height;
+            // 启动时获取不到物理屏幕尺寸：用第一个 SPS 的分辨率代替
+            if config.device_width == 0 || config.device_height == 0 {
+                info!("📐 Using video resolution {}x{} as screen size", width, height);
+                config.device_width = width;
+                config.device_height = height;
+            }
+            config.is_landscape = new_is_landscape;
+            should_broadcast = true;
+            geometry_changed = true;
+            info!("🔄 Resolution changed: {}x{}, Landscape: {}", width, height, new_is_landscape);
+        }
+    }
+
+    // profile_idc 紧跟 NAL 头（前面不会出现防竞争字节）
+    let profile = sps.get(1).map(|&idc| ws::server::H264Profile::from_idc(idc));
+    if profile != config.profile {
+        if let Some(profile) = profile {
+            info!("🎞️  H.264 profile: {} ({})", profile.name, profile.idc);
+            if !profile.broadway_compatible {
+                warn!("⚠️  {} profile stream cannot be decoded by Broadway.js; use --codec-profile baseline if you need the Broadway fallback", profile.name);
+            }
+        }
+        config.profile = profile;
+        should_broadcast = true;
+    }
+
+    // 如果分辨率/方向变化，广播配置更新给所有客户端
+    if should_broadcast {
+        let _ = config_sender.send(config.config_message());
+    }
+    geometry_changed
+}
+
+/// 读取 --dump-nal 转储文件中的全部 NAL 单元
+async fn read_nal_file(path: &std::path::Path) -> Result<Vec<scrcpy::video::VideoFrame>> {
+    let data = tokio::fs::read(path).await?;
+    let mut reader = VideoStreamReader::new(data.as_slice());
+    let mut frames = Vec::new();
+    while let Some(frame) = reader.read_frame(false).await? {
+        frames.push(frame);
+    }
+    frames.extend(reader.finish());
+    Ok(frames)
+}
+
+/// --replay-nal：把转储文件当作设备码流，经广播/WebSocket 路径循环回放（不需要 ADB 和设备）
+///
+/// 转储文件没有时间戳，按 --max-fps 的节奏发送图像帧（IDR/P），参数集和 SEI 等随后面的图像帧一起发送。
+/// 控制事件直接丢弃；新客户端请求 IDR 时只能补发缓存的 SPS/PPS，画面从下一个 IDR 开始。
+async fn run_replay(args: &Args, path: &std::path::Path, tls: Option<axum_server::tls_rustls::RustlsConfig>) -> Result<()> {
+    let frames = read_nal_file(path).await?;
+    let picture_count = frames.iter().filter(|f| matches!(f.data[0] & 0x1F, 1 | 5)).count();
+    if picture_count == 0 {
+        return Err(ScrcpyError::VideoStream(format!("{:?} contains no picture frames", path)));
+    }
+    info!("📼 Replaying {:?}: {} NAL units, {} picture frames at {} fps", path, frames.len(), picture_count, args.max_fps);
+
+    // 第一个 SPS 的分辨率作为“设备”分辨率
+    let (width, height) = frames
+        .iter()
+        .find(|f| f.data[0] & 0x1F == 7)
+        .and_then(|f| parse_sps_resolution(&f.data))
+        .unwrap_or((1080, 1920));
+
+    let (idr_request_tx, mut idr_request_rx) = tokio::sync::mpsc::channel::<()>(10);
+    let (control_tx, mut control_rx) = scrcpy::control_queue::control_queue(100);
+    let mut ws_server = WebSocketServer::new(args.ws_port, idr_request_tx, control_tx, width, height, args.bind_addr())?;
+    ws_server.set_health_stale_after(std::time::Duration::from_secs(args.health_stale_secs));
+    ws_server.set_trust_proxy(args.trust_proxy);
+    ws_server.set_start_code_len(args.start_code);
+    if let Some(tls) = tls {
+        ws_server.set_tls(tls);
+    }
+    if let Some(token) = &args.auth_token {
+        ws_server.set_auth_token(token);
+    }
+    let liveness = ws_server.get_liveness();
+    liveness.set_server_alive(true);
+    let stream_stats = ws_server.get_stream_stats();
+    let mut frame_sinks = FrameSinks::new(ws_server.get_sender());
+    frame_sinks.set_overflow_policy(args.record_overflow);
+    frame_sinks.set_start_code_len(args.start_code);
+    ws_server.set_sink_stats(frame_sinks.get_stats());
+    for target in &args.raw_output {
+        add_raw_output(&mut frame_sinks, target);
     }
+    let config_sender = ws_server.get_config_sender();
+    let video_config = ws_server.get_video_config();
+    video_config.write().await.target_aspect = args.target_aspect;
+    video_config.write().await.inline_sps_on_idr = args.inline_sps_on_idr;
 
-    // 选择设备
-    let device_id = if let Some(device) = args.device {
-        if !devices.contains(&device) {
-            eprintln!("❌ Device {} not found in connected devices", device);
-            return Ok(());
+    let actual_ws_port = ws_server.get_actual_port();
+    let listener = ws_server.bind().await?;
+    if args.print_endpoint {
+        print_endpoint(args.bind_addr(), actual_ws_port, None, args.tls_cert.is_some());
+    }
+    let (ws_failed_tx, mut ws_failed_rx) = tokio::sync::oneshot::channel::<ScrcpyError>();
+    let mut ws_failed_done = false;
+    tokio::spawn(async move {
+        if let Err(e) = ws_server.serve(listener).await {
+            error!("WebSocket server error: {}", e);
+            let _ = ws_failed_tx.send(e);
         }
-        device
-    } else {
-        devices[0].clone()
-    };
-    info!("🎯 Using device: {}", device_id);
+    });
 
-    // 获取设备信息
-    let model = adb.shell(&device_id, "getprop ro.product.model").await?;
-    let android_version = adb.shell(&device_id, "getprop ro.build.version.release").await?;
+    let mut ticker = tokio::time::interval(std::time::Duration::from_secs(1) / args.max_fps.max(1));
+    let mut next = 0;
+    loop {
+        tokio::select! {
+            result = &mut ws_failed_rx, if !ws_failed_done => {
+                ws_failed_done = true;
+                if let Ok(e) = result {
+                    return Err(e);
+                }
+            }
 
-    // 获取设备物理屏幕尺寸（用于触控坐标）
-    let wm_size_output = adb.shell(&device_id, "wm size").await?;
-    let (device_width, device_height) = parse_wm_size(&wm_size_output)?;
+            // 新客户端：补发缓存的 SPS/PPS
+            Some(_) = idr_request_rx.recv() => {
+                let config = video_config.read().await;
+                for param_set in [&config.sps, &config.pps].into_iter().flatten() {
+                    let _ = frame_sinks.broadcast().send(param_set.clone().into());
+                }
+            }
 
-    info!("📱 Device Info:");
-    info!("  Model: {}", model.trim());
-    info!("  Android: {}", android_version.trim());
-    info!("  Physical Screen: {}x{}", device_width, device_height);
+            Some(control_event) = control_rx.recv() => {
+                debug!("🎮 Replay mode, ignoring control event: {:?}", control_event);
+            }
 
-    // 部署和启动scrcpy-server
-    if !args.server_path.exists() {
-        eprintln!("❌ scrcpy-server not found at: {:?}", args.server_path);
-        eprintln!("Please specify correct server path with --server-path option");
-        return Ok(());
-    }
+            // 发送到下一个图像帧为止
+            _ = ticker.tick() => loop {
+                let frame = &frames[next];
+                next = (next + 1) % frames.len();
+                if next == 0 {
+                    debug!("🔁 Reached end of {:?}, replaying from the start", path);
+                }
 
-    let mut server = ScrcpyServer::with_config(
-        adb,
-        device_id,
-        args.server_path,
-        args.max_size,
-        args.bit_rate,
-        args.max_fps,
-        args.video_port,
-        args.control_port,
-        args.intra_refresh_period,
-    )?;
+                let nal_type = frame.data[0] & 0x1F;
+                match nal_type {
+                    7 => {
+                        cache_sps(&frame.data, &video_config, &config_sender).await;
+                    }
+                    8 => {
+                        let mut pps = vec![0x00, 0x00, 0x00, 0x01];
+                        pps.extend_from_slice(&frame.data);
+                        video_config.write().await.pps = Some(Bytes::from(pps));
+                    }
+                    _ => {}
+                }
 
-    // 部署服务器
-    if let Err(e) = server.deploy().await {
-        error!("Failed to deploy server: {}", e);
-        return Err(e);
+                let mut nal_with_start_code = vec![0x00, 0x00, 0x00, 0x01];
+                nal_with_start_code.extend_from_slice(&frame.data);
+                frame_sinks.dispatch(Bytes::from(nal_with_start_code));
+                stream_stats.record_frame();
+                if frame.is_keyframe() {
+                    stream_stats.record_keyframe();
+                }
+
+                if matches!(nal_type, 1 | 5) {
+                    liveness.mark_frame();
+                    break;
+                }
+            },
+        }
     }
+}
 
-    // 启动服务器
-    if let Err(e) = server.start().await {
-        error!("Failed to start server: {}", e);
-        return Err(e);
+// 定期检查设备是否仍在线（/healthz）
+async fn watch_device_presence(adb: AdbClient, device_id: String, liveness: std::sync::Arc<ws::health::Liveness>) {
+    let mut ticker = tokio::time::interval(tokio::time::Duration::from_secs(5));
+    loop {
+        ticker.tick().await;
+        match adb.is_device_online(&device_id).await {
+            Ok(online) => liveness.set_device_connected(online),
+            Err(e) => {
+                debug!("Failed to check device presence: {}", e);
+                liveness.set_device_connected(false);
+            }
+        }
     }
+}
+
+// 在后台读取控制流上设备发回的消息；UHID 输出报告（如键盘 LED）转发给客户端
+fn spawn_device_message_reader(
+    control_channel: &mut ControlChannel,
+    config_sender: tokio::sync::broadcast::Sender<String>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let mut reader = control_channel.take_reader()?;
+    Some(tokio::spawn(async move {
+        use scrcpy::device_msg::{read_device_message, DeviceMessage};
+        loop {
+            match read_device_message(&mut reader).await {
+                Ok(Some(DeviceMessage::UhidOutput(output))) => {
+                    debug!("⌨️  UHID output from device: id={} {:02x?}", output.id, output.data);
+                    if let Ok(json) = serde_json::to_string(&output) {
+                        let _ = config_sender.send(json);
+                    }
+                }
+                Ok(Some(DeviceMessage::Clipboard(text))) => {
+                    debug!("📋 Device clipboard changed ({} chars)", text.chars().count());
+                }
+                Ok(Some(DeviceMessage::AckClipboard(sequence))) => {
+                    debug!("📋 Clipboard set acknowledged (sequence {})", sequence);
+                }
+                Ok(None) => {
+                    debug!("🎮 Control stream closed by device");
+                    break;
+                }
+                Err(e) => {
+                    warn!("🎮 Stopped reading device messages: {}", e);
+                    break;
+                }
+            }
+        }
+    }))
+}
+
+// 读取 ANDROID_SERIAL 环境变量（adb 用它选择默认设备），空值视为未设置
+fn android_serial_from_env() -> Option<String> {
+    std::env::var("ANDROID_SERIAL")
+        .ok()
+        .map(|serial| serial.trim().to_string())
+        .filter(|serial| !serial.is_empty())
+}
 
+// 连接视频流、音频流（--audio）和控制流，并读取协议头
+#[tracing::instrument(skip_all)]
+async fn connect_streams(
+    server: &ScrcpyServer,
+    read_buffer_bytes: usize,
+    audio_sender: Option<&tokio::sync::broadcast::Sender<Bytes>>,
+) -> Result<(VideoStreamReader, ControlChannel)> {
     // 连接到视频流
     let mut video_stream = match server.connect_video().await {
         Ok(stream) => stream,
@@ -226,6 +2185,9 @@ async fn main() -> Result<()> {
         }
     };
 
+    // scrcpy 按连接顺序区分通道：音频连接必须在视频之后、控制之前建立
+    let audio_reader = if server.audio_enabled() { Some(server.connect_audio().await?) } else { None };
+
     // 当 control=true 时，scrcpy server 需要两个连接都建立后才会发送数据
     // 所以必须先连接控制流，再读取 video header
     info!("🎮 Connecting to control stream...");
@@ -239,249 +2201,285 @@ async fn main() -> Result<()> {
             return Err(e);
         }
     };
-    let mut control_channel = ControlChannel::new(control_stream);
+    let control_channel = ControlChannel::new(control_stream);
 
     // 两个连接都建立后，现在可以读取 video header 了
-    let codec_info = scrcpy::ScrcpyServer::read_video_header(&mut video_stream).await?;
+    scrcpy::ScrcpyServer::read_video_header(&mut video_stream).await?;
 
     info!("🎥 Video stream ready!");
     info!("   Resolution will be parsed from SPS in NAL stream");
 
-    // 创建视频流读取器
-    let mut reader = VideoStreamReader::new(video_stream);
-
-    // 创建 IDR 请求通道
-    let (idr_request_tx, mut idr_request_rx) = tokio::sync::mpsc::channel::<()>(10);
-
-    // 创建控制事件通道
-    let (control_tx, mut control_rx) = tokio::sync::mpsc::channel::<scrcpy::control::ControlEvent>(100);
-
-    // 创建 WebSocket 服务器（自动寻找可用端口）
-    let ws_server = WebSocketServer::new(args.ws_port, idr_request_tx, control_tx, device_width, device_height, args.public)?;
-    let actual_ws_port = ws_server.get_actual_port();
-    let frame_sender = ws_server.get_sender();
-    let config_sender = ws_server.get_config_sender();
-    let video_config = ws_server.get_video_config();
-
-    // 显示实际使用的端口信息
-    if actual_ws_port != args.ws_port {
-        info!("📌 WebSocket port {} was occupied, using port {} instead", args.ws_port, actual_ws_port);
+    if let (Some(audio_reader), Some(audio_sender)) = (audio_reader, audio_sender) {
+        spawn_audio_forwarder(audio_reader, audio_sender.clone());
     }
 
-    // raw_stream 模式：SPS/PPS 将在视频帧循环中从 NAL 流提取并缓存
+    // 创建视频流读取器
+    Ok((VideoStreamReader::with_read_buffer(video_stream, read_buffer_bytes), control_channel))
+}
 
-    // 在后台启动 WebSocket 服务器
+/// 把音频流转发到 /audio 广播通道，直到连接关闭（server 重启后由新的连接接替）
+fn spawn_audio_forwarder(mut reader: scrcpy::audio::AudioStreamReader, audio_sender: tokio::sync::broadcast::Sender<Bytes>) {
     tokio::spawn(async move {
-        if let Err(e) = ws_server.start().await {
-            error!("WebSocket server error: {}", e);
+        // 配置包在音频编码器启动后才到达，在转发任务中读取，不阻塞视频
+        match reader.read_header().await {
+            Ok(header) => info!("🔊 Audio stream ready: Opus, {} channel(s), input {} Hz", header.channels, header.input_sample_rate),
+            Err(e) => {
+                warn!("🔇 {}, continuing with video only", e);
+                return;
+            }
+        }
+        let mut packets = 0u64;
+        loop {
+            match reader.read_packet().await {
+                Ok(Some(packet)) => {
+                    packets += 1;
+                    // 没有 /audio 客户端时发送失败，直接丢弃
+                    let _ = audio_sender.send(packet);
+                }
+                Ok(None) => {
+                    debug!("🔊 Audio stream ended after {} packets", packets);
+                    break;
+                }
+                Err(e) => {
+                    warn!("🔇 {}, continuing with video only", e);
+                    break;
+                }
+            }
         }
     });
+}
 
-    info!("📺 Starting to receive and broadcast video frames...");
-    info!("   Press Ctrl+C to stop");
-
-    let mut keyframe_count = 0;
-    let mut config_frame_count = 0;
-    let mut frame_counter = 0;
-    let mut sps_cached = false;
-    let mut pps_cached = false;
-    let mut pending_idr_request = false;
+/// `--print-endpoint` 的握手行；设备端口为 None 表示没有 TCP 端口（回放、Unix 域套接字模式）
+///
+/// 地址是本机可以连接的监听地址：监听所有接口时为同一协议族的回环地址，IPv6 带方括号。
+fn endpoint_line(bind: std::net::IpAddr, ws_port: u16, device_ports: Option<(u16, u16)>, tls: bool) -> String {
+    let (ws_scheme, http_scheme) = if tls { ("wss", "https") } else { ("ws", "http") };
+    let addr = std::net::SocketAddr::new(utils::port::connect_host(bind), ws_port);
+    serde_json::json!({
+        "ws": format!("{}://{}/ws", ws_scheme, addr),
+        "http": format!("{}://{}/", http_scheme, addr),
+        "video_port": device_ports.map(|(video, _)| video),
+        "control_port": device_ports.map(|(_, control)| control),
+    })
+    .to_string()
+}
 
-    // 持续接收并广播视频帧
-    loop {
-        tokio::select! {
-            // 处理控制事件
-            Some(control_event) = control_rx.recv() => {
-                debug!("🎮 Received control event: {:?}", control_event);
-                let result = match control_event {
-                    scrcpy::control::ControlEvent::Touch(touch) => {
-                        control_channel.send_touch_event(&touch).await
-                    }
-                    scrcpy::control::ControlEvent::Key(key) => {
-                        control_channel.send_key_event(&key).await
-                    }
-                    scrcpy::control::ControlEvent::Text(text) => {
-                        control_channel.send_text(&text.text).await
-                    }
-                    scrcpy::control::ControlEvent::Clipboard(clip) => {
-                        control_channel.set_clipboard(&clip.text, clip.paste).await
-                    }
-                    scrcpy::control::ControlEvent::Scroll(scroll) => {
-                        control_channel.send_scroll_event(
-                            scroll.x, scroll.y,
-                            scroll.width, scroll.height,
-                            scroll.hscroll, scroll.vscroll
-                        ).await
-                    }
-                };
-                if let Err(e) = result {
-                    error!("Failed to send control event to device: {}", e);
-                } else {
-                    debug!("✅ Control event sent successfully");
+/// 把配置文件中的进程级参数合并到 args，命令行显式传入的参数优先
+///
+/// 视频流参数（max_size 等）可以按设备覆盖，之后由 [`config::FileConfig::resolve`] 单独合并。
+fn apply_file_settings(args: &mut Args, file: &config::FileConfig, from_cli: impl Fn(&str) -> bool) -> Result<()> {
+    macro_rules! merge {
+        ($($field:ident),* $(,)?) => {$(
+            if let Some(value) = file.$field.clone() {
+                if !from_cli(stringify!($field)) {
+                    args.$field = value.into();
                 }
             }
+        )*};
+    }
+    merge!(
+        adb_path, server_path, ws_port, video_port, control_port, intra_refresh_period, log_level,
+        public, bind, tls_cert, tls_key, auth_token, trust_proxy, audio, uhid, display_id, no_cleanup,
+    );
+    if let Some(serial) = &file.serial {
+        if !from_cli("device") {
+            args.device = Some(serial.clone());
+        }
+    }
 
-            // 处理IDR请求
-            Some(_) = idr_request_rx.recv() => {
-                debug!("🎬 Received IDR request from new client");
-                pending_idr_request = true;
+    // 命令行的 requires 不检查来自文件的值
+    if args.tls_cert.is_some() != args.tls_key.is_some() {
+        return Err(ScrcpyError::Config("tls_cert and tls_key must be set together".to_string()));
+    }
+    Ok(())
+}
 
-                // 立即重新发送缓存的SPS/PPS
-                if sps_cached {
-                    // 获取当前缓存的SPS并重新广播
-                    let config = video_config.read().await;
-                    if let Some(sps) = &config.sps {
-                        let _ = frame_sender.send(sps.clone());
-                    }
-                    if let Some(pps) = &config.pps {
-                        let _ = frame_sender.send(pps.clone());
-                    }
-                    drop(config);
+/// 向 stdout 写出握手行并立即刷新（stdout 是管道时默认按块缓冲）
+fn print_endpoint(bind: std::net::IpAddr, ws_port: u16, device_ports: Option<(u16, u16)>, tls: bool) {
+    use std::io::Write;
+    let mut stdout = std::io::stdout().lock();
+    if let Err(e) = writeln!(stdout, "{}", endpoint_line(bind, ws_port, device_ports, tls)).and_then(|_| stdout.flush()) {
+        warn!("Failed to print endpoint: {}", e);
+    }
+}
+
+/// 隐藏 JSON 中名称像密钥的字段（token、password、secret）
+fn redact_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.to_ascii_lowercase();
+                if ["token", "password", "secret"].iter().any(|s| key.contains(s)) && !value.is_null() {
+                    *value = serde_json::Value::String("<redacted>".to_string());
+                } else {
+                    redact_secrets(value);
                 }
             }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
 
-            // 处理视频帧
-            frame_result = tokio::time::timeout(
-                tokio::time::Duration::from_secs(10),
-                reader.read_frame(false)
-            ) => {
-                match frame_result {
-                    Ok(Ok(Some(frame))) => {
-                        if frame.is_keyframe() {
-                            keyframe_count += 1;
-
-                            // 如果收到IDR帧并且有pending请求，清除标志
-                            let nal_type = frame.data[0] & 0x1F;
-                            if nal_type == 5 && pending_idr_request {
-                                debug!("✅ Got requested IDR frame");
-                                pending_idr_request = false;
-                            }
-                        }
-
-                        if frame.frame_type == scrcpy::FrameType::Config {
-                            config_frame_count += 1;
+/// 校验 --server-extra-args（保留原字符串，启动时再拆分）
+fn validate_server_extra_args(s: &str) -> std::result::Result<String, String> {
+    scrcpy::server::parse_server_extra_args(s).map(|_| s.to_string())
+}
 
-                            // 缓存 SPS/PPS
-                            let nal_type = frame.data[0] & 0x1F;
-                            if nal_type == 7 {
-                                // SPS - 从中解析分辨率
-                                let mut nal_with_start_code = vec![0x00, 0x00, 0x00, 0x01];
-                                nal_with_start_code.extend_from_slice(&frame.data);
+/// 解析 `WxH` 形式的显示屏尺寸（--display-size）
+fn parse_display_size(s: &str) -> std::result::Result<(u32, u32), String> {
+    let (w, h) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("expected WxH, got '{}'", s))?;
+    let w: u32 = w.trim().parse().map_err(|_| format!("invalid width in '{}'", s))?;
+    let h: u32 = h.trim().parse().map_err(|_| format!("invalid height in '{}'", s))?;
+    if w == 0 || h == 0 {
+        return Err(format!("display size must be non-zero, got '{}'", s));
+    }
+    Ok((w, h))
+}
 
-                                let mut config = video_config.write().await;
-                                config.sps = Some(Bytes::from(nal_with_start_code.clone()));
-
-                                // 解析 SPS 获取分辨率，检测横竖屏变化
-                                let mut should_broadcast = false;
-                                if let Some((width, height)) = parse_sps_resolution(&frame.data) {
-                                    let new_is_landscape = width > height;
-                                    let resolution_changed = config.width != width || config.height != height;
-                                    let orientation_changed = config.is_landscape != new_is_landscape;
-
-                                    if resolution_changed || orientation_changed {
-                                        config.width = width;
-                                        config.height = height;
-                                        config.is_landscape = new_is_landscape;
-                                        should_broadcast = true;
-                                        info!("🔄 Resolution changed: {}x{}, Landscape: {}", width, height, new_is_landscape);
-                                    }
-                                }
+/// 从 `dumpsys display` 的结果中取出指定显示屏的当前尺寸
+fn display_size(displays: &[adb::client::DisplayInfo], id: u32) -> std::result::Result<(u32, u32), String> {
+    if let Some(display) = displays.iter().find(|display| display.id == id) {
+        return Ok((display.width, display.height));
+    }
+    let available: Vec<String> = displays.iter().map(|display| display.id.to_string()).collect();
+    Err(format!("Display {} not found (available: {})",
+        id, if available.is_empty() { "unknown".to_string() } else { available.join(", ") }))
+}
 
-                                // 如果分辨率/方向变化，广播配置更新给所有客户端
-                                if should_broadcast {
-                                    let config_msg = format!(
-                                        "{{\"type\":\"config\",\"width\":{},\"height\":{},\"device_width\":{},\"device_height\":{},\"is_landscape\":{}}}",
-                                        config.width, config.height, config.device_width, config.device_height, config.is_landscape
-                                    );
-                                    let _ = config_sender.send(config_msg);
-                                }
+/// 解析 `W:H` 形式的宽高比
+fn parse_aspect(s: &str) -> std::result::Result<(u32, u32), String> {
+    let (w, h) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected W:H, got '{}'", s))?;
+    let w: u32 = w.trim().parse().map_err(|_| format!("invalid width in '{}'", s))?;
+    let h: u32 = h.trim().parse().map_err(|_| format!("invalid height in '{}'", s))?;
+    if w == 0 || h == 0 {
+        return Err(format!("aspect ratio must be non-zero, got '{}'", s));
+    }
+    Ok((w, h))
+}
 
-                                drop(config);
+// --orientation-poll-ms：0 关闭，其余至少 100ms（每次轮询都要执行一次 dumpsys）
+fn parse_orientation_poll_ms(s: &str) -> std::result::Result<u64, String> {
+    let ms: u64 = s.trim().parse().map_err(|_| format!("invalid interval '{}'", s))?;
+    if ms != 0 && ms < 100 {
+        return Err(format!("poll interval must be 0 (off) or at least 100ms, got {}", ms));
+    }
+    Ok(ms)
+}
 
-                                if !sps_cached {
-                                    info!("✅ SPS cached ({} bytes)", nal_with_start_code.len());
-                                    sps_cached = true;
-                                }
+/// --connect 解析和探测地址的总超时
+const WIRELESS_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
 
-                            } else if nal_type == 8 && !pps_cached {
-                                // PPS
-                                let mut nal_with_start_code = vec![0x00, 0x00, 0x00, 0x01];
-                                nal_with_start_code.extend_from_slice(&frame.data);
+/// 超过该时间没有视频帧时认为设备已锁屏
+///
+/// scrcpy 在画面静止时也会重复发送上一帧（约每 100ms），只有安全界面（锁屏）下编码器才会完全停止输出。
+const DEVICE_LOCKED_FRAME_GAP: std::time::Duration = std::time::Duration::from_secs(3);
 
-                                let mut config = video_config.write().await;
-                                config.pps = Some(Bytes::from(nal_with_start_code.clone()));
-                                drop(config);
+/// 启动时等待编码器输出第一批数据的最长时间（超时且没有编码错误时视为锁屏，继续启动）
+const ENCODER_START_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
 
-                                info!("✅ PPS cached ({} bytes)", nal_with_start_code.len());
-                                pps_cached = true;
-                            }
-                        }
+/// 启动阶段等待设备重连的超时时间
+const STARTUP_RECONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
 
-                        // 构建完整的 NAL 单元（包含起始码）
-                        let mut nal_with_start_code = vec![0x00, 0x00, 0x00, 0x01];
-                        nal_with_start_code.extend_from_slice(&frame.data);
-
-                        // 广播给所有连接的 WebSocket 客户端
-                        let _ = frame_sender.send(Bytes::from(nal_with_start_code));
-
-                        frame_counter += 1;
-
-                        // 注释掉帧统计日志以提升性能
-                        // if frame_counter % 60 == 0 {
-                        //     info!(
-                        //         "  Frames: {}, Keyframes: {}, Config: {}, Subscribers: {}",
-                        //         reader.frame_count(),
-                        //         keyframe_count,
-                        //         config_frame_count,
-                        //         frame_sender.receiver_count()
-                        //     );
-                        // }
-                    }
-                    Ok(Ok(None)) => {
-                        warn!("Stream ended, waiting for reconnect...");
-                        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-                        continue;
-                    }
-                    Ok(Err(e)) => {
-                        error!("Error reading frame: {}, retrying...", e);
-                        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-                        continue;
-                    }
-                    Err(_) => {
-                        warn!("Timeout waiting for frame, continuing...");
-                        continue;
-                    }
-                }
+/// 启动阶段获取设备信息：型号、Android 版本、物理屏幕尺寸
+///
+/// 任何一步失败时重新检查设备是否仍在线，离线则返回 `DeviceDisconnected`，
+/// 而不是笼统的 ADB 错误。
+async fn gather_device_info(adb: &AdbClient, device_id: &str) -> Result<(String, String, u32, u32)> {
+    let result = async {
+        let model = adb.shell(device_id, "getprop ro.product.model").await?;
+        let android_version = adb.shell(device_id, "getprop ro.build.version.release").await?;
+        // 获取设备物理屏幕尺寸（用于触控坐标）；都获取不到时为 0，由第一个 SPS 的分辨率代替
+        let (device_width, device_height) = match query_screen_size(adb, device_id).await {
+            Some(((width, height), source)) => {
+                info!("📐 Screen size {}x{} (from {})", width, height, source);
+                (width, height)
+            }
+            None if !adb.is_device_online(device_id).await.unwrap_or(false) => {
+                return Err(ScrcpyError::Adb("device went offline while querying screen size".to_string()));
             }
+            None => {
+                warn!("⚠️  Screen size unavailable (wm size, dumpsys display, dumpsys window), using the first video frame's resolution");
+                (0, 0)
+            }
+        };
+        Ok((model, android_version, device_width, device_height))
+    }
+    .await;
+
+    match result {
+        Err(ScrcpyError::Adb(msg)) if !adb.is_device_online(device_id).await.unwrap_or(false) => {
+            debug!("Startup ADB call failed while device offline: {}", msg);
+            Err(ScrcpyError::DeviceDisconnected(device_id.to_string()))
         }
+        other => other,
     }
+}
 
-    // 停止服务器
-    server.stop().await?;
+/// 依次尝试各个来源获取物理屏幕尺寸，返回尺寸和来源
+///
+/// 部分设备（Wear OS、受限的 shell）上 `wm size` 没有输出或提示权限不足，
+/// 这时改用 `dumpsys display` 中默认显示屏的尺寸，再不行用 `dumpsys window displays` 的 `init=WxH`。
+async fn query_screen_size(adb: &AdbClient, device_id: &str) -> Option<((u32, u32), &'static str)> {
+    match adb.shell(device_id, "wm size").await.and_then(|output| parse_wm_size(&output)) {
+        Ok(size) => return Some((size, "wm size")),
+        Err(e) => debug!("wm size unavailable: {}", e),
+    }
+    match adb.list_displays(device_id).await {
+        Ok(displays) => {
+            if let Some(display) = displays.iter().find(|d| d.id == 0) {
+                return Some(((display.width, display.height), "dumpsys display"));
+            }
+            debug!("dumpsys display lists no default display");
+        }
+        Err(e) => debug!("dumpsys display unavailable: {}", e),
+    }
+    match adb.shell(device_id, "dumpsys window displays").await {
+        Ok(output) => {
+            if let Some(size) = parse_window_displays(&output) {
+                return Some((size, "dumpsys window displays"));
+            }
+            debug!("dumpsys window displays has no init size");
+        }
+        Err(e) => debug!("dumpsys window displays unavailable: {}", e),
+    }
+    None
+}
 
-    info!("👋 Shutting down...");
-    Ok(())
+/// 从 `dumpsys window displays` 中提取第一个显示屏的初始尺寸
+///
+/// 格式：`    init=1080x2400 420dpi base=1080x2400 410dpi cur=1080x2400 app=1080x2232 rng=...`
+fn parse_window_displays(output: &str) -> Option<(u32, u32)> {
+    output.split_whitespace().find_map(|token| {
+        let (w, h) = token.strip_prefix("init=")?.split_once('x')?;
+        Some((w.parse().ok()?, h.parse().ok()?)).filter(|&(w, h)| w > 0 && h > 0)
+    })
 }
 
-// 解析 wm size 输出获取屏幕尺寸
-// 输出格式: "Physical size: 1440x2960"
+/// 解析 `wm size` 输出获取屏幕尺寸
+///
+/// 输出可能有两行：
+/// ```text
+/// Physical size: 1440x2960
+/// Override size: 1080x2220
+/// ```
+/// `wm size WxH` 设置过的设备以 Override size 为准（这是实际生效的分辨率），否则用 Physical size。
+/// 各行可以带前导空白，格式不对的行忽略。
 fn parse_wm_size(output: &str) -> Result<(u32, u32)> {
-    let trimmed = output.trim();
-
-    // 查找 "Physical size: " 后面的部分
-    if let Some(size_part) = trimmed.strip_prefix("Physical size: ") {
-        // 分割 "1440x2960"
-        let parts: Vec<&str> = size_part.split('x').collect();
-        if parts.len() == 2 {
-            let width = parts[0].trim().parse::<u32>()
-                .map_err(|_| ScrcpyError::Parse("Invalid width".to_string()))?;
-            let height = parts[1].trim().parse::<u32>()
-                .map_err(|_| ScrcpyError::Parse("Invalid height".to_string()))?;
-            return Ok((width, height));
-        }
-    }
+    let size_after = |prefix: &str| {
+        output.lines().find_map(|line| {
+            let (w, h) = line.trim().strip_prefix(prefix)?.trim().split_once('x')?;
+            Some((w.trim().parse::<u32>().ok()?, h.trim().parse::<u32>().ok()?)).filter(|&(w, h)| w > 0 && h > 0)
+        })
+    };
 
-    Err(ScrcpyError::Parse(format!("Failed to parse wm size output: {}", trimmed)))
+    size_after("Override size:")
+        .or_else(|| size_after("Physical size:"))
+        .ok_or_else(|| ScrcpyError::Parse(format!("Failed to parse wm size output: {}", output.trim())))
 }
 
 /// H.264 SPS 解析器 - 用于提取视频分辨率
@@ -537,7 +2535,7 @@ impl<'a> BitReader<'a> {
     /// 读取 Exp-Golomb 编码的有符号整数 (se(v))
     fn read_se(&mut self) -> Option<i32> {
         let ue = self.read_ue()?;
-        let value = ((ue + 1) / 2) as i32;
+        let value = ue.div_ceil(2) as i32;
         if ue % 2 == 0 {
             Some(-value)
         } else {
@@ -546,13 +2544,29 @@ impl<'a> BitReader<'a> {
     }
 }
 
+// 去除防竞争字节（emulation prevention）：NAL 中的 00 00 03 还原为 RBSP 中的 00 00
+fn nal_to_rbsp(nal: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &byte in nal {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+    rbsp
+}
+
 // 解析 H.264 SPS 获取分辨率
 fn parse_sps_resolution(sps_data: &[u8]) -> Option<(u32, u32)> {
     if sps_data.len() < 4 {
         return None;
     }
 
-    let mut reader = BitReader::new(sps_data);
+    let rbsp = nal_to_rbsp(sps_data);
+    let mut reader = BitReader::new(&rbsp);
 
     // NAL header (1 byte): forbidden_zero_bit(1) + nal_ref_idc(2) + nal_unit_type(5)
     reader.read_bits(8)?;
@@ -676,3 +2690,231 @@ fn parse_sps_resolution(sps_data: &[u8]) -> Option<(u32, u32)> {
 
     Some((final_width, final_height))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_file_settings() {
+        let file = config::FileConfig::parse(r#"
+            serial = "R58M12345"
+            ws_port = 9000
+            public = true
+            log_level = "debug"
+            auth_token = "s3cret"
+        "#).unwrap();
+
+        // 命令行显式传入的参数优先，其余取文件中的值
+        let matches = Args::command().get_matches_from(["rust-scrcpy", "--ws-port", "8081", "--device", "emulator-5554"]);
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        apply_file_settings(&mut args, &file, |id| matches.value_source(id) == Some(ValueSource::CommandLine)).unwrap();
+        assert_eq!((args.ws_port, args.device.as_deref()), (8081, Some("emulator-5554")));
+        assert!(args.public);
+        assert_eq!((args.log_level.as_str(), args.auth_token.as_deref()), ("debug", Some("s3cret")));
+
+        // 没有配置文件时保持纯命令行的行为
+        let matches = Args::command().get_matches_from(["rust-scrcpy"]);
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        apply_file_settings(&mut args, &config::FileConfig::default(), |_| false).unwrap();
+        assert_eq!((args.ws_port, args.public, args.device.as_deref()), (8080, false, None));
+
+        // 文件只提供证书、没有私钥
+        let file = config::FileConfig::parse(r#"tls_cert = "cert.pem""#).unwrap();
+        assert!(matches!(apply_file_settings(&mut args, &file, |_| false), Err(ScrcpyError::Config(_))));
+    }
+
+    #[test]
+    fn test_redact_secrets() {
+        let mut value = serde_json::json!({
+            "auth_token": "hunter2",
+            "max_size": 1920,
+            "nested": [{ "tls_key_password": "x", "port": 1 }],
+            "api_secret": null,
+        });
+        redact_secrets(&mut value);
+        assert_eq!(value["auth_token"], "<redacted>");
+        assert_eq!(value["nested"][0]["tls_key_password"], "<redacted>");
+        assert_eq!(value["nested"][0]["port"], 1);
+        assert_eq!(value["max_size"], 1920);
+        assert!(value["api_secret"].is_null());
+    }
+
+    #[test]
+    fn test_stable_reading() {
+        let mut debounce = StableReading::default();
+        assert_eq!(debounce.push(1), None);
+        assert_eq!(debounce.push(1), Some(1));
+        assert_eq!(debounce.push(1), Some(1));
+        // 快速旋转：中间的读数只出现一次，不会返回
+        assert_eq!(debounce.push(2), None);
+        assert_eq!(debounce.push(3), None);
+        assert_eq!(debounce.push(3), Some(3));
+
+        assert_eq!(parse_orientation_poll_ms("0"), Ok(0));
+        assert_eq!(parse_orientation_poll_ms("500"), Ok(500));
+        assert!(parse_orientation_poll_ms("50").is_err());
+        assert!(parse_orientation_poll_ms("fast").is_err());
+    }
+
+    #[test]
+    fn test_bind_addr() {
+        let parse = |argv: &[&str]| Args::try_parse_from(std::iter::once("rust-scrcpy").chain(argv.iter().copied()));
+        assert_eq!(parse(&[]).unwrap().bind_addr().to_string(), "127.0.0.1");
+        assert_eq!(parse(&["--public"]).unwrap().bind_addr().to_string(), "0.0.0.0");
+        assert_eq!(parse(&["--bind", "::"]).unwrap().bind_addr().to_string(), "::");
+        assert_eq!(parse(&["--bind", "192.168.1.5"]).unwrap().bind_addr().to_string(), "192.168.1.5");
+        assert!(parse(&["--bind", "lan0"]).is_err());
+        assert!(parse(&["--bind", "::1", "--public"]).is_err());
+    }
+
+    #[test]
+    fn test_endpoint_line() {
+        let line = endpoint_line("0.0.0.0".parse().unwrap(), 8081, Some((27183, 27185)), false);
+        assert!(!line.contains('\n'));
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value, serde_json::json!({
+            "ws": "ws://127.0.0.1:8081/ws",
+            "http": "http://127.0.0.1:8081/",
+            "video_port": 27183,
+            "control_port": 27185,
+        }));
+
+        let value: serde_json::Value = serde_json::from_str(&endpoint_line("127.0.0.1".parse().unwrap(), 8080, None, true)).unwrap();
+        assert!(value["video_port"].is_null() && value["control_port"].is_null());
+        assert_eq!(value["ws"], "wss://127.0.0.1:8080/ws");
+        assert_eq!(value["http"], "https://127.0.0.1:8080/");
+
+        // --bind 指定的地址；IPv6 带方括号
+        let value: serde_json::Value = serde_json::from_str(&endpoint_line("::".parse().unwrap(), 8080, None, false)).unwrap();
+        assert_eq!(value["ws"], "ws://[::1]:8080/ws");
+        let value: serde_json::Value = serde_json::from_str(&endpoint_line("192.168.1.5".parse().unwrap(), 8080, None, false)).unwrap();
+        assert_eq!(value["http"], "http://192.168.1.5:8080/");
+    }
+
+    #[test]
+    fn test_parse_window_displays() {
+        let output = "WINDOW MANAGER DISPLAY CONTENTS (dumpsys window displays)\n  Display: mDisplayId=0 rootTasks=[]\n    init=454x454 320dpi base=454x454 320dpi cur=454x454 app=454x454 rng=454x454-454x454\n";
+        assert_eq!(parse_window_displays(output), Some((454, 454)));
+        assert_eq!(parse_window_displays("Permission Denial: can't dump WindowManager"), None);
+        assert_eq!(parse_window_displays("init=0x0 cur=1x1"), None);
+    }
+
+    #[test]
+    fn test_parse_wm_size() {
+        assert_eq!(parse_wm_size("Physical size: 1440x2960\n").unwrap(), (1440, 2960));
+        // 设置过 wm size 时以 Override size 为准，与行的顺序无关
+        assert_eq!(parse_wm_size("Physical size: 1440x2960\r\nOverride size: 1080x2220\r\n").unwrap(), (1080, 2220));
+        assert_eq!(parse_wm_size("  Override size: 720x1280\n  Physical size: 1080x1920").unwrap(), (720, 1280));
+        // Override 行格式不对时退回 Physical size
+        assert_eq!(parse_wm_size("Physical size: 1080x2400\nOverride size: garbage").unwrap(), (1080, 2400));
+
+        assert!(parse_wm_size("").is_err());
+        assert!(parse_wm_size("/system/bin/sh: wm: permission denied").is_err());
+        assert!(parse_wm_size("Physical size: 1080").is_err());
+        assert!(parse_wm_size("Physical size: 0x2400").is_err());
+        assert!(parse_wm_size("Physical size: 1080x2400x3").is_err());
+    }
+
+    #[test]
+    fn test_reconnect_limit() {
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(None);
+        assert!(reconnect_limit_error(100, 0, &shutdown_tx).is_none());  // 0 = 无限重试
+        assert!(reconnect_limit_error(2, 3, &shutdown_tx).is_none());
+        assert!(shutdown_rx.borrow().is_none());
+
+        assert!(matches!(reconnect_limit_error(3, 3, &shutdown_tx), Some(ScrcpyError::Network(_))));
+        assert_eq!(shutdown_rx.borrow().as_deref(), Some("Device lost: 3 reconnect attempts failed"));
+
+        assert_eq!(reconnect_backoff(1), RECONNECT_INITIAL_BACKOFF);
+        assert_eq!(reconnect_backoff(2), RECONNECT_INITIAL_BACKOFF * 2);
+        assert_eq!(reconnect_backoff(5), RECONNECT_MAX_BACKOFF);
+        assert_eq!(reconnect_backoff(u32::MAX), RECONNECT_MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_display_size() {
+        assert_eq!(parse_display_size("720x748"), Ok((720, 748)));
+        assert_eq!(parse_display_size("1920X1080"), Ok((1920, 1080)));
+        assert!(parse_display_size("1920:1080").is_err());
+        assert!(parse_display_size("0x1080").is_err());
+
+        let display = |id, width, height| adb::client::DisplayInfo { id, name: String::new(), width, height };
+        let displays = [display(0, 1080, 2400), display(2, 720, 748)];
+        assert_eq!(display_size(&displays, 2), Ok((720, 748)));
+        assert_eq!(display_size(&displays, 5), Err("Display 5 not found (available: 0, 2)".to_string()));
+        assert_eq!(display_size(&[], 1), Err("Display 1 not found (available: unknown)".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_session_against_fake_server() {
+        use scrcpy::fake_server::{canned_h264, FakeScrcpyServer, CANNED_SPS};
+
+        let mut fake = FakeScrcpyServer::start(canned_h264(3)).await.unwrap();
+        let mut server = ScrcpyServer::new(AdbClient::new("adb".into()), "fake".to_string(), "scrcpy-server".into()).unwrap();
+        server.set_local_ports(fake.video_port, fake.control_port);
+
+        // 连接两个通道并读取 dummy byte，之后按起始码切分出全部 NAL 单元
+        let (mut reader, mut control) = connect_streams(&server, scrcpy::video::DEFAULT_READ_BUFFER_BYTES, None).await.unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = reader.read_frame(false).await.unwrap() {
+            frames.push(frame);
+        }
+        let types: Vec<u8> = frames.iter().map(|frame| frame.data[0] & 0x1F).collect();
+        assert_eq!(types, [7, 8, 5, 1, 1, 1]);
+        assert!(frames[0].data.starts_with(CANNED_SPS));
+        assert_eq!(frames[0].frame_type, scrcpy::FrameType::Config);
+
+        // 缓存 SPS：分辨率来自 SPS，屏幕尺寸未知时用视频分辨率代替，并广播配置
+        let video_config = tokio::sync::RwLock::new(ws::server::VideoConfig {
+            sps: None,
+            pps: None,
+            width: 0,
+            height: 0,
+            device_width: 0,
+            device_height: 0,
+            is_landscape: false,
+            target_aspect: None,
+            profile: None,
+            inline_sps_on_idr: false,
+            uhid_keyboard: false,
+            frame_meta: false,
+        });
+        let (config_sender, mut config_rx) = tokio::sync::broadcast::channel(4);
+        assert!(cache_sps(&frames[0].data, &video_config, &config_sender).await);
+        {
+            let config = video_config.read().await;
+            assert_eq!((config.width, config.height, config.device_width, config.device_height), (1080, 2400, 1080, 2400));
+            assert!(config.sps.as_ref().unwrap().starts_with(&[0x00, 0x00, 0x00, 0x01, 0x67]));
+        }
+        let msg: serde_json::Value = serde_json::from_str(&config_rx.try_recv().unwrap()).unwrap();
+        assert_eq!((msg["type"].as_str(), msg["width"].as_u64(), msg["height"].as_u64()), (Some("config"), Some(1080), Some(2400)));
+        // 同一个 SPS 不会重复广播
+        assert!(!cache_sps(&frames[0].data, &video_config, &config_sender).await);
+        assert!(config_rx.try_recv().is_err());
+
+        // 控制消息按 scrcpy 协议编码后到达 server
+        control.send_syskey(scrcpy::control::SysKey::Back).await.unwrap();
+        let received = fake.control_received(28).await;
+        assert_eq!(received.len(), 28);  // BACK 按下 + 抬起，各 14 字节
+        assert_eq!(&received[..6], [0, 0, 0, 0, 0, 4]);  // InjectKeycode, Down, KEYCODE_BACK
+        assert_eq!(received[14..16], [0, 1]);  // 抬起
+    }
+
+    #[test]
+    fn test_nal_to_rbsp() {
+        assert_eq!(nal_to_rbsp(&[0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x03, 0x03]), vec![0x00, 0x00, 0x01, 0x00, 0x00, 0x03]);
+        assert_eq!(nal_to_rbsp(&[0x00, 0x03, 0x00]), vec![0x00, 0x03, 0x00]);
+    }
+
+    #[test]
+    fn test_parse_sps_with_emulation_prevention() {
+        // Baseline SPS，1080x2400（68x150 宏块，右侧裁剪 8 像素），
+        // pic_order_cnt_type=1 且 offset_for_non_ref_pic 很大，编码后包含两处 00 00 03
+        let sps = [
+            0x67, 0x42, 0xc0, 0x1f, 0xd0, 0x00, 0x00, 0x03, 0x02, 0x00,
+            0x00, 0x03, 0x03, 0xa0, 0x11, 0x00, 0x4b, 0x79, 0x74,
+        ];
+        assert_eq!(parse_sps_resolution(&sps), Some((1080, 2400)));
+    }
+}
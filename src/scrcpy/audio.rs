@@ -0,0 +1,183 @@
+//! 音频流读取 - `--audio` 时 scrcpy-server 通过第三个连接发送设备声音
+//!
+//! 音频使用 `audio_codec=opus`。Opus 包没有自己的边界，必须依靠 scrcpy 的帧头切分，
+//! 而帧头（`send_frame_meta`）对所有流统一开关，因此开启音频时视频流也带帧头（与 `--frame-meta` 相同的读取方式）。
+//! 音频连接上没有 dummy byte 和 codec 头（`send_codec_meta=false`），第一个数据包是配置包，
+//! 内容是 Opus 的 `OpusHead` 头，由 [`AudioStreamReader::read_header`] 解析；之后每个数据包是一个 Opus 包，
+//! 原样转发给浏览器用 WebCodecs 的 `AudioDecoder` 解码。
+//!
+//! 设备无法采集音频（Android 11 以下等）时，scrcpy 在音频连接上只写入 4 字节的禁用代码就关闭连接：
+//! `00 00 00 00` 表示只禁用音频，`00 00 00 01` 表示配置错误。正常的流以配置包的帧头开头（最高位为 1），不会与之混淆。
+
+use crate::error::{Result, ScrcpyError};
+use crate::scrcpy::stream::DeviceStream;
+use crate::scrcpy::video::{FrameHeader, FRAME_HEADER_LEN};
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::debug;
+
+/// Opus 输出采样率（scrcpy 固定以 48kHz 采集）
+pub const AUDIO_SAMPLE_RATE: u32 = 48_000;
+/// 声道数
+pub const AUDIO_CHANNELS: u16 = 2;
+/// scrcpy 禁用音频流时写入的代码长度
+const DISABLE_CODE_LEN: usize = 4;
+/// 单个音频数据包的上限：Opus 包最大约 1275 字节 x 帧数，超过时视为流损坏
+const MAX_AUDIO_PACKET_BYTES: usize = 64 * 1024;
+
+/// 解析禁用代码：只收到这 4 个字节就关闭的音频连接表示设备禁用了音频
+fn disable_reason(code: &[u8]) -> Option<&'static str> {
+    match code {
+        [0, 0, 0, 0] => Some("the device cannot capture audio (Android 11+ required)"),
+        [0, 0, 0, 1] => Some("audio configuration error on the device"),
+        _ => None,
+    }
+}
+
+/// 配置包中的 `OpusHead`（RFC 7845 5.1）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpusHeader {
+    pub channels: u8,
+    pub pre_skip: u16,
+    pub input_sample_rate: u32,  // 编码前的采样率（仅供参考，解码输出总是 48kHz）
+}
+
+impl OpusHeader {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 19 || &data[..8] != b"OpusHead" {
+            return Err(ScrcpyError::AudioStream(format!("Invalid Opus config packet ({} bytes)", data.len())));
+        }
+        Ok(Self {
+            channels: data[9],
+            pre_skip: u16::from_le_bytes([data[10], data[11]]),
+            input_sample_rate: u32::from_le_bytes([data[12], data[13], data[14], data[15]]),
+        })
+    }
+}
+
+/// 音频流读取器
+pub struct AudioStreamReader<S = DeviceStream> {
+    stream: S,
+    packets: u64,  // 已返回的 Opus 包数
+}
+
+impl<S: AsyncRead + Unpin> AudioStreamReader<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream, packets: 0 }
+    }
+
+    /// 读取音频流开头的配置包，解析 `OpusHead`（scrcpy 在音频编码器启动后发送）
+    ///
+    /// 设备禁用音频时返回错误，视频不受影响。
+    pub async fn read_header(&mut self) -> Result<OpusHeader> {
+        let mut header = [0u8; FRAME_HEADER_LEN];
+        if !self.read_or_eof(&mut header[..DISABLE_CODE_LEN]).await? {
+            return Err(ScrcpyError::AudioStream("Audio stream closed before the config packet".to_string()));
+        }
+        if let Some(reason) = disable_reason(&header[..DISABLE_CODE_LEN]) {
+            return Err(ScrcpyError::AudioStream(format!("Audio disabled: {}", reason)));
+        }
+        if !self.read_or_eof(&mut header[DISABLE_CODE_LEN..]).await? {
+            return Err(ScrcpyError::AudioStream("Audio stream closed inside the first frame header".to_string()));
+        }
+        let header = FrameHeader::parse(&header);
+        if !header.config {
+            return Err(ScrcpyError::AudioStream("Audio stream does not start with a config packet".to_string()));
+        }
+        let config = self.read_payload(header.len).await?
+            .ok_or_else(|| ScrcpyError::AudioStream("Audio stream closed inside the config packet".to_string()))?;
+        OpusHeader::parse(&config)
+    }
+
+    /// 读取下一个 Opus 包，流结束时返回 None（应先调用 [`read_header`](Self::read_header)）
+    pub async fn read_packet(&mut self) -> Result<Option<Bytes>> {
+        loop {
+            let mut header = [0u8; FRAME_HEADER_LEN];
+            if !self.read_or_eof(&mut header).await? {
+                debug!("Audio stream closed (EOF) after {} packets", self.packets);
+                return Ok(None);
+            }
+            let header = FrameHeader::parse(&header);
+            let Some(packet) = self.read_payload(header.len).await? else {
+                debug!("Audio stream closed (EOF) inside a {} byte packet", header.len);
+                return Ok(None);
+            };
+            // 编码器重新配置时可能再次发送配置包，浏览器的解码器不需要
+            if header.config {
+                debug!("Skipping {} byte audio config packet", packet.len());
+                continue;
+            }
+            self.packets += 1;
+            return Ok(Some(packet));
+        }
+    }
+
+    /// 读取帧头之后的数据包
+    async fn read_payload(&mut self, len: u32) -> Result<Option<Bytes>> {
+        if len as usize > MAX_AUDIO_PACKET_BYTES {
+            return Err(ScrcpyError::AudioStream(format!("Invalid audio frame header: packet of {} bytes", len)));
+        }
+        let mut packet = vec![0u8; len as usize];
+        Ok(self.read_or_eof(&mut packet).await?.then(|| Bytes::from(packet)))
+    }
+
+    /// 读满 `buf`，流在此之前结束时返回 false
+    async fn read_or_eof(&mut self, buf: &mut [u8]) -> Result<bool> {
+        match self.stream.read_exact(buf).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(ScrcpyError::AudioStream(format!("Failed to read audio stream: {}", e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG_FLAG: u64 = 1 << 63;
+
+    fn packet(pts_and_flags: u64, payload: &[u8]) -> Vec<u8> {
+        let mut data = pts_and_flags.to_be_bytes().to_vec();
+        data.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn opus_head() -> Vec<u8> {
+        let mut head = b"OpusHead".to_vec();
+        head.extend_from_slice(&[1, 2, 0x38, 0x01, 0x80, 0xbb, 0, 0, 0, 0, 0]);
+        head
+    }
+
+    #[tokio::test]
+    async fn test_read_opus_packets() {
+        let mut data = packet(CONFIG_FLAG, &opus_head());
+        data.extend(packet(20_000, &[0xfc, 1, 2]));
+        data.extend(packet(CONFIG_FLAG, &opus_head()));
+        data.extend(packet(40_000, &[0xfc, 3]));
+        data.extend(&packet(60_000, &[0xfc, 4, 5])[..14]);  // 最后一个包不完整
+
+        let mut reader = AudioStreamReader::new(data.as_slice());
+        let header = reader.read_header().await.unwrap();
+        assert_eq!(header, OpusHeader { channels: 2, pre_skip: 312, input_sample_rate: 48_000 });
+        assert_eq!(reader.read_packet().await.unwrap().unwrap(), [0xfc, 1, 2][..]);
+        assert_eq!(reader.read_packet().await.unwrap().unwrap(), [0xfc, 3][..]);
+        assert!(reader.read_packet().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_header_errors() {
+        async fn header(data: &[u8]) -> Result<OpusHeader> {
+            AudioStreamReader::new(data).read_header().await
+        }
+        // 禁用代码
+        assert!(header(&[0, 0, 0, 0]).await.unwrap_err().to_string().contains("cannot capture audio"));
+        assert!(header(&[0, 0, 0, 1]).await.unwrap_err().to_string().contains("configuration error"));
+        assert!(header(&[]).await.is_err());
+        // 第一个包不是配置包，或内容不是 OpusHead
+        assert!(header(&packet(0, &opus_head())).await.is_err());
+        assert!(header(&packet(CONFIG_FLAG, b"AOPUSHDR")).await.is_err());
+        assert!(header(&packet(CONFIG_FLAG, &opus_head())[..20]).await.is_err());
+    }
+}
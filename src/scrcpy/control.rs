@@ -1,11 +1,14 @@
 // 控制事件模块
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
+use tokio::io::{AsyncWriteExt, ReadHalf, WriteHalf};
+use crate::scrcpy::stream::DeviceStream;
+use crate::scrcpy::uhid;
 use crate::error::{Result, ScrcpyError};
-use tracing::{info, debug, error};
+use tracing::{info, debug, error, warn};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time::Instant;
 
-// scrcpy控制消息类型（基于scrcpy 3.x协议）
+// scrcpy控制消息类型（基于scrcpy 3.x协议，只列出用到的类型）
 // 参考：https://github.com/Genymobile/scrcpy/blob/master/app/src/control_msg.h
 #[repr(u8)]
 #[derive(Debug, Clone, Copy)]
@@ -14,23 +17,17 @@ pub enum ControlMessageType {
     InjectText = 1,
     InjectTouch = 2,
     InjectScroll = 3,
-    SetScreenPowerMode = 4,
     ExpandNotificationPanel = 5,
-    CollapseNotificationPanel = 6,
-    GetClipboard = 7,
-    SetClipboard = 8,
-    SetScreenPowerModeExpanded = 9,
-    RotateDevice = 10,
-    UhidCreate = 11,
-    UhidInput = 12,
-    OpenHardKeyboardSettings = 13,
+    SetClipboard = 9,
+    RotateDevice = 11,
+    UhidCreate = 12,
+    UhidInput = 13,
     UhidDestroy = 14,
-    StartApp = 15,
 }
 
 // Android触摸事件动作
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AndroidMotionEventAction {
     Down = 0,        // ACTION_DOWN
     Up = 1,          // ACTION_UP
@@ -153,6 +150,410 @@ pub struct ScrollEvent {
     pub vscroll: i32,     // 垂直滚动量
 }
 
+// 修改最大分辨率事件（从WebSocket接收），会重启设备端编码器
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetMaxSizeEvent {
+    pub value: u32,       // 新的 max_size（宽高中较大者），0 表示不限制
+}
+
+// 修改编码参数事件（从WebSocket接收），未给出的参数保持不变，会重启设备端编码器
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReconfigureEvent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bit_rate: Option<u32>,  // 码率（bps）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_fps: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u32>,  // 0 表示不限制
+}
+
+/// reconfigure 允许的最大码率（200 Mbps，远超手机编码器的实际能力）
+pub const MAX_RECONFIGURE_BIT_RATE: u32 = 200_000_000;
+/// reconfigure 允许的最大帧率
+pub const MAX_RECONFIGURE_FPS: u32 = 240;
+/// reconfigure / set_max_size 允许的最大分辨率（0 表示不限制）
+pub const MAX_RECONFIGURE_SIZE: u32 = 8192;
+
+impl ReconfigureEvent {
+    fn validate(&self) -> std::result::Result<(), String> {
+        if *self == Self::default() {
+            return Err("reconfigure needs at least one of bit_rate, max_fps, max_size".to_string());
+        }
+        if self.bit_rate == Some(0) || self.max_fps == Some(0) {
+            return Err("bit_rate and max_fps must be greater than 0".to_string());
+        }
+        if self.bit_rate.is_some_and(|bit_rate| bit_rate > MAX_RECONFIGURE_BIT_RATE) {
+            return Err(format!("bit_rate must be at most {}", MAX_RECONFIGURE_BIT_RATE));
+        }
+        if self.max_fps.is_some_and(|max_fps| max_fps > MAX_RECONFIGURE_FPS) {
+            return Err(format!("max_fps must be at most {}", MAX_RECONFIGURE_FPS));
+        }
+        if self.max_size.is_some_and(|max_size| max_size > MAX_RECONFIGURE_SIZE) {
+            return Err(format!("max_size must be at most {} (0 = unlimited)", MAX_RECONFIGURE_SIZE));
+        }
+        Ok(())
+    }
+}
+
+impl From<SetMaxSizeEvent> for ReconfigureEvent {
+    fn from(size: SetMaxSizeEvent) -> Self {
+        Self { max_size: Some(size.value), ..Self::default() }
+    }
+}
+
+// IME 动作（表单提交/跳转），映射为对应的 Android 按键
+// 新增动作时只需在 key() 中补充映射
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImeAction {
+    Done,
+    Go,
+    Search,
+    Send,
+    Next,
+    Previous,
+}
+
+impl ImeAction {
+    /// 返回 (keycode, metastate)
+    ///
+    /// | 动作                       | 按键                                   |
+    /// | -------------------------- | -------------------------------------- |
+    /// | done / go / search / send  | KEYCODE_ENTER (66)                     |
+    /// | next                       | KEYCODE_TAB (61)                       |
+    /// | previous                   | KEYCODE_TAB (61) + META_SHIFT_ON (0x1) |
+    ///
+    /// 输入框收到 ENTER 时，Android 会按其 imeOptions 触发对应的编辑器动作。
+    pub fn key(self) -> (u32, u32) {
+        const KEYCODE_ENTER: u32 = 66;
+        const KEYCODE_TAB: u32 = 61;
+        const META_SHIFT_ON: u32 = 0x1;
+        match self {
+            ImeAction::Done | ImeAction::Go | ImeAction::Search | ImeAction::Send => (KEYCODE_ENTER, 0),
+            ImeAction::Next => (KEYCODE_TAB, 0),
+            ImeAction::Previous => (KEYCODE_TAB, META_SHIFT_ON),
+        }
+    }
+}
+
+// IME 动作事件（从WebSocket接收）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImeActionEvent {
+    pub action: ImeAction,
+}
+
+// 设备系统按键（名称 → 按键/控制消息的映射集中在 SysKey::action 中）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SysKey {
+    Back,
+    Home,
+    #[serde(alias = "app_switch")]
+    Recents,
+    Power,
+    VolumeUp,
+    VolumeDown,
+    Menu,
+    Camera,
+    Wakeup,
+    Notifications,
+}
+
+// 系统按键对应的设备操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysKeyAction {
+    Keycode(u32),               // 按下并抬起 Android 按键
+    ExpandNotificationPanel,    // scrcpy 控制消息：展开通知栏
+}
+
+impl SysKey {
+    pub fn action(self) -> SysKeyAction {
+        match self {
+            SysKey::Back => SysKeyAction::Keycode(4),          // KEYCODE_BACK
+            SysKey::Home => SysKeyAction::Keycode(3),          // KEYCODE_HOME
+            SysKey::Recents => SysKeyAction::Keycode(187),     // KEYCODE_APP_SWITCH
+            SysKey::Power => SysKeyAction::Keycode(26),        // KEYCODE_POWER
+            SysKey::VolumeUp => SysKeyAction::Keycode(24),     // KEYCODE_VOLUME_UP
+            SysKey::VolumeDown => SysKeyAction::Keycode(25),   // KEYCODE_VOLUME_DOWN
+            SysKey::Menu => SysKeyAction::Keycode(82),         // KEYCODE_MENU
+            SysKey::Camera => SysKeyAction::Keycode(27),       // KEYCODE_CAMERA
+            SysKey::Wakeup => SysKeyAction::Keycode(224),      // KEYCODE_WAKEUP（已亮屏时无效果）
+            SysKey::Notifications => SysKeyAction::ExpandNotificationPanel,
+        }
+    }
+}
+
+// 系统按键事件（从WebSocket接收）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SysKeyEvent {
+    pub key: SysKey,
+}
+
+// 长按按键事件（从WebSocket接收）：按下、按 Android 自动重复节奏重复、到时抬起
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyHoldEvent {
+    pub keycode: u32,
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub metastate: u32,
+}
+
+// UHID 相对鼠标事件（网页指针锁定时发送，从WebSocket接收）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UhidMouseRelEvent {
+    pub dx: i32,
+    pub dy: i32,
+    #[serde(default)]
+    pub buttons: u8,  // 与 MouseEvent.buttons 相同：bit0 左键、bit1 右键、bit2 中键
+    #[serde(default)]
+    pub wheel: i32,   // 垂直滚轮格数，正数向上
+    #[serde(default)]
+    pub hwheel: i32,  // 水平滚轮格数，正数向右
+}
+
+// UHID 键盘按键事件（`--uhid` 时网页按物理键位发送，从WebSocket接收）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UhidKeyEvent {
+    pub code: String,  // KeyboardEvent.code，如 "KeyA"、"ShiftLeft"
+    pub down: bool,
+}
+
+/// 首次重复前的延迟（ViewConfiguration.getKeyRepeatTimeout）
+pub const KEY_REPEAT_TIMEOUT: Duration = Duration::from_millis(500);
+/// 重复间隔（ViewConfiguration.getKeyRepeatDelay）
+pub const KEY_REPEAT_DELAY: Duration = Duration::from_millis(50);
+/// 长按时长上限，避免客户端断开后按键一直处于按下状态
+pub const MAX_KEY_HOLD: Duration = Duration::from_secs(10);
+
+/// 正在进行的长按（由主循环驱动）
+#[derive(Debug, Clone)]
+pub struct KeyHold {
+    keycode: u32,
+    metastate: u32,
+    repeat: u32,
+    next_repeat: Instant,
+    release_at: Instant,
+}
+
+impl KeyHold {
+    /// 开始长按，返回状态和需要立即发送的 DOWN 事件
+    pub fn start(event: &KeyHoldEvent, now: Instant) -> (Self, KeyEvent) {
+        let duration = Duration::from_millis(event.duration_ms).min(MAX_KEY_HOLD);
+        let hold = Self {
+            keycode: event.keycode,
+            metastate: event.metastate,
+            repeat: 0,
+            next_repeat: now + KEY_REPEAT_TIMEOUT,
+            release_at: now + duration,
+        };
+        let down = hold.key_event(AndroidKeyEventAction::Down);
+        (hold, down)
+    }
+
+    fn key_event(&self, action: AndroidKeyEventAction) -> KeyEvent {
+        KeyEvent { action, keycode: self.keycode, repeat: self.repeat, metastate: self.metastate }
+    }
+
+    /// 下一次需要发送事件的时间
+    pub fn deadline(&self) -> Instant {
+        self.next_repeat.min(self.release_at)
+    }
+
+    /// 到达 deadline 时调用：返回要发送的事件，以及长按是否已结束（已发送 UP）
+    pub fn advance(&mut self, now: Instant) -> (KeyEvent, bool) {
+        if now >= self.release_at {
+            return (self.release(), true);
+        }
+        self.repeat += 1;
+        self.next_repeat += KEY_REPEAT_DELAY;
+        (self.key_event(AndroidKeyEventAction::Down), false)
+    }
+
+    /// 提前结束长按（新的长按开始时）
+    pub fn release(&self) -> KeyEvent {
+        self.key_event(AndroidKeyEventAction::Up)
+    }
+}
+
+/// 旋转后等待新 SPS 的最长时间（应用锁定方向时画面不会变化，超时后恢复触摸）
+pub const ROTATION_TOUCH_PAUSE_MAX: Duration = Duration::from_secs(2);
+
+/// 旋转期间的触摸暂停（由主循环驱动）
+///
+/// 旋转命令发出后、新分辨率的 SPS 到达之前，客户端仍按旧的画面尺寸计算坐标，
+/// 这段时间的触摸会落到错误的位置。暂停期间：
+/// - 新的 DOWN 以及之后该手指的所有事件都丢弃（恢复后直到它抬起为止）
+/// - 旋转前已按下的手指只转发 UP/CANCEL，保证设备端不会残留按下状态
+#[derive(Debug, Default)]
+pub struct TouchGate {
+    paused_at: Option<Instant>,
+    active: std::collections::HashSet<i64>,   // 已转发 DOWN、尚未抬起的手指
+    dropped: std::collections::HashSet<i64>,  // DOWN 被丢弃、尚未抬起的手指
+}
+
+impl TouchGate {
+    /// 发出旋转命令时调用
+    pub fn pause(&mut self, now: Instant) {
+        self.paused_at = Some(now);
+    }
+
+    /// 新分辨率的 SPS 已更新 VideoConfig
+    pub fn resume(&mut self) {
+        if self.paused_at.take().is_some() {
+            info!("👆 Rotation applied, touch forwarding resumed");
+        }
+    }
+
+    /// 判断触摸事件是否转发
+    pub fn allow(&mut self, touch: &TouchEvent, now: Instant) -> bool {
+        if self.paused_at.is_some_and(|at| now.duration_since(at) >= ROTATION_TOUCH_PAUSE_MAX) {
+            warn!("👆 No resolution change {} ms after rotation, resuming touch forwarding", ROTATION_TOUCH_PAUSE_MAX.as_millis());
+            self.paused_at = None;
+        }
+        let id = touch.pointer_id;
+        let released = matches!(touch.action, AndroidMotionEventAction::Up | AndroidMotionEventAction::PointerUp | AndroidMotionEventAction::Cancel);
+        let pressed = matches!(touch.action, AndroidMotionEventAction::Down | AndroidMotionEventAction::PointerDown);
+
+        if self.dropped.contains(&id) {
+            if released {
+                self.dropped.remove(&id);
+            }
+            return false;
+        }
+        if self.paused_at.is_some() {
+            if pressed {
+                self.dropped.insert(id);
+            }
+            if released && self.active.remove(&id) {
+                return true;
+            }
+            return false;
+        }
+        if pressed {
+            self.active.insert(id);
+        } else if released {
+            self.active.remove(&id);
+        }
+        true
+    }
+}
+
+/// scrcpy 为注入的触摸保留的指针 id，不与设备上真实手指的 id 冲突
+pub const POINTER_ID_GENERIC_FINGER: i64 = -2;
+/// 鼠标的指针 id（网页用鼠标操作时发送）
+pub const POINTER_ID_MOUSE: i64 = -1;
+/// scrcpy-server 同时跟踪的指针数上限（PointerList.MAX_POINTERS）
+pub const MAX_POINTERS: usize = 10;
+
+/// 触摸事件的指针 id 分配方式（`--pointer-mode`）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PointerMode {
+    #[default]
+    Indexed,  // 每根手指映射到 0..MAX_POINTERS 中的一个槽位，支持多点触控
+    Generic,  // 单指使用 POINTER_ID_GENERIC_FINGER，不支持多点触控
+}
+
+/// 解析 `--pointer-mode` 参数
+pub fn parse_pointer_mode(s: &str) -> std::result::Result<PointerMode, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "indexed" => Ok(PointerMode::Indexed),
+        "generic" => Ok(PointerMode::Generic),
+        _ => Err(format!("unknown pointer mode '{}' (expected indexed or generic)", s)),
+    }
+}
+
+/// 按 `--pointer-mode` 改写触摸事件的指针 id 和动作（由主循环驱动）
+///
+/// scrcpy-server 只接受 DOWN/UP：它按当前按下的指针数自行改为 `ACTION_POINTER_DOWN/UP` 并加上指针索引，
+/// 直接转发网页发来的 PointerDown(5)/PointerUp(6) 时高位的索引为 0，第二根手指不会生效（双指缩放无效），
+/// 因此两种模式都把它们改回 Down/Up。
+///
+/// indexed 模式下每根按下的手指占用 0..[`MAX_POINTERS`] 中最小的空闲槽位，槽位号作为指针 id 发送：
+/// 浏览器的 touch.identifier 可能很大或为负数（与 `POINTER_ID_MOUSE` 冲突），槽位号始终是稳定的非负数。
+/// 没有按下过的手指的 MOVE/UP 丢弃。
+///
+/// generic 模式下只有一根手指：第一根按下的手指映射为 `POINTER_ID_GENERIC_FINGER`，
+/// 它抬起之前按下的其他手指整个丢弃（包括之后的 MOVE/UP）。
+/// 鼠标（`POINTER_ID_MOUSE`）不受影响。
+#[derive(Debug, Default)]
+pub struct PointerIdMapper {
+    mode: PointerMode,
+    finger: Option<i64>,  // 当前映射为 generic finger 的浏览器手指 id
+    slots: [Option<i64>; MAX_POINTERS],  // indexed 模式：各槽位上按下的浏览器手指 id
+}
+
+impl PointerIdMapper {
+    pub fn new(mode: PointerMode) -> Self {
+        Self { mode, ..Self::default() }
+    }
+
+    /// 返回要发送的事件，None 表示丢弃
+    pub fn map(&mut self, touch: TouchEvent) -> Option<TouchEvent> {
+        if touch.pointer_id == POINTER_ID_MOUSE {
+            return Some(touch);
+        }
+        match self.mode {
+            PointerMode::Indexed => self.map_indexed(touch),
+            PointerMode::Generic => self.map_generic(touch),
+        }
+    }
+
+    fn map_indexed(&mut self, mut touch: TouchEvent) -> Option<TouchEvent> {
+        let id = touch.pointer_id;
+        if touch.action == AndroidMotionEventAction::Down {
+            // 网页只在没有其他手指时发送 Down：之前因 UP 被丢弃等原因残留的槽位一并释放
+            self.slots = Default::default();
+        }
+        let slot = self.slots.iter().position(|slot| *slot == Some(id));
+        let slot = match touch.action {
+            AndroidMotionEventAction::Down | AndroidMotionEventAction::PointerDown => {
+                let Some(slot) = slot.or_else(|| self.slots.iter().position(Option::is_none)) else {
+                    debug!("👆 {} fingers already down, dropping finger {}", MAX_POINTERS, id);
+                    return None;
+                };
+                self.slots[slot] = Some(id);
+                touch.action = AndroidMotionEventAction::Down;
+                slot
+            }
+            AndroidMotionEventAction::Up | AndroidMotionEventAction::PointerUp | AndroidMotionEventAction::Cancel => {
+                let slot = slot?;
+                self.slots[slot] = None;
+                if touch.action == AndroidMotionEventAction::PointerUp {
+                    touch.action = AndroidMotionEventAction::Up;
+                }
+                slot
+            }
+            _ => slot?,
+        };
+        touch.pointer_id = slot as i64;
+        Some(touch)
+    }
+
+    fn map_generic(&mut self, mut touch: TouchEvent) -> Option<TouchEvent> {
+        let id = touch.pointer_id;
+        match touch.action {
+            AndroidMotionEventAction::Down | AndroidMotionEventAction::PointerDown if self.finger.is_none() => {
+                self.finger = Some(id);
+                touch.action = AndroidMotionEventAction::Down;
+            }
+            _ if self.finger != Some(id) => {
+                debug!("👆 Generic pointer mode, dropping extra finger {}", id);
+                return None;
+            }
+            AndroidMotionEventAction::Up | AndroidMotionEventAction::PointerUp | AndroidMotionEventAction::Cancel => {
+                self.finger = None;
+                if touch.action == AndroidMotionEventAction::PointerUp {
+                    touch.action = AndroidMotionEventAction::Up;
+                }
+            }
+            _ => {}
+        }
+        touch.pointer_id = POINTER_ID_GENERIC_FINGER;
+        Some(touch)
+    }
+}
+
 // 统一的控制事件类型（从WebSocket接收）
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -167,15 +568,73 @@ pub enum ControlEvent {
     Clipboard(ClipboardEvent),
     #[serde(rename = "scroll")]
     Scroll(ScrollEvent),
+    #[serde(rename = "set_max_size")]
+    SetMaxSize(SetMaxSizeEvent),
+    #[serde(rename = "reconfigure")]
+    Reconfigure(ReconfigureEvent),
+    #[serde(rename = "ime_action")]
+    ImeAction(ImeActionEvent),
+    #[serde(rename = "syskey")]
+    SysKey(SysKeyEvent),
+    #[serde(rename = "key_hold")]
+    KeyHold(KeyHoldEvent),
+    #[serde(rename = "uhid_mouse_rel")]
+    UhidMouseRel(UhidMouseRelEvent),
+    #[serde(rename = "uhid_mouse_close")]
+    UhidMouseClose,
+    #[serde(rename = "uhid_key")]
+    UhidKey(UhidKeyEvent),
+    #[serde(rename = "uhid_key_release_all")]
+    UhidKeyReleaseAll,
+    #[serde(rename = "rotate")]
+    Rotate,
+    /// 不认识的 type（如新版客户端连接旧版服务端）：WebSocket 层回复 `unknown_type` 错误，不会转发
+    #[serde(other, skip_serializing)]
+    Unknown,
+}
+
+/// InjectText 文本的最大字节数（scrcpy 的 SC_CONTROL_MSG_INJECT_TEXT_MAX_LENGTH）
+pub const MAX_INJECT_TEXT_BYTES: usize = 300;
+/// SetClipboard 文本的最大字节数（scrcpy 控制消息上限 256KB 减去 14 字节消息头）
+pub const MAX_CLIPBOARD_TEXT_BYTES: usize = (1 << 18) - 14;
+
+impl ControlEvent {
+    /// 检查文本长度是否超过 scrcpy 的限制、编码参数是否有效，无效时返回发给客户端的错误信息
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        let (kind, len, max) = match self {
+            ControlEvent::Text(text) => ("text", text.text.len(), MAX_INJECT_TEXT_BYTES),
+            ControlEvent::Clipboard(clip) => ("clipboard", clip.text.len(), MAX_CLIPBOARD_TEXT_BYTES),
+            ControlEvent::Reconfigure(change) => return change.validate(),
+            ControlEvent::SetMaxSize(size) => return ReconfigureEvent::from(size.clone()).validate(),
+            ControlEvent::UhidKey(key) if uhid::keyboard_usage(&key.code).is_none() => {
+                return Err(format!("unsupported key code for UHID keyboard: {}", key.code));
+            }
+            _ => return Ok(()),
+        };
+        if len > max {
+            Err(format!("{} too long: {} bytes (max {})", kind, len, max))
+        } else {
+            Ok(())
+        }
+    }
 }
 
 pub struct ControlChannel {
-    stream: TcpStream,
+    stream: WriteHalf<DeviceStream>,
+    reader: Option<ReadHalf<DeviceStream>>,  // 设备发回的消息（见 device_msg 模块）
+    uhid_mouse: bool,                        // 是否已在设备上创建 UHID 鼠标（连接断开时设备端自动销毁）
+    uhid_keyboard: Option<uhid::HidKeyboard>,  // 已创建的 UHID 键盘及其按键状态
 }
 
 impl ControlChannel {
-    pub fn new(stream: TcpStream) -> Self {
-        Self { stream }
+    pub fn new(stream: DeviceStream) -> Self {
+        let (reader, stream) = tokio::io::split(stream);
+        Self { stream, reader: Some(reader), uhid_mouse: false, uhid_keyboard: None }
+    }
+
+    /// 取出读取端，交给后台任务读取设备消息（只能取一次）
+    pub fn take_reader(&mut self) -> Option<ReadHalf<DeviceStream>> {
+        self.reader.take()
     }
 
     /// 发送触摸事件到设备
@@ -348,12 +807,12 @@ impl ControlChannel {
 
         // 6. 水平滚动 (2 bytes, Big Endian, i16)
         // 官方 scrcpy 使用 0x0800 (2048) 作为滚动单位
-        // 前端传入 -1, 0, 1，需要乘以 2048
-        let hscroll_i16 = (hscroll * 2048).clamp(-32768, 32767) as i16;
+        // 前端传入 -1, 0, 1，需要乘以 2048；队列合并后的累加值可能很大，乘法不能溢出
+        let hscroll_i16 = hscroll.saturating_mul(2048).clamp(-32768, 32767) as i16;
         msg.extend_from_slice(&hscroll_i16.to_be_bytes());
 
         // 7. 垂直滚动 (2 bytes, Big Endian, i16)
-        let vscroll_i16 = (vscroll * 2048).clamp(-32768, 32767) as i16;
+        let vscroll_i16 = vscroll.saturating_mul(2048).clamp(-32768, 32767) as i16;
         msg.extend_from_slice(&vscroll_i16.to_be_bytes());
 
         // 8. 按钮状态 (4 bytes, Big Endian)
@@ -371,47 +830,117 @@ impl ControlChannel {
         Ok(())
     }
 
-    /// 发送返回键
-    pub async fn send_back_key(&mut self) -> Result<()> {
-        info!("◀️  Sending BACK key");
-
-        // Android KEYCODE_BACK = 4
+    /// 按下并抬起一个按键
+    async fn press_key(&mut self, keycode: u32, metastate: u32) -> Result<()> {
         self.send_key_event(&KeyEvent {
             action: AndroidKeyEventAction::Down,
-            keycode: 4,
+            keycode,
             repeat: 0,
-            metastate: 0,
+            metastate,
         }).await?;
 
         self.send_key_event(&KeyEvent {
             action: AndroidKeyEventAction::Up,
-            keycode: 4,
+            keycode,
             repeat: 0,
-            metastate: 0,
-        }).await?;
+            metastate,
+        }).await
+    }
+
+    /// 发送 IME 动作（按下并抬起映射后的按键）
+    pub async fn send_ime_action(&mut self, action: ImeAction) -> Result<()> {
+        info!("⏎ Sending IME action: {:?}", action);
+
+        let (keycode, metastate) = action.key();
+        self.press_key(keycode, metastate).await
+    }
+
+    /// 发送系统按键（返回、主页、电源、音量、通知栏等）
+    pub async fn send_syskey(&mut self, key: SysKey) -> Result<()> {
+        info!("🔘 Sending system key: {:?}", key);
 
+        match key.action() {
+            SysKeyAction::Keycode(keycode) => self.press_key(keycode, 0).await,
+            SysKeyAction::ExpandNotificationPanel => self.expand_notification_panel().await,
+        }
+    }
+
+    /// 旋转设备屏幕（与 scrcpy 的 Ctrl+→ 相同，在横竖屏之间切换）
+    /// scrcpy 3.x 消息格式：[type=11]
+    pub async fn rotate_device(&mut self) -> Result<()> {
+        info!("🔄 Rotating device");
+        self.write_message(&[ControlMessageType::RotateDevice as u8]).await
+    }
+
+    /// 展开通知栏
+    /// scrcpy 3.x 消息格式：[type=5]
+    pub async fn expand_notification_panel(&mut self) -> Result<()> {
+        self.stream.write_all(&[ControlMessageType::ExpandNotificationPanel as u8]).await
+            .map_err(|e| ScrcpyError::Network(format!("Failed to send expand notification panel: {}", e)))?;
+        self.stream.flush().await
+            .map_err(|e| ScrcpyError::Network(format!("Failed to flush control stream: {}", e)))?;
         Ok(())
     }
 
-    /// 发送Home键
-    pub async fn send_home_key(&mut self) -> Result<()> {
-        info!("🏠 Sending HOME key");
+    /// 发送相对鼠标移动（UHID 鼠标，首次使用时创建）
+    pub async fn send_uhid_mouse_rel(&mut self, event: &UhidMouseRelEvent) -> Result<()> {
+        if !self.uhid_mouse {
+            info!("🖱️  Creating UHID mouse for relative mode");
+            self.write_message(&uhid::uhid_create_message(uhid::UHID_MOUSE_ID, uhid::UHID_MOUSE_NAME, uhid::MOUSE_REPORT_DESC)).await?;
+            self.uhid_mouse = true;
+        }
+        let mut msg = Vec::new();
+        for report in uhid::mouse_reports(event.dx, event.dy, event.wheel, event.hwheel, event.buttons) {
+            msg.extend_from_slice(&uhid::uhid_input_message(uhid::UHID_MOUSE_ID, &report));
+        }
+        self.write_message(&msg).await
+    }
 
-        // Android KEYCODE_HOME = 3
-        self.send_key_event(&KeyEvent {
-            action: AndroidKeyEventAction::Down,
-            keycode: 3,
-            repeat: 0,
-            metastate: 0,
-        }).await?;
+    /// 销毁 UHID 鼠标（网页退出指针锁定时），设备上的鼠标指针随之消失
+    pub async fn close_uhid_mouse(&mut self) -> Result<()> {
+        if !self.uhid_mouse {
+            return Ok(());
+        }
+        info!("🖱️  Destroying UHID mouse");
+        self.uhid_mouse = false;
+        self.write_message(&uhid::uhid_destroy_message(uhid::UHID_MOUSE_ID)).await
+    }
 
-        self.send_key_event(&KeyEvent {
-            action: AndroidKeyEventAction::Up,
-            keycode: 3,
-            repeat: 0,
-            metastate: 0,
-        }).await?;
+    /// 在设备上创建 UHID 键盘（已创建时不重复创建）
+    pub async fn create_uhid_keyboard(&mut self) -> Result<()> {
+        if self.uhid_keyboard.is_some() {
+            return Ok(());
+        }
+        info!("⌨️  Creating UHID keyboard");
+        self.write_message(&uhid::uhid_create_message(uhid::UHID_KEYBOARD_ID, uhid::UHID_KEYBOARD_NAME, uhid::KEYBOARD_REPORT_DESC)).await?;
+        self.uhid_keyboard = Some(uhid::HidKeyboard::default());
+        Ok(())
+    }
+
+    /// 发送 UHID 键盘的按下/抬起（键盘未创建时先创建），按键状态不变时不发送
+    pub async fn send_uhid_key(&mut self, event: &UhidKeyEvent) -> Result<()> {
+        let usage = uhid::keyboard_usage(&event.code)
+            .ok_or_else(|| ScrcpyError::Parse(format!("Unsupported key code for UHID keyboard: {}", event.code)))?;
+        self.create_uhid_keyboard().await?;
+        let Some(report) = self.uhid_keyboard.as_mut().and_then(|keyboard| keyboard.apply(usage, event.down)) else {
+            return Ok(());
+        };
+        self.write_message(&uhid::uhid_input_message(uhid::UHID_KEYBOARD_ID, &report)).await
+    }
 
+    /// 抬起 UHID 键盘上所有按下的键（网页失去焦点时，避免按键卡住）
+    pub async fn release_uhid_keys(&mut self) -> Result<()> {
+        let Some(report) = self.uhid_keyboard.as_mut().and_then(uhid::HidKeyboard::release_all) else {
+            return Ok(());
+        };
+        self.write_message(&uhid::uhid_input_message(uhid::UHID_KEYBOARD_ID, &report)).await
+    }
+
+    async fn write_message(&mut self, msg: &[u8]) -> Result<()> {
+        self.stream.write_all(msg).await
+            .map_err(|e| ScrcpyError::Network(format!("Failed to send control message: {}", e)))?;
+        self.stream.flush().await
+            .map_err(|e| ScrcpyError::Network(format!("Failed to flush control stream: {}", e)))?;
         Ok(())
     }
 
@@ -419,7 +948,7 @@ impl ControlChannel {
     /// scrcpy 3.x 文本消息格式：
     /// [type=1][length:4][text:variable]
     pub async fn send_text(&mut self, text: &str) -> Result<()> {
-        info!("📝 Sending text: {} chars", text.len());
+        info!("📝 Sending text: {} chars ({} bytes)", text.chars().count(), text.len());
 
         let text_bytes = text.as_bytes();
         let mut msg = Vec::with_capacity(5 + text_bytes.len());
@@ -446,14 +975,14 @@ impl ControlChannel {
 
     /// 设置设备剪贴板内容
     /// scrcpy 3.x 剪贴板消息格式：
-    /// [type=8][sequence:8][paste:1][length:4][text:variable]
+    /// [type=9][sequence:8][paste:1][length:4][text:variable]
     pub async fn set_clipboard(&mut self, text: &str, paste: bool) -> Result<()> {
         info!("📋 Setting clipboard: {} chars, paste={}", text.len(), paste);
 
         let text_bytes = text.as_bytes();
         let mut msg = Vec::with_capacity(14 + text_bytes.len());
 
-        // 1. 消息类型 (1 byte) = SetClipboard (8)
+        // 1. 消息类型 (1 byte) = SetClipboard (9)
         msg.push(ControlMessageType::SetClipboard as u8);
 
         // 2. sequence (8 bytes, Big Endian) - 用于同步，这里使用0
@@ -479,3 +1008,292 @@ impl ControlChannel {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrcpy::stream::DeviceEndpoint;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+
+    /// 连接到本地监听端口的控制通道，返回通道和设备端的连接
+    async fn connected_channel() -> (ControlChannel, TcpStream) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let channel = ControlChannel::new(DeviceEndpoint::Tcp(port).connect().await.unwrap());
+        let (device, _) = listener.accept().await.unwrap();
+        (channel, device)
+    }
+
+    #[test]
+    fn test_validate_text_lengths() {
+        let text = |len: usize| ControlEvent::Text(TextEvent { text: "a".repeat(len) });
+        assert!(text(MAX_INJECT_TEXT_BYTES).validate().is_ok());
+        assert!(text(MAX_INJECT_TEXT_BYTES + 1).validate().unwrap_err().contains("text too long"));
+
+        // 按 UTF-8 字节数计算：100 个汉字 = 300 字节
+        assert!(ControlEvent::Text(TextEvent { text: "字".repeat(100) }).validate().is_ok());
+        assert!(ControlEvent::Text(TextEvent { text: "字".repeat(101) }).validate().is_err());
+
+        let clipboard = ControlEvent::Clipboard(ClipboardEvent { text: "a".repeat(MAX_CLIPBOARD_TEXT_BYTES + 1), paste: false });
+        assert!(clipboard.validate().unwrap_err().contains("clipboard too long"));
+    }
+
+    #[tokio::test]
+    async fn test_send_text_preserves_composed_text() {
+        // 组合字符（e + U+0301、韩文字母）、阿拉伯文（RTL + 变音符号）和 emoji 序列：原样按 UTF-8 字节发送，不做规范化
+        let sample = "e\u{301}\u{1100}\u{1161} مَرْحَبًا 👩\u{200d}💻";
+        let event: ControlEvent = serde_json::from_str(&serde_json::json!({ "type": "text", "text": sample }).to_string()).unwrap();
+        let ControlEvent::Text(event) = event else { panic!("expected text") };
+        assert_eq!(event.text, sample);
+
+        let (mut channel, mut device) = connected_channel().await;
+        channel.send_text(&event.text).await.unwrap();
+
+        let mut header = [0u8; 5];
+        device.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[0], ControlMessageType::InjectText as u8);
+        let mut text = vec![0u8; u32::from_be_bytes(header[1..].try_into().unwrap()) as usize];
+        device.read_exact(&mut text).await.unwrap();
+        assert_eq!(text, sample.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_hardware_button_helpers() {
+        let (mut channel, mut device) = connected_channel().await;
+
+        for key in [SysKey::Recents, SysKey::Power, SysKey::VolumeUp, SysKey::VolumeDown] {
+            channel.send_syskey(key).await.unwrap();
+        }
+
+        // 每个按键是一对 DOWN/UP 的 InjectKeycode 消息：[type][action][keycode u32][repeat u32][metastate u32]
+        for keycode in [187u32, 26, 24, 25] {
+            for action in [AndroidKeyEventAction::Down, AndroidKeyEventAction::Up] {
+                let mut message = [0u8; 14];
+                device.read_exact(&mut message).await.unwrap();
+                assert_eq!(message[0], ControlMessageType::InjectKeycode as u8);
+                assert_eq!(message[1], action as u8);
+                assert_eq!(u32::from_be_bytes(message[2..6].try_into().unwrap()), keycode);
+            }
+        }
+
+        let event: ControlEvent = serde_json::from_str(r#"{"type":"syskey","key":"app_switch"}"#).unwrap();
+        assert!(matches!(event, ControlEvent::SysKey(SysKeyEvent { key: SysKey::Recents })));
+    }
+
+    #[tokio::test]
+    async fn test_scroll_clamps_coalesced_deltas() {
+        let (mut channel, mut device) = connected_channel().await;
+        channel.send_scroll_event(0.5, 0.5, 1080, 1920, i32::MAX, i32::MIN).await.unwrap();
+
+        // [type][x i32][y i32][w u16][h u16][hscroll i16][vscroll i16][buttons u32]
+        let mut message = [0u8; 21];
+        device.read_exact(&mut message).await.unwrap();
+        assert_eq!(message[0], ControlMessageType::InjectScroll as u8);
+        assert_eq!(i16::from_be_bytes([message[13], message[14]]), i16::MAX);
+        assert_eq!(i16::from_be_bytes([message[15], message[16]]), i16::MIN);
+    }
+
+    #[tokio::test]
+    async fn test_uhid_keyboard() {
+        let (mut channel, mut device) = connected_channel().await;
+
+        let key = |code: &str, down| UhidKeyEvent { code: code.to_string(), down };
+        // 第一个按键先创建键盘；重复的按下不发送
+        channel.send_uhid_key(&key("ShiftLeft", true)).await.unwrap();
+        channel.send_uhid_key(&key("KeyA", true)).await.unwrap();
+        channel.send_uhid_key(&key("KeyA", true)).await.unwrap();
+        channel.release_uhid_keys().await.unwrap();
+        channel.release_uhid_keys().await.unwrap();
+        assert!(channel.send_uhid_key(&key("LaunchMail", true)).await.is_err());
+        drop(channel);
+
+        let mut expected = uhid::uhid_create_message(uhid::UHID_KEYBOARD_ID, uhid::UHID_KEYBOARD_NAME, uhid::KEYBOARD_REPORT_DESC);
+        for report in [[0x02, 0, 0, 0, 0, 0, 0, 0], [0x02, 0, 0x04, 0, 0, 0, 0, 0], [0; 8]] {
+            expected.extend_from_slice(&uhid::uhid_input_message(uhid::UHID_KEYBOARD_ID, &report));
+        }
+        let mut received = Vec::new();
+        device.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, expected);
+
+        let event: ControlEvent = serde_json::from_str(r#"{"type":"uhid_key","code":"Fn","down":true}"#).unwrap();
+        assert!(event.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_two_finger_pinch_messages() {
+        use AndroidMotionEventAction::{Down, Move, PointerDown, PointerUp, Up};
+
+        let (mut channel, mut device) = connected_channel().await;
+
+        // 双指缩放：浏览器的手指 id 为负数和一个很大的值，映射为槽位 0 和 1
+        let mut mapper = PointerIdMapper::new(PointerMode::Indexed);
+        let touch = |action, pointer_id, x| TouchEvent { action, pointer_id, x, y: 0.5, pressure: 1.0, width: 1000, height: 2000, buttons: 1 };
+        let finger = -7;
+        let sequence = [
+            touch(Down, finger, 0.4),
+            touch(PointerDown, 1 << 40, 0.6),
+            touch(Move, finger, 0.3),
+            touch(Move, 1 << 40, 0.7),
+            touch(PointerUp, finger, 0.3),
+            touch(Up, 1 << 40, 0.7),
+        ];
+        let mut sent = 0;
+        for event in sequence {
+            if let Some(event) = mapper.map(event) {
+                channel.send_touch_event(&event).await.unwrap();
+                sent += 1;
+            }
+        }
+        assert_eq!((sent, mapper.slots.iter().flatten().count()), (6, 0));
+        // 未按下的手指的 MOVE 丢弃
+        assert!(mapper.map(touch(Move, 3, 0.5)).is_none());
+
+        // [type][action][pointer_id i64][x u32][y u32][w u16][h u16][pressure u16][action_button u32][buttons u32]
+        let expected: [(u8, i64, u32); 6] = [(0, 0, 400), (0, 1, 600), (2, 0, 300), (2, 1, 700), (1, 0, 300), (1, 1, 700)];
+        for (action, pointer_id, x) in expected {
+            let mut message = [0u8; 32];
+            device.read_exact(&mut message).await.unwrap();
+            assert_eq!(message[0], ControlMessageType::InjectTouch as u8);
+            assert_eq!(message[1], action);
+            assert_eq!(i64::from_be_bytes(message[2..10].try_into().unwrap()), pointer_id);
+            assert_eq!(u32::from_be_bytes(message[10..14].try_into().unwrap()), x);
+            assert_eq!(u32::from_be_bytes(message[14..18].try_into().unwrap()), 1000);
+            assert_eq!((u16::from_be_bytes([message[18], message[19]]), u16::from_be_bytes([message[20], message[21]])), (1000, 2000));
+            // 触摸的 action_button 和 buttons 都为 0
+            assert_eq!(message[24..32], [0; 8]);
+        }
+    }
+
+    #[test]
+    fn test_touch_gate_pauses_during_rotation() {
+        let touch = |action, pointer_id| TouchEvent { action, pointer_id, x: 0.0, y: 0.0, pressure: 1.0, width: 1080, height: 2400, buttons: 0 };
+        let start = Instant::now();
+        let mut gate = TouchGate::default();
+        assert!(gate.allow(&touch(AndroidMotionEventAction::Down, 0), start));
+
+        // 旋转：旋转前按下的手指只转发 UP，新的手势整个丢弃
+        gate.pause(start);
+        assert!(!gate.allow(&touch(AndroidMotionEventAction::Move, 0), start));
+        assert!(!gate.allow(&touch(AndroidMotionEventAction::PointerDown, 1), start));
+        assert!(gate.allow(&touch(AndroidMotionEventAction::PointerUp, 0), start));
+
+        // 新 SPS 到达后恢复，暂停期间按下的手指直到抬起前仍然丢弃
+        gate.resume();
+        assert!(gate.paused_at.is_none());
+        assert!(!gate.allow(&touch(AndroidMotionEventAction::Move, 1), start));
+        assert!(!gate.allow(&touch(AndroidMotionEventAction::Up, 1), start));
+        assert!(gate.allow(&touch(AndroidMotionEventAction::Down, 1), start));
+
+        // 画面没有变化时超时恢复
+        gate.pause(start);
+        assert!(!gate.allow(&touch(AndroidMotionEventAction::Down, 2), start + Duration::from_millis(100)));
+        assert!(gate.allow(&touch(AndroidMotionEventAction::Down, 3), start + ROTATION_TOUCH_PAUSE_MAX));
+        assert!(gate.paused_at.is_none());
+    }
+
+    #[test]
+    fn test_generic_pointer_mode() {
+        let touch = |action, pointer_id| TouchEvent { action, pointer_id, x: 0.0, y: 0.0, pressure: 1.0, width: 1080, height: 2400, buttons: 0 };
+        let mapped = |mapper: &mut PointerIdMapper, action, pointer_id| mapper.map(touch(action, pointer_id)).map(|t| (t.action, t.pointer_id));
+        const GENERIC: i64 = POINTER_ID_GENERIC_FINGER;
+
+        // 第一根手指映射为 generic finger，第二根手指整个丢弃
+        let mut generic = PointerIdMapper::new(parse_pointer_mode("Generic").unwrap());
+        assert_eq!(mapped(&mut generic, AndroidMotionEventAction::Down, 0), Some((AndroidMotionEventAction::Down, GENERIC)));
+        assert_eq!(mapped(&mut generic, AndroidMotionEventAction::PointerDown, 1), None);
+        assert_eq!(mapped(&mut generic, AndroidMotionEventAction::Move, 1), None);
+        assert_eq!(mapped(&mut generic, AndroidMotionEventAction::Move, 0), Some((AndroidMotionEventAction::Move, GENERIC)));
+        assert_eq!(mapped(&mut generic, AndroidMotionEventAction::PointerUp, 0), Some((AndroidMotionEventAction::Up, GENERIC)));
+        assert_eq!(mapped(&mut generic, AndroidMotionEventAction::Up, 1), None);
+
+        // 抬起后下一根手指成为新的 generic finger；鼠标不受影响
+        assert_eq!(mapped(&mut generic, AndroidMotionEventAction::Down, 2), Some((AndroidMotionEventAction::Down, GENERIC)));
+        assert_eq!(mapped(&mut generic, AndroidMotionEventAction::Down, POINTER_ID_MOUSE), Some((AndroidMotionEventAction::Down, POINTER_ID_MOUSE)));
+        assert!(parse_pointer_mode("finger").is_err());
+    }
+
+    #[test]
+    fn test_key_hold_repeats_then_releases() {
+        let event: ControlEvent = serde_json::from_str(r#"{"type":"key_hold","keycode":24,"duration_ms":700}"#).unwrap();
+        let ControlEvent::KeyHold(event) = event else { panic!("expected key_hold") };
+
+        let start = Instant::now();
+        let (mut hold, down) = KeyHold::start(&event, start);
+        assert_eq!((down.action as u8, down.repeat), (0, 0));
+
+        // 500ms 后开始重复，每 50ms 一次，700ms 时抬起
+        let mut events = Vec::new();
+        loop {
+            let now = hold.deadline();
+            let (key, done) = hold.advance(now);
+            events.push((now - start, key.action as u8, key.repeat));
+            if done {
+                break;
+            }
+        }
+        assert_eq!(events.first(), Some(&(Duration::from_millis(500), 0, 1)));
+        assert_eq!(events.len(), 4 + 1);  // 500/550/600/650 四次重复，700 抬起
+        assert_eq!(events.last(), Some(&(Duration::from_millis(700), 1, 4)));
+
+        // 时长上限
+        let long = KeyHoldEvent { keycode: 24, duration_ms: u64::MAX, metastate: 0 };
+        let (hold, _) = KeyHold::start(&long, start);
+        assert_eq!(hold.release_at - start, MAX_KEY_HOLD);
+    }
+
+    #[test]
+    fn test_parse_ime_action_event() {
+        let event: ControlEvent = serde_json::from_str(r#"{"type":"ime_action","action":"search"}"#).unwrap();
+        match event {
+            ControlEvent::ImeAction(e) => assert_eq!(e.action, ImeAction::Search),
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert!(serde_json::from_str::<ControlEvent>(r#"{"type":"ime_action","action":"jump"}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_syskey_event() {
+        let event: ControlEvent = serde_json::from_str(r#"{"type":"syskey","key":"volume_up"}"#).unwrap();
+        match event {
+            ControlEvent::SysKey(e) => assert_eq!(e.key.action(), SysKeyAction::Keycode(24)),
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert!(serde_json::from_str::<ControlEvent>(r#"{"type":"syskey","key":"turbo"}"#).is_err());
+        assert_eq!(SysKey::Wakeup.action(), SysKeyAction::Keycode(224));
+        assert_eq!(SysKey::Notifications.action(), SysKeyAction::ExpandNotificationPanel);
+    }
+
+    #[test]
+    fn test_parse_reconfigure_event() {
+        let event: ControlEvent = serde_json::from_str(r#"{"type":"reconfigure","bit_rate":4000000,"max_fps":30}"#).unwrap();
+        assert!(event.validate().is_ok());
+        match event {
+            ControlEvent::Reconfigure(e) => assert_eq!(e, ReconfigureEvent { bit_rate: Some(4_000_000), max_fps: Some(30), max_size: None }),
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        // 至少需要一个参数，码率和帧率不能为 0（max_size 为 0 表示不限制）
+        let parse = |json: &str| serde_json::from_str::<ControlEvent>(json).unwrap().validate();
+        assert!(parse(r#"{"type":"reconfigure"}"#).is_err());
+        assert!(parse(r#"{"type":"reconfigure","bit_rate":0}"#).is_err());
+        assert!(parse(r#"{"type":"reconfigure","max_fps":0}"#).is_err());
+        assert!(parse(r#"{"type":"reconfigure","max_size":0}"#).is_ok());
+        // 上限
+        assert!(parse(r#"{"type":"reconfigure","bit_rate":200000000,"max_fps":240,"max_size":8192}"#).is_ok());
+        assert!(parse(r#"{"type":"reconfigure","bit_rate":4000000000}"#).is_err());
+        assert!(parse(r#"{"type":"reconfigure","max_fps":1000}"#).is_err());
+        assert!(parse(r#"{"type":"set_max_size","value":100000}"#).is_err());
+        assert!(parse(r#"{"type":"set_max_size","value":0}"#).is_ok());
+
+        let size: ReconfigureEvent = SetMaxSizeEvent { value: 540 }.into();
+        assert_eq!(size, ReconfigureEvent { max_size: Some(540), ..Default::default() });
+    }
+
+    #[test]
+    fn test_ime_action_keys() {
+        assert_eq!(ImeAction::Go.key(), (66, 0));
+        assert_eq!(ImeAction::Next.key(), (61, 0));
+        assert_eq!(ImeAction::Previous.key(), (61, 1));
+    }
+}
@@ -0,0 +1,263 @@
+// 控制事件队列模块：带背压和 MOVE/滚动合并的有界队列
+//
+// 快速拖动或惯性滚动会产生大量 MOVE/滚动事件，普通 mpsc 通道被填满后
+// 发送方会阻塞，UP 事件也要排在所有旧 MOVE 之后，延迟越积越大。
+// 这里在入队时合并：队尾是同一指针的 MOVE（或滚动）时直接替换为最新事件，
+// 按键状态相同的 UHID 相对鼠标移动累加位移，DOWN/UP 等其他事件始终按顺序保留。
+use crate::scrcpy::control::{AndroidMotionEventAction, ControlEvent};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+struct Shared {
+    queue: Mutex<VecDeque<ControlEvent>>,
+    capacity: usize,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+    item_ready: Notify,
+    space_ready: Notify,
+}
+
+/// 发送端（可克隆，每个 WebSocket 客户端持有一份）
+pub struct ControlSender {
+    shared: Arc<Shared>,
+}
+
+/// 接收端（主循环持有）
+pub struct ControlReceiver {
+    shared: Arc<Shared>,
+}
+
+/// 接收端已关闭
+#[derive(Debug)]
+pub struct SendError;
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "control queue closed")
+    }
+}
+
+/// 创建容量为 `capacity` 的控制事件队列
+pub fn control_queue(capacity: usize) -> (ControlSender, ControlReceiver) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(VecDeque::with_capacity(capacity)),
+        capacity: capacity.max(1),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+        item_ready: Notify::new(),
+        space_ready: Notify::new(),
+    });
+    (ControlSender { shared: shared.clone() }, ControlReceiver { shared })
+}
+
+/// 可合并事件的键：同一指针的 MOVE，滚动事件，或按键状态相同的相对鼠标移动
+#[derive(PartialEq, Eq)]
+enum CoalesceKey {
+    TouchMove(i64),
+    Scroll,
+    MouseRel(u8),
+}
+
+fn coalesce_key(event: &ControlEvent) -> Option<CoalesceKey> {
+    match event {
+        ControlEvent::Touch(touch) => match touch.action {
+            AndroidMotionEventAction::Move | AndroidMotionEventAction::HoverMove => {
+                Some(CoalesceKey::TouchMove(touch.pointer_id))
+            }
+            _ => None,
+        },
+        ControlEvent::Scroll(_) => Some(CoalesceKey::Scroll),
+        ControlEvent::UhidMouseRel(event) => Some(CoalesceKey::MouseRel(event.buttons)),
+        _ => None,
+    }
+}
+
+/// 尝试把 event 合并进队尾事件，成功返回 None
+fn try_coalesce(queue: &mut VecDeque<ControlEvent>, event: ControlEvent) -> Option<ControlEvent> {
+    let Some(key) = coalesce_key(&event) else {
+        return Some(event);
+    };
+    let Some(last) = queue.back_mut() else {
+        return Some(event);
+    };
+    if coalesce_key(last).as_ref() != Some(&key) {
+        return Some(event);
+    }
+
+    match (last, event) {
+        // 滚动量累加，位置取最新
+        (ControlEvent::Scroll(prev), ControlEvent::Scroll(mut next)) => {
+            next.hscroll = next.hscroll.saturating_add(prev.hscroll);
+            next.vscroll = next.vscroll.saturating_add(prev.vscroll);
+            *prev = next;
+        }
+        // 相对位移和滚轮累加（按键变化时不合并，保证点击发生在正确的位置）
+        (ControlEvent::UhidMouseRel(prev), ControlEvent::UhidMouseRel(next)) => {
+            prev.dx = prev.dx.saturating_add(next.dx);
+            prev.dy = prev.dy.saturating_add(next.dy);
+            prev.wheel = prev.wheel.saturating_add(next.wheel);
+            prev.hwheel = prev.hwheel.saturating_add(next.hwheel);
+        }
+        // MOVE 只保留最新位置
+        (last, event) => *last = event,
+    }
+    None
+}
+
+impl ControlSender {
+    /// 发送控制事件：可合并时直接替换队尾，队列满时等待空位（背压）
+    pub async fn send(&self, event: ControlEvent) -> Result<(), SendError> {
+        let mut event = Some(event);
+        loop {
+            let space = self.shared.space_ready.notified();
+            tokio::pin!(space);
+            space.as_mut().enable();
+
+            if !self.shared.receiver_alive.load(Ordering::Acquire) {
+                return Err(SendError);
+            }
+            {
+                let mut queue = self.shared.queue.lock().unwrap();
+                match try_coalesce(&mut queue, event.take().unwrap()) {
+                    None => return Ok(()),
+                    Some(e) if queue.len() < self.shared.capacity => {
+                        queue.push_back(e);
+                        drop(queue);
+                        self.shared.item_ready.notify_one();
+                        return Ok(());
+                    }
+                    Some(e) => event = Some(e),
+                }
+            }
+            space.await;
+        }
+    }
+}
+
+impl Clone for ControlSender {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::AcqRel);
+        Self { shared: self.shared.clone() }
+    }
+}
+
+impl Drop for ControlSender {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // 最后一个发送端关闭，唤醒接收端
+            self.shared.item_ready.notify_one();
+        }
+    }
+}
+
+impl ControlReceiver {
+    /// 接收下一个事件，所有发送端关闭且队列为空时返回 None
+    pub async fn recv(&mut self) -> Option<ControlEvent> {
+        loop {
+            let ready = self.shared.item_ready.notified();
+            tokio::pin!(ready);
+            ready.as_mut().enable();
+
+            if let Some(event) = self.shared.queue.lock().unwrap().pop_front() {
+                self.shared.space_ready.notify_one();
+                return Some(event);
+            }
+            if self.shared.senders.load(Ordering::Acquire) == 0 {
+                return None;
+            }
+            ready.await;
+        }
+    }
+}
+
+impl Drop for ControlReceiver {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::Release);
+        self.shared.space_ready.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrcpy::control::{ScrollEvent, TouchEvent, UhidMouseRelEvent};
+
+    fn touch(action: AndroidMotionEventAction, pointer_id: i64, x: f32) -> ControlEvent {
+        ControlEvent::Touch(TouchEvent { action, pointer_id, x, y: 0.5, pressure: 1.0, width: 1080, height: 1920, buttons: 0 })
+    }
+
+    fn scroll(vscroll: i32) -> ControlEvent {
+        ControlEvent::Scroll(ScrollEvent { x: 0.5, y: 0.5, width: 1080, height: 1920, hscroll: 0, vscroll })
+    }
+
+    fn summary(event: &ControlEvent) -> String {
+        match event {
+            ControlEvent::Touch(t) => format!("{:?}:{}:{}", t.action, t.pointer_id, t.x),
+            ControlEvent::Scroll(s) => format!("scroll:{}", s.vscroll),
+            ControlEvent::UhidMouseRel(m) => format!("rel:{}:{}:{}", m.buttons, m.dx, m.dy),
+            other => format!("{:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_coalesces_consecutive_moves() {
+        use AndroidMotionEventAction::*;
+        let (tx, mut rx) = control_queue(100);
+        tx.send(touch(Down, 0, 0.1)).await.unwrap();
+        tx.send(touch(Move, 0, 0.2)).await.unwrap();
+        tx.send(touch(Move, 0, 0.3)).await.unwrap();
+        tx.send(touch(Move, 1, 0.4)).await.unwrap();
+        tx.send(touch(Move, 1, 0.5)).await.unwrap();
+        tx.send(touch(Up, 0, 0.3)).await.unwrap();
+        tx.send(scroll(1)).await.unwrap();
+        tx.send(scroll(2)).await.unwrap();
+        let rel = |dx, dy, buttons| ControlEvent::UhidMouseRel(UhidMouseRelEvent { dx, dy, buttons, wheel: 0, hwheel: 0 });
+        tx.send(rel(3, -1, 0)).await.unwrap();
+        tx.send(rel(4, -2, 0)).await.unwrap();
+        tx.send(rel(1, 1, 1)).await.unwrap();
+        drop(tx);
+
+        let mut received = Vec::new();
+        while let Some(event) = rx.recv().await {
+            received.push(summary(&event));
+        }
+        assert_eq!(received, vec!["Down:0:0.1", "Move:0:0.3", "Move:1:0.5", "Up:0:0.3", "scroll:3", "rel:0:7:-3", "rel:1:1:1"]);
+    }
+
+    #[tokio::test]
+    async fn test_backpressure_preserves_down_up() {
+        use AndroidMotionEventAction::*;
+        let (tx, mut rx) = control_queue(2);
+        let producer = tokio::spawn(async move {
+            for i in 0..50 {
+                tx.send(touch(Down, 0, i as f32)).await.unwrap();
+                tx.send(touch(Move, 0, i as f32)).await.unwrap();
+                tx.send(touch(Up, 0, i as f32)).await.unwrap();
+            }
+        });
+
+        let mut downs = 0;
+        let mut ups = 0;
+        while let Some(event) = rx.recv().await {
+            if let ControlEvent::Touch(t) = event {
+                match t.action {
+                    Down => downs += 1,
+                    Up => ups += 1,
+                    _ => {}
+                }
+            }
+        }
+        producer.await.unwrap();
+        assert_eq!((downs, ups), (50, 50));
+    }
+
+    #[tokio::test]
+    async fn test_send_fails_after_receiver_dropped() {
+        let (tx, rx) = control_queue(1);
+        drop(rx);
+        assert!(tx.send(scroll(1)).await.is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! 设备消息模块 - 解析 scrcpy-server 通过控制流发回的消息（设备 → 主机）
+//!
+//! 参考：https://github.com/Genymobile/scrcpy/blob/master/app/src/device_msg.h
+//! - CLIPBOARD (0)：`[len:4][utf8]`，设备剪贴板变化（clipboard_autosync）
+//! - ACK_CLIPBOARD (1)：`[sequence:8]`
+//! - UHID_OUTPUT (2)：`[id:2][size:2][data]`，UHID 设备的输出报告（如键盘 LED 状态）
+//!
+//! 消息没有整体长度前缀，遇到未知类型无法跳过，只能停止读取。
+
+use crate::error::{Result, ScrcpyError};
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// 剪贴板文本上限（与 scrcpy 的 DEVICE_MSG_MAX_SIZE 一致）
+pub const DEVICE_MSG_MAX_SIZE: usize = 1 << 18;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+pub enum DeviceMessageType {
+    Clipboard = 0,
+    AckClipboard = 1,
+    UhidOutput = 2,
+}
+
+/// 设备发回的消息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceMessage {
+    Clipboard(String),
+    AckClipboard(u64),
+    UhidOutput(UhidOutput),
+}
+
+/// UHID 输出报告，转发给客户端：`{"type":"uhid_output","id":..,"data":[...]}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename = "uhid_output")]
+pub struct UhidOutput {
+    pub id: u16,
+    pub data: Vec<u8>,
+}
+
+/// 读取一条设备消息，连接正常关闭时返回 None
+pub async fn read_device_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<DeviceMessage>> {
+    let msg_type = match reader.read_u8().await {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let message = match msg_type {
+        t if t == DeviceMessageType::Clipboard as u8 => {
+            let len = reader.read_u32().await? as usize;
+            if len > DEVICE_MSG_MAX_SIZE {
+                return Err(ScrcpyError::Parse(format!("Clipboard message too large: {} bytes", len)));
+            }
+            let mut text = vec![0u8; len];
+            reader.read_exact(&mut text).await?;
+            DeviceMessage::Clipboard(String::from_utf8_lossy(&text).into_owned())
+        }
+        t if t == DeviceMessageType::AckClipboard as u8 => DeviceMessage::AckClipboard(reader.read_u64().await?),
+        t if t == DeviceMessageType::UhidOutput as u8 => {
+            let id = reader.read_u16().await?;
+            let size = reader.read_u16().await? as usize;
+            let mut data = vec![0u8; size];
+            reader.read_exact(&mut data).await?;
+            DeviceMessage::UhidOutput(UhidOutput { id, data })
+        }
+        t => return Err(ScrcpyError::Parse(format!("Unknown device message type: {}", t))),
+    };
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_device_messages() {
+        let mut bytes: Vec<u8> = vec![0x00, 0x00, 0x00, 0x00, 0x02, b'h', b'i'];
+        bytes.extend_from_slice(&[0x01, 0, 0, 0, 0, 0, 0, 0, 0x07]);
+        bytes.extend_from_slice(&[0x02, 0x00, 0x01, 0x00, 0x01, 0x02]);  // Caps Lock LED
+        let mut reader = bytes.as_slice();
+
+        assert_eq!(read_device_message(&mut reader).await.unwrap(), Some(DeviceMessage::Clipboard("hi".to_string())));
+        assert_eq!(read_device_message(&mut reader).await.unwrap(), Some(DeviceMessage::AckClipboard(7)));
+        let Some(DeviceMessage::UhidOutput(output)) = read_device_message(&mut reader).await.unwrap() else {
+            panic!("expected UHID output");
+        };
+        assert_eq!(serde_json::to_string(&output).unwrap(), r#"{"type":"uhid_output","id":1,"data":[2]}"#);
+        assert_eq!(read_device_message(&mut reader).await.unwrap(), None);
+
+        assert!(read_device_message(&mut [0x09u8].as_slice()).await.is_err());
+    }
+}
@@ -0,0 +1,107 @@
+//! 测试用的假 scrcpy-server - 不需要 ADB 和真机即可端到端测试连接、读流和控制消息
+//!
+//! 在 127.0.0.1 上监听视频和控制两个端口，行为与 `raw_stream=true` 的 scrcpy 3.3.4 一致：
+//! 两个连接都建立后，视频连接先发送 1 字节 dummy byte，再发送预先准备好的 Annex-B H.264 码流，然后关闭；
+//! 控制连接收到的所有字节都记录下来，供测试检查消息编码。
+//!
+//! 用 [`ScrcpyServer::set_local_ports`](crate::scrcpy::ScrcpyServer::set_local_ports) 把 `ScrcpyServer` 的连接方法指向它。
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// 1080x2400 的 Baseline SPS（含防竞争字节，见 main.rs 的 SPS 解析测试）
+pub const CANNED_SPS: &[u8] = &[
+    0x67, 0x42, 0xc0, 0x1f, 0xd0, 0x00, 0x00, 0x03, 0x02, 0x00,
+    0x00, 0x03, 0x03, 0xa0, 0x11, 0x00, 0x4b, 0x79, 0x74,
+];
+pub const CANNED_PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+
+/// 生成 scrcpy 风格的码流：SPS、PPS、IDR，然后是 `p_frames` 个 P 帧，都使用 4 字节起始码
+///
+/// 结尾追加一个起始码，让最后一帧也能被 [`VideoStreamReader`](crate::scrcpy::VideoStreamReader) 切分出来。
+pub fn canned_h264(p_frames: usize) -> Vec<u8> {
+    let mut stream = Vec::new();
+    let mut push = |nal: &[u8]| {
+        stream.extend_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+        stream.extend_from_slice(nal);
+    };
+    push(CANNED_SPS);
+    push(CANNED_PPS);
+    push(&[0x65, 0x88, 0x84, 0x21, 0xa0]);
+    for i in 0..p_frames {
+        push(&[0x41, 0x9a, i as u8 | 0x10, 0x22]);
+    }
+    stream.extend_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+    stream
+}
+
+/// 正在运行的假 server（drop 时结束后台任务）
+pub struct FakeScrcpyServer {
+    pub video_port: u16,
+    pub control_port: u16,
+    control_bytes: Arc<Mutex<Vec<u8>>>,
+    control_len: watch::Receiver<usize>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl FakeScrcpyServer {
+    /// 开始监听，视频连接上发送 dummy byte 和 `video` 码流
+    pub async fn start(video: Vec<u8>) -> io::Result<Self> {
+        let video_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let control_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let video_port = video_listener.local_addr()?.port();
+        let control_port = control_listener.local_addr()?.port();
+
+        let control_bytes = Arc::new(Mutex::new(Vec::new()));
+        let (len_tx, control_len) = watch::channel(0);
+        let (connected_tx, mut connected_rx) = watch::channel(false);
+
+        let bytes = control_bytes.clone();
+        let control_task = tokio::spawn(async move {
+            let Ok((mut socket, _)) = control_listener.accept().await else {
+                return;
+            };
+            let _ = connected_tx.send(true);
+            let mut buf = [0u8; 1024];
+            while let Ok(n @ 1..) = socket.read(&mut buf).await {
+                let mut bytes = bytes.lock().unwrap();
+                bytes.extend_from_slice(&buf[..n]);
+                let _ = len_tx.send(bytes.len());
+            }
+        });
+
+        let video_task = tokio::spawn(async move {
+            let Ok((mut socket, _)) = video_listener.accept().await else {
+                return;
+            };
+            // 与 scrcpy 一致：控制连接也建立后才开始发送数据
+            if connected_rx.wait_for(|connected| *connected).await.is_err() {
+                return;
+            }
+            let _ = socket.write_all(&[0x00]).await;
+            let _ = socket.write_all(&video).await;
+            let _ = socket.shutdown().await;
+        });
+
+        Ok(Self { video_port, control_port, control_bytes, control_len, tasks: vec![control_task, video_task] })
+    }
+
+    /// 等待控制连接累计收到至少 `len` 字节，返回收到的全部字节
+    pub async fn control_received(&mut self, len: usize) -> Vec<u8> {
+        let _ = tokio::time::timeout(Duration::from_secs(5), self.control_len.wait_for(|&received| received >= len)).await;
+        self.control_bytes.lock().unwrap().clone()
+    }
+}
+
+impl Drop for FakeScrcpyServer {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
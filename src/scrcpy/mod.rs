@@ -1,7 +1,15 @@
 pub mod server;
+pub mod audio;
+pub mod server_log;
 pub mod video;
 pub mod control;
+pub mod control_queue;
+pub mod device_msg;
+pub mod stream;
+pub mod uhid;
+#[cfg(test)]
+pub mod fake_server;
 
 pub use server::ScrcpyServer;
-pub use video::{VideoFrame, VideoStreamReader, CodecInfo, FrameType};
+pub use video::{VideoStreamReader, FrameType};
 pub use control::ControlChannel;
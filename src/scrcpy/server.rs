@@ -1,29 +1,25 @@
 use crate::adb::AdbClient;
 use crate::error::{Result, ScrcpyError};
-use crate::scrcpy::video::CodecInfo;
-use crate::utils::find_available_port;
+use crate::scrcpy::server_log::ServerLogStats;
+use crate::scrcpy::audio::AudioStreamReader;
+use crate::scrcpy::stream::{DeviceEndpoint, DeviceStream};
+use crate::utils::{reserve_ports, PortReservation};
 use std::path::PathBuf;
 use tokio::io::AsyncReadExt;
-use tokio::net::TcpStream;
 use tokio::process::{Child, Command};
 use std::process::Stdio;
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, instrument, warn};
 
 const DEVICE_SERVER_PATH: &str = "/data/local/tmp/scrcpy-server.jar";
 const SOCKET_NAME: &str = "scrcpy";
-
-/// scrcpy 3.3.4 的 codec_meta JSON 格式
-#[derive(Debug, serde::Deserialize)]
-struct CodecMeta {
-    codec: String,
-    width: u32,
-    height: u32,
-    #[serde(rename = "csd-0")]
-    csd_0: Option<String>,  // SPS (base64)
-    #[serde(rename = "csd-1")]
-    csd_1: Option<String>,  // PPS (base64)
-}
-
+/// 官方 scrcpy-server 的主类
+pub const DEFAULT_SERVER_CLASS: &str = "com.genymobile.scrcpy.Server";
+/// audio_dup 需要的最低 Android SDK（Android 13，AudioPlaybackCapture 的 keepPlayingOnDevice）
+pub const AUDIO_DUP_MIN_SDK: u32 = 33;
+/// 编码器启动失败时自动降低码率的下限（bps）
+pub const MIN_FALLBACK_BIT_RATE: u32 = 1_000_000;
+/// 关闭连接后等待 scrcpy 自行退出（执行 cleanup）的最长时间
+const GRACEFUL_EXIT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
 
 pub struct ScrcpyServer {
     adb: AdbClient,
@@ -33,36 +29,41 @@ pub struct ScrcpyServer {
     actual_video_port: u16,    // 实际使用的视频端口
     control_port: u16,
     actual_control_port: u16,  // 实际使用的控制端口
+    actual_audio_port: u16,    // 实际使用的音频端口（0 表示未启用音频）
     max_size: u32,
     bit_rate: u32,
     max_fps: u32,
     intra_refresh_period: u32,  // 强制IDR帧间隔（秒）
+    cleanup: bool,              // 退出时是否让 scrcpy 恢复设备状态
+    server_class: String,       // app_process 启动的主类（自定义 server 构建可替换）
+    extra_args: Vec<String>,    // 追加到启动命令的 key=value 参数
+    codec_profile: Option<CodecProfile>,  // 请求编码器使用的 H.264 profile（None 表示设备默认）
+    power_on: bool,             // 启动时是否点亮屏幕（scrcpy 默认点亮）
+    display_id: Option<u32>,    // 镜像的显示屏（--display-id），None 时使用 scrcpy 默认的 0
+    scid: Option<u32>,          // scrcpy 会话 id：同一台设备上同时运行多个 server 时区分抽象套接字
+    crop: Option<Crop>,         // 只镜像屏幕的一部分（--crop）
+    orientation_lock: Option<OrientationLock>,  // 锁定视频方向（--lock-orientation），None 时随设备旋转
+    frame_meta: bool,           // 视频数据包带 12 字节帧头（--frame-meta），用于读取 PTS
+    audio: bool,                // 是否采集设备声音（--audio，audio_codec=opus）
+    audio_dup: bool,            // 采集设备播放的声音时，设备扬声器继续播放（scrcpy audio_dup）
+    log_level: ServerLogLevel,  // 设备端 scrcpy-server 的日志级别
+    time_limit: Option<std::time::Instant>,  // 设备端采集的截止时间（--device-time-limit），重启时传递剩余时间
+    #[cfg(unix)]
+    unix_socket_dir: Option<PathBuf>,  // 使用 Unix 域套接字代替 TCP 时的目录
+    port_reservation: Option<PortReservation>,  // 端口预留，adb forward 之前释放
     server_process: Option<Child>,
+    log_stats: ServerLogStats,  // 从 server 输出解析的编码器信息（/stats）
 }
 
 impl ScrcpyServer {
+    /// 测试用：默认参数（端口从 27183 开始搜索）
+    #[cfg(test)]
     pub fn new(adb: AdbClient, device_id: String, server_path: PathBuf) -> Result<Self> {
-        // 自动寻找可用端口
-        let actual_video_port = find_available_port(27183, 100)?;
-        let actual_control_port = find_available_port(actual_video_port + 1, 100)?;
-
-        Ok(Self {
-            adb,
-            device_id,
-            server_path,
-            video_port: 27183,
-            actual_video_port,
-            control_port: 27184,
-            actual_control_port,
-            max_size: 1920,       // 最大分辨率
-            bit_rate: 16_000_000, // 16Mbps - 提高码率改善画质
-            max_fps: 60,
-            intra_refresh_period: 1,  // 每1秒强制一个IDR帧
-            server_process: None,
-        })
+        Self::with_config(adb, device_id, server_path, 1920, 16_000_000, 60, 27183, 27184, 1)
     }
 
     /// 创建带自定义配置的服务器（自动寻找可用端口）
+    #[allow(clippy::too_many_arguments)]
     pub fn with_config(
         adb: AdbClient,
         device_id: String,
@@ -75,12 +76,17 @@ impl ScrcpyServer {
         intra_refresh_period: u32,
     ) -> Result<Self> {
         // 自动寻找可用端口
-        let actual_video_port = find_available_port(video_port, 100)?;
+        // 预留期间端口保持绑定，直到 start() 中真正交给 adb forward
+        let video_reservation = reserve_ports(video_port, 1, 100)?;
+        let actual_video_port = video_reservation.ports()[0];
         // 控制端口从视频端口+1开始搜索，避免冲突
-        let actual_control_port = find_available_port(
+        let control_reservation = reserve_ports(
             if control_port <= actual_video_port { actual_video_port + 1 } else { control_port },
-            100
+            1,
+            100,
         )?;
+        let actual_control_port = control_reservation.ports()[0];
+        let port_reservation = video_reservation.merge(control_reservation);
 
         Ok(Self {
             adb,
@@ -90,11 +96,30 @@ impl ScrcpyServer {
             actual_video_port,
             control_port,
             actual_control_port,
+            actual_audio_port: 0,
             max_size,
             bit_rate,
             max_fps,
             intra_refresh_period,
+            cleanup: true,
+            server_class: DEFAULT_SERVER_CLASS.to_string(),
+            extra_args: Vec::new(),
+            codec_profile: None,
+            power_on: true,
+            display_id: None,
+            scid: None,
+            crop: None,
+            orientation_lock: None,
+            frame_meta: false,
+            audio: false,
+            audio_dup: false,
+            log_level: ServerLogLevel::Info,
+            time_limit: None,
+            #[cfg(unix)]
+            unix_socket_dir: None,
+            port_reservation: Some(port_reservation),
             server_process: None,
+            log_stats: ServerLogStats::default(),
         })
     }
 
@@ -108,7 +133,343 @@ impl ScrcpyServer {
         self.actual_control_port
     }
 
+    /// 从 server 输出解析的编码器信息（重启后继续更新同一个句柄）
+    pub fn get_log_stats(&self) -> ServerLogStats {
+        self.log_stats.clone()
+    }
+
+    /// 设置退出时是否由 scrcpy 恢复设备状态（show_touches、stay_awake、电源模式等）
+    pub fn set_cleanup(&mut self, cleanup: bool) {
+        self.cleanup = cleanup;
+    }
+
+    /// 设置 app_process 启动的主类（用于测试 fork 的 scrcpy-server）
+    pub fn set_server_class(&mut self, class: String) {
+        self.server_class = class;
+    }
+
+    /// 设置追加到启动命令末尾的 `key=value` 参数（应先经过 [`parse_server_extra_args`] 校验）
+    pub fn set_extra_args(&mut self, args: Vec<String>) {
+        self.extra_args = args;
+    }
+
+    /// 启用音频采集（`audio=true audio_codec=opus`），为音频连接预留第三个端口
+    ///
+    /// Opus 包需要帧头切分，启用音频时所有流都带帧头（见 [`stream_frame_meta`](Self::stream_frame_meta)）。
+    pub fn set_audio(&mut self, audio: bool) -> Result<()> {
+        self.audio = audio;
+        if audio && self.actual_audio_port == 0 {
+            let reservation = reserve_ports(self.actual_video_port.max(self.actual_control_port) + 1, 1, 100)?;
+            self.actual_audio_port = reservation.ports()[0];
+            self.port_reservation = Some(match self.port_reservation.take() {
+                Some(ports) => ports.merge(reservation),
+                None => reservation,
+            });
+        }
+        Ok(())
+    }
+
+    /// 是否采集音频
+    pub fn audio_enabled(&self) -> bool {
+        self.audio
+    }
+
+    /// 获取实际使用的音频端口（未启用音频时为 None）
+    pub fn get_actual_audio_port(&self) -> Option<u16> {
+        self.audio.then_some(self.actual_audio_port)
+    }
+
+    /// 设置采集音频时是否在设备上继续播放（scrcpy `audio_dup`，需要 Android 13+，见 [`AUDIO_DUP_MIN_SDK`]）
+    pub fn set_audio_dup(&mut self, audio_dup: bool) {
+        self.audio_dup = audio_dup;
+    }
+
+    /// 设置启动时是否点亮屏幕（false 对应 scrcpy 的 `power_on=false`，熄屏的设备保持熄屏）
+    pub fn set_power_on(&mut self, power_on: bool) {
+        self.power_on = power_on;
+    }
+
+    /// 设置镜像的显示屏（`--display-id`，None 为默认显示屏）
+    pub fn set_display_id(&mut self, display_id: Option<u32>) {
+        self.display_id = display_id;
+    }
+
+    /// 设置 scrcpy 会话 id（`scid`，31 位），None 时使用默认的抽象套接字 `scrcpy`
+    ///
+    /// `--display-ids` 在同一台设备上为每个显示屏运行一个 server，各自的套接字为 `scrcpy_<scid>`。
+    pub fn set_scid(&mut self, scid: Option<u32>) {
+        self.scid = scid.map(|scid| scid & 0x7fff_ffff);
+    }
+
+    /// 设备端 scrcpy-server 监听的抽象套接字名
+    fn socket_name(&self) -> String {
+        match self.scid {
+            Some(scid) => format!("{}_{:08x}", SOCKET_NAME, scid),
+            None => SOCKET_NAME.to_string(),
+        }
+    }
+
+    /// 设置裁剪区域（`--crop`，None 为整个屏幕）
+    pub fn set_crop(&mut self, crop: Option<Crop>) {
+        self.crop = crop;
+    }
+
+    /// 锁定视频方向（`--lock-orientation`，None 时画面随设备旋转）
+    pub fn set_orientation_lock(&mut self, lock: Option<OrientationLock>) {
+        self.orientation_lock = lock;
+    }
+
+    /// 视频数据包是否带帧头（`--frame-meta`）
+    pub fn set_frame_meta(&mut self, frame_meta: bool) {
+        self.frame_meta = frame_meta;
+    }
+
+    /// 设备端实际发送的流是否带帧头：scrcpy 的帧头对所有流统一开关，音频（Opus）必须带帧头
+    ///
+    /// 读取视频流时以此为准，而不是 `--frame-meta`。
+    pub fn stream_frame_meta(&self) -> bool {
+        self.frame_meta || self.audio
+    }
+
+    /// 设置设备端采集时长上限（`--device-time-limit`），从现在开始计时
+    ///
+    /// scrcpy-server 没有 `time_limit` 参数（scrcpy 的 `--time-limit` 由客户端计时），
+    /// 这里用设备上 toybox 的 `timeout` 包装 app_process：主机崩溃、没有机会停止 server 时，
+    /// 设备端也会在到期后结束采集。之后的重启只传递剩余时间。
+    pub fn set_time_limit(&mut self, limit: Option<std::time::Duration>) {
+        self.time_limit = limit.map(|limit| std::time::Instant::now() + limit);
+    }
+
+    /// 设备端采集时长是否已经到期（此时视频流结束是预期的，不应重连）
+    pub fn time_limit_reached(&self) -> bool {
+        self.time_limit.is_some_and(|deadline| std::time::Instant::now() >= deadline)
+    }
+
+    /// 设置设备端 scrcpy-server 的日志级别（`--server-log-level`）
+    pub fn set_log_level(&mut self, level: ServerLogLevel) {
+        self.log_level = level;
+    }
+
+    /// 设置请求编码器使用的 H.264 profile
+    pub fn set_codec_profile(&mut self, profile: Option<CodecProfile>) {
+        self.codec_profile = profile;
+    }
+
+    /// 生成在设备 shell 中启动 scrcpy-server 的完整命令（/debug/config 也会展示）
+    pub fn server_command(&self) -> String {
+        // scrcpy 3.x 必须明确指定参数来启用视频流
+        // 使用 video_codec_options=i-frame-interval 来控制IDR帧间隔
+        // i-frame-interval 单位是秒
+        // scrcpy v3.3.4 参数 (按照 SUMMARY.md 的工作配置)
+        // --device-time-limit：剩余时间向上取整到秒，至少 1 秒
+        let timeout = self.time_limit
+            .map(|deadline| deadline.saturating_duration_since(std::time::Instant::now()).as_millis().div_ceil(1000).max(1))
+            .map(|secs| format!("timeout {} ", secs))
+            .unwrap_or_default();
+        let mut server_args = format!(
+            "CLASSPATH={} {}app_process / {} 3.3.4 \
+             log_level={} \
+             max_size={} \
+             video_bit_rate={} \
+             max_fps={} \
+             video_codec_options={} \
+             tunnel_forward=true \
+             send_device_meta=false \
+             send_frame_meta={} \
+             send_dummy_byte=true \
+             send_codec_meta=false \
+             raw_stream={} \
+             audio={} \
+             control=true \
+             cleanup={}",
+            DEVICE_SERVER_PATH,
+            timeout,
+            self.server_class,
+            self.log_level.as_str(),
+            self.max_size,
+            self.bit_rate,
+            self.max_fps,
+            video_codec_options(self.intra_refresh_period, self.codec_profile),
+            // raw_stream=true 会把 send_frame_meta 重新设为 false，带帧头时不能传
+            self.stream_frame_meta(),
+            !self.stream_frame_meta(),
+            self.audio,
+            self.cleanup
+        );
+
+        // Opus 包按帧头切分（见 scrcpy::audio）
+        if self.audio {
+            server_args.push_str(" audio_codec=opus");
+        }
+
+        // scrcpy 默认 power_on=true，只在关闭时显式传递
+        if !self.power_on {
+            server_args.push_str(" power_on=false");
+        }
+
+        // scrcpy 默认镜像 display_id=0
+        if let Some(display_id) = self.display_id {
+            server_args.push_str(&format!(" display_id={}", display_id));
+        }
+
+        if let Some(scid) = self.scid {
+            server_args.push_str(&format!(" scid={:08x}", scid));
+        }
+
+        // 裁剪后视频分辨率变为裁剪区域（再按 max_size 缩放），触控坐标由 server 加上偏移换算回屏幕
+        if let Some(crop) = self.crop {
+            server_args.push_str(&format!(" crop={}", crop));
+        }
+
+        // scrcpy 3.x 用 capture_orientation 代替了 2.x 的 lock_video_orientation，`@` 前缀表示锁定
+        if let Some(lock) = self.orientation_lock {
+            server_args.push_str(&format!(" capture_orientation={}", lock.capture_orientation()));
+        }
+
+        // audio_dup 只对 playback 音频源有效
+        if self.audio_dup {
+            server_args.push_str(" audio_source=playback audio_dup=true");
+        }
+
+        // 自定义参数追加在最后，同名参数会覆盖前面的默认值
+        for arg in &self.extra_args {
+            server_args.push(' ');
+            server_args.push_str(arg);
+        }
+        server_args
+    }
+
+    /// 直接连接已在本地监听的端口（测试中的假 server），释放端口预留，不经过 adb forward
+    #[cfg(test)]
+    pub fn set_local_ports(&mut self, video_port: u16, control_port: u16) {
+        if let Some(reservation) = self.port_reservation.take() {
+            reservation.release();
+        }
+        self.actual_video_port = video_port;
+        self.actual_control_port = control_port;
+    }
+
+    /// 使用指定目录下的 Unix 域套接字代替 TCP 端口转发（None 表示使用 TCP）
+    #[cfg(unix)]
+    pub fn set_unix_socket_dir(&mut self, dir: Option<PathBuf>) {
+        self.unix_socket_dir = dir;
+    }
+
+    /// Unix 域套接字模式下某个通道（video/control）的套接字路径
+    #[cfg(unix)]
+    fn unix_socket_path(&self, channel: &str) -> Option<PathBuf> {
+        let serial: String = self.device_id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        self.unix_socket_dir
+            .as_ref()
+            .map(|dir| match self.scid {
+                Some(scid) => dir.join(format!("scrcpy_{}_{:08x}_{}.sock", serial, scid, channel)),
+                None => dir.join(format!("scrcpy_{}_{}.sock", serial, channel)),
+            })
+    }
+
+    /// adb forward 的本地端点：`tcp:PORT` 或 `localfilesystem:PATH`
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    fn local_endpoint(&self, channel: &str, port: u16) -> String {
+        #[cfg(unix)]
+        if let Some(path) = self.unix_socket_path(channel) {
+            return format!("localfilesystem:{}", path.display());
+        }
+        format!("tcp:{}", port)
+    }
+
+    /// 某个通道的本地端点
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    fn endpoint(&self, channel: &str, port: u16) -> DeviceEndpoint {
+        #[cfg(unix)]
+        if let Some(path) = self.unix_socket_path(channel) {
+            return DeviceEndpoint::Unix(path);
+        }
+        DeviceEndpoint::Tcp(port)
+    }
+
+    /// 连接到某个通道的本地端点
+    async fn connect_endpoint(&self, channel: &str, port: u16) -> std::io::Result<DeviceStream> {
+        self.endpoint(channel, port).connect().await
+    }
+
+    /// 检查转发端口是否能从局域网访问，能访问时输出错误日志
+    async fn check_forward_exposure(&self) {
+        if crate::utils::port::lan_address().is_none() {
+            debug!("  No non-loopback address, skipping forward exposure check");
+            return;
+        }
+        let mut channels = vec![("video", self.actual_video_port), ("control", self.actual_control_port)];
+        if self.audio {
+            channels.push(("audio", self.actual_audio_port));
+        }
+        for (channel, port) in channels {
+            if !matches!(self.endpoint(channel, port), DeviceEndpoint::Tcp(_)) {
+                continue;  // Unix 域套接字不经过网络
+            }
+            match crate::utils::port::lan_exposure(port).await {
+                Some(ip) => error!(
+                    "🚨 The {} forward port {} is reachable from {} — the raw stream is exposed to the LAN without auth. \
+                     Restart the adb server without `-a` (adb kill-server), or use --unix-socket",
+                    channel, port, ip
+                ),
+                None => debug!("  {} forward port {} is loopback-only", channel, port),
+            }
+        }
+    }
+
+    /// 控制流的本地端点（`--print-endpoint`）
+    pub fn control_endpoint(&self) -> DeviceEndpoint {
+        self.endpoint("control", self.actual_control_port)
+    }
+
+    /// 设备端 server 进程（adb shell）是否仍在运行
+    pub fn is_running(&mut self) -> bool {
+        match self.server_process.as_mut() {
+            Some(child) => matches!(child.try_wait(), Ok(None)),
+            None => false,
+        }
+    }
+
+    /// 是否已经启动过设备端进程（之后需要 restart 而不是 start）
+    pub fn is_started(&self) -> bool {
+        self.server_process.is_some()
+    }
+
+    /// 当前请求的视频码率（bps）
+    pub fn get_bit_rate(&self) -> u32 {
+        self.bit_rate
+    }
+
+    /// 当前请求的最大帧率
+    pub fn get_max_fps(&self) -> u32 {
+        self.max_fps
+    }
+
+    /// 当前请求的最大分辨率
+    pub fn get_max_size(&self) -> u32 {
+        self.max_size
+    }
+
+    /// 设置视频码率（下次 start/restart 时生效）
+    pub fn set_bit_rate(&mut self, bit_rate: u32) {
+        self.bit_rate = bit_rate;
+    }
+
+    /// 设置最大帧率（下次 start/restart 时生效）
+    pub fn set_max_fps(&mut self, max_fps: u32) {
+        self.max_fps = max_fps;
+    }
+
+    /// 设置最大分辨率（下次 start/restart 时生效）
+    pub fn set_max_size(&mut self, max_size: u32) {
+        self.max_size = max_size;
+    }
+
     /// 部署服务器到设备
+    #[instrument(skip(self), fields(device = %self.device_id))]
     pub async fn deploy(&self) -> Result<()> {
         info!("📦 Deploying scrcpy-server to device...");
 
@@ -125,6 +486,10 @@ impl ScrcpyServer {
             ScrcpyError::Parse("Invalid server path".to_string())
         })?;
 
+        // --display-ids 的多个会话会同时部署到同一台设备，推送逐个进行
+        // （adb push 先删除再创建目标文件，已在运行的 server 不受影响）
+        static PUSH_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+        let _push = PUSH_LOCK.lock().await;
         info!("  Pushing {} to device...", local_path);
         self.adb
             .push(&self.device_id, local_path, DEVICE_SERVER_PATH)
@@ -135,61 +500,68 @@ impl ScrcpyServer {
     }
 
     /// 启动scrcpy-server
+    #[instrument(skip(self), fields(device = %self.device_id, max_size = self.max_size, bit_rate = self.bit_rate))]
     pub async fn start(&mut self) -> Result<()> {
         info!("🚀 Starting scrcpy-server...");
+        self.log_stats.clear_encoder_error();
         info!("   Video port: {} (requested: {})", self.actual_video_port, self.video_port);
         info!("   Control port: {} (requested: {})", self.actual_control_port, self.control_port);
 
+        // Unix 域套接字模式：清理上次残留的套接字文件，否则 adb 无法创建
+        #[cfg(unix)]
+        for channel in ["video", "audio", "control"] {
+            if let Some(path) = self.unix_socket_path(channel) {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+
+        // 释放端口预留，交给 adb forward 绑定
+        if let Some(reservation) = self.port_reservation.take() {
+            reservation.release();
+        }
+
         // 设置端口转发 - 视频socket
-        info!("  Setting up video port forwarding: localabstract:{}", SOCKET_NAME);
+        let video_local = self.local_endpoint("video", self.actual_video_port);
+        info!("  Setting up video port forwarding: {} -> localabstract:{}", video_local, self.socket_name());
         self.adb
-            .forward(
+            .forward_local(
                 &self.device_id,
-                self.actual_video_port,
-                &format!("localabstract:{}", SOCKET_NAME),
+                &video_local,
+                &format!("localabstract:{}", self.socket_name()),
             )
             .await?;
 
         // 设置端口转发 - 控制socket (使用同一个 abstract socket，scrcpy 会区分连接)
-        info!("  Setting up control port forwarding: localabstract:{}", SOCKET_NAME);
+        let control_local = self.local_endpoint("control", self.actual_control_port);
+        info!("  Setting up control port forwarding: {} -> localabstract:{}", control_local, self.socket_name());
         self.adb
-            .forward(
+            .forward_local(
                 &self.device_id,
-                self.actual_control_port,
-                &format!("localabstract:{}", SOCKET_NAME),
+                &control_local,
+                &format!("localabstract:{}", self.socket_name()),
             )
             .await?;
 
-        // 启动server的命令
-        // scrcpy 3.x 必须明确指定参数来启用视频流
-        // 使用 video_codec_options=i-frame-interval 来控制IDR帧间隔
-        // i-frame-interval 单位是秒
+        // 设置端口转发 - 音频socket（同一个 abstract socket，scrcpy 按连接顺序区分：视频、音频、控制）
+        if self.audio {
+            let audio_local = self.local_endpoint("audio", self.actual_audio_port);
+            info!("  Setting up audio port forwarding: {} -> localabstract:{}", audio_local, self.socket_name());
+            self.adb
+                .forward_local(
+                    &self.device_id,
+                    &audio_local,
+                    &format!("localabstract:{}", self.socket_name()),
+                )
+                .await?;
+        }
 
-        info!("  IDR frame interval: {}s", self.intra_refresh_period);
+        // 原始视频/控制流没有任何认证，端口必须只监听回环地址。
+        // adb 的 tcp: 转发默认只绑定 localhost，但 adb server 以 `-a` 启动时会绑定所有网卡。
+        // 在 server 启动前检查（此时 adb 接受连接后找不到设备端套接字，会直接关闭，不影响后续连接）
+        self.check_forward_exposure().await;
 
-        // scrcpy v3.3.4 参数 (按照 SUMMARY.md 的工作配置)
-        let server_args = format!(
-            "CLASSPATH={} app_process / com.genymobile.scrcpy.Server 3.3.4 \
-             log_level=info \
-             max_size={} \
-             video_bit_rate={} \
-             max_fps={} \
-             video_codec_options=i-frame-interval={} \
-             tunnel_forward=true \
-             send_device_meta=false \
-             send_frame_meta=false \
-             send_dummy_byte=true \
-             send_codec_meta=false \
-             raw_stream=true \
-             audio=false \
-             control=true \
-             cleanup=true",
-            DEVICE_SERVER_PATH,
-            self.max_size,
-            self.bit_rate,
-            self.max_fps,
-            self.intra_refresh_period
-        );
+        info!("  IDR frame interval: {}s", self.intra_refresh_period);
+        let server_args = self.server_command();
 
         info!("  Executing: shell {}", server_args);
 
@@ -199,7 +571,7 @@ impl ScrcpyServer {
         let device_id = self.device_id.clone();
 
         let mut child = Command::new(&adb_path)
-            .args(&["-s", &device_id, "shell", &server_args])
+            .args(["-s", &device_id, "shell", &server_args])
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
@@ -208,6 +580,7 @@ impl ScrcpyServer {
 
         // 先获取 stderr 用于后台监控
         if let Some(stderr) = child.stderr.take() {
+            let log_stats = self.log_stats.clone();
             tokio::spawn(async move {
                 use tokio::io::{AsyncBufReadExt, BufReader};
                 let mut reader = BufReader::new(stderr);
@@ -215,6 +588,7 @@ impl ScrcpyServer {
                 while let Ok(n) = reader.read_line(&mut line).await {
                     if n == 0 { break; }
                     warn!("  Server stderr: {}", line.trim());
+                    log_stats.parse_line(&line);
                     line.clear();
                 }
             });
@@ -233,6 +607,7 @@ impl ScrcpyServer {
                     match result {
                         Ok(n) if n > 0 => {
                             info!("  Server output: {}", line.trim());
+                            self.log_stats.parse_line(&line);
                             server_started = true;
                         }
                         Ok(_) => {
@@ -247,31 +622,62 @@ impl ScrcpyServer {
                     warn!("  Timeout waiting for server output (might still be starting)");
                 }
             }
+
+            // scrcpy 把 verbose/debug/info 日志写到 stdout（warn/error 写到 stderr），
+            // 持续读取并输出，同时避免管道写满后阻塞 server
+            let log_stats = self.log_stats.clone();
+            tokio::spawn(async move {
+                let mut line = String::new();
+                while let Ok(n) = reader.read_line(&mut line).await {
+                    if n == 0 { break; }
+                    info!("  Server output: {}", line.trim());
+                    log_stats.parse_line(&line);
+                    line.clear();
+                }
+            });
         } else {
             warn!("  Could not capture server stdout");
         }
 
         self.server_process = Some(child);
 
+        if !server_started {
+            debug!("  Server did not confirm startup via stdout, continuing anyway");
+        }
+
         // 等待服务器启动 - 增加等待时间确保服务器完全就绪
         info!("  Waiting for server to initialize...");
         tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
 
+        // 设备实际选择的参数可能与请求的不同（如编码失败后自动降低 max_size）
+        let reported = self.log_stats.snapshot();
+        if let Some(device) = &reported.device {
+            info!("  Device reports: {}", device);
+        }
+        if let Some(encoder) = &reported.encoder {
+            info!("  Device encoder: {}", encoder);
+        }
+        if let Some(display_api) = &reported.display {
+            info!("  Device display: {}", display_api);
+        }
+        if let Some(size) = reported.max_size_retry {
+            warn!("  Device lowered max_size to {} after an encoder error", size);
+        }
+
         info!("✅ Server started on port {}", self.actual_video_port);
         Ok(())
     }
 
     /// 连接到scrcpy-server的视频流
-    pub async fn connect_video(&self) -> Result<TcpStream> {
+    #[instrument(skip(self), fields(device = %self.device_id))]
+    pub async fn connect_video(&self) -> Result<DeviceStream> {
         info!("🔌 Connecting to video stream...");
 
-        let addr = format!("127.0.0.1:{}", self.actual_video_port);
-
         // 尝试连接，带重试机制
         let mut stream = None;
         for attempt in 1..=5 {
             info!("  Connection attempt {}/5...", attempt);
-            match TcpStream::connect(&addr).await {
+            match self.connect_endpoint("video", self.actual_video_port).await {
                 Ok(s) => {
                     stream = Some(s);
                     break;
@@ -297,24 +703,37 @@ impl ScrcpyServer {
         Ok(stream)
     }
 
+    /// 连接到scrcpy-server的音频流
+    ///
+    /// scrcpy 按连接顺序区分通道，必须在视频流之后、控制流之前连接
+    #[instrument(skip(self), fields(device = %self.device_id))]
+    pub async fn connect_audio(&self) -> Result<AudioStreamReader> {
+        info!("🔊 Connecting to audio stream...");
+
+        let stream = self.connect_endpoint("audio", self.actual_audio_port).await
+            .map_err(|e| ScrcpyError::Network(format!("Failed to connect audio: {}", e)))?;
+
+        info!("✅ Connected to audio stream on {}", self.local_endpoint("audio", self.actual_audio_port));
+        Ok(AudioStreamReader::new(stream))
+    }
+
     /// 连接到scrcpy-server的控制流
     /// 控制流使用独立的端口 (control_port)，通过 adb forward 映射到同一个 abstract socket
-    pub async fn connect_control(&self) -> Result<TcpStream> {
+    #[instrument(skip(self), fields(device = %self.device_id))]
+    pub async fn connect_control(&self) -> Result<DeviceStream> {
         info!("🎮 Connecting to control stream...");
 
-        // 使用实际的控制端口
-        let addr = format!("127.0.0.1:{}", self.actual_control_port);
-
-        // 连接到控制流
-        let stream = TcpStream::connect(&addr).await
+        // 连接到控制流（使用实际的控制端口或套接字）
+        let stream = self.connect_endpoint("control", self.actual_control_port).await
             .map_err(|e| ScrcpyError::Network(format!("Failed to connect control: {}", e)))?;
 
-        info!("✅ Connected to control stream on port {}", self.actual_control_port);
+        info!("✅ Connected to control stream on {}", self.local_endpoint("control", self.actual_control_port));
         Ok(stream)
     }
 
     /// 从已连接的video stream读取scrcpy协议头
-    pub async fn read_video_header(stream: &mut TcpStream) -> Result<CodecInfo> {
+    #[instrument(skip_all)]
+    pub async fn read_video_header(stream: &mut DeviceStream) -> Result<()> {
         info!("📖 Reading scrcpy protocol header...");
 
         // scrcpy 3.3.4 + raw_stream=true 模式：
@@ -327,39 +746,465 @@ impl ScrcpyServer {
         info!("  Dummy byte: 0x{:02x}", dummy_byte[0]);
 
         info!("✅ Protocol header read successfully");
+        // raw_stream 模式没有 codec_id 和尺寸：分辨率从 SPS 中解析，SPS/PPS 从 NAL 流中提取
         info!("ℹ️  SPS/PPS will be extracted from raw NAL stream");
+        Ok(())
+    }
 
-        // 返回默认的 CodecInfo，SPS/PPS 将从视频流中提取
-        Ok(CodecInfo {
-            codec_id: 0,  // raw_stream 模式没有 codec_id
-            width: 0,     // 将从 SPS 中解析
-            height: 0,    // 将从 SPS 中解析
-            config_data: None,  // SPS/PPS 将从 NAL 流中提取
-        })
+    /// 以新的参数重启 scrcpy-server，端口转发保持不变
+    ///
+    /// 调用前应先关闭旧的视频流和控制流，之后需要重新连接
+    #[instrument(skip(self), fields(device = %self.device_id))]
+    pub async fn restart(&mut self) -> Result<()> {
+        info!("🔄 Restarting scrcpy-server...");
+        self.stop_process().await;
+        self.start().await
     }
 
     /// 停止服务器
+    ///
+    /// 调用前应先关闭视频流和控制流：scrcpy 检测到连接断开后会自行退出，
+    /// 并执行 cleanup（恢复设备设置）。这里先等待它自行退出，超时后才强制结束。
+    #[instrument(skip(self), fields(device = %self.device_id))]
     pub async fn stop(&mut self) -> Result<()> {
         info!("🛑 Stopping scrcpy-server...");
 
-        // 杀死server进程
-        if let Some(mut child) = self.server_process.take() {
-            let _ = child.kill().await;
+        self.stop_process().await;
+
+        // 移除端口转发（使用实际端口或套接字）
+        let video_local = self.local_endpoint("video", self.actual_video_port);
+        let control_local = self.local_endpoint("control", self.actual_control_port);
+        let _ = self.adb.forward_remove_local(&self.device_id, &video_local).await;
+        let _ = self.adb.forward_remove_local(&self.device_id, &control_local).await;
+        if self.audio {
+            let audio_local = self.local_endpoint("audio", self.actual_audio_port);
+            let _ = self.adb.forward_remove_local(&self.device_id, &audio_local).await;
         }
 
-        // 移除端口转发（使用实际端口）
-        let _ = self.adb.forward_remove(&self.device_id, self.actual_video_port).await;
-        let _ = self.adb.forward_remove(&self.device_id, self.actual_control_port).await;
+        #[cfg(unix)]
+        for channel in ["video", "audio", "control"] {
+            if let Some(path) = self.unix_socket_path(channel) {
+                let _ = std::fs::remove_file(path);
+            }
+        }
 
         info!("✅ Server stopped");
         Ok(())
     }
+
+    /// 等待 server 进程自行退出，超时后强制结束
+    async fn stop_process(&mut self) {
+        if let Some(mut child) = self.server_process.take() {
+            match tokio::time::timeout(GRACEFUL_EXIT_TIMEOUT, child.wait()).await {
+                Ok(Ok(status)) => {
+                    debug!("  Server exited by itself: {}", status);
+                }
+                Ok(Err(e)) => {
+                    warn!("  Failed to wait for server exit: {}", e);
+                    let _ = child.kill().await;
+                }
+                Err(_) => {
+                    warn!("  Server did not exit within {:?}, killing it", GRACEFUL_EXIT_TIMEOUT);
+                    let _ = child.kill().await;
+                }
+            }
+        }
+    }
 }
 
 impl Drop for ScrcpyServer {
+    // 兜底：未调用 stop() 时强制结束进程，此时设备端 cleanup 可能来不及执行
     fn drop(&mut self) {
         if let Some(mut child) = self.server_process.take() {
             let _ = child.start_kill();
         }
     }
 }
+
+/// 编码器 H.264 profile（`--codec-profile`）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CodecProfile {
+    Baseline,
+    Main,
+    High,
+}
+
+impl CodecProfile {
+    /// Android MediaCodecInfo.CodecProfileLevel 中的取值
+    pub fn android_value(self) -> u32 {
+        match self {
+            CodecProfile::Baseline => 1,  // AVCProfileBaseline
+            CodecProfile::Main => 2,      // AVCProfileMain
+            CodecProfile::High => 8,      // AVCProfileHigh
+        }
+    }
+}
+
+/// 裁剪区域（`--crop`），坐标相对设备的自然方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct Crop {
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl Crop {
+    /// 检查裁剪区域是否在屏幕内（`screen` 为设备自然方向的尺寸）
+    pub fn check_bounds(&self, screen: (u32, u32)) -> std::result::Result<(), String> {
+        let (screen_width, screen_height) = screen;
+        if self.x as u64 + self.width as u64 > screen_width as u64 || self.y as u64 + self.height as u64 > screen_height as u64 {
+            return Err(format!("crop {} exceeds the {}x{} screen", self, screen_width, screen_height));
+        }
+        Ok(())
+    }
+}
+
+/// scrcpy 的 `crop` 参数格式：`WIDTH:HEIGHT:X:Y`
+impl std::fmt::Display for Crop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}:{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+/// 解析 `--crop` 参数：`WxH:X:Y`（也接受 scrcpy 的 `W:H:X:Y`）
+pub fn parse_crop(s: &str) -> std::result::Result<Crop, String> {
+    let parts: Vec<&str> = match s.split_once(['x', 'X']) {
+        Some((width, rest)) => std::iter::once(width).chain(rest.split(':')).collect(),
+        None => s.split(':').collect(),
+    };
+    let [width, height, x, y] = parts[..] else {
+        return Err(format!("expected WxH:X:Y, got '{}'", s));
+    };
+    let number = |value: &str, name: &str| value.trim().parse::<u32>().map_err(|_| format!("invalid {} in '{}'", name, s));
+    let crop = Crop { width: number(width, "width")?, height: number(height, "height")?, x: number(x, "x")?, y: number(y, "y")? };
+    if crop.width == 0 || crop.height == 0 {
+        return Err(format!("crop size must be non-zero, got '{}'", s));
+    }
+    Ok(crop)
+}
+
+/// 视频方向锁定（`--lock-orientation`），取值沿用 scrcpy 2.x 的 `--lock-video-orientation`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrientationLock {
+    Initial,    // 启动时的方向
+    Natural,    // 0：自然方向
+    Ccw90,      // 1：逆时针 90°
+    Rotate180,  // 2：180°
+    Cw90,       // 3：顺时针 90°
+}
+
+impl OrientationLock {
+    /// scrcpy 3.x 的 `capture_orientation` 取值（角度为顺时针）
+    pub fn capture_orientation(self) -> &'static str {
+        match self {
+            OrientationLock::Initial => "@",
+            OrientationLock::Natural => "@0",
+            OrientationLock::Ccw90 => "@270",
+            OrientationLock::Rotate180 => "@180",
+            OrientationLock::Cw90 => "@90",
+        }
+    }
+}
+
+/// 解析 `--lock-orientation` 参数
+pub fn parse_orientation_lock(s: &str) -> std::result::Result<OrientationLock, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "initial" => Ok(OrientationLock::Initial),
+        "0" | "natural" => Ok(OrientationLock::Natural),
+        "1" => Ok(OrientationLock::Ccw90),
+        "2" => Ok(OrientationLock::Rotate180),
+        "3" => Ok(OrientationLock::Cw90),
+        _ => Err(format!("unknown orientation '{}' (expected 0, 1, 2, 3, natural or initial)", s)),
+    }
+}
+
+/// scrcpy-server 的日志级别（`--server-log-level`）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerLogLevel {
+    Verbose,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl ServerLogLevel {
+    /// 启动参数 `log_level=` 的取值
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ServerLogLevel::Verbose => "verbose",
+            ServerLogLevel::Debug => "debug",
+            ServerLogLevel::Info => "info",
+            ServerLogLevel::Warn => "warn",
+            ServerLogLevel::Error => "error",
+        }
+    }
+
+    /// 与本程序日志级别对应的 server 日志级别（trace 对应 verbose）
+    pub fn from_level(level: tracing::Level) -> Self {
+        match level {
+            tracing::Level::TRACE => ServerLogLevel::Verbose,
+            tracing::Level::DEBUG => ServerLogLevel::Debug,
+            tracing::Level::INFO => ServerLogLevel::Info,
+            tracing::Level::WARN => ServerLogLevel::Warn,
+            tracing::Level::ERROR => ServerLogLevel::Error,
+        }
+    }
+}
+
+/// 解析 `--server-log-level` 参数
+pub fn parse_server_log_level(s: &str) -> std::result::Result<ServerLogLevel, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "verbose" => Ok(ServerLogLevel::Verbose),
+        "debug" => Ok(ServerLogLevel::Debug),
+        "info" => Ok(ServerLogLevel::Info),
+        "warn" => Ok(ServerLogLevel::Warn),
+        "error" => Ok(ServerLogLevel::Error),
+        _ => Err(format!("unknown server log level '{}' (expected verbose, debug, info, warn or error)", s)),
+    }
+}
+
+/// 解析 `--codec-profile` 参数
+pub fn parse_codec_profile(s: &str) -> std::result::Result<CodecProfile, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "baseline" => Ok(CodecProfile::Baseline),
+        "main" => Ok(CodecProfile::Main),
+        "high" => Ok(CodecProfile::High),
+        _ => Err(format!("unknown profile '{}' (expected baseline, main or high)", s)),
+    }
+}
+
+/// 生成 scrcpy 的 `video_codec_options` 参数值（逗号分隔的 key=value）
+///
+/// 注意：部分设备的编码器会忽略 profile 请求，实际 profile 以 SPS 为准。
+fn video_codec_options(intra_refresh_period: u32, profile: Option<CodecProfile>) -> String {
+    let mut options = format!("i-frame-interval={}", intra_refresh_period);
+    if let Some(profile) = profile {
+        options = format!("profile={},{}", profile.android_value(), options);
+    }
+    options
+}
+
+/// 校验 Java 主类名：只允许字母、数字、`_` 和 `.`（命令会经过设备 shell）
+pub fn parse_server_class(s: &str) -> std::result::Result<String, String> {
+    let valid = !s.is_empty()
+        && !s.starts_with('.')
+        && !s.ends_with('.')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if valid {
+        Ok(s.to_string())
+    } else {
+        Err(format!("invalid server class '{}'", s))
+    }
+}
+
+/// 编码器无法以当前码率启动时的下一个尝试码率（减半），低于 [`MIN_FALLBACK_BIT_RATE`] 时返回 None
+pub fn downgraded_bit_rate(bit_rate: u32) -> Option<u32> {
+    Some(bit_rate / 2).filter(|&next| next >= MIN_FALLBACK_BIT_RATE)
+}
+
+/// 解析并校验空格分隔的 `key=value` 参数列表
+///
+/// key 只允许字母、数字和 `_`；value 不能为空，也不能包含 shell 元字符或引号。
+pub fn parse_server_extra_args(s: &str) -> std::result::Result<Vec<String>, String> {
+    const FORBIDDEN: &[char] = &[';', '&', '|', '$', '`', '\\', '"', '\'', '<', '>', '(', ')', '*', '?', '#', '~', '!'];
+
+    s.split_whitespace()
+        .map(|pair| {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("'{}' is not a key=value pair", pair))?;
+            if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(format!("invalid key in '{}'", pair));
+            }
+            if value.is_empty() || value.contains(FORBIDDEN) {
+                return Err(format!("invalid value in '{}'", pair));
+            }
+            Ok(pair.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_video_codec_options() {
+        assert_eq!(video_codec_options(1, None), "i-frame-interval=1");
+        assert_eq!(video_codec_options(2, Some(CodecProfile::Baseline)), "profile=1,i-frame-interval=2");
+        assert_eq!(parse_codec_profile("High"), Ok(CodecProfile::High));
+        assert!(parse_codec_profile("extended").is_err());
+    }
+
+    #[test]
+    fn test_downgraded_bit_rate() {
+        let mut steps = vec![16_000_000];
+        while let Some(next) = downgraded_bit_rate(*steps.last().unwrap()) {
+            steps.push(next);
+        }
+        assert_eq!(steps, vec![16_000_000, 8_000_000, 4_000_000, 2_000_000, 1_000_000]);
+        assert_eq!(downgraded_bit_rate(1_500_000), None);
+    }
+
+    #[test]
+    fn test_server_log_level() {
+        assert_eq!(parse_server_log_level("VERBOSE").unwrap(), ServerLogLevel::Verbose);
+        assert!(parse_server_log_level("trace").is_err());
+        assert_eq!(ServerLogLevel::from_level(tracing::Level::TRACE), ServerLogLevel::Verbose);
+        assert_eq!(ServerLogLevel::from_level(tracing::Level::WARN).as_str(), "warn");
+
+        let adb = AdbClient::new(PathBuf::from("adb"));
+        let mut server = ScrcpyServer::new(adb, "emulator-5554".to_string(), PathBuf::from("scrcpy-server")).unwrap();
+        assert!(server.server_command().contains(" log_level=info "));
+        server.set_log_level(ServerLogLevel::Verbose);
+        assert!(server.server_command().contains(" log_level=verbose "));
+    }
+
+    #[test]
+    fn test_server_command_power_on() {
+        let adb = AdbClient::new(PathBuf::from("adb"));
+        let mut server = ScrcpyServer::new(adb, "emulator-5554".to_string(), PathBuf::from("scrcpy-server")).unwrap();
+        assert!(!server.server_command().contains("power_on"));
+
+        server.set_power_on(false);
+        server.set_extra_args(vec!["log_level=debug".to_string()]);
+        assert!(server.server_command().ends_with("cleanup=true power_on=false log_level=debug"));
+    }
+
+    #[test]
+    fn test_server_command_display_id() {
+        let adb = AdbClient::new(PathBuf::from("adb"));
+        let mut server = ScrcpyServer::new(adb, "emulator-5554".to_string(), PathBuf::from("scrcpy-server")).unwrap();
+        assert!(!server.server_command().contains("display_id"));
+
+        server.set_display_id(Some(2));
+        assert!(server.server_command().ends_with("cleanup=true display_id=2"));
+
+        // 多显示屏会话各自使用 scrcpy_<scid> 套接字
+        assert_eq!(server.socket_name(), "scrcpy");
+        server.set_scid(Some(2));
+        assert!(server.server_command().ends_with("display_id=2 scid=00000002"));
+        assert_eq!(server.socket_name(), "scrcpy_00000002");
+    }
+
+    #[test]
+    fn test_parse_crop() {
+        let crop = parse_crop("1080x1200:0:600").unwrap();
+        assert_eq!(crop, Crop { width: 1080, height: 1200, x: 0, y: 600 });
+        assert_eq!(parse_crop("1080:1200:0:600"), Ok(crop));
+        assert_eq!(crop.to_string(), "1080:1200:0:600");
+        for invalid in ["1080x1200", "1080x1200:0", "0x1200:0:0", "1080x1200:-1:0", "axb:0:0", "1080x1200:0:0:0"] {
+            assert!(parse_crop(invalid).is_err(), "{}", invalid);
+        }
+
+        assert!(crop.check_bounds((1080, 2400)).is_ok());
+        assert!(crop.check_bounds((1080, 1700)).is_err());
+        assert!(parse_crop("1081x100:0:0").unwrap().check_bounds((1080, 2400)).is_err());
+
+        let adb = AdbClient::new(PathBuf::from("adb"));
+        let mut server = ScrcpyServer::new(adb, "emulator-5554".to_string(), PathBuf::from("scrcpy-server")).unwrap();
+        assert!(!server.server_command().contains("crop="));
+        server.set_crop(Some(crop));
+        assert!(server.server_command().ends_with("cleanup=true crop=1080:1200:0:600"));
+    }
+
+    #[test]
+    fn test_server_command_orientation_lock() {
+        let adb = AdbClient::new(PathBuf::from("adb"));
+        let mut server = ScrcpyServer::new(adb, "emulator-5554".to_string(), PathBuf::from("scrcpy-server")).unwrap();
+        assert!(!server.server_command().contains("orientation"));
+
+        server.set_orientation_lock(Some(parse_orientation_lock("natural").unwrap()));
+        assert!(server.server_command().ends_with("cleanup=true capture_orientation=@0"));
+        let values: Vec<_> = ["1", "2", "3", "Initial"]
+            .iter()
+            .map(|s| parse_orientation_lock(s).unwrap().capture_orientation())
+            .collect();
+        assert_eq!(values, ["@270", "@180", "@90", "@"]);
+        assert!(parse_orientation_lock("4").is_err());
+    }
+
+    #[test]
+    fn test_server_command_frame_meta() {
+        let adb = AdbClient::new(PathBuf::from("adb"));
+        let mut server = ScrcpyServer::new(adb, "emulator-5554".to_string(), PathBuf::from("scrcpy-server")).unwrap();
+        let command = server.server_command();
+        assert!(command.contains("send_frame_meta=false") && command.contains("raw_stream=true"));
+
+        server.set_frame_meta(true);
+        let command = server.server_command();
+        assert!(command.contains("send_frame_meta=true") && command.contains("raw_stream=false"));
+    }
+
+    #[test]
+    fn test_server_command_audio_dup() {
+        let adb = AdbClient::new(PathBuf::from("adb"));
+        let mut server = ScrcpyServer::new(adb, "emulator-5554".to_string(), PathBuf::from("scrcpy-server")).unwrap();
+        assert!(!server.server_command().contains("audio_dup"));
+
+        server.set_audio_dup(true);
+        assert!(server.server_command().ends_with("cleanup=true audio_source=playback audio_dup=true"));
+    }
+
+    #[test]
+    fn test_server_command_audio() {
+        let adb = AdbClient::new(PathBuf::from("adb"));
+        let mut server = ScrcpyServer::new(adb, "emulator-5554".to_string(), PathBuf::from("scrcpy-server")).unwrap();
+        assert!(server.server_command().contains(" audio=false "));
+        assert_eq!(server.get_actual_audio_port(), None);
+
+        server.set_audio(true).unwrap();
+        let command = server.server_command();
+        assert!(command.contains(" audio=true ") && command.contains("cleanup=true audio_codec=opus"));
+        // Opus 需要帧头，视频流也随之带帧头
+        assert!(command.contains("send_frame_meta=true") && command.contains("raw_stream=false"));
+        assert!(server.stream_frame_meta());
+        // 第三个端口与视频、控制端口不同
+        let audio_port = server.get_actual_audio_port().unwrap();
+        assert!(audio_port != server.get_actual_video_port() && audio_port != server.get_actual_control_port());
+    }
+
+    #[test]
+    fn test_server_command_time_limit() {
+        let adb = AdbClient::new(PathBuf::from("adb"));
+        let mut server = ScrcpyServer::new(adb, "emulator-5554".to_string(), PathBuf::from("scrcpy-server")).unwrap();
+        assert!(!server.server_command().contains("timeout"));
+        assert!(!server.time_limit_reached());
+
+        server.set_time_limit(Some(std::time::Duration::from_secs(600)));
+        assert!(server.server_command().starts_with(&format!("CLASSPATH={} timeout 600 app_process / ", DEVICE_SERVER_PATH)));
+        assert!(!server.time_limit_reached());
+
+        // 到期后重启至少传 1 秒
+        server.set_time_limit(Some(std::time::Duration::ZERO));
+        assert!(server.server_command().contains(" timeout 1 app_process "));
+        assert!(server.time_limit_reached());
+    }
+
+    #[test]
+    fn test_parse_server_extra_args() {
+        assert_eq!(
+            parse_server_extra_args("video_codec=h265  log_level=debug").unwrap(),
+            vec!["video_codec=h265".to_string(), "log_level=debug".to_string()]
+        );
+        assert!(parse_server_extra_args("").unwrap().is_empty());
+        assert!(parse_server_extra_args("log_level").is_err());
+        assert!(parse_server_extra_args("=debug").is_err());
+        assert!(parse_server_extra_args("log_level=").is_err());
+        assert!(parse_server_extra_args("a=1;reboot").is_err());
+        assert!(parse_server_extra_args("a-b=1").is_err());
+    }
+
+    #[test]
+    fn test_parse_server_class() {
+        assert!(parse_server_class("com.example.MyServer").is_ok());
+        assert!(parse_server_class("com.example.My$Server").is_err());
+        assert!(parse_server_class(".Server").is_err());
+        assert!(parse_server_class("").is_err());
+    }
+}
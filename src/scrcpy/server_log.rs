@@ -0,0 +1,157 @@
+// Server 日志解析模块 - 从 scrcpy-server 的输出中提取设备实际选择的编码参数
+//
+// scrcpy 的日志格式为 `[server] LEVEL: message`，info 及以下写到 stdout，warn/error 写到 stderr，
+// 两个读取任务都会把每一行交给 `ServerLogStats::parse_line`。
+// 只做宽松匹配（忽略大小写和级别前缀），不认识的行直接忽略，scrcpy 调整措辞时最多丢失某个字段。
+
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+
+/// 从 server 日志中解析出的信息（`/stats` 的 `encoder` 字段）
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ServerLogInfo {
+    pub device: Option<String>,         // "Device: [Google] google Pixel 7 (Android 14)"
+    pub encoder: Option<String>,        // "Using video encoder: 'c2.android.avc.encoder'"
+    pub display: Option<String>,        // "Display: using DisplayManager API"
+    pub video_size: Option<(u32, u32)>, // 日志中出现的实际视频尺寸
+    pub bit_rate: Option<u64>,          // 日志中出现的实际码率（bps）
+    pub max_size_retry: Option<u32>,    // 编码失败后 scrcpy 自动降低的 max_size（"Retrying with -m1920..."）
+    pub encoder_error: Option<String>,  // 最近一次启动的编码错误（"Encoding error: ..." / "Capture/encoding error: ..."）
+}
+
+/// 解析结果的共享句柄（日志读取任务更新，/stats 和启动日志读取）
+#[derive(Debug, Clone, Default)]
+pub struct ServerLogStats(Arc<RwLock<ServerLogInfo>>);
+
+impl ServerLogStats {
+    pub fn snapshot(&self) -> ServerLogInfo {
+        self.0.read().map(|info| info.clone()).unwrap_or_default()
+    }
+
+    /// 解析一行 server 输出，返回是否识别
+    pub fn parse_line(&self, line: &str) -> bool {
+        let Ok(mut info) = self.0.write() else {
+            return false;
+        };
+        parse_line_into(&mut info, line)
+    }
+
+    /// 清除上一次启动的编码错误（每次启动 server 前调用）
+    pub fn clear_encoder_error(&self) {
+        if let Ok(mut info) = self.0.write() {
+            info.encoder_error = None;
+        }
+    }
+}
+
+fn parse_line_into(info: &mut ServerLogInfo, line: &str) -> bool {
+    let message = strip_level(line.trim());
+    let lower = message.to_ascii_lowercase();
+
+    // 只认 scrcpy 编码循环报错的前缀，编码器名称或其他消息中出现 "error" 不算
+    if lower.starts_with("encoding error:") || lower.starts_with("capture/encoding error:") {
+        info.encoder_error = Some(message.to_string());
+    } else if let Some(device) = strip_prefix_ci(message, "device:") {
+        info.device = Some(device.trim().to_string());
+    } else if lower.contains("encoder") && (lower.starts_with("using") || lower.contains("selected")) {
+        let Some(name) = quoted(message) else {
+            return false;
+        };
+        info.encoder = Some(name.to_string());
+    } else if let Some(display) = strip_prefix_ci(message, "display:") {
+        info.display = Some(display.trim().to_string());
+    } else if lower.starts_with("retrying with -m") {
+        let Some(size) = leading_number(&message["retrying with -m".len()..]) else {
+            return false;
+        };
+        info.max_size_retry = Some(size as u32);
+    } else if lower.contains("bit rate") || lower.contains("bitrate") || lower.contains("bit-rate") {
+        let Some(bit_rate) = message.split(|c: char| !c.is_ascii_digit()).find(|s| !s.is_empty()).and_then(|s| s.parse().ok()) else {
+            return false;
+        };
+        info.bit_rate = Some(bit_rate);
+    } else if lower.contains("size") || lower.contains("resolution") {
+        let Some(size) = message.split(|c: char| !(c.is_ascii_digit() || c == 'x')).find_map(parse_size) else {
+            return false;
+        };
+        info.video_size = Some(size);
+    } else {
+        return false;
+    }
+    true
+}
+
+/// 去掉 `[server] INFO: ` 之类的前缀
+fn strip_level(line: &str) -> &str {
+    let line = line.strip_prefix("[server]").map_or(line, str::trim_start);
+    for level in ["VERBOSE:", "DEBUG:", "INFO:", "WARN:", "ERROR:"] {
+        if let Some(rest) = line.strip_prefix(level) {
+            return rest.trim_start();
+        }
+    }
+    line
+}
+
+fn strip_prefix_ci<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = s.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix).then(|| &s[prefix.len()..])
+}
+
+/// 第一个单引号或双引号中的内容
+fn quoted(s: &str) -> Option<&str> {
+    let start = s.find(['\'', '"'])?;
+    let quote = s[start..].chars().next()?;
+    let rest = &s[start + 1..];
+    let end = rest.find(quote)?;
+    Some(&rest[..end]).filter(|name| !name.is_empty())
+}
+
+fn leading_number(s: &str) -> Option<u64> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s[..end].parse().ok()
+}
+
+/// `1080x2400`
+fn parse_size(s: &str) -> Option<(u32, u32)> {
+    let (w, h) = s.split_once('x')?;
+    Some((w.parse().ok()?, h.parse().ok()?)).filter(|&(w, h)| w > 0 && h > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_server_log() {
+        let stats = ServerLogStats::default();
+        assert!(stats.parse_line("[server] INFO: Device: [Google] google Pixel 7 (Android 14)\n"));
+        assert!(stats.parse_line("[server] DEBUG: Using video encoder: 'c2.exynos.h264.encoder'"));
+        assert!(stats.parse_line("[server] INFO: Display: using DisplayManager API"));
+        assert!(stats.parse_line("[server] INFO: Retrying with -m1600..."));
+        assert!(stats.parse_line("INFO: Video size: 1080x2400"));
+        assert!(stats.parse_line("[server] INFO: Video bit rate: 8000000 bps"));
+        assert!(stats.parse_line("[server] ERROR: Encoding error: android.media.MediaCodec$CodecException: Error 0xfffffc0e"));
+
+        // 不认识的行、缺少关键内容的行忽略，不覆盖已解析的值
+        assert!(!stats.parse_line("[server] WARN: Audio disabled: it is only supported on Android 11+"));
+        assert!(!stats.parse_line("[server] INFO: Using encoder"));
+        assert!(!stats.parse_line("[server] WARN: Audio encoding error recovered, encoder exception ignored"));
+        assert!(!stats.parse_line("[server] INFO: Video encoder configured, no error"));
+        assert!(!stats.parse_line(""));
+
+        assert_eq!(stats.snapshot(), ServerLogInfo {
+            device: Some("[Google] google Pixel 7 (Android 14)".to_string()),
+            encoder: Some("c2.exynos.h264.encoder".to_string()),
+            display: Some("using DisplayManager API".to_string()),
+            video_size: Some((1080, 2400)),
+            bit_rate: Some(8_000_000),
+            max_size_retry: Some(1600),
+            encoder_error: Some("Encoding error: android.media.MediaCodec$CodecException: Error 0xfffffc0e".to_string()),
+        });
+        stats.clear_encoder_error();
+        assert_eq!(stats.snapshot().encoder_error, None);
+
+        assert!(stats.parse_line("[server] ERROR: Capture/encoding error: java.lang.IllegalStateException"));
+        assert_eq!(stats.snapshot().encoder_error.as_deref(), Some("Capture/encoding error: java.lang.IllegalStateException"));
+    }
+}
@@ -0,0 +1,97 @@
+//! 与 scrcpy-server 通信的底层连接
+//!
+//! 默认通过 `adb forward tcp:PORT` 走 TCP 回环；在 Unix 上也可以使用
+//! `adb forward localfilesystem:PATH` 转发到 Unix 域套接字，开销更小，
+//! 也适用于限制回环 TCP 的沙箱环境。
+
+use std::io;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+/// 视频流/控制流连接
+pub enum DeviceStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// 某个通道的本地端点，可以重复连接（控制流断开后重连使用）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceEndpoint {
+    Tcp(u16),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl DeviceEndpoint {
+    pub async fn connect(&self) -> io::Result<DeviceStream> {
+        match self {
+            DeviceEndpoint::Tcp(port) => TcpStream::connect(("127.0.0.1", *port)).await.map(DeviceStream::from),
+            #[cfg(unix)]
+            DeviceEndpoint::Unix(path) => UnixStream::connect(path).await.map(DeviceStream::from),
+        }
+    }
+}
+
+impl From<TcpStream> for DeviceStream {
+    fn from(stream: TcpStream) -> Self {
+        DeviceStream::Tcp(stream)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for DeviceStream {
+    fn from(stream: UnixStream) -> Self {
+        DeviceStream::Unix(stream)
+    }
+}
+
+impl AsyncRead for DeviceStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            DeviceStream::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(unix)]
+            DeviceStream::Unix(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for DeviceStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            DeviceStream::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(unix)]
+            DeviceStream::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            DeviceStream::Tcp(s) => Pin::new(s).poll_flush(cx),
+            #[cfg(unix)]
+            DeviceStream::Unix(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            DeviceStream::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            #[cfg(unix)]
+            DeviceStream::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
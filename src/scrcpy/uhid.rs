@@ -0,0 +1,341 @@
+// UHID 模块 - 在设备上创建虚拟 HID 设备（scrcpy 3.x UHID_CREATE / UHID_INPUT / UHID_DESTROY）
+//
+// 相对鼠标：网页通过 Pointer Lock API 采集鼠标位移，服务端转换成 HID 鼠标报告，
+// 设备把它当作真实的 USB 鼠标处理（FPS 游戏的视角控制需要相对位移，触摸模拟做不到）。
+// 报告描述符与 scrcpy 的 hid_mouse.c 相同：[buttons:1][x:i8][y:i8][wheel:i8][hwheel:i8]
+//
+// 键盘（`--uhid`）：网页按物理键位（`KeyboardEvent.code`）发送按下/抬起，服务端维护按键状态并生成
+// 标准的 8 字节启动键盘报告 [modifiers][reserved][key1..key6]，修饰键和长按由设备按真实键盘处理。
+
+use crate::scrcpy::control::ControlMessageType;
+
+/// UHID 键盘的设备 id
+pub const UHID_KEYBOARD_ID: u16 = 1;
+/// UHID 鼠标的设备 id（与 scrcpy 一致：1 为键盘，2 为鼠标）
+pub const UHID_MOUSE_ID: u16 = 2;
+/// UHID 设备名称（scrcpy 限制为 127 字节）
+pub const UHID_KEYBOARD_NAME: &str = "rust-scrcpy keyboard";
+pub const UHID_MOUSE_NAME: &str = "rust-scrcpy mouse";
+
+/// 键盘报告描述符（与 scrcpy 的 hid_keyboard.c 相同）：8 个修饰键位 + 保留字节 + 6 个按键，
+/// 以及 5 位 LED 输出报告（设备通过 UHID_OUTPUT 发回 Num/Caps/Scroll Lock 状态）
+pub const KEYBOARD_REPORT_DESC: &[u8] = &[
+    0x05, 0x01,        // Usage Page (Generic Desktop)
+    0x09, 0x06,        // Usage (Keyboard)
+    0xA1, 0x01,        // Collection (Application)
+    0x05, 0x07,        //   Usage Page (Key Codes)
+    0x19, 0xE0,        //   Usage Minimum (224)
+    0x29, 0xE7,        //   Usage Maximum (231)
+    0x15, 0x00,        //   Logical Minimum (0)
+    0x25, 0x01,        //   Logical Maximum (1)
+    0x75, 0x01,        //   Report Size (1)
+    0x95, 0x08,        //   Report Count (8)
+    0x81, 0x02,        //   Input (Data, Variable, Absolute)：修饰键
+    0x75, 0x08,        //   Report Size (8)
+    0x95, 0x01,        //   Report Count (1)
+    0x81, 0x01,        //   Input (Constant)：保留字节
+    0x05, 0x08,        //   Usage Page (LEDs)
+    0x19, 0x01,        //   Usage Minimum (1)
+    0x29, 0x05,        //   Usage Maximum (5)
+    0x75, 0x01,        //   Report Size (1)
+    0x95, 0x05,        //   Report Count (5)
+    0x91, 0x02,        //   Output (Data, Variable, Absolute)：LED
+    0x75, 0x03,        //   Report Size (3)
+    0x95, 0x01,        //   Report Count (1)
+    0x91, 0x01,        //   Output (Constant)：填充到 1 字节
+    0x05, 0x07,        //   Usage Page (Key Codes)
+    0x19, 0x00,        //   Usage Minimum (0)
+    0x29, 0x65,        //   Usage Maximum (101)
+    0x15, 0x00,        //   Logical Minimum (0)
+    0x25, 0x65,        //   Logical Maximum (101)
+    0x75, 0x08,        //   Report Size (8)
+    0x95, 0x06,        //   Report Count (6)
+    0x81, 0x00,        //   Input (Data, Array, Absolute)：按下的按键
+    0xC0,              // End Collection
+];
+
+/// 同时按下的普通按键上限（启动键盘报告的 6 个按键槽）
+const MAX_PRESSED_KEYS: usize = 6;
+
+/// 鼠标报告描述符：5 个按键 + X/Y/滚轮/水平滚轮（均为 -127 ~ 127 的相对值）
+pub const MOUSE_REPORT_DESC: &[u8] = &[
+    0x05, 0x01,        // Usage Page (Generic Desktop)
+    0x09, 0x02,        // Usage (Mouse)
+    0xA1, 0x01,        // Collection (Application)
+    0x09, 0x01,        //   Usage (Pointer)
+    0xA1, 0x00,        //   Collection (Physical)
+    0x05, 0x09,        //     Usage Page (Buttons)
+    0x19, 0x01,        //     Usage Minimum (1)
+    0x29, 0x05,        //     Usage Maximum (5)
+    0x15, 0x00,        //     Logical Minimum (0)
+    0x25, 0x01,        //     Logical Maximum (1)
+    0x95, 0x05,        //     Report Count (5)
+    0x75, 0x01,        //     Report Size (1)
+    0x81, 0x02,        //     Input (Data, Variable, Absolute)
+    0x95, 0x01,        //     Report Count (1)
+    0x75, 0x03,        //     Report Size (3)
+    0x81, 0x01,        //     Input (Constant)：填充到 1 字节
+    0x05, 0x01,        //     Usage Page (Generic Desktop)
+    0x09, 0x30,        //     Usage (X)
+    0x09, 0x31,        //     Usage (Y)
+    0x09, 0x38,        //     Usage (Wheel)
+    0x15, 0x81,        //     Logical Minimum (-127)
+    0x25, 0x7F,        //     Logical Maximum (127)
+    0x75, 0x08,        //     Report Size (8)
+    0x95, 0x03,        //     Report Count (3)
+    0x81, 0x06,        //     Input (Data, Variable, Relative)
+    0x05, 0x0C,        //     Usage Page (Consumer)
+    0x0A, 0x38, 0x02,  //     Usage (AC Pan)
+    0x15, 0x81,        //     Logical Minimum (-127)
+    0x25, 0x7F,        //     Logical Maximum (127)
+    0x75, 0x08,        //     Report Size (8)
+    0x95, 0x01,        //     Report Count (1)
+    0x81, 0x06,        //     Input (Data, Variable, Relative)
+    0xC0,              //   End Collection
+    0xC0,              // End Collection
+];
+
+/// 单个报告中位移/滚轮的最大绝对值
+const MAX_REPORT_DELTA: i32 = 127;
+/// 一个事件最多拆成的报告数（防止客户端发送极大的位移）
+const MAX_REPORTS_PER_EVENT: usize = 64;
+
+/// UHID_CREATE：[type][id:2][vendor_id:2][product_id:2][name_len:1][name][desc_size:2][desc]
+pub fn uhid_create_message(id: u16, name: &str, report_desc: &[u8]) -> Vec<u8> {
+    let name = &name.as_bytes()[..name.len().min(127)];
+    let mut msg = Vec::with_capacity(10 + name.len() + report_desc.len());
+    msg.push(ControlMessageType::UhidCreate as u8);
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&0u16.to_be_bytes());  // vendor_id
+    msg.extend_from_slice(&0u16.to_be_bytes());  // product_id
+    msg.push(name.len() as u8);
+    msg.extend_from_slice(name);
+    msg.extend_from_slice(&(report_desc.len() as u16).to_be_bytes());
+    msg.extend_from_slice(report_desc);
+    msg
+}
+
+/// UHID_INPUT：[type][id:2][size:2][data]
+pub fn uhid_input_message(id: u16, report: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(5 + report.len());
+    msg.push(ControlMessageType::UhidInput as u8);
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&(report.len() as u16).to_be_bytes());
+    msg.extend_from_slice(report);
+    msg
+}
+
+/// UHID_DESTROY：[type][id:2]
+pub fn uhid_destroy_message(id: u16) -> Vec<u8> {
+    let mut msg = vec![ControlMessageType::UhidDestroy as u8];
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg
+}
+
+/// 把一次相对移动转换成鼠标报告
+///
+/// 单个报告的位移限制在 ±127，更大的位移拆成多个报告（按键状态相同），
+/// 滚轮只放在第一个报告中。`buttons` 与 DOM `MouseEvent.buttons` 的位定义相同：
+/// bit0 左键、bit1 右键、bit2 中键、bit3 后退、bit4 前进。
+pub fn mouse_reports(dx: i32, dy: i32, wheel: i32, hwheel: i32, buttons: u8) -> Vec<[u8; 5]> {
+    let clamp = |v: i32| v.clamp(-MAX_REPORT_DELTA, MAX_REPORT_DELTA);
+    let (mut dx, mut dy) = (dx, dy);
+    let mut reports = vec![[buttons & 0x1F, clamp(dx) as i8 as u8, clamp(dy) as i8 as u8, clamp(wheel) as i8 as u8, clamp(hwheel) as i8 as u8]];
+    dx -= clamp(dx);
+    dy -= clamp(dy);
+    while (dx != 0 || dy != 0) && reports.len() < MAX_REPORTS_PER_EVENT {
+        reports.push([buttons & 0x1F, clamp(dx) as i8 as u8, clamp(dy) as i8 as u8, 0, 0]);
+        dx -= clamp(dx);
+        dy -= clamp(dy);
+    }
+    reports
+}
+
+/// `KeyboardEvent.code` 对应的 HID 键盘用法（Usage Page 0x07），不支持的键返回 None
+pub fn keyboard_usage(code: &str) -> Option<u8> {
+    if let Some(letter) = code.strip_prefix("Key") {
+        if let [c @ b'A'..=b'Z'] = letter.as_bytes() {
+            return Some(0x04 + (c - b'A'));
+        }
+    }
+    if let Some(digit) = code.strip_prefix("Digit") {
+        return match digit.as_bytes() {
+            [b'0'] => Some(0x27),
+            [c @ b'1'..=b'9'] => Some(0x1E + (c - b'1')),
+            _ => None,
+        };
+    }
+    if let Some(n) = code.strip_prefix('F').and_then(|n| n.parse::<u8>().ok()) {
+        return (1..=12).contains(&n).then(|| 0x3A + n - 1);
+    }
+    if let Some(digit) = code.strip_prefix("Numpad") {
+        match digit.as_bytes() {
+            [b'0'] => return Some(0x62),
+            [c @ b'1'..=b'9'] => return Some(0x59 + (c - b'1')),
+            _ => {}
+        }
+    }
+    let usage = match code {
+        "Enter" => 0x28,
+        "Escape" => 0x29,
+        "Backspace" => 0x2A,
+        "Tab" => 0x2B,
+        "Space" => 0x2C,
+        "Minus" => 0x2D,
+        "Equal" => 0x2E,
+        "BracketLeft" => 0x2F,
+        "BracketRight" => 0x30,
+        "Backslash" => 0x31,
+        "Semicolon" => 0x33,
+        "Quote" => 0x34,
+        "Backquote" => 0x35,
+        "Comma" => 0x36,
+        "Period" => 0x37,
+        "Slash" => 0x38,
+        "CapsLock" => 0x39,
+        "PrintScreen" => 0x46,
+        "ScrollLock" => 0x47,
+        "Pause" => 0x48,
+        "Insert" => 0x49,
+        "Home" => 0x4A,
+        "PageUp" => 0x4B,
+        "Delete" => 0x4C,
+        "End" => 0x4D,
+        "PageDown" => 0x4E,
+        "ArrowRight" => 0x4F,
+        "ArrowLeft" => 0x50,
+        "ArrowDown" => 0x51,
+        "ArrowUp" => 0x52,
+        "NumLock" => 0x53,
+        "NumpadDivide" => 0x54,
+        "NumpadMultiply" => 0x55,
+        "NumpadSubtract" => 0x56,
+        "NumpadAdd" => 0x57,
+        "NumpadEnter" => 0x58,
+        "NumpadDecimal" => 0x63,
+        "IntlBackslash" => 0x64,
+        "ContextMenu" => 0x65,
+        "ControlLeft" => 0xE0,
+        "ShiftLeft" => 0xE1,
+        "AltLeft" => 0xE2,
+        "MetaLeft" => 0xE3,
+        "ControlRight" => 0xE4,
+        "ShiftRight" => 0xE5,
+        "AltRight" => 0xE6,
+        "MetaRight" => 0xE7,
+        _ => return None,
+    };
+    Some(usage)
+}
+
+/// UHID 键盘的按键状态，每次按下/抬起生成一个完整的键盘报告
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HidKeyboard {
+    modifiers: u8,       // bit0~7：左 Ctrl/Shift/Alt/Meta、右 Ctrl/Shift/Alt/Meta
+    keys: Vec<u8>,       // 按下顺序排列的普通按键（最多 6 个）
+}
+
+impl HidKeyboard {
+    /// 更新按键状态，状态变化时返回新的报告
+    ///
+    /// 浏览器的自动重复（重复的按下）和未按下的抬起不产生报告，长按的重复由设备自己完成；
+    /// 已有 6 个普通按键按下时忽略新的按键。
+    pub fn apply(&mut self, usage: u8, down: bool) -> Option<[u8; 8]> {
+        if (0xE0..=0xE7).contains(&usage) {
+            let bit = 1 << (usage - 0xE0);
+            let modifiers = if down { self.modifiers | bit } else { self.modifiers & !bit };
+            if modifiers == self.modifiers {
+                return None;
+            }
+            self.modifiers = modifiers;
+        } else if down {
+            if self.keys.contains(&usage) || self.keys.len() >= MAX_PRESSED_KEYS {
+                return None;
+            }
+            self.keys.push(usage);
+        } else {
+            let index = self.keys.iter().position(|&key| key == usage)?;
+            self.keys.remove(index);
+        }
+        Some(self.report())
+    }
+
+    /// 抬起所有按键（网页失去焦点时），已经全部抬起时返回 None
+    pub fn release_all(&mut self) -> Option<[u8; 8]> {
+        if self.modifiers == 0 && self.keys.is_empty() {
+            return None;
+        }
+        *self = Self::default();
+        Some(self.report())
+    }
+
+    fn report(&self) -> [u8; 8] {
+        let mut report = [0u8; 8];
+        report[0] = self.modifiers;
+        report[2..2 + self.keys.len()].copy_from_slice(&self.keys);
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uhid_messages_and_mouse_reports() {
+        let create = uhid_create_message(UHID_MOUSE_ID, "m", &[0xAA, 0xBB]);
+        assert_eq!(create, vec![12, 0, 2, 0, 0, 0, 0, 1, b'm', 0, 2, 0xAA, 0xBB]);
+        assert_eq!(uhid_input_message(UHID_MOUSE_ID, &[1, 2]), vec![13, 0, 2, 0, 2, 1, 2]);
+        assert_eq!(uhid_destroy_message(UHID_MOUSE_ID), vec![14, 0, 2]);
+
+        // 小位移一个报告，负数按补码
+        assert_eq!(mouse_reports(5, -3, 1, 0, 0b001), vec![[1, 5, 0xFD, 1, 0]]);
+
+        // 大位移拆分，位移总和不变，滚轮只在第一个报告中
+        let reports = mouse_reports(300, -20, -1, 0, 0b010);
+        assert_eq!(reports, vec![[2, 127, 0xEC, 0xFF, 0], [2, 127, 0, 0, 0], [2, 46, 0, 0, 0]]);
+
+        // 拆分数量有上限
+        assert_eq!(mouse_reports(i32::MAX, 0, 0, 0, 0).len(), MAX_REPORTS_PER_EVENT);
+    }
+
+    #[test]
+    fn test_keyboard_usage() {
+        assert_eq!(keyboard_usage("KeyA"), Some(0x04));
+        assert_eq!(keyboard_usage("KeyZ"), Some(0x1D));
+        assert_eq!(keyboard_usage("Digit1"), Some(0x1E));
+        assert_eq!(keyboard_usage("Digit0"), Some(0x27));
+        assert_eq!(keyboard_usage("F1"), Some(0x3A));
+        assert_eq!(keyboard_usage("F12"), Some(0x45));
+        assert_eq!(keyboard_usage("Numpad1"), Some(0x59));
+        assert_eq!(keyboard_usage("Numpad0"), Some(0x62));
+        assert_eq!(keyboard_usage("NumpadEnter"), Some(0x58));
+        assert_eq!(keyboard_usage("ShiftRight"), Some(0xE5));
+        for unknown in ["F13", "Keyaa", "Digit10", "Fn", ""] {
+            assert_eq!(keyboard_usage(unknown), None, "{}", unknown);
+        }
+    }
+
+    #[test]
+    fn test_hid_keyboard_reports() {
+        let mut keyboard = HidKeyboard::default();
+        // Ctrl + Shift + A
+        assert_eq!(keyboard.apply(0xE0, true), Some([0x01, 0, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(keyboard.apply(0xE1, true), Some([0x03, 0, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(keyboard.apply(0x04, true), Some([0x03, 0, 0x04, 0, 0, 0, 0, 0]));
+        // 浏览器自动重复不产生报告
+        assert_eq!(keyboard.apply(0x04, true), None);
+        assert_eq!(keyboard.apply(0x05, true), Some([0x03, 0, 0x04, 0x05, 0, 0, 0, 0]));
+        assert_eq!(keyboard.apply(0x04, false), Some([0x03, 0, 0x05, 0, 0, 0, 0, 0]));
+        assert_eq!(keyboard.apply(0x04, false), None);
+
+        // 最多 6 个普通按键
+        for usage in 0x06..0x0B {
+            assert!(keyboard.apply(usage, true).is_some());
+        }
+        assert_eq!(keyboard.apply(0x0B, true), None);
+
+        assert_eq!(keyboard.release_all(), Some([0; 8]));
+        assert_eq!(keyboard.release_all(), None);
+    }
+}
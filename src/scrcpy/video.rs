@@ -1,8 +1,9 @@
 use crate::error::{Result, ScrcpyError};
-use bytes::{Bytes, BytesMut};
-use tokio::io::AsyncReadExt;
-use tokio::net::TcpStream;
-use tracing::{debug, info, warn};
+use bytes::{Buf, Bytes, BytesMut};
+use std::collections::VecDeque;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use crate::scrcpy::stream::DeviceStream;
+use tracing::{debug, warn};
 
 /// 视频帧类型
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -42,109 +43,326 @@ impl VideoFrame {
     }
 }
 
+/// NAL 头中的 nal_ref_idc（高 3 位中的第 2、3 位）
+pub fn nal_ref_idc(header: u8) -> u8 {
+    (header >> 5) & 0x03
+}
+
+/// 是否为可丢弃的 NAL 单元：nal_ref_idc == 0 的普通切片（类型 1）
+///
+/// 这类帧不会被后续帧参考，丢弃后解码端不会花屏。
+/// IDR 和参数集的 nal_ref_idc 总是非 0，不会被误判。
+pub fn is_discardable_nal(header: u8) -> bool {
+    header & 0x1F == 1 && nal_ref_idc(header) == 0
+}
+
+/// 默认每次从 socket 读取的字节数
+pub const DEFAULT_READ_BUFFER_BYTES: usize = 64 * 1024;
+/// 读取块大小下限：更小的值每个帧都要多次系统调用，CPU 开销明显上升
+pub const MIN_READ_BUFFER_BYTES: usize = 4 * 1024;
+/// 读取块大小上限
+pub const MAX_READ_BUFFER_BYTES: usize = 8 * 1024 * 1024;
+
+/// 缓冲区超过该大小仍找不到下一个起始码时视为流损坏，丢弃重新同步
+const MAX_PENDING_BYTES: usize = 10 * 1024 * 1024;
+
+/// scrcpy 帧头长度（`--frame-meta`）：8 字节 PTS 和标志位 + 4 字节数据包长度，均为大端
+pub const FRAME_HEADER_LEN: usize = 12;
+/// 帧头 PTS 字段的最高位：配置数据包（SPS/PPS）
+const PACKET_FLAG_CONFIG: u64 = 1 << 63;
+/// 帧头 PTS 字段的次高位：关键帧
+const PACKET_FLAG_KEY_FRAME: u64 = 1 << 62;
+
+/// scrcpy 的帧头
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub pts: u64,  // 微秒，配置数据包为 0
+    pub config: bool,
+    pub key_frame: bool,
+    pub len: u32,
+}
+
+impl FrameHeader {
+    pub fn parse(header: &[u8; FRAME_HEADER_LEN]) -> Self {
+        let mut buf = &header[..];
+        let pts_and_flags = buf.get_u64();
+        Self {
+            pts: pts_and_flags & !(PACKET_FLAG_CONFIG | PACKET_FLAG_KEY_FRAME),
+            config: pts_and_flags & PACKET_FLAG_CONFIG != 0,
+            key_frame: pts_and_flags & PACKET_FLAG_KEY_FRAME != 0,
+            len: buf.get_u32(),
+        }
+    }
+}
+
+/// 把一个 Annex-B 数据包切分成不带起始码的 NAL 单元
+///
+/// 带帧头时一个数据包可能包含多个 NAL（配置数据包同时包含 SPS 和 PPS）；
+/// 4 字节起始码多出的 0x00 不计入前一个 NAL。不以起始码开头的数据包整体作为一个 NAL。
+pub fn split_annexb(packet: Bytes) -> Vec<Bytes> {
+    let mut nals = Vec::new();
+    let Some(mut start) = find_start_code(&packet, 0) else {
+        if !packet.is_empty() {
+            nals.push(packet);
+        }
+        return nals;
+    };
+    loop {
+        let nal_start = start + 3;
+        let next = find_start_code(&packet, nal_start);
+        let mut end = next.unwrap_or(packet.len());
+        if next.is_some() && end > nal_start && packet[end - 1] == 0x00 {
+            end -= 1;
+        }
+        if end > nal_start {
+            nals.push(packet.slice(nal_start..end));
+        }
+        match next {
+            Some(next) => start = next,
+            None => return nals,
+        }
+    }
+}
+
+/// 把读取块大小限制在 [`MIN_READ_BUFFER_BYTES`, `MAX_READ_BUFFER_BYTES`] 内
+pub fn clamp_read_buffer_bytes(bytes: usize) -> usize {
+    bytes.clamp(MIN_READ_BUFFER_BYTES, MAX_READ_BUFFER_BYTES)
+}
+
+/// 把带起始码的 NAL 单元改为指定长度（3 或 4 字节）的起始码（`--start-code`）
+///
+/// 3 → 4 需要复制一次；4 → 3 只是切片，不复制数据。
+pub fn with_start_code_len(nal: Bytes, len: u8) -> Bytes {
+    let is_long = nal.starts_with(&[0x00, 0x00, 0x00, 0x01]);
+    match (len, is_long) {
+        (3, true) => nal.slice(1..),
+        (4, false) if nal.starts_with(&[0x00, 0x00, 0x01]) => {
+            let mut long = BytesMut::with_capacity(nal.len() + 1);
+            long.extend_from_slice(&[0x00]);
+            long.extend_from_slice(&nal);
+            long.freeze()
+        }
+        _ => nal,
+    }
+}
+
+/// 在 buf[from..] 中查找 3 字节起始码 00 00 01，返回其位置
+///
+/// 4 字节起始码 00 00 00 01 的后 3 字节同样会被找到。逐个检查起始码的最后一个字节：
+/// 大于 1 时它不可能是起始码中的任何字节，直接跳过 3 字节，负载数据中平均每次跳过接近 3 字节。
+fn find_start_code(buf: &[u8], from: usize) -> Option<usize> {
+    let mut end = from.checked_add(2)?;
+    while let Some(&byte) = buf.get(end) {
+        match byte {
+            0x01 if buf[end - 1] == 0x00 && buf[end - 2] == 0x00 => return Some(end - 2),
+            // 非 0 字节不能出现在下一个起始码的前两个字节中
+            0x01.. => end += 3,
+            0x00 => end += 1,
+        }
+    }
+    None
+}
+
+/// 带起始码（3 或 4 字节）的 NAL 单元的类型
+pub(crate) fn nal_type(nal: &[u8]) -> u8 {
+    let start_code_len = if nal.starts_with(&[0x00, 0x00, 0x00, 0x01]) { 4 } else { 3 };
+    nal.get(start_code_len).map(|b| b & 0x1F).unwrap_or(0)
+}
+
 /// 视频流读取器
-pub struct VideoStreamReader {
-    stream: TcpStream,
+///
+/// 按块读取 socket（默认 64KB，见 `--read-buffer-bytes`），在缓冲区中查找起始码切分 NAL 单元。
+/// `read` 在有数据时立即返回，块大小只影响系统调用次数，不会增加延迟。
+pub struct VideoStreamReader<S = DeviceStream> {
+    stream: S,
     buffer: BytesMut,
+    read_size: usize,                     // 每次读取的最大字节数
+    scan_pos: usize,                      // 下一次查找起始码的起点（避免重复扫描）
     frame_count: u64,
-    first_read: bool,  // 标记是否是第一次读取
-    first_start_code_pos: Option<usize>,  // 第一个起始码的位置
+    first_start_code_pos: Option<usize>,  // 当前 NAL 起始码的位置
+    queued: VecDeque<VideoFrame>,         // 带帧头时，同一数据包中尚未返回的 NAL
 }
 
-impl VideoStreamReader {
-    pub fn new(stream: TcpStream) -> Self {
+impl<S: AsyncRead + Unpin> VideoStreamReader<S> {
+    pub fn new(stream: S) -> Self {
+        Self::with_read_buffer(stream, DEFAULT_READ_BUFFER_BYTES)
+    }
+
+    /// 指定每次读取的字节数（会被限制在允许范围内）
+    pub fn with_read_buffer(stream: S, read_size: usize) -> Self {
         Self {
             stream,
             buffer: BytesMut::with_capacity(1024 * 1024), // 1MB缓冲区
+            read_size: clamp_read_buffer_bytes(read_size),
+            scan_pos: 0,
             frame_count: 0,
-            first_read: true,
             first_start_code_pos: None,
+            queued: VecDeque::new(),
         }
     }
 
     /// 读取下一个视频帧
     ///
-    /// scrcpy 3.3.4 raw_stream=true 模式：
-    /// 直接的 Annex-B H.264 NAL 流，使用 00 00 01 或 00 00 00 01 起始码分隔
-    pub async fn read_frame(&mut self, _with_meta: bool) -> Result<Option<VideoFrame>> {
+    /// - `with_meta == false`：scrcpy 3.3.4 raw_stream=true 模式，
+    ///   直接的 Annex-B H.264 NAL 流，使用 00 00 01 或 00 00 00 01 起始码分隔，PTS 为 0
+    /// - `with_meta == true`（`--frame-meta`）：每个数据包前有 12 字节帧头，
+    ///   数据包中的每个 NAL 作为一帧返回，PTS 取自帧头
+    pub async fn read_frame(&mut self, with_meta: bool) -> Result<Option<VideoFrame>> {
+        if with_meta {
+            return self.read_meta_frame().await;
+        }
         loop {
-            // 逐字节读取
-            let mut byte = [0u8; 1];
-            match self.stream.read_exact(&mut byte).await {
-                Ok(_) => {
-                    self.buffer.extend_from_slice(&byte);
+            // 先在已缓冲的数据中查找起始码
+            while let Some(pos) = find_start_code(&self.buffer, self.scan_pos) {
+                let Some(start_pos) = self.first_start_code_pos else {
+                    // 这是第一个起始码，记录位置
+                    self.first_start_code_pos = Some(pos);
+                    self.scan_pos = pos + 3;
+                    continue;
+                };
+
+                // 这是第二个起始码，提取中间的NAL单元
+                // NAL数据从第一个起始码之后开始，到第二个起始码之前结束；
+                // 找到的是 00 00 01，4 字节起始码多出的 0x00 不计入前一个 NAL（与 split_annexb 一致）
+                let nal_start = start_pos + 3;
+                let nal_end = if self.buffer[pos - 1] == 0x00 { pos - 1 } else { pos };
+                if nal_start >= nal_end {
+                    // 两个起始码相邻，没有数据
+                    self.first_start_code_pos = Some(pos);
+                    self.scan_pos = pos + 3;
+                    continue;
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+
+                // 取出已处理的数据（零拷贝），缓冲区从第二个起始码开始
+                let nal_data = self.buffer.split_to(pos).freeze().slice(nal_start..nal_end);
+                self.first_start_code_pos = Some(0);  // 新的起始码现在在位置0
+                self.scan_pos = 3;
+
+                return Ok(Some(self.make_frame(nal_data)));
+            }
+            // 末尾最多 2 字节可能是被截断的起始码，下次从这里继续查找
+            self.scan_pos = self.scan_pos.max(self.buffer.len().saturating_sub(2));
+
+            // 检查缓冲区溢出
+            if self.buffer.len() > MAX_PENDING_BYTES {
+                warn!("Buffer overflow, clearing");
+                self.buffer.clear();
+                self.first_start_code_pos = None;
+                self.scan_pos = 0;
+            }
+
+            // 读取下一块数据（直接读入缓冲区的空闲容量，不预先填零）
+            self.buffer.reserve(self.read_size);
+            let result = (&mut self.stream).take(self.read_size as u64).read_buf(&mut self.buffer).await;
+            match result {
+                Ok(0) => {
                     debug!("Stream closed (EOF)");
                     return Ok(None);
                 }
+                Ok(_) => {}
                 Err(e) => {
-                    warn!("Failed to read byte: {}", e);
-                    return Err(ScrcpyError::VideoStream(format!("Failed to read byte: {}", e)));
+                    warn!("Failed to read video stream: {}", e);
+                    return Err(ScrcpyError::VideoStream(format!("Failed to read video stream: {}", e)));
                 }
             }
+        }
+    }
 
-            // 检查缓冲区溢出
-            if self.buffer.len() > 10 * 1024 * 1024 {
-                warn!("Buffer overflow, clearing");
-                self.buffer.clear();
-                self.first_start_code_pos = None;
-                continue;
+    /// 读取带帧头的数据包，依次返回其中的 NAL 单元
+    async fn read_meta_frame(&mut self) -> Result<Option<VideoFrame>> {
+        loop {
+            if let Some(frame) = self.queued.pop_front() {
+                return Ok(Some(frame));
             }
 
-            // 查找 3-byte 起始码 00 00 01
-            let buf_len = self.buffer.len();
-            if buf_len >= 3 {
-                let last_3 = &self.buffer[buf_len - 3..];
-
-                if last_3 == [0x00, 0x00, 0x01] {
-                    // 找到一个起始码
-
-                    if self.first_start_code_pos.is_none() {
-                        // 这是第一个起始码，记录位置
-                        self.first_start_code_pos = Some(buf_len - 3);
-                        continue;
-                    } else {
-                        // 这是第二个起始码，提取中间的NAL单元
-                        let start_pos = self.first_start_code_pos.unwrap();
-
-                        // NAL数据从第一个起始码之后开始，到第二个起始码之前结束
-                        // 跳过起始码本身(3字节)，提取NAL数据
-                        let nal_start = start_pos + 3;
-                        let nal_end = buf_len - 3;
-
-                        if nal_start >= nal_end {
-                            // 两个起始码相邻，没有数据
-                            self.first_start_code_pos = Some(buf_len - 3);
-                            continue;
-                        }
-
-                        let nal_data = self.buffer[nal_start..nal_end].to_vec();
-
-                        // 清除已处理的数据，保留第二个起始码
-                        self.buffer = BytesMut::from(&self.buffer[buf_len - 3..]);
-                        self.first_start_code_pos = Some(0);  // 新的起始码现在在位置0
-
-                        // 解析 NAL 类型
-                        let nal_type = nal_data[0] & 0x1F;
-
-                        let frame_type = if matches!(nal_type, 7 | 8) {
-                            FrameType::Config
-                        } else {
-                            FrameType::Video
-                        };
-
-                        self.frame_count += 1;
-
-                        return Ok(Some(VideoFrame::new(
-                            0, // raw_stream 模式没有 PTS
-                            frame_type,
-                            Bytes::from(nal_data),
-                        )));
-                    }
+            if !self.fill(FRAME_HEADER_LEN).await? {
+                return Ok(None);
+            }
+            let mut header = [0u8; FRAME_HEADER_LEN];
+            self.buffer.copy_to_slice(&mut header);
+            let header = FrameHeader::parse(&header);
+            if header.len as usize > MAX_PENDING_BYTES {
+                return Err(ScrcpyError::VideoStream(format!("Invalid frame header: packet of {} bytes", header.len)));
+            }
+
+            if !self.fill(header.len as usize).await? {
+                debug!("Stream closed (EOF) inside a {} byte packet", header.len);
+                return Ok(None);
+            }
+            let packet = self.buffer.split_to(header.len as usize).freeze();
+            for nal in split_annexb(packet) {
+                let mut frame = self.make_frame(nal);
+                frame.pts = header.pts;
+                self.queued.push_back(frame);
+            }
+        }
+    }
+
+    /// 读取数据直到缓冲区中至少有 `len` 字节，流在此之前结束时返回 false
+    async fn fill(&mut self, len: usize) -> Result<bool> {
+        while self.buffer.len() < len {
+            let want = self.read_size.max(len - self.buffer.len());
+            self.buffer.reserve(want);
+            match (&mut self.stream).take(want as u64).read_buf(&mut self.buffer).await {
+                Ok(0) => {
+                    debug!("Stream closed (EOF)");
+                    return Ok(false);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("Failed to read video stream: {}", e);
+                    return Err(ScrcpyError::VideoStream(format!("Failed to read video stream: {}", e)));
                 }
             }
         }
+        Ok(true)
+    }
+
+    /// 等待流中出现数据（读入缓冲区，不消耗帧），流已关闭时返回 false
+    ///
+    /// 用于启动时确认编码器已开始输出：scrcpy 在编码器初始化失败时直接关闭视频连接。
+    pub async fn wait_for_data(&mut self) -> Result<bool> {
+        if !self.buffer.is_empty() {
+            return Ok(true);
+        }
+        self.buffer.reserve(self.read_size);
+        match (&mut self.stream).take(self.read_size as u64).read_buf(&mut self.buffer).await {
+            Ok(n) => Ok(n > 0),
+            Err(e) => Err(ScrcpyError::VideoStream(format!("Failed to read video stream: {}", e))),
+        }
+    }
+
+    /// 流结束后取出缓冲区中最后一个 NAL 单元（没有后继起始码）
+    ///
+    /// 实时流断开时最后一个 NAL 可能不完整，因此 `read_frame` 不会返回它；
+    /// 读取完整的文件（`--replay-nal`）时用它取出最后一帧。
+    pub fn finish(&mut self) -> Option<VideoFrame> {
+        let nal_start = self.first_start_code_pos.take()? + 3;
+        self.scan_pos = 0;
+        let rest = self.buffer.split().freeze();
+        if nal_start >= rest.len() {
+            return None;
+        }
+        Some(self.make_frame(rest.slice(nal_start..)))
+    }
+
+    fn make_frame(&mut self, nal_data: Bytes) -> VideoFrame {
+        // 解析 NAL 类型
+        let nal_type = nal_data[0] & 0x1F;
+
+        let frame_type = if matches!(nal_type, 7 | 8) {
+            FrameType::Config
+        } else {
+            FrameType::Video
+        };
+
+        self.frame_count += 1;
+
+        VideoFrame::new(
+            0, // raw_stream 模式没有 PTS，带帧头时由调用方填入
+            frame_type,
+            nal_data,
+        )
     }
 
     /// 获取已接收的帧数
@@ -153,70 +371,234 @@ impl VideoStreamReader {
     }
 }
 
-/// 视频编解码器配置数据
-#[derive(Debug, Clone)]
-pub struct ConfigData {
-    pub sps: Vec<u8>,
-    pub pps: Vec<u8>,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-/// 视频编解码器信息
-#[derive(Debug, Clone)]
-pub struct CodecInfo {
-    pub codec_id: u32,
-    pub width: u32,
-    pub height: u32,
-    pub config_data: Option<ConfigData>,
-}
+    #[test]
+    fn test_discardable_nal() {
+        assert!(is_discardable_nal(0x01));   // nal_ref_idc 0，P 帧
+        assert!(!is_discardable_nal(0x21));  // nal_ref_idc 1
+        assert!(!is_discardable_nal(0x41));  // nal_ref_idc 2（scrcpy 常见）
+        assert!(!is_discardable_nal(0x65));  // IDR
+        assert!(!is_discardable_nal(0x06));  // SEI 的 nal_ref_idc 为 0，但不是图像帧
+        assert_eq!(nal_ref_idc(0x67), 3);
+    }
 
-impl CodecInfo {
-    /// 从流中读取编解码器信息
-    ///
-    /// scrcpy 3.x 格式（如果 send_codec_meta=true）：
-    /// - 4字节 codec_id (big-endian u32)
-    /// - 4字节 width (big-endian u32)
-    /// - 4字节 height (big-endian u32)
-    pub async fn read_from_stream(stream: &mut TcpStream) -> Result<Self> {
-        let mut buf = [0u8; 12];
-
-        match tokio::time::timeout(
-            tokio::time::Duration::from_secs(3),
-            stream.read_exact(&mut buf)
-        ).await {
-            Ok(Ok(_)) => {
-                let codec_id = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
-                let width = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
-                let height = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
-
-                info!("📹 Codec info: codec_id={}, {}x{}", codec_id, width, height);
-
-                Ok(Self {
-                    codec_id,
-                    width,
-                    height,
-                    config_data: None,
-                })
+    #[test]
+    fn test_with_start_code_len() {
+        let long = Bytes::from_static(&[0x00, 0x00, 0x00, 0x01, 0x67, 0x42]);
+        let short = Bytes::from_static(&[0x00, 0x00, 0x01, 0x67, 0x42]);
+        assert_eq!(with_start_code_len(long.clone(), 3), short);
+        assert_eq!(with_start_code_len(short.clone(), 4), long);
+        assert_eq!(with_start_code_len(long.clone(), 4), long);
+        assert_eq!(with_start_code_len(short.clone(), 3), short);
+    }
+
+    #[test]
+    fn test_nal_type() {
+        assert_eq!(nal_type(&[0x00, 0x00, 0x00, 0x01, 0x67, 0x42]), 7);
+        assert_eq!(nal_type(&[0x00, 0x00, 0x01, 0x65]), 5);
+        assert_eq!(nal_type(&[0x00, 0x00, 0x00, 0x01]), 0);
+    }
+
+    /// 生成模拟码流：SPS、PPS，然后是 IDR/P 帧交替，混合 3 字节和 4 字节起始码
+    fn canned_stream(frames: usize, frame_len: usize) -> Vec<u8> {
+        let mut stream = Vec::new();
+        stream.extend_from_slice(&[0x00, 0x00, 0x00, 0x01, 0x67, 0x42, 0xc0, 0x1f]);
+        stream.extend_from_slice(&[0x00, 0x00, 0x01, 0x68, 0xce, 0x3c, 0x80]);
+        for i in 0..frames {
+            let nal_type = if i % 60 == 0 { 0x65 } else { 0x41 };
+            stream.extend_from_slice(&[0x00, 0x00, 0x00, 0x01, nal_type]);
+            // 负载中不含 00 00 0x
+            stream.extend((0..frame_len).map(|j| (j % 251) as u8 | 0x10));
+        }
+        // 结尾的起始码让最后一帧可以被切分出来
+        stream.extend_from_slice(&[0x00, 0x00, 0x01]);
+        stream
+    }
+
+    async fn read_all(data: &[u8], read_size: usize) -> Vec<VideoFrame> {
+        let mut reader = VideoStreamReader::with_read_buffer(data, read_size);
+        let mut frames = Vec::new();
+        while let Some(frame) = reader.read_frame(false).await.unwrap() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    #[test]
+    fn test_find_start_code() {
+        fn naive(buf: &[u8], from: usize) -> Option<usize> {
+            buf.get(from..)?.windows(3).position(|w| w == [0x00, 0x00, 0x01]).map(|pos| from + pos)
+        }
+        assert_eq!(find_start_code(&[0x00, 0x00, 0x01], 0), Some(0));
+        assert_eq!(find_start_code(&[0x00, 0x00, 0x00, 0x01], 0), Some(1));
+        assert_eq!(find_start_code(&[0x00, 0x01, 0x00, 0x00, 0x01], 0), Some(2));
+        assert_eq!(find_start_code(&[0x00, 0x00, 0x01], 1), None);
+        assert_eq!(find_start_code(&[0x00, 0x00], 0), None);
+        assert_eq!(find_start_code(&[], 5), None);
+
+        // 与逐窗口比较的结果一致：伪随机数据中 0x00/0x01 较多，覆盖各种跳过情况
+        let mut state = 0x2545_f491u32;
+        let data: Vec<u8> = (0..20_000).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            [0x00, 0x00, 0x01, 0x03, (state >> 8) as u8][state as usize % 5]
+        }).collect();
+        for from in 0..64 {
+            let mut pos = from;
+            loop {
+                let found = find_start_code(&data, pos);
+                assert_eq!(found, naive(&data, pos), "from {}", pos);
+                let Some(found) = found else { break };
+                pos = found + 1;
             }
-            Ok(Err(e)) => {
-                debug!("Could not read codec info: {}", e);
-                // 返回默认值
-                Ok(Self {
-                    codec_id: 0x68323634, // "h264"
-                    width: 0,
-                    height: 0,
-                    config_data: None,
-                })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_splits_nals_across_chunk_boundaries() {
+        let data = canned_stream(10, 5000);
+        // 块大小不会整除帧长：起始码会被切在两次读取之间
+        for read_size in [MIN_READ_BUFFER_BYTES, 4099, 65536] {
+            let frames = read_all(&data, read_size).await;
+            assert_eq!(frames.len(), 12, "read_size {}", read_size);
+            assert_eq!(frames[0].frame_type, FrameType::Config);
+            assert_eq!(&frames[0].data[..], &[0x67, 0x42, 0xc0, 0x1f]);
+            assert_eq!(&frames[1].data[..], &[0x68, 0xce, 0x3c, 0x80]);
+            assert!(frames[2].is_keyframe());
+            assert!(frames[2..].iter().all(|frame| frame.data.len() == 5001));
+        }
+        assert_eq!(clamp_read_buffer_bytes(1), MIN_READ_BUFFER_BYTES);
+    }
+
+    #[tokio::test]
+    async fn test_mixed_start_codes() {
+        // 3 字节和 4 字节起始码交替出现，NAL 不带多出的 0x00，类型按第一个字节识别
+        let data = [
+            &[0x00, 0x00, 0x00, 0x01, 0x67, 0x42][..],
+            &[0x00, 0x00, 0x01, 0x68, 0xce],
+            &[0x00, 0x00, 0x00, 0x01, 0x65, 0x88, 0x84],
+            &[0x00, 0x00, 0x00, 0x01, 0x41, 0x9a],
+            &[0x00, 0x00, 0x01, 0x41, 0x9b],
+            &[0x00, 0x00, 0x00, 0x01],
+        ]
+        .concat();
+        for read_size in [1, MIN_READ_BUFFER_BYTES] {
+            let mut reader = VideoStreamReader { read_size, ..VideoStreamReader::new(&data[..]) };
+            let mut nals = Vec::new();
+            while let Some(frame) = reader.read_frame(false).await.unwrap() {
+                nals.push((frame.data.to_vec(), frame.frame_type));
             }
-            Err(_) => {
-                debug!("Timeout reading codec info, using defaults");
-                Ok(Self {
-                    codec_id: 0x68323634,
-                    width: 0,
-                    height: 0,
-                    config_data: None,
-                })
+            assert_eq!(nals, vec![
+                (vec![0x67, 0x42], FrameType::Config),
+                (vec![0x68, 0xce], FrameType::Config),
+                (vec![0x65, 0x88, 0x84], FrameType::Video),
+                (vec![0x41, 0x9a], FrameType::Video),
+                (vec![0x41, 0x9b], FrameType::Video),
+            ], "read_size {}", read_size);
+        }
+        // 只有多出的 0x00 的空 NAL 被跳过
+        let frames = read_all(&[0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x41, 0x00, 0x00, 0x01], MIN_READ_BUFFER_BYTES).await;
+        assert_eq!(frames.len(), 1);
+        assert_eq!(&frames[0].data[..], &[0x41]);
+    }
+
+    /// 按 scrcpy 的格式给数据包加上帧头
+    fn with_header(pts: u64, flags: u64, packet: &[u8]) -> Vec<u8> {
+        let mut data = (pts | flags).to_be_bytes().to_vec();
+        data.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+        data.extend_from_slice(packet);
+        data
+    }
+
+    #[test]
+    fn test_split_annexb() {
+        let packet = Bytes::from_static(&[0x00, 0x00, 0x00, 0x01, 0x67, 0x42, 0x00, 0x00, 0x01, 0x68, 0xce, 0x00, 0x00, 0x00, 0x01, 0x06, 0x05]);
+        let nals = split_annexb(packet);
+        assert_eq!(nals, vec![
+            Bytes::from_static(&[0x67, 0x42]),
+            Bytes::from_static(&[0x68, 0xce]),
+            Bytes::from_static(&[0x06, 0x05]),
+        ]);
+        assert_eq!(split_annexb(Bytes::from_static(&[0x65, 0x88])), vec![Bytes::from_static(&[0x65, 0x88])]);
+        assert!(split_annexb(Bytes::new()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_frames_with_meta() {
+        let mut data = with_header(0, PACKET_FLAG_CONFIG, &[0x00, 0x00, 0x00, 0x01, 0x67, 0x42, 0x00, 0x00, 0x00, 0x01, 0x68, 0xce]);
+        data.extend(with_header(16_666, PACKET_FLAG_KEY_FRAME, &[0x00, 0x00, 0x00, 0x01, 0x65, 0x88, 0x84]));
+        data.extend(with_header(33_333, 0, &[0x00, 0x00, 0x00, 0x01, 0x41, 0x9a]));
+        // 结尾被截断的数据包不返回
+        data.extend(&with_header(50_000, 0, &[0x00, 0x00, 0x00, 0x01, 0x41, 0x9a])[..15]);
+
+        let header = FrameHeader::parse(data[..FRAME_HEADER_LEN].try_into().unwrap());
+        assert_eq!(header, FrameHeader { pts: 0, config: true, key_frame: false, len: 12 });
+
+        for read_size in [1, MIN_READ_BUFFER_BYTES] {
+            let mut reader = VideoStreamReader { read_size, ..VideoStreamReader::new(&data[..]) };
+            let mut frames = Vec::new();
+            while let Some(frame) = reader.read_frame(true).await.unwrap() {
+                frames.push((frame.data[0], frame.pts, frame.frame_type));
             }
+            assert_eq!(frames, vec![
+                (0x67, 0, FrameType::Config),
+                (0x68, 0, FrameType::Config),
+                (0x65, 16_666, FrameType::Video),
+                (0x41, 33_333, FrameType::Video),
+            ], "read_size {}", read_size);
         }
+
+        // 长度字段明显不合理时报错，而不是等待 4GB 数据
+        let bad = with_header(0, 0, &[])[..8].iter().copied().chain([0xff; 4]).collect::<Vec<_>>();
+        assert!(VideoStreamReader::new(&bad[..]).read_frame(true).await.is_err());
     }
-}
 
+    /// 微基准：通过 TCP 回环读取约 16MB 模拟码流，输出不同块大小的吞吐量（数据量大，默认不运行）
+    ///
+    /// `cargo test --release bench_read_buffer -- --ignored --nocapture` 查看结果
+    #[tokio::test]
+    #[ignore]
+    async fn bench_read_buffer_sizes() {
+        use std::time::Instant;
+        use tokio::io::AsyncWriteExt;
+        use tokio::net::{TcpListener, TcpStream};
+
+        // 约 16MB：相当于 8 Mbps 码流 16 秒
+        let data = std::sync::Arc::new(canned_stream(400, 40_000));
+        let expected = read_all(&data, DEFAULT_READ_BUFFER_BYTES).await.len();
+
+        for read_size in [4 * 1024, 16 * 1024, 64 * 1024, 256 * 1024, 1024 * 1024] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let writer_data = data.clone();
+            let writer = tokio::spawn(async move {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                // 按 scrcpy 编码器输出的粒度分块写入
+                for chunk in writer_data.chunks(16 * 1024) {
+                    socket.write_all(chunk).await.unwrap();
+                }
+            });
+
+            let start = Instant::now();
+            let mut reader = VideoStreamReader::with_read_buffer(TcpStream::connect(addr).await.unwrap(), read_size);
+            let mut frames = 0;
+            while reader.read_frame(false).await.unwrap().is_some() {
+                frames += 1;
+            }
+            let elapsed = start.elapsed();
+            writer.await.unwrap();
+
+            assert_eq!(frames, expected, "read_buffer {} B", read_size);
+            println!(
+                "read_buffer {:>8} B: {:>8.1} MB/s ({:?})",
+                read_size,
+                data.len() as f64 / elapsed.as_secs_f64() / 1_000_000.0,
+                elapsed
+            );
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! 广播帧率限制模块 - 在不重启编码器的情况下降低广播帧率
+//!
+//! 只丢弃普通的 P 帧（NAL 类型 1），SPS/PPS/IDR 等总是放行。
+//! 优先丢弃可丢弃帧（nal_ref_idc == 0）：一旦流中出现过这类帧，
+//! 参考 P 帧就不再丢弃，降帧不会造成花屏（帧率上限变为尽力而为）。
+//! 编码器只输出参考 P 帧时（scrcpy 的常见情况），只能丢弃参考帧，
+//! 解码端可能出现花屏直到下一个 IDR 帧到来，建议配合较短的 IDR 间隔（`-i 1`）使用。
+//!
+//! `--scale-fps-to-clients` 在没有观看者时切换到只放行关键帧（`set_keyframes_only`），
+//! 省去每个 P 帧的复制和分发；有观看者连接时立即恢复。
+
+use crate::scrcpy::video::is_discardable_nal;
+use std::time::{Duration, Instant};
+
+/// 广播帧率限制器
+pub struct FrameRateLimiter {
+    min_interval: Option<Duration>,  // 两个广播帧之间的最小间隔，None 表示不限制
+    last_sent: Option<Instant>,      // 上一个广播帧的时间
+    dropped: u64,                    // 已丢弃的帧数
+    has_discardable: bool,           // 流中出现过可丢弃帧
+    keyframes_only: bool,            // 只放行关键帧和参数集（没有观看者时）
+}
+
+impl FrameRateLimiter {
+    /// 创建限制器，`max_fps` 为 None 或 0 时不限制
+    pub fn new(max_fps: Option<u32>) -> Self {
+        let mut limiter = Self {
+            min_interval: None,
+            last_sent: None,
+            dropped: 0,
+            has_discardable: false,
+            keyframes_only: false,
+        };
+        limiter.set_max_fps(max_fps);
+        limiter
+    }
+
+    /// 修改最大广播帧率
+    pub fn set_max_fps(&mut self, max_fps: Option<u32>) {
+        self.min_interval = match max_fps {
+            Some(fps) if fps > 0 => Some(Duration::from_secs(1) / fps),
+            _ => None,
+        };
+    }
+
+    /// 设置是否只放行关键帧（P 帧全部丢弃，SPS/PPS/IDR 照常放行）
+    pub fn set_keyframes_only(&mut self, keyframes_only: bool) {
+        self.keyframes_only = keyframes_only;
+    }
+
+    /// 是否处于只放行关键帧的状态
+    pub fn is_keyframes_only(&self) -> bool {
+        self.keyframes_only
+    }
+
+    /// 判断 NAL 单元是否应该广播
+    ///
+    /// # Arguments
+    /// * `nal_header` - H.264 NAL 头（首字节）
+    /// * `now` - 当前时间
+    pub fn allow(&mut self, nal_header: u8, now: Instant) -> bool {
+        if self.keyframes_only && nal_header & 0x1F == 1 {
+            self.dropped += 1;
+            return false;
+        }
+        let Some(min_interval) = self.min_interval else {
+            return true;
+        };
+
+        match nal_header & 0x1F {
+            // 普通 P 帧：距离上一帧不足最小间隔则丢弃
+            1 => {
+                let discardable = is_discardable_nal(nal_header);
+                self.has_discardable |= discardable;
+                // 有可丢弃帧可用时，参考帧总是放行
+                let may_drop = discardable || !self.has_discardable;
+                if let Some(last) = self.last_sent {
+                    if may_drop && now.duration_since(last) < min_interval {
+                        self.dropped += 1;
+                        return false;
+                    }
+                }
+                self.last_sent = Some(now);
+                true
+            }
+            // IDR 帧总是放行，并作为新的计时起点
+            5 => {
+                self.last_sent = Some(now);
+                true
+            }
+            // SPS/PPS/SEI/AUD 等非图像数据总是放行
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited_passes_everything() {
+        let mut limiter = FrameRateLimiter::new(None);
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert!(limiter.allow(0x41, now));
+        }
+        assert_eq!(limiter.dropped, 0);
+    }
+
+    #[test]
+    fn test_limits_p_frames_but_keeps_keyframes() {
+        // 10fps -> 100ms 间隔，输入 60fps，全部是参考 P 帧
+        let mut limiter = FrameRateLimiter::new(Some(10));
+        let start = Instant::now();
+        let frame_interval = Duration::from_micros(16_667);
+
+        let mut sent = 0;
+        for i in 0..60 {
+            if limiter.allow(0x41, start + frame_interval * i) {
+                sent += 1;
+            }
+        }
+        assert!((9..=11).contains(&sent), "sent {} frames", sent);
+
+        // 配置帧和 IDR 帧不受限制
+        let t = start + frame_interval * 60;
+        assert!(limiter.allow(0x67, t));
+        assert!(limiter.allow(0x68, t));
+        assert!(limiter.allow(0x65, t));
+        assert!(!limiter.allow(0x41, t));
+    }
+
+    #[test]
+    fn test_keyframes_only() {
+        let mut limiter = FrameRateLimiter::new(None);
+        let now = Instant::now();
+        limiter.set_keyframes_only(true);
+        assert!(!limiter.allow(0x41, now));
+        assert!(!limiter.allow(0x01, now));
+        assert!(limiter.allow(0x67, now));
+        assert!(limiter.allow(0x68, now));
+        assert!(limiter.allow(0x65, now));
+        assert_eq!(limiter.dropped, 2);
+
+        // 恢复后立即放行，不受上一个 IDR 的计时影响
+        limiter.set_keyframes_only(false);
+        assert!(limiter.allow(0x41, now));
+    }
+
+    #[test]
+    fn test_drops_discardable_frames_first() {
+        // 参考帧与非参考帧交替（分层 P 帧），输入 60fps，限制 10fps
+        let mut limiter = FrameRateLimiter::new(Some(10));
+        let start = Instant::now();
+        let frame_interval = Duration::from_micros(16_667);
+
+        for i in 0..60 {
+            let header = if i % 2 == 0 { 0x41 } else { 0x01 };
+            let allowed = limiter.allow(header, start + frame_interval * i);
+            if header == 0x41 {
+                assert!(allowed, "reference frame {} dropped", i);
+            }
+        }
+        // 只有 nal_ref_idc == 0 的帧被丢弃
+        assert_eq!(limiter.dropped, 30);
+    }
+}
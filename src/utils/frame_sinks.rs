@@ -0,0 +1,390 @@
+//! 帧分发模块 - 把每个 NAL 单元同时分发给 WebSocket 广播和若干写入端（录制文件、管道、Unix 套接字）
+//!
+//! 所有分发都是非阻塞的：写入端通过有界队列交给独立任务写出，内存占用有上限。
+//! 队列满时按 `--record-overflow` 处理：`drop`（默认）丢弃该写入端的帧并等待下一个 IDR 重新同步，
+//! `stop` 停止该写入端（已排队的数据写完后关闭文件）。两种情况下卡住的录制都不会拖慢 WebSocket 广播。
+
+use crate::scrcpy::video::{nal_type, with_start_code_len};
+use bytes::Bytes;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{error, info, warn};
+
+/// 写入端队列满时的处理方式（`--record-overflow`）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverflowPolicy {
+    #[default]
+    Drop,  // 丢帧，等待下一个 IDR
+    Stop,  // 停止该写入端
+}
+
+/// 解析 `--record-overflow` 参数
+pub fn parse_overflow_policy(s: &str) -> std::result::Result<OverflowPolicy, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "drop" => Ok(OverflowPolicy::Drop),
+        "stop" => Ok(OverflowPolicy::Stop),
+        _ => Err(format!("unknown overflow policy '{}' (expected drop or stop)", s)),
+    }
+}
+
+/// 广播通道中的一帧：带 4 字节起始码的 NAL 单元和设备 PTS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BroadcastFrame {
+    pub nal: Bytes,
+    pub pts: Option<u64>,  // 微秒，只有 `--frame-meta` 时才有
+}
+
+impl From<Bytes> for BroadcastFrame {
+    fn from(nal: Bytes) -> Self {
+        Self { nal, pts: None }
+    }
+}
+
+/// 单个写入端的运行状态（主循环和写入任务更新，/stats 读取）
+#[derive(Debug)]
+struct SinkStat {
+    name: String,
+    dropped: AtomicU64,
+    stopped: AtomicBool,
+}
+
+/// /stats 中的写入端状态
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SinkSnapshot {
+    pub name: String,
+    pub dropped_frames: u64,
+    pub stopped: bool,
+}
+
+/// 所有写入端状态的共享句柄
+#[derive(Debug, Clone, Default)]
+pub struct SinkStats(Arc<RwLock<Vec<Arc<SinkStat>>>>);
+
+impl SinkStats {
+    pub fn snapshot(&self) -> Vec<SinkSnapshot> {
+        self.0
+            .read()
+            .map(|stats| {
+                stats
+                    .iter()
+                    .map(|s| SinkSnapshot {
+                        name: s.name.clone(),
+                        dropped_frames: s.dropped.load(Ordering::Relaxed),
+                        stopped: s.stopped.load(Ordering::Relaxed),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// 单个写入端
+struct WriterSink {
+    tx: Option<mpsc::Sender<Bytes>>,  // None 表示已停止
+    needs_keyframe: bool,  // 丢帧后等待下一个 IDR 再继续写，避免写出无法解码的帧
+    wrote_last: bool,  // 最近一次分发的 NAL 是否进入了写入队列
+    stat: Arc<SinkStat>,
+}
+
+/// 帧分发器：拥有广播通道和所有可选的写入端
+pub struct FrameSinks {
+    broadcast: broadcast::Sender<BroadcastFrame>,
+    writers: Vec<WriterSink>,
+    sps: Option<Bytes>,  // 最近的 SPS/PPS，写入端重新同步时先补发
+    pps: Option<Bytes>,
+    overflow_policy: OverflowPolicy,
+    stats: SinkStats,
+    start_code_len: u8,  // 写入端使用的起始码长度（`--start-code`），广播通道不受影响
+}
+
+impl FrameSinks {
+    pub fn new(broadcast: broadcast::Sender<BroadcastFrame>) -> Self {
+        Self {
+            broadcast,
+            writers: Vec::new(),
+            sps: None,
+            pps: None,
+            overflow_policy: OverflowPolicy::default(),
+            stats: SinkStats::default(),
+            start_code_len: 4,
+        }
+    }
+
+    /// 设置写入端输出的起始码长度（3 或 4）
+    pub fn set_start_code_len(&mut self, len: u8) {
+        self.start_code_len = len;
+    }
+
+    /// 设置队列满时的处理方式
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow_policy = policy;
+    }
+
+    /// 写入端状态句柄（用于 /stats）
+    pub fn get_stats(&self) -> SinkStats {
+        self.stats.clone()
+    }
+
+    /// WebSocket 广播通道（仅发给浏览器的数据，如补发 SPS/PPS）
+    pub fn broadcast(&self) -> &broadcast::Sender<BroadcastFrame> {
+        &self.broadcast
+    }
+
+    /// 添加写入端：`open` 在后台任务中执行（打开 FIFO 等可能阻塞的操作不会卡住主循环）
+    ///
+    /// 返回写入端编号，用于 [`wrote_last`](Self::wrote_last)。
+    pub fn add_writer<W, F>(&mut self, name: impl Into<String>, capacity: usize, open: F) -> usize
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+        F: Future<Output = std::io::Result<W>> + Send + 'static,
+    {
+        let name = name.into();
+        let (tx, mut rx) = mpsc::channel::<Bytes>(capacity.max(1));
+
+        let stat = Arc::new(SinkStat { name, dropped: AtomicU64::new(0), stopped: AtomicBool::new(false) });
+        if let Ok(mut stats) = self.stats.0.write() {
+            stats.push(stat.clone());
+        }
+
+        let task_stat = stat.clone();
+        tokio::spawn(async move {
+            let task_name = &task_stat.name;
+            let mut writer = match open.await {
+                Ok(writer) => writer,
+                Err(e) => {
+                    warn!("❌ Failed to open sink {}: {}", task_name, e);
+                    task_stat.stopped.store(true, Ordering::Relaxed);
+                    return;
+                }
+            };
+            info!("💾 Sink {} opened", task_name);
+            while let Some(nal) = rx.recv().await {
+                if let Err(e) = writer.write_all(&nal).await {
+                    warn!("❌ Sink {} write failed: {}", task_name, e);
+                    task_stat.stopped.store(true, Ordering::Relaxed);
+                    return;
+                }
+            }
+            let _ = writer.flush().await;
+            let _ = writer.shutdown().await;
+            info!("💾 Sink {} closed", task_name);
+        });
+
+        self.writers.push(WriterSink { tx: Some(tx), needs_keyframe: true, wrote_last: false, stat });
+        self.writers.len() - 1
+    }
+
+    /// 最近一次分发的 NAL 是否进入了该写入端的队列（等待关键帧、队列满丢弃或已停止时为 false）
+    pub fn wrote_last(&self, writer: usize) -> bool {
+        self.writers.get(writer).is_some_and(|writer| writer.wrote_last)
+    }
+
+    /// 是否有仍在写入的写入端（录制文件、管道等）
+    pub fn has_active_writers(&self) -> bool {
+        self.writers.iter().any(|writer| writer.tx.is_some())
+    }
+
+    /// 分发一个带起始码的 NAL 单元
+    pub fn dispatch(&mut self, nal: Bytes) {
+        self.dispatch_with_pts(nal, None);
+    }
+
+    /// 分发一个 NAL 单元，PTS 只随广播通道发给浏览器，写入端输出裸码流
+    pub fn dispatch_with_pts(&mut self, nal: Bytes, pts: Option<u64>) {
+        let nal_type = nal_type(&nal);
+        match nal_type {
+            7 => self.sps = Some(nal.clone()),
+            8 => self.pps = Some(nal.clone()),
+            _ => {}
+        }
+
+        let _ = self.broadcast.send(BroadcastFrame { nal: nal.clone(), pts });
+
+        let start_code_len = self.start_code_len;
+        let out = with_start_code_len(nal, start_code_len);
+        for writer in self.writers.iter_mut() {
+            writer.wrote_last = false;
+            let Some(tx) = &writer.tx else {
+                continue;
+            };
+            if writer.needs_keyframe {
+                // SPS 或 IDR 处重新开始；IDR 前先补发缓存的 SPS/PPS
+                match nal_type {
+                    7 => writer.needs_keyframe = false,
+                    5 => {
+                        let params = [self.sps.clone(), self.pps.clone()];
+                        if params.iter().flatten().all(|p| tx.try_send(with_start_code_len(p.clone(), start_code_len)).is_ok()) {
+                            writer.needs_keyframe = false;
+                        } else {
+                            continue;
+                        }
+                    }
+                    _ => continue,
+                }
+            }
+
+            match tx.try_send(out.clone()) {
+                Ok(()) => writer.wrote_last = true,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    let dropped = writer.stat.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                    match self.overflow_policy {
+                        OverflowPolicy::Drop => {
+                            writer.needs_keyframe = true;
+                            warn!("⚠️  Sink {} is falling behind, dropping until next keyframe ({} dropped)", writer.stat.name, dropped);
+                        }
+                        OverflowPolicy::Stop => {
+                            // 关闭发送端：写入任务写完已排队的数据后关闭文件
+                            writer.tx = None;
+                            writer.stat.stopped.store(true, Ordering::Relaxed);
+                            error!("❌ Sink {} cannot keep up, stopped writing (--record-overflow stop)", writer.stat.name);
+                        }
+                    }
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => {
+                    writer.tx = None;
+                    writer.stat.stopped.store(true, Ordering::Relaxed);
+                    warn!("Sink {} stopped, no longer writing to it", writer.stat.name);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn nal(nal_type: u8, len: usize) -> Bytes {
+        let mut data = vec![0x00, 0x00, 0x00, 0x01, nal_type];
+        data.resize(len, 0xAA);
+        Bytes::from(data)
+    }
+
+    #[tokio::test]
+    async fn test_stalled_writer_does_not_block_broadcast() {
+        let (tx, mut rx) = broadcast::channel(1024);
+        let mut sinks = FrameSinks::new(tx);
+
+        // 写入端一直不被读取：duplex 缓冲区很快写满
+        let (writer, _reader) = tokio::io::duplex(16);
+        sinks.add_writer("stalled", 2, async move { Ok(writer) });
+
+        sinks.dispatch(nal(0x67, 8));
+        sinks.dispatch(nal(0x68, 8));
+        for _ in 0..100 {
+            sinks.dispatch(nal(0x65, 64));
+        }
+
+        // 所有帧都已广播
+        let mut received = 0;
+        while rx.try_recv().is_ok() {
+            received += 1;
+        }
+        assert_eq!(received, 102);
+        assert!(sinks.get_stats().snapshot()[0].dropped_frames > 0);
+        assert!(!sinks.get_stats().snapshot()[0].stopped);  // 默认策略只丢帧
+    }
+
+    #[tokio::test]
+    async fn test_overflow_stop_policy_closes_sink() {
+        let (tx, _rx) = broadcast::channel(1024);
+        let mut sinks = FrameSinks::new(tx);
+        sinks.set_overflow_policy(OverflowPolicy::Stop);
+        let stats = sinks.get_stats();
+
+        // 写入任务在本测试让出执行权之前不会运行：容量为 2 的队列放入 SPS/PPS 后已满，相当于写得很慢的磁盘
+        let (writer, mut reader) = tokio::io::duplex(4096);
+        sinks.add_writer("slow", 2, async move { Ok(writer) });
+        sinks.dispatch(nal(0x67, 6));
+        sinks.dispatch(nal(0x68, 6));
+        sinks.dispatch(nal(0x65, 6));
+        sinks.dispatch(nal(0x41, 6));
+
+        assert_eq!(stats.snapshot(), vec![SinkSnapshot { name: "slow".to_string(), dropped_frames: 1, stopped: true }]);
+
+        // 已排队的数据写完后关闭（读到 EOF），之后的帧不再写入
+        let mut out = Vec::new();
+        tokio::time::timeout(Duration::from_secs(1), tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut out))
+            .await
+            .unwrap()
+            .unwrap();
+        let types: Vec<u8> = out.chunks(6).map(|c| c[4] & 0x1F).collect();
+        assert_eq!(types, vec![7, 8]);
+    }
+
+    #[tokio::test]
+    async fn test_writer_resyncs_on_idr() {
+        let (tx, _rx) = broadcast::channel(16);
+        let mut sinks = FrameSinks::new(tx);
+
+        let (writer, mut reader) = tokio::io::duplex(4096);
+        sinks.add_writer("test", 16, async move { Ok(writer) });
+
+        // 写入端从 SPS 之后才加入：第一个 P 帧被跳过，IDR 前补发 SPS/PPS
+        sinks.sps = Some(nal(0x67, 6));
+        sinks.pps = Some(nal(0x68, 6));
+        sinks.dispatch(nal(0x41, 6));
+        sinks.dispatch(nal(0x65, 6));
+        drop(sinks);
+
+        let mut out = Vec::new();
+        tokio::time::timeout(Duration::from_secs(1), tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut out))
+            .await
+            .unwrap()
+            .unwrap();
+        let types: Vec<u8> = out.chunks(6).map(|c| c[4] & 0x1F).collect();
+        assert_eq!(types, vec![7, 8, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_writer_start_code_len() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let mut sinks = FrameSinks::new(tx);
+        sinks.set_start_code_len(3);
+
+        let (writer, mut reader) = tokio::io::duplex(4096);
+        sinks.add_writer("test", 16, async move { Ok(writer) });
+        sinks.pps = Some(nal(0x68, 6));
+        sinks.sps = Some(nal(0x67, 6));
+        sinks.dispatch(nal(0x65, 6));
+        drop(sinks);
+
+        // 补发的 SPS/PPS 和 IDR 都使用 3 字节起始码；广播通道保持 4 字节
+        let mut out = Vec::new();
+        tokio::time::timeout(Duration::from_secs(1), tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut out))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out, [[0x00, 0x00, 0x01, 0x67, 0xAA], [0x00, 0x00, 0x01, 0x68, 0xAA], [0x00, 0x00, 0x01, 0x65, 0xAA]].concat());
+        assert_eq!(rx.try_recv().unwrap().nal.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_wrote_last_tracks_each_writer() {
+        let (tx, _rx) = broadcast::channel(16);
+        let mut sinks = FrameSinks::new(tx);
+
+        // 写入任务在本测试让出执行权之前不会运行：容量为 3 的队列放入 SPS/PPS/IDR 后已满
+        let (writer, _reader) = tokio::io::duplex(4096);
+        let first = sinks.add_writer("first", 3, async move { Ok(writer) });
+        sinks.dispatch(nal(0x41, 6));
+        assert!(!sinks.wrote_last(first));  // 等待关键帧
+        sinks.dispatch(nal(0x67, 6));
+        sinks.dispatch(nal(0x68, 6));
+        sinks.dispatch(nal(0x65, 6));
+        assert!(sinks.wrote_last(first));
+
+        let (writer, _reader) = tokio::io::duplex(4096);
+        let second = sinks.add_writer("second", 16, async move { Ok(writer) });
+        sinks.dispatch(nal(0x65, 6));
+        assert!(!sinks.wrote_last(first));  // 队列已满，丢弃
+        assert!(sinks.wrote_last(second));  // 补发 SPS/PPS 后写入
+        assert!(!sinks.wrote_last(second + 1));
+    }
+}
@@ -0,0 +1,111 @@
+//! 帧统计模块 - `--verbose-frames` 的 NAL 类型直方图
+//!
+//! 每帧只做计数，由主循环的定时分支定期输出一行摘要并清零，
+//! 避免逐帧日志带来的开销。
+
+use std::fmt;
+use std::time::Duration;
+
+/// 一个统计周期内各类 NAL 单元的数量和字节数
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NalHistogram {
+    pub idr: u64,
+    pub p: u64,
+    pub sps: u64,
+    pub pps: u64,
+    pub sei: u64,
+    pub aud: u64,
+    pub other: u64,
+    pub bytes: u64,
+}
+
+impl NalHistogram {
+    /// 记录一个 NAL 单元（`nal_type` 为首字节低 5 位）
+    pub fn record(&mut self, nal_type: u8, len: usize) {
+        match nal_type {
+            5 => self.idr += 1,
+            1 => self.p += 1,
+            7 => self.sps += 1,
+            8 => self.pps += 1,
+            6 => self.sei += 1,
+            9 => self.aud += 1,
+            _ => self.other += 1,
+        }
+        self.bytes += len as u64;
+    }
+
+    /// 取出当前统计并清零，返回周期内的摘要
+    pub fn take(&mut self, elapsed: Duration) -> FrameStatsSummary {
+        FrameStatsSummary { histogram: std::mem::take(self), elapsed }
+    }
+}
+
+/// 一个统计周期的摘要（用于日志输出）
+#[derive(Debug)]
+pub struct FrameStatsSummary {
+    pub histogram: NalHistogram,
+    pub elapsed: Duration,
+}
+
+impl FrameStatsSummary {
+    /// 图像帧（IDR + P）的帧率
+    pub fn fps(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            (self.histogram.idr + self.histogram.p) as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// 码率（kbps）
+    pub fn kbps(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.histogram.bytes as f64 * 8.0 / 1000.0 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for FrameStatsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let h = &self.histogram;
+        write!(
+            f,
+            "{:.1} fps, {:.0} kbps, {} bytes | IDR {} P {} SPS {} PPS {} SEI {} AUD {}",
+            self.fps(), self.kbps(), h.bytes, h.idr, h.p, h.sps, h.pps, h.sei, h.aud
+        )?;
+        if h.other > 0 {
+            write!(f, " other {}", h.other)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_summary_and_reset() {
+        let mut histogram = NalHistogram::default();
+        histogram.record(7, 20);
+        histogram.record(8, 10);
+        histogram.record(5, 5000);
+        for _ in 0..59 {
+            histogram.record(1, 1000);
+        }
+        histogram.record(12, 2);
+
+        let summary = histogram.take(Duration::from_secs(2));
+        assert_eq!(summary.fps(), 30.0);
+        assert_eq!(summary.histogram.bytes, 20 + 10 + 5000 + 59_000 + 2);
+        assert_eq!(
+            summary.to_string(),
+            "30.0 fps, 256 kbps, 64032 bytes | IDR 1 P 59 SPS 1 PPS 1 SEI 0 AUD 0 other 1"
+        );
+        assert_eq!(histogram, NalHistogram::default());
+    }
+}
@@ -0,0 +1,164 @@
+//! 实例锁模块 - 防止两个进程同时连接同一台设备
+//!
+//! 两个实例会同时推送 server、抢占同一个抽象套接字，表现为难以理解的连接错误。
+//! 启动时在临时目录下按序列号创建锁文件（内容为进程 PID），退出时删除；
+//! 锁文件对应的进程已不存在时视为残留锁，自动接管。
+//!
+//! 锁文件先以临时文件写好 PID，再用 `hard_link` 原子地放到锁文件路径上（目标已存在时失败），
+//! 其他实例不会读到内容还没写入的锁文件。
+//! 只有 Linux 能通过 `/proc` 判断持有者是否存活；其他平台上残留锁不会自动清除，需要删除锁文件或使用 `--force`。
+
+use crate::error::{Result, ScrcpyError};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// 设备实例锁，drop 时删除锁文件
+#[derive(Debug)]
+pub struct InstanceLock {
+    path: PathBuf,
+    pid: u32,
+}
+
+/// 锁文件路径：序列号中的 `:`（网络设备 `ip:port`）等字符替换为 `_`
+pub fn lock_path(dir: &Path, serial: &str) -> PathBuf {
+    let name: String = serial
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+        .collect();
+    dir.join(format!("rust-scrcpy-{}.lock", name))
+}
+
+/// 进程是否仍在运行；无法判断时返回 None
+fn process_alive(pid: u32) -> Option<bool> {
+    if cfg!(target_os = "linux") {
+        Some(Path::new(&format!("/proc/{}", pid)).exists())
+    } else {
+        None
+    }
+}
+
+impl InstanceLock {
+    /// 在系统临时目录下为设备加锁
+    pub fn acquire(serial: &str, force: bool) -> Result<Self> {
+        Self::acquire_in(&std::env::temp_dir(), serial, force)
+    }
+
+    /// 在指定目录下为设备加锁
+    ///
+    /// 已被其他存活进程锁定时返回 [`ScrcpyError::DeviceInUse`]；`force` 时直接接管。
+    pub fn acquire_in(dir: &Path, serial: &str, force: bool) -> Result<Self> {
+        let path = lock_path(dir, serial);
+        let pid = std::process::id();
+
+        // 先写好临时文件，再链接到锁文件路径：锁文件一出现就带有完整的 PID
+        let temp = path.with_extension(format!("lock.{}.tmp", pid));
+        std::fs::write(&temp, pid.to_string())?;
+        let result = Self::link_lock(&temp, &path, serial, pid, force);
+        let _ = std::fs::remove_file(&temp);
+        result
+    }
+
+    fn link_lock(temp: &Path, path: &Path, serial: &str, pid: u32, force: bool) -> Result<Self> {
+        // 最多重试一次：删除残留锁后重新创建
+        for _ in 0..2 {
+            match std::fs::hard_link(temp, path) {
+                Ok(()) => {
+                    info!("🔒 Acquired device lock {:?}", path);
+                    return Ok(Self { path: path.to_path_buf(), pid });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let owner = std::fs::read_to_string(path)
+                        .ok()
+                        .and_then(|s| s.trim().parse::<u32>().ok());
+                    let alive = owner.and_then(process_alive);
+                    let stale = match owner {
+                        Some(_) => alive == Some(false),
+                        None => true,  // 内容损坏（锁文件总是带着 PID 创建，不会是写入中途的状态）
+                    };
+                    if !stale && !force {
+                        if alive.is_none() {
+                            warn!("Cannot check whether pid {:?} is still running on this platform; if it is not, delete {:?} or use --force", owner, path);
+                        }
+                        return Err(ScrcpyError::DeviceInUse(serial.to_string(), owner.unwrap_or(0), path.to_path_buf()));
+                    }
+                    if stale {
+                        warn!("🔓 Removing stale device lock {:?} (pid {:?})", path, owner);
+                    } else {
+                        warn!("⚠️  --force: taking over device lock {:?} from pid {:?}", path, owner);
+                    }
+                    let _ = std::fs::remove_file(path);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(ScrcpyError::Io(std::io::Error::other(format!("failed to create lock file {:?}", path))))
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // 只删除自己的锁（--force 接管后原进程退出时不应删掉新进程的锁）
+        let owned = std::fs::read_to_string(&self.path)
+            .map(|s| s.trim() == self.pid.to_string())
+            .unwrap_or(false);
+        if owned {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rust-scrcpy-lock-test-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_second_acquire_is_refused_until_drop() {
+        let dir = test_dir("refuse");
+        let lock = InstanceLock::acquire_in(&dir, "192.168.1.5:5555", false).unwrap();
+        assert!(lock.path.ends_with("rust-scrcpy-192.168.1.5_5555.lock"));
+
+        // 同一进程持有锁（进程存活）：第二次加锁被拒绝
+        if cfg!(target_os = "linux") {
+            assert!(matches!(
+                InstanceLock::acquire_in(&dir, "192.168.1.5:5555", false),
+                Err(ScrcpyError::DeviceInUse(..))
+            ));
+        }
+        // 其他设备不受影响
+        let other = InstanceLock::acquire_in(&dir, "emulator-5554", false).unwrap();
+
+        // 临时文件不会留在目录中
+        let files = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(files, 2);
+
+        let path = lock.path.clone();
+        drop(lock);
+        assert!(!path.exists());
+        drop(other);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_stale_and_forced_takeover() {
+        let dir = test_dir("stale");
+        let path = lock_path(&dir, "abc");
+
+        // 损坏的锁文件视为残留
+        std::fs::write(&path, "garbage").unwrap();
+        let lock = InstanceLock::acquire_in(&dir, "abc", false).unwrap();
+
+        // --force 接管后，原持有者 drop 不会删除新锁
+        let forced = InstanceLock::acquire_in(&dir, "abc", true).unwrap();
+        std::fs::write(&path, "1").unwrap();
+        drop(lock);
+        assert!(path.exists());
+        drop(forced);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,3 +1,19 @@
+pub mod frame_limiter;
+pub mod frame_sinks;
+pub mod frame_stats;
+pub mod instance_lock;
+pub mod nal_dump;
 pub mod port;
+#[cfg(feature = "selftest")]
+pub mod selftest;
+pub mod timeline;
+pub mod ts_mux;
 
-pub use port::{find_available_port, find_available_ports, is_port_available};
+pub use frame_limiter::FrameRateLimiter;
+pub use frame_sinks::FrameSinks;
+pub use frame_stats::NalHistogram;
+pub use instance_lock::InstanceLock;
+pub use nal_dump::NalDump;
+pub use port::{find_available_port, reserve_ports, PortReservation};
+pub use timeline::TimelineSidecar;
+pub use ts_mux::TsMuxer;
@@ -0,0 +1,105 @@
+//! NAL 转储模块 - `--dump-nal` 把 VideoStreamReader 输出的每个 NAL 单元原样写入文件
+//!
+//! 每个 NAL 前加 3 字节起始码 `00 00 01`：读取器输出的 NAL 不带起始码（4 字节起始码多出的 0x00 也已去掉），
+//! 因此转储文件再经 VideoStreamReader 读取（`--replay-nal`）时，得到的 NAL 与原始输出逐字节相同。
+//! 转储发生在帧率限制、`--only-app` 等过滤之前，记录的是设备发来的完整码流。
+//!
+//! 写入在独立任务中进行，队列不设上限以保证不丢帧（仅用于排查问题，磁盘过慢时内存会增长）。
+
+use crate::error::Result;
+use bytes::Bytes;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+const START_CODE: &[u8] = &[0x00, 0x00, 0x01];
+
+/// NAL 转储文件
+pub struct NalDump {
+    tx: mpsc::UnboundedSender<Bytes>,
+    task: JoinHandle<u64>,  // 返回写入的字节数
+}
+
+impl NalDump {
+    /// 创建转储文件（启动时立即打开，路径无效时尽早报错）
+    pub async fn create(path: &Path) -> Result<Self> {
+        let file = tokio::fs::File::create(path).await?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(write_dump(BufWriter::new(file), rx, path.to_path_buf()));
+        info!("💾 Dumping NAL stream to {:?}", path);
+        Ok(Self { tx, task })
+    }
+
+    /// 写入一个不带起始码的 NAL 单元（零拷贝）
+    pub fn write(&self, nal: &Bytes) {
+        let _ = self.tx.send(Bytes::from_static(START_CODE));
+        let _ = self.tx.send(nal.clone());
+    }
+
+    /// 写完队列中的数据并关闭文件
+    pub async fn finish(self) {
+        drop(self.tx);
+        match self.task.await {
+            Ok(bytes) => info!("💾 NAL dump closed ({} bytes)", bytes),
+            Err(e) => warn!("❌ NAL dump task failed: {}", e),
+        }
+    }
+}
+
+async fn write_dump<W: tokio::io::AsyncWrite + Unpin>(mut writer: W, mut rx: mpsc::UnboundedReceiver<Bytes>, path: PathBuf) -> u64 {
+    let mut written = 0u64;
+    while let Some(chunk) = rx.recv().await {
+        if let Err(e) = writer.write_all(&chunk).await {
+            warn!("❌ NAL dump {:?} write failed: {}", path, e);
+            return written;
+        }
+        written += chunk.len() as u64;
+    }
+    if let Err(e) = writer.flush().await {
+        warn!("❌ NAL dump {:?} flush failed: {}", path, e);
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrcpy::VideoStreamReader;
+
+    #[tokio::test]
+    async fn test_dump_round_trip() {
+        // 混合 3/4 字节起始码的设备码流
+        let mut device_stream = vec![0x00, 0x00, 0x00, 0x01, 0x67, 0x42, 0xc0, 0x1f];
+        device_stream.extend_from_slice(&[0x00, 0x00, 0x01, 0x68, 0xce, 0x3c, 0x80]);
+        device_stream.extend_from_slice(&[0x00, 0x00, 0x00, 0x01, 0x65, 0x88, 0x84]);
+        device_stream.extend_from_slice(&[0x00, 0x00, 0x00, 0x01, 0x41, 0x9a, 0x02]);
+        device_stream.extend_from_slice(&[0x00, 0x00, 0x01]);
+
+        let mut reader = VideoStreamReader::new(device_stream.as_slice());
+        let mut original = Vec::new();
+        while let Some(frame) = reader.read_frame(false).await.unwrap() {
+            original.push(frame.data);
+        }
+        assert_eq!(original.len(), 4);
+
+        let path = std::env::temp_dir().join(format!("rust-scrcpy-dump-test-{}.h264", std::process::id()));
+        let dump = NalDump::create(&path).await.unwrap();
+        for nal in &original {
+            dump.write(nal);
+        }
+        dump.finish().await;
+
+        // 回放：最后一个 NAL 没有后继起始码，由 finish() 取出
+        let replayed_bytes = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut reader = VideoStreamReader::new(replayed_bytes.as_slice());
+        let mut replayed = Vec::new();
+        while let Some(frame) = reader.read_frame(false).await.unwrap() {
+            replayed.push(frame.data);
+        }
+        replayed.extend(reader.finish().map(|frame| frame.data));
+        assert_eq!(replayed, original);
+    }
+}
@@ -1,28 +1,42 @@
 //! 端口工具模块 - 提供端口可用性检测和自动寻找功能
 
-use std::net::TcpListener;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpListener, UdpSocket};
+use std::time::Duration;
 use crate::error::{Result, ScrcpyError};
 use tracing::{debug, info};
 
-/// 检查端口是否可用
-pub fn is_port_available(port: u16) -> bool {
-    TcpListener::bind(("127.0.0.1", port)).is_ok()
+/// 暴露检查中连接非回环地址的超时
+const EXPOSURE_CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// 检查端口在指定地址上是否可用
+pub fn is_port_available(ip: IpAddr, port: u16) -> bool {
+    TcpListener::bind((ip, port)).is_ok()
+}
+
+/// 本机连接监听地址时使用的地址：监听 0.0.0.0 / :: 时使用同一协议族的回环地址
+pub fn connect_host(bind: IpAddr) -> IpAddr {
+    match bind {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    }
 }
 
-/// 从指定端口开始，寻找第一个可用端口
+/// 从指定端口开始，在将要监听的地址上寻找第一个可用端口
 ///
 /// # Arguments
+/// * `ip` - 监听地址（在这个地址上检测，其他网卡上被占用的端口不影响结果）
 /// * `start_port` - 起始端口
 /// * `max_attempts` - 最大尝试次数（向后搜索的范围）
 ///
 /// # Returns
 /// * `Ok(port)` - 找到的可用端口
 /// * `Err` - 在范围内未找到可用端口
-pub fn find_available_port(start_port: u16, max_attempts: u16) -> Result<u16> {
+pub fn find_available_port(ip: IpAddr, start_port: u16, max_attempts: u16) -> Result<u16> {
     let end_port = start_port.saturating_add(max_attempts);
 
     for port in start_port..=end_port {
-        if is_port_available(port) {
+        if is_port_available(ip, port) {
             if port != start_port {
                 info!("📌 Port {} is occupied, using port {} instead", start_port, port);
             }
@@ -34,50 +48,166 @@ pub fn find_available_port(start_port: u16, max_attempts: u16) -> Result<u16> {
     Err(ScrcpyError::NoAvailablePort(start_port, end_port))
 }
 
-/// 寻找多个连续可用端口
+/// 端口预留：持有已绑定的监听器，直到调用方释放
+///
+/// 在监听器被释放之前，其他进程（以及本进程中的其他搜索）无法占用这些端口，
+/// 避免“检测可用 → 实际使用”之间被抢占的 TOCTOU 问题。
+#[derive(Debug)]
+pub struct PortReservation {
+    listeners: Vec<TcpListener>,
+}
+
+impl PortReservation {
+    /// 预留的端口列表（按预留顺序）
+    pub fn ports(&self) -> Vec<u16> {
+        self.listeners
+            .iter()
+            .filter_map(|l| l.local_addr().ok())
+            .map(|addr| addr.port())
+            .collect()
+    }
+
+    /// 合并两个预留
+    pub fn merge(mut self, other: PortReservation) -> PortReservation {
+        self.listeners.extend(other.listeners);
+        self
+    }
+
+    /// 释放所有预留，端口随后可以交给 adb forward 等使用
+    pub fn release(self) {
+        drop(self.listeners);
+    }
+}
+
+/// 预留多个可用端口
+///
+/// 所有监听器在找齐 N 个端口之前都保持绑定，因此搜索过程中已找到的端口不会被抢占，
+/// 返回的 [`PortReservation`] 会继续持有它们。
 ///
 /// # Arguments
 /// * `start_port` - 起始端口
 /// * `count` - 需要的端口数量
 /// * `max_attempts` - 每个端口的最大尝试次数
-///
-/// # Returns
-/// * `Ok(Vec<u16>)` - 找到的可用端口列表
-/// * `Err` - 未能找到足够的可用端口
-pub fn find_available_ports(start_port: u16, count: usize, max_attempts: u16) -> Result<Vec<u16>> {
-    let mut ports = Vec::with_capacity(count);
+pub fn reserve_ports(start_port: u16, count: usize, max_attempts: u16) -> Result<PortReservation> {
+    let mut listeners = Vec::with_capacity(count);
     let mut current_port = start_port;
 
     for i in 0..count {
-        let port = find_available_port(current_port, max_attempts)?;
-        ports.push(port);
-        // 下一个端口从当前端口+1开始搜索，避免冲突
-        current_port = port.saturating_add(1);
+        let end_port = current_port.saturating_add(max_attempts);
+        let listener = (current_port..=end_port)
+            .find_map(|port| TcpListener::bind(("127.0.0.1", port)).ok())
+            .ok_or(ScrcpyError::NoAvailablePort(current_port, end_port))?;
+        let port = listener.local_addr()?.port();
+
+        if port != current_port {
+            info!("📌 Port {} is occupied, using port {} instead", current_port, port);
+        }
+        debug!("Reserved port {} for slot {}", port, i);
 
-        debug!("Found available port {} for slot {}", port, i);
+        listeners.push(listener);
+        // 下一个端口从当前端口+1开始搜索
+        current_port = port.saturating_add(1);
     }
 
-    Ok(ports)
+    Ok(PortReservation { listeners })
+}
+
+/// 本机对外通信使用的非回环地址
+///
+/// UDP 的 connect 只选择路由、不发送数据包；没有默认路由（离线）时返回 None。
+pub fn lan_address() -> Option<IpAddr> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).ok()?;
+    socket.connect(("192.0.2.1", 9)).ok()?;  // TEST-NET-1，不会真正发出
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_loopback() && !ip.is_unspecified()).then_some(ip)
+}
+
+/// 检查本机端口能否从非回环地址连接，返回可连接的地址
+///
+/// 只监听 127.0.0.1 的端口连接会被拒绝；监听 0.0.0.0 的端口（局域网可访问）会连接成功。
+pub async fn lan_exposure(port: u16) -> Option<IpAddr> {
+    let ip = lan_address()?;
+    let connect = tokio::net::TcpStream::connect((ip, port));
+    matches!(tokio::time::timeout(EXPOSURE_CONNECT_TIMEOUT, connect).await, Ok(Ok(_))).then_some(ip)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn test_lan_exposure() {
+        let loopback = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        assert_eq!(lan_exposure(loopback.local_addr().unwrap().port()).await, None);
+
+        // 离线环境没有非回环地址，只能检查回环的情况
+        if let Some(ip) = lan_address() {
+            let any = TcpListener::bind(("0.0.0.0", 0)).unwrap();
+            assert_eq!(lan_exposure(any.local_addr().unwrap().port()).await, Some(ip));
+        }
+    }
+
     #[test]
     fn test_is_port_available() {
         // 测试一个通常可用的高端口
         let port = 59999;
-        let available = is_port_available(port);
+        let available = is_port_available(Ipv4Addr::LOCALHOST.into(), port);
         println!("Port {} available: {}", port, available);
     }
 
     #[test]
     fn test_find_available_port() {
-        let result = find_available_port(50000, 100);
+        let result = find_available_port(Ipv4Addr::LOCALHOST.into(), 50000, 100);
         assert!(result.is_ok());
         let port = result.unwrap();
-        assert!(port >= 50000 && port <= 50100);
+        assert!((50000..=50100).contains(&port));
         println!("Found available port: {}", port);
+
+        // 在实际监听的地址上检测：占用 127.0.0.1 上的端口后，同一地址跳过它
+        let taken = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let taken_port = taken.local_addr().unwrap().port();
+        assert!(!is_port_available(Ipv4Addr::LOCALHOST.into(), taken_port));
+        assert_ne!(find_available_port(Ipv4Addr::LOCALHOST.into(), taken_port, 10).unwrap(), taken_port);
+    }
+
+    #[test]
+    fn test_connect_host() {
+        assert_eq!(connect_host("0.0.0.0".parse().unwrap()), "127.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(connect_host("::".parse().unwrap()), "::1".parse::<IpAddr>().unwrap());
+        assert_eq!(connect_host("192.168.1.5".parse().unwrap()), "192.168.1.5".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_reserve_ports_holds_reservation() {
+        let reservation = reserve_ports(51000, 3, 100).unwrap();
+        let ports = reservation.ports();
+        assert_eq!(ports.len(), 3);
+        for port in &ports {
+            assert!(!is_port_available(Ipv4Addr::LOCALHOST.into(), *port));
+        }
+        reservation.release();
+    }
+
+    #[test]
+    fn test_reserve_ports_concurrently_unique() {
+        // 多个线程同时预留，持有期间得到的端口不能重复
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(8));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    reserve_ports(52000, 4, 500).unwrap()
+                })
+            })
+            .collect();
+
+        let reservations: Vec<PortReservation> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        let mut all_ports: Vec<u16> = reservations.iter().flat_map(|r| r.ports()).collect();
+        let total = all_ports.len();
+        all_ports.sort_unstable();
+        all_ports.dedup();
+        assert_eq!(all_ports.len(), total);
+        assert_eq!(total, 32);
     }
 }
@@ -0,0 +1,210 @@
+//! 自检模块 - `--selftest-frames N`（需要 `selftest` feature）在无浏览器的 CI 中验证采集 + 解码管线
+//!
+//! 从视频流读取 SPS/PPS 和从第一个 IDR 开始的 N 个视频帧，交给外部 `ffmpeg` 解码成 PNG
+//! （`<目录>/frame_001.png` …），再逐帧计算平均颜色和像素校验和，写入 `<目录>/selftest.json`。
+//! 所有帧都是黑屏（平均亮度低于阈值）或一帧都解码不出来时返回错误，进程以非 0 退出码退出。
+//!
+//! 解码器是运行时的外部程序（`--ffmpeg-path`），不引入编译期依赖；feature 关闭时这些参数不存在。
+
+use crate::error::{Result, ScrcpyError};
+use crate::scrcpy::VideoStreamReader;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// 平均亮度（0~255）低于该值的帧视为黑屏
+pub const BLACK_LUMA_THRESHOLD: f64 = 8.0;
+/// 等待 N 个视频帧的最长时间（锁屏时编码器不输出）
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// 单帧的解码结果
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FrameCheck {
+    pub file: String,
+    pub width: u32,
+    pub height: u32,
+    pub average_rgb: [f64; 3],
+    pub luma: f64,      // BT.601 平均亮度
+    pub black: bool,
+    pub sha256: String, // RGB 像素的校验和
+}
+
+/// `selftest.json` 的内容
+#[derive(Debug, Clone, Serialize)]
+pub struct SelftestReport {
+    pub captured_frames: usize,
+    pub decoded_frames: usize,
+    pub frames: Vec<FrameCheck>,
+    pub passed: bool,
+}
+
+/// 计算一帧的平均颜色和校验和
+pub fn check_frame(file: String, image: &image::RgbImage) -> FrameCheck {
+    let pixels = (image.width() as u64 * image.height() as u64).max(1) as f64;
+    let mut sum = [0u64; 3];
+    for pixel in image.pixels() {
+        for (channel, value) in sum.iter_mut().zip(pixel.0) {
+            *channel += value as u64;
+        }
+    }
+    let average_rgb = sum.map(|channel| channel as f64 / pixels);
+    let luma = 0.299 * average_rgb[0] + 0.587 * average_rgb[1] + 0.114 * average_rgb[2];
+    FrameCheck {
+        file,
+        width: image.width(),
+        height: image.height(),
+        average_rgb,
+        luma,
+        black: luma < BLACK_LUMA_THRESHOLD,
+        sha256: Sha256::digest(image.as_raw()).iter().map(|b| format!("{:02x}", b)).collect(),
+    }
+}
+
+/// 自检通过：至少解码出一帧，且不是所有帧都是黑屏
+pub fn passed(frames: &[FrameCheck]) -> bool {
+    frames.iter().any(|frame| !frame.black)
+}
+
+/// 采集、解码、写出并检查 `frames` 个视频帧
+pub async fn run(reader: &mut VideoStreamReader, frames: usize, dir: &Path, ffmpeg: &Path) -> Result<SelftestReport> {
+    info!("🧪 Selftest: capturing {} frames", frames);
+    let (stream, captured) = tokio::time::timeout(CAPTURE_TIMEOUT, capture(reader, frames))
+        .await
+        .map_err(|_| ScrcpyError::VideoStream(format!("selftest: no video within {}s (device locked?)", CAPTURE_TIMEOUT.as_secs())))??;
+
+    tokio::fs::create_dir_all(dir).await?;
+    tokio::fs::write(dir.join("capture.h264"), &stream).await?;
+    let files = decode(&stream, dir, ffmpeg).await?;
+
+    let mut checks = Vec::with_capacity(files.len());
+    for path in files {
+        let image = image::open(&path)
+            .map_err(|e| ScrcpyError::Parse(format!("selftest: cannot read {:?}: {}", path, e)))?
+            .to_rgb8();
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let check = check_frame(name, &image);
+        info!("🧪 {} {}x{} avg rgb ({:.0}, {:.0}, {:.0}){}",
+            check.file, check.width, check.height,
+            check.average_rgb[0], check.average_rgb[1], check.average_rgb[2],
+            if check.black { " [black]" } else { "" });
+        checks.push(check);
+    }
+
+    let report = SelftestReport { captured_frames: captured, decoded_frames: checks.len(), passed: passed(&checks), frames: checks };
+    let json = serde_json::to_vec_pretty(&report).map_err(|e| ScrcpyError::Parse(e.to_string()))?;
+    tokio::fs::write(dir.join("selftest.json"), json).await?;
+
+    if report.decoded_frames == 0 {
+        return Err(ScrcpyError::VideoStream("selftest: ffmpeg decoded no frames".to_string()));
+    }
+    if !report.passed {
+        return Err(ScrcpyError::VideoStream(format!("selftest: all {} decoded frames are black", report.decoded_frames)));
+    }
+    info!("✅ Selftest passed: {} frames decoded, report in {:?}", report.decoded_frames, dir.join("selftest.json"));
+    Ok(report)
+}
+
+/// 读取 SPS/PPS 和从第一个 IDR 开始的视频帧，拼成 Annex-B 码流
+async fn capture(reader: &mut VideoStreamReader, frames: usize) -> Result<(Vec<u8>, usize)> {
+    let mut stream = Vec::new();
+    let (mut sps, mut pps) = (None, None);
+    let mut captured = 0;
+    while captured < frames {
+        let Some(frame) = reader.read_frame(false).await? else {
+            return Err(ScrcpyError::VideoStream(format!("selftest: stream ended after {} frames", captured)));
+        };
+        match frame.data[0] & 0x1F {
+            7 => sps = Some(frame.data),
+            8 => pps = Some(frame.data),
+            // 从第一个 IDR 开始，之前的 P 帧无法解码
+            5 if captured == 0 => {
+                let (Some(sps), Some(pps)) = (&sps, &pps) else {
+                    warn!("🧪 IDR before SPS/PPS, waiting for the next one");
+                    continue;
+                };
+                for nal in [sps, pps, &frame.data] {
+                    stream.extend_from_slice(&[0, 0, 0, 1]);
+                    stream.extend_from_slice(nal);
+                }
+                captured += 1;
+            }
+            1 | 5 if captured > 0 => {
+                stream.extend_from_slice(&[0, 0, 0, 1]);
+                stream.extend_from_slice(&frame.data);
+                captured += 1;
+            }
+            _ => {}
+        }
+    }
+    Ok((stream, captured))
+}
+
+/// 用 ffmpeg 把码流解码成 PNG，返回按帧序排列的文件
+async fn decode(stream: &[u8], dir: &Path, ffmpeg: &Path) -> Result<Vec<PathBuf>> {
+    // 清除上一次自检留下的帧，避免混入本次结果
+    for path in frame_files(dir).await? {
+        tokio::fs::remove_file(path).await?;
+    }
+
+    let mut child = Command::new(ffmpeg)
+        .args(["-hide_banner", "-loglevel", "error", "-y", "-f", "h264", "-i", "pipe:0"])
+        .arg(dir.join("frame_%03d.png"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| ScrcpyError::Io(std::io::Error::new(e.kind(), format!("selftest: cannot run {:?}: {}", ffmpeg, e))))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(stream).await?;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(ScrcpyError::VideoStream(format!(
+            "selftest: ffmpeg failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    frame_files(dir).await
+}
+
+async fn frame_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with("frame_") && name.ends_with(".png") {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_checks() {
+        let black = check_frame("frame_001.png".to_string(), &image::RgbImage::new(4, 2));
+        assert!(black.black);
+        assert_eq!((black.width, black.height, black.luma), (4, 2, 0.0));
+        assert!(!passed(std::slice::from_ref(&black)));
+        assert!(!passed(&[]));
+
+        // 一半白一半黑：平均亮度约 127
+        let half = image::RgbImage::from_fn(4, 2, |x, _| if x < 2 { image::Rgb([255, 255, 255]) } else { image::Rgb([0, 0, 0]) });
+        let half = check_frame("frame_002.png".to_string(), &half);
+        assert_eq!(half.average_rgb, [127.5, 127.5, 127.5]);
+        assert!(!half.black);
+        assert_ne!(half.sha256, black.sha256);
+        assert!(passed(&[black, half]));
+    }
+}
@@ -0,0 +1,188 @@
+//! 录制时间线模块 - `--record-timeline` 在 `--raw-output` 录制文件旁写一个 WebVTT 元数据轨道（`<文件>.vtt`）
+//!
+//! 每个 IDR 帧和每个控制事件一条 cue，cue 内容是一行 JSON，便于把录制定位到"点击 X 的时刻"：
+//! - `{"kind":"keyframe","frame":120,"pts":0,"wall_ms":1735689600000}`
+//! - `{"kind":"control","frame":125,"wall_ms":1735689600123,"event":{"type":"touch",...}}`
+//!
+//! cue 时间是相对录制第一帧的时间：设备提供 PTS 时按 PTS 计算，raw_stream 模式没有 PTS，
+//! 按收到帧的时间计算（与 `ffmpeg -use_wallclock_as_timestamps` 封装的时间线一致）。
+//! `frame` 是录制中的视频帧序号（从 0 开始，不含 SPS/PPS），按帧率换算可以精确到帧。
+//! 控制事件以最近一帧为基准加上经过的时间；连续的触摸 MOVE 和不改变按键状态的相对鼠标移动不记录。
+
+use crate::error::Result;
+use crate::scrcpy::control::{AndroidMotionEventAction, ControlEvent};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// cue 时长（时间线上的点事件）
+const CUE_DURATION_US: u64 = 1_000;
+
+/// 录制时间线旁路文件
+pub struct TimelineSidecar {
+    tx: mpsc::UnboundedSender<String>,
+    task: JoinHandle<usize>,       // 返回写入的 cue 数量
+    origin: Option<(u64, Instant)>,  // 第一帧的 (PTS, 收到时间)
+    last: Option<(u64, Instant)>,    // 最近一帧的 (时间线位置, 收到时间)
+    frames: u64,                     // 已录制的视频帧数
+    mouse_buttons: u8,               // UHID 相对鼠标的按键状态（只记录按键变化）
+}
+
+impl TimelineSidecar {
+    /// 旁路文件路径：录制文件名后加 `.vtt`
+    pub fn sidecar_path(recording: &Path) -> PathBuf {
+        let mut path = recording.as_os_str().to_owned();
+        path.push(".vtt");
+        PathBuf::from(path)
+    }
+
+    /// 创建旁路文件并写入 WebVTT 文件头
+    pub async fn create(path: &Path) -> Result<Self> {
+        let file = tokio::fs::File::create(path).await?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(write_cues(BufWriter::new(file), rx, path.to_path_buf()));
+        info!("🕒 Recording timeline: {:?}", path);
+        Ok(Self { tx, task, origin: None, last: None, frames: 0, mouse_buttons: 0 })
+    }
+
+    /// 录制了一个 NAL 单元（在写入录制文件的同一位置调用）
+    pub fn on_frame(&mut self, nal_header: u8, pts: u64, now: Instant) {
+        let nal_type = nal_header & 0x1F;
+        if !matches!(nal_type, 1 | 5) {
+            return;
+        }
+        let (origin_pts, origin_at) = *self.origin.get_or_insert((pts, now));
+        let offset = if pts != 0 && origin_pts != 0 {
+            pts.saturating_sub(origin_pts)
+        } else {
+            now.duration_since(origin_at).as_micros() as u64
+        };
+        // cue 必须按开始时间排序
+        let offset = self.last.map_or(offset, |(last, _)| offset.max(last));
+        self.last = Some((offset, now));
+
+        if nal_type == 5 {
+            self.push_cue(offset, json!({ "kind": "keyframe", "frame": self.frames, "pts": pts, "wall_ms": wall_ms() }));
+        }
+        self.frames += 1;
+    }
+
+    /// 转发给设备的控制事件（录制开始前的事件不记录）
+    pub fn on_control(&mut self, event: &ControlEvent, now: Instant) {
+        let Some((last, last_at)) = self.last else {
+            return;
+        };
+        match event {
+            ControlEvent::Touch(touch) if matches!(touch.action, AndroidMotionEventAction::Move | AndroidMotionEventAction::HoverMove) => return,
+            ControlEvent::UhidMouseRel(mouse) if mouse.buttons == self.mouse_buttons => return,
+            ControlEvent::UhidMouseRel(mouse) => self.mouse_buttons = mouse.buttons,
+            _ => {}
+        }
+        let offset = last + now.saturating_duration_since(last_at).as_micros() as u64;
+        self.last = Some((offset, now));
+        self.push_cue(offset, json!({ "kind": "control", "frame": self.frames, "wall_ms": wall_ms(), "event": event }));
+    }
+
+    fn push_cue(&self, offset_us: u64, payload: serde_json::Value) {
+        let cue = format!(
+            "{} --> {}\n{}\n\n",
+            format_timestamp(offset_us),
+            format_timestamp(offset_us + CUE_DURATION_US),
+            payload
+        );
+        let _ = self.tx.send(cue);
+    }
+
+    /// 写完所有 cue 并关闭文件（关闭服务时调用）
+    pub async fn finish(self) {
+        drop(self.tx);
+        match self.task.await {
+            Ok(cues) => info!("🕒 Recording timeline closed ({} cues)", cues),
+            Err(e) => warn!("❌ Recording timeline task failed: {}", e),
+        }
+    }
+}
+
+async fn write_cues<W: tokio::io::AsyncWrite + Unpin>(mut writer: W, mut rx: mpsc::UnboundedReceiver<String>, path: PathBuf) -> usize {
+    let mut cues = 0;
+    if let Err(e) = writer.write_all(b"WEBVTT\n\n").await {
+        warn!("❌ Recording timeline {:?} write failed: {}", path, e);
+        return cues;
+    }
+    while let Some(cue) = rx.recv().await {
+        if let Err(e) = writer.write_all(cue.as_bytes()).await {
+            warn!("❌ Recording timeline {:?} write failed: {}", path, e);
+            return cues;
+        }
+        cues += 1;
+    }
+    if let Err(e) = writer.flush().await {
+        warn!("❌ Recording timeline {:?} flush failed: {}", path, e);
+    }
+    cues
+}
+
+/// WebVTT 时间戳：`HH:MM:SS.mmm`
+fn format_timestamp(us: u64) -> String {
+    let ms = us / 1000;
+    format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+fn wall_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrcpy::control::TouchEvent;
+    use std::time::Duration;
+
+    fn touch(action: AndroidMotionEventAction) -> ControlEvent {
+        ControlEvent::Touch(TouchEvent { action, pointer_id: -2, x: 100.0, y: 200.0, pressure: 1.0, width: 1080, height: 2400, buttons: 0 })
+    }
+
+    #[tokio::test]
+    async fn test_timeline_cues() {
+        assert_eq!(format_timestamp(3_723_004_000), "01:02:03.004");
+        assert_eq!(TimelineSidecar::sidecar_path(Path::new("/tmp/rec.h264")), PathBuf::from("/tmp/rec.h264.vtt"));
+
+        let path = std::env::temp_dir().join(format!("rust-scrcpy-timeline-test-{}.vtt", std::process::id()));
+        let mut timeline = TimelineSidecar::create(&path).await.unwrap();
+        let start = Instant::now();
+
+        // 录制开始前的控制事件不记录
+        timeline.on_control(&touch(AndroidMotionEventAction::Down), start);
+        timeline.on_frame(0x67, 0, start);  // SPS 不计帧
+        timeline.on_frame(0x65, 0, start);
+        timeline.on_frame(0x41, 0, start + Duration::from_millis(33));
+        timeline.on_control(&touch(AndroidMotionEventAction::Down), start + Duration::from_millis(50));
+        timeline.on_control(&touch(AndroidMotionEventAction::Move), start + Duration::from_millis(60));
+        timeline.on_frame(0x65, 0, start + Duration::from_millis(2_000));
+        timeline.finish().await;
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut blocks = content.split("\n\n").filter(|block| !block.is_empty());
+        assert_eq!(blocks.next(), Some("WEBVTT"));
+        let cues: Vec<(&str, serde_json::Value)> = blocks
+            .map(|block| {
+                let (timing, payload) = block.split_once('\n').unwrap();
+                (timing, serde_json::from_str(payload).unwrap())
+            })
+            .collect();
+
+        assert_eq!(cues.len(), 3);
+        assert_eq!(cues[0].0, "00:00:00.000 --> 00:00:00.001");
+        assert_eq!((cues[0].1["kind"].as_str(), cues[0].1["frame"].as_u64()), (Some("keyframe"), Some(0)));
+        assert_eq!(cues[1].0, "00:00:00.050 --> 00:00:00.051");
+        assert_eq!(cues[1].1["event"]["type"], "touch");
+        assert_eq!(cues[1].1["frame"], 2);
+        assert_eq!(cues[2].0, "00:00:02.000 --> 00:00:02.001");
+        assert_eq!(cues[2].1["frame"], 2);
+    }
+}
@@ -0,0 +1,255 @@
+//! MPEG-TS 封装 - 把 Annex-B H.264 NAL 单元封装为 188 字节的 TS 包
+//!
+//! 只有一路视频（PID 0x100，stream_type 0x1B），PCR 使用视频 PID。
+//! SPS/PPS/SEI 先缓存，和后面的 slice 组成一个访问单元放进同一个 PES；
+//! 每个 IDR 前重复发送 PAT/PMT，播放器可以从任意关键帧开始解码。
+
+use crate::scrcpy::video::nal_type;
+
+/// TS 包大小
+pub const TS_PACKET_SIZE: usize = 188;
+
+const PAT_PID: u16 = 0x0000;
+const PMT_PID: u16 = 0x1000;
+const VIDEO_PID: u16 = 0x0100;
+const PROGRAM_NUMBER: u16 = 1;
+const STREAM_TYPE_H264: u8 = 0x1B;
+
+/// 访问单元分隔符（AUD），放在每个 PES 开头，方便播放器切分帧
+const ACCESS_UNIT_DELIMITER: [u8; 6] = [0x00, 0x00, 0x00, 0x01, 0x09, 0xF0];
+
+/// MPEG-2 CRC32（多项式 0x04C11DB7，不反转，初值 0xFFFFFFFF）
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// MPEG-TS 封装器（每个 /raw?fmt=ts 消费者一个）
+#[derive(Debug, Default)]
+pub struct TsMuxer {
+    pat_cc: u8,
+    pmt_cc: u8,
+    video_cc: u8,
+    pending: Vec<u8>,  // 等待下一个 slice 的 SPS/PPS/SEI
+}
+
+impl TsMuxer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 封装一个带起始码的 NAL 单元；`pts` 为 90kHz 时间戳
+    ///
+    /// 参数集等非 slice NAL 会被缓存，返回空；遇到 slice 时输出整个访问单元的 TS 包。
+    pub fn push_nal(&mut self, nal: &[u8], pts: u64) -> Vec<u8> {
+        let nal_type = nal_type(nal);
+        if !matches!(nal_type, 1 | 5) {
+            if nal_type != 9 {
+                self.pending.extend_from_slice(nal);
+            }
+            return Vec::new();
+        }
+
+        let keyframe = nal_type == 5;
+        let mut out = Vec::new();
+        if keyframe {
+            self.write_psi(&mut out);
+        }
+
+        let mut access_unit = Vec::with_capacity(ACCESS_UNIT_DELIMITER.len() + self.pending.len() + nal.len());
+        access_unit.extend_from_slice(&ACCESS_UNIT_DELIMITER);
+        access_unit.append(&mut self.pending);
+        access_unit.extend_from_slice(nal);
+
+        let pes = pes_packet(&access_unit, pts);
+        let mut cc = self.video_cc;
+        write_packets(&mut out, VIDEO_PID, &mut cc, &pes, Some(pts), keyframe);
+        self.video_cc = cc;
+        out
+    }
+
+    /// 输出 PAT 和 PMT
+    fn write_psi(&mut self, out: &mut Vec<u8>) {
+        // PAT：program 1 → PMT PID
+        let mut pat = vec![
+            0x00,                                         // table_id
+            0xB0, 0x00,                                   // section_syntax_indicator + section_length（稍后填写）
+            0x00, 0x01,                                   // transport_stream_id
+            0xC1,                                         // version 0, current_next 1
+            0x00, 0x00,                                   // section_number, last_section_number
+        ];
+        pat.extend_from_slice(&PROGRAM_NUMBER.to_be_bytes());
+        pat.extend_from_slice(&(0xE000 | PMT_PID).to_be_bytes());
+        let mut cc = self.pat_cc;
+        write_packets(out, PAT_PID, &mut cc, &psi_section(pat), None, false);
+        self.pat_cc = cc;
+
+        // PMT：一路 H.264 视频
+        let mut pmt = vec![0x02, 0xB0, 0x00];
+        pmt.extend_from_slice(&PROGRAM_NUMBER.to_be_bytes());
+        pmt.extend_from_slice(&[0xC1, 0x00, 0x00]);
+        pmt.extend_from_slice(&(0xE000 | VIDEO_PID).to_be_bytes());  // PCR_PID
+        pmt.extend_from_slice(&[0xF0, 0x00]);                         // program_info_length = 0
+        pmt.push(STREAM_TYPE_H264);
+        pmt.extend_from_slice(&(0xE000 | VIDEO_PID).to_be_bytes());
+        pmt.extend_from_slice(&[0xF0, 0x00]);                         // ES_info_length = 0
+        let mut cc = self.pmt_cc;
+        write_packets(out, PMT_PID, &mut cc, &psi_section(pmt), None, false);
+        self.pmt_cc = cc;
+    }
+}
+
+/// 填写 section_length、追加 CRC，并加上 pointer_field
+fn psi_section(mut section: Vec<u8>) -> Vec<u8> {
+    let section_length = section.len() - 3 + 4;  // 从 section_length 之后到 CRC 结尾
+    section[1] = 0xB0 | ((section_length >> 8) as u8 & 0x0F);
+    section[2] = section_length as u8;
+    let crc = crc32_mpeg2(&section);
+    section.extend_from_slice(&crc.to_be_bytes());
+    section.insert(0, 0x00);  // pointer_field
+    section
+}
+
+/// 33 位时间戳的 5 字节 PES 编码（前缀 0010：只有 PTS）
+fn encode_pts(pts: u64) -> [u8; 5] {
+    let pts = pts & 0x1_FFFF_FFFF;
+    [
+        0x21 | ((pts >> 29) as u8 & 0x0E),
+        (pts >> 22) as u8,
+        ((pts >> 14) as u8 & 0xFE) | 0x01,
+        (pts >> 7) as u8,
+        ((pts << 1) as u8 & 0xFE) | 0x01,
+    ]
+}
+
+/// 视频 PES 包（PES_packet_length 为 0：视频流允许不限长度）
+fn pes_packet(payload: &[u8], pts: u64) -> Vec<u8> {
+    let mut pes = Vec::with_capacity(14 + payload.len());
+    pes.extend_from_slice(&[0x00, 0x00, 0x01, 0xE0, 0x00, 0x00]);
+    pes.extend_from_slice(&[0x80, 0x80, 0x05]);  // marker、PTS_DTS_flags=10、header_data_length
+    pes.extend_from_slice(&encode_pts(pts));
+    pes.extend_from_slice(payload);
+    pes
+}
+
+/// 6 字节 PCR（base 为 90kHz，extension 为 0）
+fn encode_pcr(pcr: u64) -> [u8; 6] {
+    let base = pcr & 0x1_FFFF_FFFF;
+    [
+        (base >> 25) as u8,
+        (base >> 17) as u8,
+        (base >> 9) as u8,
+        (base >> 1) as u8,
+        ((base & 1) << 7) as u8 | 0x7E,
+        0x00,
+    ]
+}
+
+/// 把负载切成 TS 包；第一个包带 PUSI，可选 PCR；最后一个包用自适应字段填充
+fn write_packets(out: &mut Vec<u8>, pid: u16, cc: &mut u8, payload: &[u8], pcr: Option<u64>, random_access: bool) {
+    let mut offset = 0;
+    let mut first = true;
+    while first || offset < payload.len() {
+        // 自适应字段内容（不含长度字节）
+        let mut adaptation = Vec::new();
+        if first && (pcr.is_some() || random_access) {
+            let mut flags = 0u8;
+            if random_access {
+                flags |= 0x40;
+            }
+            if pcr.is_some() {
+                flags |= 0x10;
+            }
+            adaptation.push(flags);
+            if let Some(pcr) = pcr {
+                adaptation.extend_from_slice(&encode_pcr(pcr));
+            }
+        }
+
+        let header_len = 4;
+        let mut adaptation_total = if adaptation.is_empty() { 0 } else { adaptation.len() + 1 };
+        let remaining = payload.len() - offset;
+        let space = TS_PACKET_SIZE - header_len - adaptation_total;
+        let take = remaining.min(space);
+        if take < space {
+            // 填充：自适应字段扩展到刚好填满整个包
+            let needed = TS_PACKET_SIZE - header_len - take;
+            if adaptation.is_empty() && needed >= 2 {
+                adaptation.push(0x00);
+            }
+            if needed >= 2 {
+                adaptation.resize(needed - 1, 0xFF);
+            }
+            adaptation_total = needed;
+        }
+
+        let pusi = if first { 0x40 } else { 0x00 };
+        let adaptation_control = if adaptation_total > 0 { 0x30 } else { 0x10 };
+        out.push(0x47);
+        out.push(pusi | ((pid >> 8) as u8 & 0x1F));
+        out.push(pid as u8);
+        out.push(adaptation_control | (*cc & 0x0F));
+        *cc = (*cc + 1) & 0x0F;
+        if adaptation_total > 0 {
+            out.push((adaptation_total - 1) as u8);
+            out.extend_from_slice(&adaptation);
+        }
+        out.extend_from_slice(&payload[offset..offset + take]);
+
+        offset += take;
+        first = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nal(nal_type: u8, len: usize) -> Vec<u8> {
+        let mut data = vec![0x00, 0x00, 0x00, 0x01, nal_type];
+        data.resize(len, 0xAB);
+        data
+    }
+
+    #[test]
+    fn test_crc32_mpeg2() {
+        // CRC-32/MPEG-2 的标准校验值
+        assert_eq!(crc32_mpeg2(b"123456789"), 0x0376_E6E7);
+    }
+
+    #[test]
+    fn test_mux_access_units() {
+        let mut muxer = TsMuxer::new();
+        assert!(muxer.push_nal(&nal(0x67, 12), 0).is_empty());
+        assert!(muxer.push_nal(&nal(0x68, 8), 0).is_empty());
+
+        let idr = muxer.push_nal(&nal(0x65, 1000), 90_000);
+        assert_eq!(idr.len() % TS_PACKET_SIZE, 0);
+        assert!(idr.chunks(TS_PACKET_SIZE).all(|p| p[0] == 0x47));
+
+        // PAT、PMT，然后是带 PUSI、随机访问标志和 PCR 的视频包
+        let pids: Vec<u16> = idr.chunks(TS_PACKET_SIZE).map(|p| ((p[1] as u16 & 0x1F) << 8) | p[2] as u16).collect();
+        assert_eq!(&pids[..3], &[PAT_PID, PMT_PID, VIDEO_PID]);
+        let video = &idr[2 * TS_PACKET_SIZE..];
+        assert_eq!(video[1] & 0x40, 0x40);
+        assert_eq!(video[5] & 0x50, 0x50);
+
+        // PES 中包含 AUD、SPS、PPS 和 IDR
+        let payload_start = 4 + 1 + video[4] as usize;
+        assert_eq!(&video[payload_start..payload_start + 4], &[0x00, 0x00, 0x01, 0xE0]);
+        assert_eq!(&video[payload_start + 14..payload_start + 20], &ACCESS_UNIT_DELIMITER);
+        assert_eq!(video[payload_start + 24], 0x67);
+
+        // P 帧不重复 PAT/PMT，连续计数器递增
+        let p = muxer.push_nal(&nal(0x41, 100), 93_000);
+        assert_eq!(p.len(), TS_PACKET_SIZE);
+        let last_idr_cc = idr[idr.len() - TS_PACKET_SIZE + 3] & 0x0F;
+        assert_eq!(p[3] & 0x0F, (last_idr_cc + 1) & 0x0F);
+    }
+}
@@ -0,0 +1,107 @@
+//! 内嵌的前端解码器资源
+//!
+//! 解码器脚本在编译时通过 `include_str!` 打包进二进制。启动时计算并输出每个文件的
+//! SHA-256，同时通过 `/stats` 暴露，方便排查“解码器加载失败”时确认实际提供的内容。
+
+use crate::error::{Result, ScrcpyError};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+use tracing::info;
+
+/// Broadway.js H.264 软解码器
+pub const BROADWAY_JS: &str = include_str!("../decoder/Decoder.min.js");
+
+/// JMuxer MSE 封装器
+pub const JMUXER_JS: &str = include_str!("../decoder/jmuxer.min.js");
+
+// 编译期检查：解码器文件不能为空
+const _: () = assert!(!BROADWAY_JS.is_empty(), "src/decoder/Decoder.min.js is empty");
+const _: () = assert!(!JMUXER_JS.is_empty(), "src/decoder/jmuxer.min.js is empty");
+
+/// 解码器资源信息
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DecoderAsset {
+    pub name: &'static str,
+    #[serde(skip)]
+    pub content: &'static str,
+    #[serde(skip)]
+    pub global: &'static str,  // 脚本导出的全局对象名，用于基本校验
+    pub bytes: usize,
+    pub sha256: String,
+}
+
+/// 获取所有内嵌解码器的信息（SHA-256 只计算一次）
+pub fn decoder_assets() -> &'static [DecoderAsset] {
+    static ASSETS: OnceLock<Vec<DecoderAsset>> = OnceLock::new();
+    ASSETS.get_or_init(|| {
+        [
+            ("Decoder.min.js", BROADWAY_JS, "Decoder"),
+            ("jmuxer.min.js", JMUXER_JS, "JMuxer"),
+        ]
+        .into_iter()
+        .map(|(name, content, global)| DecoderAsset {
+            name,
+            content,
+            global,
+            bytes: content.len(),
+            sha256: sha256_hex(content.as_bytes()),
+        })
+        .collect()
+    })
+}
+
+/// 启动时校验解码器资源并输出哈希
+///
+/// 只做基本校验：不是 HTML 错误页、不含 NUL 字节、包含预期的全局导出名
+pub fn verify_decoder_assets() -> Result<()> {
+    for asset in decoder_assets() {
+        check_script(asset.name, asset.content, asset.global)?;
+        info!("🧩 Decoder {} ({} bytes) sha256={}", asset.name, asset.bytes, asset.sha256);
+    }
+    Ok(())
+}
+
+fn check_script(name: &str, content: &str, global: &str) -> Result<()> {
+    let trimmed = content.trim_start_matches('\u{feff}').trim_start();
+    if trimmed.is_empty() {
+        return Err(ScrcpyError::Parse(format!("Decoder {} is empty", name)));
+    }
+    if trimmed.starts_with('<') {
+        return Err(ScrcpyError::Parse(format!("Decoder {} looks like HTML, not JavaScript", name)));
+    }
+    if content.contains('\0') {
+        return Err(ScrcpyError::Parse(format!("Decoder {} contains binary data", name)));
+    }
+    if !content.contains(global) {
+        return Err(ScrcpyError::Parse(format!("Decoder {} does not export `{}`", name, global)));
+    }
+    Ok(())
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundled_decoders_are_valid() {
+        assert!(verify_decoder_assets().is_ok());
+        for asset in decoder_assets() {
+            assert_eq!(asset.sha256.len(), 64);
+        }
+    }
+
+    #[test]
+    fn test_check_script_rejects_html() {
+        assert!(check_script("x.js", "<!DOCTYPE html><html></html>", "X").is_err());
+        assert!(check_script("x.js", "   ", "X").is_err());
+        assert!(check_script("x.js", "var Y = 1;", "X").is_err());
+        assert!(check_script("x.js", "var X = 1;", "X").is_ok());
+    }
+}
@@ -0,0 +1,115 @@
+//! /audio 路由 - `--audio` 时通过单独的 WebSocket 向浏览器广播设备声音
+//!
+//! 连接后先发送一条文本消息说明格式：
+//! `{"type":"audio_config","codec":"opus","sample_rate":48000,"channels":2}`，
+//! 之后每条二进制消息是一个 Opus 包（约 20ms），网页用 WebCodecs 的 `AudioDecoder` 解码后按顺序排入 WebAudio 播放。
+//!
+//! 音频不经过视频的追帧逻辑：跟不上广播（Lagged）时直接跳过丢失的部分，播放端会出现一次短暂的断音。
+//! `--only-app` 的目标应用不在前台时丢弃音频（连接保持），与视频一起暂停。
+
+use crate::scrcpy::audio::{AUDIO_CHANNELS, AUDIO_SAMPLE_RATE};
+use crate::ws::clients::{ClientGuard, ClientKind, ClientRegistry};
+use crate::ws::server::PrivacyGate;
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::response::IntoResponse;
+use bytes::Bytes;
+use tokio::sync::{broadcast, watch};
+use tracing::{debug, info, warn};
+
+/// 音频广播通道的容量（每块约 20ms，足够吸收短暂的网络抖动）
+pub const AUDIO_CHANNEL_CAPACITY: usize = 32;
+
+/// 连接后发送的格式说明
+pub fn audio_config_message() -> String {
+    serde_json::json!({
+        "type": "audio_config",
+        "codec": "opus",
+        "sample_rate": AUDIO_SAMPLE_RATE,
+        "channels": AUDIO_CHANNELS,
+    })
+    .to_string()
+}
+
+/// 处理 /audio 连接
+pub async fn handle_audio_socket(
+    ws: WebSocketUpgrade,
+    clients: ClientRegistry,
+    addr: String,
+    audio_tx: broadcast::Sender<Bytes>,
+    privacy: PrivacyGate,
+    shutdown_rx: watch::Receiver<Option<String>>,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| {
+        let client = clients.register(addr, ClientKind::Audio);
+        handle_audio_client(socket, client, audio_tx.subscribe(), privacy, shutdown_rx)
+    })
+}
+
+async fn handle_audio_client(
+    mut socket: WebSocket,
+    mut client: ClientGuard,
+    mut rx: broadcast::Receiver<Bytes>,
+    privacy: PrivacyGate,
+    mut shutdown_rx: watch::Receiver<Option<String>>,
+) {
+    let addr = client.info().addr.clone();
+    info!("🔊 New audio client connected: {} (id {})", addr, client.info().id);
+    if socket.send(Message::Text(audio_config_message())).await.is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            packet = rx.recv() => {
+                match packet {
+                    // 隐私模式：丢弃音频，不断开连接
+                    Ok(_) if privacy.is_hidden() => {}
+                    Ok(packet) => {
+                        if socket.send(Message::Binary(packet.to_vec())).await.is_err() {
+                            info!("👋 Audio client {} disconnected (send failed)", addr);
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!("🔊 Audio client {} lagged, skipped {} packets", addr, skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+            reason = client.kicked() => {
+                info!("🚫 Kicking audio client {}: {}", addr, reason);
+                let _ = socket.send(Message::Close(Some(CloseFrame {
+                    code: close_code::POLICY,
+                    reason: reason.into(),
+                }))).await;
+                break;
+            }
+            Ok(()) = shutdown_rx.changed() => {
+                let reason = shutdown_rx.borrow_and_update().clone();
+                if let Some(reason) = reason {
+                    info!("🛑 Closing audio client {}: {}", addr, reason);
+                    let _ = socket.send(Message::Close(Some(CloseFrame {
+                        code: close_code::AWAY,
+                        reason: reason.into(),
+                    }))).await;
+                    break;
+                }
+            }
+            msg = socket.recv() => {
+                match msg {
+                    Some(Ok(Message::Close(_))) | None => {
+                        info!("👋 Audio client {} disconnected", addr);
+                        break;
+                    }
+                    Some(Err(e)) => {
+                        warn!("❌ Audio client {} disconnected (recv error): {}", addr, e);
+                        break;
+                    }
+                    _ => {
+                        // 音频连接只接收，忽略客户端消息
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! 访问令牌 - `--auth-token` 时除 /healthz 外的所有路由都要求客户端携带令牌
+//!
+//! 令牌放在查询参数 `?token=` 或 `Authorization: Bearer <token>` 请求头中，缺少或不匹配时返回 401。
+//! 浏览器打开 `/?token=...` 后，响应会设置 `scrcpy_token` Cookie（令牌的 SHA-256，HttpOnly + SameSite=Strict），
+//! 页面随后加载的解码器脚本和 /ws、/control、/audio 连接由浏览器自动带上，不需要在每个 URL 上重复令牌。
+//!
+//! 比较前先对两边做 SHA-256，再对摘要逐字节异或累加，耗时与令牌的内容和长度都无关。
+
+use axum::extract::{Query, Request};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+/// 保存令牌摘要的 Cookie 名称
+pub const AUTH_COOKIE: &str = "scrcpy_token";
+
+/// 请求的认证结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthResult {
+    Token,   // 查询参数或 Authorization 头中的令牌匹配（响应中设置 Cookie）
+    Cookie,  // Cookie 中的令牌摘要匹配
+    Denied,
+}
+
+#[derive(serde::Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// 服务端配置的令牌（只保存摘要）
+#[derive(Clone)]
+pub struct AuthToken {
+    digest: [u8; 32],
+}
+
+impl std::fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AuthToken(<redacted>)")
+    }
+}
+
+impl AuthToken {
+    pub fn new(token: &str) -> Self {
+        Self { digest: digest(token.as_bytes()) }
+    }
+
+    /// 检查请求携带的令牌：查询参数、Authorization 头、Cookie 依次尝试
+    pub fn check(&self, headers: &HeaderMap, uri: &Uri) -> AuthResult {
+        let query_token = Query::<TokenQuery>::try_from_uri(uri).ok().and_then(|Query(query)| query.token);
+        if query_token.is_some_and(|token| self.matches(token.as_bytes())) {
+            return AuthResult::Token;
+        }
+
+        let bearer = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if bearer.is_some_and(|token| self.matches(token.trim().as_bytes())) {
+            return AuthResult::Token;
+        }
+
+        let cookie = headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(name, _)| *name == AUTH_COOKIE);
+        if cookie.is_some_and(|(_, value)| constant_time_eq(value.as_bytes(), self.cookie_value().as_bytes())) {
+            return AuthResult::Cookie;
+        }
+
+        AuthResult::Denied
+    }
+
+    fn matches(&self, candidate: &[u8]) -> bool {
+        constant_time_eq(&self.digest, &digest(candidate))
+    }
+
+    /// Cookie 中保存的值：令牌摘要的十六进制
+    fn cookie_value(&self) -> String {
+        self.digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn set_cookie_header(&self) -> HeaderValue {
+        let cookie = format!("{}={}; Path=/; HttpOnly; SameSite=Strict", AUTH_COOKIE, self.cookie_value());
+        HeaderValue::from_str(&cookie).expect("hex cookie value is a valid header")
+    }
+}
+
+fn digest(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// 逐字节异或累加后比较，不在第一个不同的字节处提前返回
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// 认证中间件：令牌不匹配时返回 401，不调用后面的处理函数（WebSocket 不会升级）
+pub async fn require_token(token: AuthToken, request: Request, next: Next) -> Response {
+    match token.check(request.headers(), request.uri()) {
+        AuthResult::Cookie => next.run(request).await,
+        AuthResult::Token => {
+            let mut response = next.run(request).await;
+            response.headers_mut().append(header::SET_COOKIE, token.set_cookie_header());
+            response
+        }
+        AuthResult::Denied => {
+            debug!("🔒 Rejected unauthenticated request: {}", request.uri().path());
+            (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                "Unauthorized: pass ?token=<token> or an Authorization: Bearer header\n",
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(token: &AuthToken, uri: &str, headers: &[(header::HeaderName, &str)]) -> AuthResult {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(name.clone(), HeaderValue::from_str(value).unwrap());
+        }
+        token.check(&map, &uri.parse().unwrap())
+    }
+
+    #[test]
+    fn test_check_token() {
+        let token = AuthToken::new("s3cret");
+        assert_eq!(check(&token, "/ws?token=s3cret&codec=h264", &[]), AuthResult::Token);
+        assert_eq!(check(&token, "/?token=s3cre", &[]), AuthResult::Denied);
+        assert_eq!(check(&token, "/", &[]), AuthResult::Denied);
+        assert_eq!(check(&token, "/stats", &[(header::AUTHORIZATION, "Bearer s3cret")]), AuthResult::Token);
+        assert_eq!(check(&token, "/stats", &[(header::AUTHORIZATION, "Basic s3cret")]), AuthResult::Denied);
+
+        // 首次认证后设置的 Cookie 可以单独使用
+        let cookie = token.set_cookie_header();
+        let pair = cookie.to_str().unwrap().split(';').next().unwrap().to_string();
+        assert!(!pair.contains("s3cret"));
+        assert_eq!(check(&token, "/decoder/Decoder.min.js", &[(header::COOKIE, &format!("theme=dark; {}", pair))]), AuthResult::Cookie);
+        assert_eq!(check(&AuthToken::new("other"), "/", &[(header::COOKIE, &pair)]), AuthResult::Denied);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+    }
+}
@@ -0,0 +1,246 @@
+//! 客户端登记模块 - 记录当前连接的 WebSocket 客户端（地址、类型、连接时间）
+
+use axum::http::HeaderMap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, watch};
+
+/// 客户端类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientKind {
+    Viewer,       // /ws：视频 + 控制
+    ControlOnly,  // /control：仅控制
+    Audio,        // /audio：仅音频（--audio）
+}
+
+/// 已连接客户端的信息（/stats 中的 viewers 列表）
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientInfo {
+    pub id: u64,
+    pub addr: String,
+    pub kind: ClientKind,
+    pub connected_at: u64,  // Unix 时间戳（秒）
+    pub lag_recoveries: u64,  // 反复积压后重新同步参数集的次数（--lag-resync-threshold）
+}
+
+/// 客户端登记表（可克隆，内部共享）
+#[derive(Debug, Clone, Default)]
+pub struct ClientRegistry {
+    next_id: Arc<AtomicU64>,
+    clients: Arc<Mutex<BTreeMap<u64, ClientEntry>>>,
+    events: Option<broadcast::Sender<String>>,  // 连接/断开时广播 session_event
+}
+
+#[derive(Debug)]
+struct ClientEntry {
+    info: ClientInfo,
+    kick_tx: watch::Sender<Option<String>>,  // Some(原因) 时该客户端断开
+}
+
+/// 登记凭据：drop 时自动注销
+pub struct ClientGuard {
+    registry: ClientRegistry,
+    info: ClientInfo,
+    kick_rx: watch::Receiver<Option<String>>,
+}
+
+/// 要踢出的客户端：按 id，或按地址（完整 `IP:端口` 或只写 IP）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KickTarget {
+    Id(u64),
+    Addr(String),
+}
+
+impl KickTarget {
+    fn matches(&self, info: &ClientInfo) -> bool {
+        match self {
+            KickTarget::Id(id) => info.id == *id,
+            KickTarget::Addr(addr) => {
+                info.addr == *addr
+                    || info.addr.parse::<SocketAddr>().is_ok_and(|a| a.ip().to_string() == *addr)
+            }
+        }
+    }
+}
+
+impl ClientRegistry {
+    /// 连接/断开时通过 `events` 广播 session_event 消息
+    pub fn with_events(events: broadcast::Sender<String>) -> Self {
+        Self { events: Some(events), ..Self::default() }
+    }
+
+    fn notify(&self, event: &str, client: &ClientInfo) {
+        if let Some(events) = &self.events {
+            let _ = events.send(session_event(event, client));
+        }
+    }
+
+    /// 登记新客户端
+    pub fn register(&self, addr: String, kind: ClientKind) -> ClientGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let connected_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let info = ClientInfo { id, addr, kind, connected_at, lag_recoveries: 0 };
+        let (kick_tx, kick_rx) = watch::channel(None);
+        self.clients.lock().unwrap().insert(id, ClientEntry { info: info.clone(), kick_tx });
+        self.notify("connected", &info);
+        ClientGuard { registry: self.clone(), info, kick_rx }
+    }
+
+    /// 当前连接的客户端列表（按 id 排序）
+    pub fn list(&self) -> Vec<ClientInfo> {
+        self.clients.lock().unwrap().values().map(|e| e.info.clone()).collect()
+    }
+
+    /// 断开匹配的客户端，返回被踢出的客户端
+    pub fn kick(&self, target: &KickTarget, reason: &str) -> Vec<ClientInfo> {
+        self.clients
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|e| target.matches(&e.info))
+            .map(|e| {
+                let _ = e.kick_tx.send(Some(reason.to_string()));
+                e.info.clone()
+            })
+            .collect()
+    }
+}
+
+impl ClientGuard {
+    pub fn info(&self) -> &ClientInfo {
+        &self.info
+    }
+
+    /// 记录一次积压恢复（/stats 中的 lag_recoveries）
+    pub fn record_lag_recovery(&mut self) {
+        self.info.lag_recoveries += 1;
+        if let Some(entry) = self.registry.clients.lock().unwrap().get_mut(&self.info.id) {
+            entry.info.lag_recoveries = self.info.lag_recoveries;
+        }
+    }
+
+    /// 等待该客户端被踢出，返回原因
+    pub async fn kicked(&mut self) -> String {
+        loop {
+            if let Some(reason) = self.kick_rx.borrow_and_update().clone() {
+                return reason;
+            }
+            if self.kick_rx.changed().await.is_err() {
+                // 登记表中的发送端不会在 guard 存活期间被移除，这里只作兜底
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.registry.clients.lock().unwrap().remove(&self.info.id);
+        self.registry.notify("disconnected", &self.info);
+    }
+}
+
+/// 确定客户端地址
+///
+/// 默认使用 TCP 对端地址；`trust_proxy` 时优先取 `X-Forwarded-For` 的最后一个地址，
+/// 即可信反向代理追加的、与它直接相连的客户端。前面的条目由客户端自己提供，可以伪造。
+/// 只应在服务位于一层可信反向代理之后时开启。
+pub fn client_addr(peer: SocketAddr, headers: &HeaderMap, trust_proxy: bool) -> String {
+    if trust_proxy {
+        // 多个 X-Forwarded-For 头按顺序拼接，代理追加的条目在最后一个头的末尾
+        let forwarded = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .next_back()
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .and_then(|v| v.trim().parse::<IpAddr>().ok());
+        if let Some(ip) = forwarded {
+            return ip.to_string();
+        }
+    }
+    peer.to_string()
+}
+
+/// 客户端连接/断开事件，广播给所有客户端
+pub fn session_event(event: &str, client: &ClientInfo) -> String {
+    serde_json::json!({ "type": "session_event", "event": event, "client": client }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_addr() {
+        let peer: SocketAddr = "10.0.0.2:51234".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        // 客户端伪造的 1.2.3.4 在前，代理追加的真实地址在最后
+        headers.insert("x-forwarded-for", "1.2.3.4, 203.0.113.7".parse().unwrap());
+
+        assert_eq!(client_addr(peer, &headers, false), "10.0.0.2:51234");
+        assert_eq!(client_addr(peer, &headers, true), "203.0.113.7");
+
+        headers.append("x-forwarded-for", "198.51.100.9".parse().unwrap());
+        assert_eq!(client_addr(peer, &headers, true), "198.51.100.9");
+
+        headers.insert("x-forwarded-for", "not-an-ip".parse().unwrap());
+        assert_eq!(client_addr(peer, &headers, true), "10.0.0.2:51234");
+    }
+
+    #[test]
+    fn test_registry_unregisters_on_drop() {
+        let registry = ClientRegistry::default();
+        let a = registry.register("1.1.1.1:1".to_string(), ClientKind::Viewer);
+        let b = registry.register("2.2.2.2:2".to_string(), ClientKind::ControlOnly);
+        assert_eq!(registry.list().len(), 2);
+        assert_ne!(a.info().id, b.info().id);
+
+        drop(a);
+        let list = registry.list();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].addr, "2.2.2.2:2");
+
+        let mut b = b;
+        b.record_lag_recovery();
+        assert_eq!(registry.list()[0].lag_recoveries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_kick_by_ip_and_id() {
+        let registry = ClientRegistry::default();
+        let mut a = registry.register("10.0.0.5:1000".to_string(), ClientKind::Viewer);
+        let mut b = registry.register("10.0.0.5:1001".to_string(), ClientKind::Viewer);
+        let c = registry.register("10.0.0.6:1000".to_string(), ClientKind::Viewer);
+
+        let kicked = registry.kick(&KickTarget::Addr("10.0.0.5".to_string()), "bye");
+        assert_eq!(kicked.len(), 2);
+        assert_eq!(a.kicked().await, "bye");
+        assert_eq!(b.kicked().await, "bye");
+
+        let kicked = registry.kick(&KickTarget::Id(c.info().id), "later");
+        assert_eq!(kicked.len(), 1);
+        assert!(registry.kick(&KickTarget::Id(999), "none").is_empty());
+    }
+
+    #[test]
+    fn test_registry_broadcasts_session_events() {
+        let (tx, mut rx) = broadcast::channel(4);
+        let registry = ClientRegistry::with_events(tx);
+        drop(registry.register("1.1.1.1:1".to_string(), ClientKind::Viewer));
+
+        let connected: serde_json::Value = serde_json::from_str(&rx.try_recv().unwrap()).unwrap();
+        let disconnected: serde_json::Value = serde_json::from_str(&rx.try_recv().unwrap()).unwrap();
+        assert_eq!(connected["event"], "connected");
+        assert_eq!(disconnected["event"], "disconnected");
+        assert_eq!(disconnected["client"]["addr"], "1.1.1.1:1");
+    }
+}
@@ -0,0 +1,91 @@
+//! 健康检查模块 - 记录管线存活状态，供 /healthz 判断
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 管线存活状态（主循环和后台任务更新，/healthz 读取）
+#[derive(Debug)]
+pub struct Liveness {
+    device_connected: AtomicBool,
+    server_alive: AtomicBool,
+    last_frame: Mutex<Option<Instant>>,
+}
+
+/// /healthz 返回的 JSON
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub device_connected: bool,
+    pub server_alive: bool,
+    pub last_frame_age_ms: Option<u64>,  // 距上一帧的时间，尚未收到帧时为 null
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Liveness {
+    pub fn new() -> Self {
+        Self {
+            device_connected: AtomicBool::new(true),
+            server_alive: AtomicBool::new(true),
+            last_frame: Mutex::new(None),
+        }
+    }
+
+    /// 收到一个视频帧
+    pub fn mark_frame(&self) {
+        *self.last_frame.lock().unwrap() = Some(Instant::now());
+    }
+
+    pub fn set_device_connected(&self, connected: bool) {
+        self.device_connected.store(connected, Ordering::Relaxed);
+    }
+
+    pub fn set_server_alive(&self, alive: bool) {
+        self.server_alive.store(alive, Ordering::Relaxed);
+    }
+
+    /// 生成健康报告：设备在线、server 进程存活、且 `stale_after` 内收到过帧才算健康
+    pub fn report(&self, stale_after: Duration) -> HealthReport {
+        let device_connected = self.device_connected.load(Ordering::Relaxed);
+        let server_alive = self.server_alive.load(Ordering::Relaxed);
+        let last_frame_age = self.last_frame.lock().unwrap().map(|t| t.elapsed());
+        let frames_flowing = last_frame_age.is_some_and(|age| age <= stale_after);
+
+        HealthReport {
+            healthy: device_connected && server_alive && frames_flowing,
+            device_connected,
+            server_alive,
+            last_frame_age_ms: last_frame_age.map(|age| age.as_millis() as u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_requires_recent_frame() {
+        let liveness = Liveness::new();
+        let stale_after = Duration::from_secs(5);
+        assert!(!liveness.report(stale_after).healthy);
+
+        liveness.mark_frame();
+        assert!(liveness.report(stale_after).healthy);
+
+        liveness.set_server_alive(false);
+        let report = liveness.report(stale_after);
+        assert!(!report.healthy);
+        assert!(!report.server_alive);
+
+        liveness.set_server_alive(true);
+        liveness.set_device_connected(false);
+        assert!(!liveness.report(stale_after).healthy);
+    }
+}
@@ -0,0 +1,413 @@
+//! 多设备 - `--all-devices` 时每台设备运行一个会话，所有会话共用一个 WebSocket 端口
+//!
+//! 每个会话照常构建自己的 Router（/ws、/control、/stats 等，各自订阅自己设备的广播通道），
+//! 注册到 [`DeviceHub`]，按查询参数 `?device=SERIAL` 分发：
+//! `/ws?device=SERIAL` 观看该设备，`/?device=SERIAL` 打开该设备的查看页面（页面中的 /ws、/audio 地址自动带上同样的参数）。
+//! 不带参数的 `/` 返回设备选择页，`GET /devices` 返回设备列表。
+//!
+//! 只有一台设备时不带参数的请求直接交给它，与单设备模式的地址保持兼容。
+//!
+//! `--display-ids` 同时镜像一台设备的多个显示屏时，每个显示屏是一个独立的会话，
+//! Router 挂载在 `/device/<serial>/display/<id>/` 下（页面、/ws、/audio 等都在这个前缀下），不参与 `?device=` 分发。
+
+use crate::error::{Result, ScrcpyError};
+use crate::utils::find_available_port;
+use crate::ws::auth::{self, AuthToken};
+use axum::extract::{Query, Request};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::get;
+use axum::{middleware, Json, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tower::ServiceExt;
+use tracing::info;
+
+/// GET /devices 中的一项
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct DeviceSummary {
+    pub serial: String,
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display: Option<u32>,  // --display-ids 的显示屏会话，页面地址见 [`display_path`]
+}
+
+/// 会话的注册键：设备序列号 +（--display-ids 时）显示屏 id
+type SessionKey = (String, Option<u32>);
+
+struct HubSession {
+    model: String,
+    router: Router,  // 会话的 WebSocketServer::build_router()（显示屏会话已挂载到 display_path 下）
+}
+
+#[derive(serde::Deserialize)]
+struct DeviceQuery {
+    device: Option<String>,
+}
+
+/// 请求交给谁处理
+#[derive(Debug, PartialEq, Eq)]
+enum Target<'a> {
+    Picker,             // 设备选择页
+    Session(&'a str),   // 该设备的 Router
+    Unknown(&'a str),   // ?device= 指定的设备不存在（或会话已结束）
+    Ambiguous,          // 多台设备时缺少 ?device=
+}
+
+/// 按 `?device=` 和路径选择处理请求的会话，`serials` 是当前注册的设备
+fn resolve<'a>(device: Option<&'a str>, path: &str, serials: &[&'a str]) -> Target<'a> {
+    match device {
+        Some(device) if serials.contains(&device) => Target::Session(device),
+        Some(device) => Target::Unknown(device),
+        None if serials.len() == 1 => Target::Session(serials[0]),
+        None if path == "/" => Target::Picker,
+        // 解码器脚本与设备无关，任何一个会话都可以提供
+        None if path.starts_with("/decoder/") && !serials.is_empty() => Target::Session(serials[0]),
+        None => Target::Ambiguous,
+    }
+}
+
+/// 页面中附加到 /ws、/audio 地址后的查询串：`?device=<百分号编码的序列号>`
+///
+/// 网络设备的序列号是 `ip:port`，其他非字母数字字符也一并编码，替换进页面后不会破坏 HTML 和 JS。
+pub fn device_query(serial: &str) -> String {
+    format!("?device={}", percent_encode(serial))
+}
+
+/// 显示屏会话的挂载路径：`/device/<百分号编码的序列号>/display/<id>`
+pub fn display_path(serial: &str, display: u32) -> String {
+    format!("/device/{}/display/{}", percent_encode(serial), display)
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::new();
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+/// 解码路径中的序列号（`%3A` 和直接写的 `:` 都可以），编码不合法时返回 None
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// 解析 `/device/<serial>/display/<id>[/...]`，返回 (序列号, 显示屏 id, 前缀之后的路径)
+fn parse_display_path(path: &str) -> Option<(String, u32, &str)> {
+    let rest = path.strip_prefix("/device/")?;
+    let (serial, rest) = rest.split_once('/')?;
+    let rest = rest.strip_prefix("display/")?;
+    let (display, rest) = rest.find('/').map_or((rest, ""), |i| rest.split_at(i));
+    Some((percent_decode(serial)?, display.parse().ok()?, rest))
+}
+
+/// 设备会话注册表（clone 后共享同一个注册表）
+#[derive(Clone, Default)]
+pub struct DeviceHub {
+    sessions: Arc<RwLock<BTreeMap<SessionKey, HubSession>>>,
+}
+
+impl DeviceHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 注册设备会话的 Router，之后 `?device=serial` 的请求交给它处理
+    ///
+    /// `display` 为 Some 时（--display-ids）Router 挂载到 [`display_path`] 下，只按路径访问。
+    pub async fn register(&self, serial: &str, display: Option<u32>, model: &str, router: Router) {
+        let router = match display {
+            Some(id) => {
+                let path = display_path(serial, id);
+                info!("🖥️  Display {} of {} ({}) available at {}/", id, serial, model, path);
+                Router::new().nest(&path, router)
+            }
+            None => {
+                info!("📱 Device {} ({}) available at /?device={}", serial, model, serial);
+                router
+            }
+        };
+        self.sessions.write().await.insert((serial.to_string(), display), HubSession { model: model.to_string(), router });
+    }
+
+    /// 会话结束时移除，之后的请求返回 404
+    pub async fn unregister(&self, serial: &str, display: Option<u32>) {
+        if self.sessions.write().await.remove(&(serial.to_string(), display)).is_some() {
+            match display {
+                Some(id) => info!("📴 Display {} of {} removed from the device list", id, serial),
+                None => info!("📴 Device {} removed from the device list", serial),
+            }
+        }
+    }
+
+    /// 当前的设备列表（按序列号排序）
+    pub async fn devices(&self) -> Vec<DeviceSummary> {
+        self.sessions
+            .read()
+            .await
+            .iter()
+            .map(|((serial, display), session)| DeviceSummary { serial: serial.clone(), model: session.model.clone(), display: *display })
+            .collect()
+    }
+
+    /// 构建分发路由：`/devices`、`/healthz` 由 hub 处理，其余请求按 `?device=` 交给设备的 Router
+    ///
+    /// 令牌由 hub 统一检查，注册的设备 Router 不需要再设置令牌。
+    pub fn build_router(&self, auth_token: Option<AuthToken>) -> Router {
+        let router = Router::new()
+            .route("/devices", get({
+                let hub = self.clone();
+                move || async move { Json(hub.devices().await) }
+            }))
+            .fallback({
+                let hub = self.clone();
+                move |request: Request| hub.dispatch(request)
+            });
+
+        // 与 WebSocketServer::build_router 一致：/healthz 在令牌检查之后添加，存活探针不需要令牌
+        let router = match auth_token {
+            Some(token) => router.layer(middleware::from_fn(move |request, next| auth::require_token(token.clone(), request, next))),
+            None => router,
+        };
+        let hub = self.clone();
+        router.route("/healthz", get(move |request: Request| hub.healthz(request)))
+    }
+
+    /// 把请求交给 `?device=` 指定的设备 Router
+    async fn dispatch(self, request: Request) -> Response {
+        if let Some((serial, display, rest)) = parse_display_path(request.uri().path()) {
+            let rest = rest.to_string();
+            return self.dispatch_display(request, serial, display, &rest).await;
+        }
+        let device = Query::<DeviceQuery>::try_from_uri(request.uri()).ok().and_then(|Query(query)| query.device);
+        let router = {
+            let sessions = self.sessions.read().await;
+            let serials: Vec<&str> = sessions.keys().filter(|(_, display)| display.is_none()).map(|(serial, _)| serial.as_str()).collect();
+            match resolve(device.as_deref(), request.uri().path(), &serials) {
+                Target::Session(serial) => sessions[&(serial.to_string(), None)].router.clone(),
+                Target::Picker => return Html(PICKER_HTML).into_response(),
+                Target::Unknown(serial) => {
+                    let message = format!("device '{}' is not available", serial);
+                    return (StatusCode::NOT_FOUND, Json(serde_json::json!({ "error": message }))).into_response();
+                }
+                Target::Ambiguous => {
+                    let message = "several devices are mirrored, pass ?device=SERIAL (see /devices)";
+                    return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": message }))).into_response();
+                }
+            }
+        };
+        match router.oneshot(request).await {
+            Ok(response) => response,
+            Err(infallible) => match infallible {},
+        }
+    }
+
+    /// 把 `/device/<serial>/display/<id>/...` 交给该显示屏的会话
+    ///
+    /// 路径中的序列号可以直接写 `:`，转发前统一改写成挂载时的编码形式。
+    async fn dispatch_display(self, mut request: Request, serial: String, display: u32, rest: &str) -> Response {
+        let router = match self.sessions.read().await.get(&(serial.clone(), Some(display))) {
+            Some(session) => session.router.clone(),
+            None => {
+                let message = format!("display {} of device '{}' is not available", display, serial);
+                return (StatusCode::NOT_FOUND, Json(serde_json::json!({ "error": message }))).into_response();
+            }
+        };
+        let query = request.uri().query().map(|query| format!("?{}", query)).unwrap_or_default();
+        let uri = format!("{}{}{}", display_path(&serial, display), rest, query);
+        match uri.parse() {
+            Ok(uri) => *request.uri_mut() = uri,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        }
+        match router.oneshot(request).await {
+            Ok(response) => response,
+            Err(infallible) => match infallible {},
+        }
+    }
+
+    /// `/healthz?device=` 检查单台设备；不带参数时至少有一台设备在线即健康
+    async fn healthz(self, request: Request) -> Response {
+        if Query::<DeviceQuery>::try_from_uri(request.uri()).is_ok_and(|Query(query)| query.device.is_some()) {
+            return self.dispatch(request).await;
+        }
+        let devices = self.sessions.read().await.len();
+        let status = if devices > 0 { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+        (status, Json(serde_json::json!({ "devices": devices }))).into_response()
+    }
+
+    /// 绑定监听地址（端口被占用时自动向后寻找）
+    pub async fn bind(&self, bind_addr: IpAddr, port: u16, tls: bool) -> Result<tokio::net::TcpListener> {
+        let actual_port = find_available_port(bind_addr, port, 100)?;
+        let addr = SocketAddr::new(bind_addr, actual_port);
+        let listener = tokio::net::TcpListener::bind(&addr)
+            .await
+            .map_err(|e| ScrcpyError::Network(format!("Failed to bind {}: {}", addr, e)))?;
+
+        let http_scheme = if tls { "https" } else { "http" };
+        info!("✅ Device picker ready at {}://{}", http_scheme, addr);
+        Ok(listener)
+    }
+
+    /// 在已绑定的监听器上提供服务，直到出错
+    pub async fn serve(self, listener: tokio::net::TcpListener, tls: Option<RustlsConfig>, auth_token: Option<AuthToken>) -> Result<()> {
+        let app = self.build_router(auth_token).into_make_service_with_connect_info::<SocketAddr>();
+        match tls {
+            Some(tls) => axum_server::from_tcp_rustls(listener.into_std()?, tls)
+                .serve(app)
+                .await
+                .map_err(|e| ScrcpyError::Network(format!("Server error: {}", e))),
+            None => axum::serve(listener, app)
+                .await
+                .map_err(|e| ScrcpyError::Network(format!("Server error: {}", e))),
+        }
+    }
+}
+
+/// 设备选择页：从 /devices 读取列表，每台设备链接到 `/?device=SERIAL`
+const PICKER_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Rust-Scrcpy Devices</title>
+    <style>
+        body { font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', sans-serif; background: #1a1a1a; color: #eee; margin: 0; padding: 32px; }
+        h1 { font-size: 20px; font-weight: 500; }
+        ul { list-style: none; padding: 0; max-width: 480px; }
+        li a { display: block; padding: 12px 16px; margin-bottom: 8px; border-radius: 6px; background: #2a2a2a; color: #eee; text-decoration: none; }
+        li a:hover { background: #3a3a3a; }
+        .serial { color: #888; font-size: 13px; margin-left: 8px; }
+    </style>
+</head>
+<body>
+    <h1>📱 选择设备</h1>
+    <ul id="devices"><li>加载中...</li></ul>
+    <script>
+        async function loadDevices() {
+            const list = document.getElementById('devices');
+            try {
+                const response = await fetch('/devices');
+                const devices = await response.json();
+                list.replaceChildren();
+                if (devices.length === 0) {
+                    list.textContent = '没有可用的设备';
+                    return;
+                }
+                for (const device of devices) {
+                    const link = document.createElement('a');
+                    link.href = device.display === undefined
+                        ? '/?device=' + encodeURIComponent(device.serial)
+                        : '/device/' + encodeURIComponent(device.serial) + '/display/' + device.display + '/';
+                    link.textContent = (device.model || device.serial) + (device.display === undefined ? '' : ' · 显示屏 ' + device.display);
+                    const serial = document.createElement('span');
+                    serial.className = 'serial';
+                    serial.textContent = device.serial;
+                    link.appendChild(serial);
+                    const item = document.createElement('li');
+                    item.appendChild(link);
+                    list.appendChild(item);
+                }
+            } catch (e) {
+                list.textContent = '加载设备列表失败: ' + e;
+            }
+        }
+        loadDevices();
+        setInterval(loadDevices, 5000);
+    </script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+
+    #[test]
+    fn test_resolve_target() {
+        let two = ["emulator-5554", "192.168.1.5:5555"];
+        assert_eq!(resolve(Some("emulator-5554"), "/ws", &two), Target::Session("emulator-5554"));
+        assert_eq!(resolve(Some("missing"), "/ws", &two), Target::Unknown("missing"));
+        assert_eq!(resolve(None, "/", &two), Target::Picker);
+        assert_eq!(resolve(None, "/ws", &two), Target::Ambiguous);
+        assert_eq!(resolve(None, "/decoder/jmuxer.min.js", &two), Target::Session("emulator-5554"));
+        // 只有一台设备时与单设备模式相同
+        assert_eq!(resolve(None, "/", &two[..1]), Target::Session("emulator-5554"));
+        assert_eq!(resolve(None, "/", &[]), Target::Picker);
+
+        assert_eq!(device_query("192.168.1.5:5555"), "?device=192.168.1.5%3A5555");
+        assert_eq!(device_query("R5CT\"<x>"), "?device=R5CT%22%3Cx%3E");
+    }
+
+    #[test]
+    fn test_parse_display_path() {
+        assert_eq!(display_path("192.168.1.5:5555", 1), "/device/192.168.1.5%3A5555/display/1");
+        assert_eq!(parse_display_path("/device/192.168.1.5%3A5555/display/1/ws"), Some(("192.168.1.5:5555".to_string(), 1, "/ws")));
+        assert_eq!(parse_display_path("/device/192.168.1.5:5555/display/1"), Some(("192.168.1.5:5555".to_string(), 1, "")));
+        assert_eq!(parse_display_path("/device/R5CT/display/2/"), Some(("R5CT".to_string(), 2, "/")));
+        assert_eq!(parse_display_path("/device/R5CT/display/x/ws"), None);
+        assert_eq!(parse_display_path("/device/R5CT%zz/display/0"), None);
+        assert_eq!(parse_display_path("/ws"), None);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_by_device() {
+        let hub = DeviceHub::new();
+        for serial in ["a", "b"] {
+            let router = Router::new().route("/stats", get(move || async move { serial }));
+            hub.register(serial, None, "Pixel", router).await;
+        }
+        // 显示屏会话只能按路径访问
+        for display in [0, 1] {
+            let router = Router::new().route("/stats", get(move || async move { format!("a:{}", display) }));
+            hub.register("a", Some(display), "Fold", router).await;
+        }
+        let app = hub.build_router(None);
+        let get_body = |uri: &str| {
+            let app = app.clone();
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        assert_eq!(get_body("/stats?device=b").await, (StatusCode::OK, "b".to_string()));
+        assert_eq!(get_body("/stats").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(get_body("/stats?device=c").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(get_body("/device/a/display/1/stats").await, (StatusCode::OK, "a:1".to_string()));
+        assert_eq!(get_body("/device/a/display/0/stats?token=x").await, (StatusCode::OK, "a:0".to_string()));
+        assert_eq!(get_body("/device/a/display/2/stats").await.0, StatusCode::NOT_FOUND);
+        let (status, devices) = get_body("/devices").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(devices, r#"[{"serial":"a","model":"Pixel"},{"serial":"a","model":"Fold","display":0},{"serial":"a","model":"Fold","display":1},{"serial":"b","model":"Pixel"}]"#);
+
+        hub.unregister("a", None).await;
+        hub.unregister("a", Some(0)).await;
+        hub.unregister("a", Some(1)).await;
+        assert_eq!(get_body("/stats").await, (StatusCode::OK, "b".to_string()));
+        assert_eq!(get_body("/healthz").await.0, StatusCode::OK);
+    }
+}
@@ -1,3 +1,14 @@
+pub mod assets;
+pub mod audio;
+pub mod auth;
+pub mod clients;
+pub mod health;
+pub mod hub;
+pub mod raw;
+pub mod reconnect;
+pub mod screencap;
 pub mod server;
+pub mod stats;
+pub mod tls;
 
 pub use server::WebSocketServer;
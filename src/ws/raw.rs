@@ -0,0 +1,181 @@
+//! /raw 路由 - 通过 HTTP 长连接输出视频流，供 ffplay、VLC 等工具直接播放
+//!
+//! - `?fmt=annexb`（默认）：裸 H.264 Annex-B 码流
+//! - `?fmt=ts`：封装为 MPEG-TS，`--frame-meta` 时时间戳取自设备 PTS，否则取服务端收到帧的时间
+//!
+//! 每个消费者从缓存的 SPS/PPS 和下一个 IDR 开始；跟不上广播（Lagged）时丢帧并等待下一个 IDR。
+
+use crate::scrcpy::video::{nal_type, with_start_code_len};
+use crate::utils::frame_sinks::BroadcastFrame;
+use crate::utils::TsMuxer;
+use crate::ws::server::VideoConfig;
+use axum::body::Body;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+/// /raw 输出格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RawFormat {
+    #[default]
+    Annexb,  // 裸 Annex-B 码流
+    Ts,      // MPEG-TS
+}
+
+/// /raw 查询参数
+#[derive(Debug, Default, Deserialize)]
+pub struct RawParams {
+    #[serde(default)]
+    pub fmt: RawFormat,
+}
+
+impl RawFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            RawFormat::Annexb => "video/h264",
+            RawFormat::Ts => "video/mp2t",
+        }
+    }
+}
+
+/// 单个 /raw 消费者的状态
+struct RawConsumer {
+    rx: broadcast::Receiver<BroadcastFrame>,
+    muxer: Option<TsMuxer>,
+    started: Instant,
+    first_pts: Option<u64>,  // 第一个设备 PTS（微秒），TS 时间戳相对它计算
+    waiting_keyframe: bool,  // 开始时和丢帧后等待 IDR
+    prefix: Vec<Bytes>,      // 开始时先发送的缓存 SPS/PPS
+    start_code_len: u8,      // 输出的起始码长度（`--start-code`）
+}
+
+impl RawConsumer {
+    /// 转换为输出格式（TS 模式下参数集会被缓存，可能返回空）
+    fn encode(&mut self, nal: &Bytes, device_pts: Option<u64>) -> Bytes {
+        match &mut self.muxer {
+            None => nal.clone(),
+            Some(muxer) => {
+                let micros = match device_pts {
+                    Some(pts) => pts.saturating_sub(*self.first_pts.get_or_insert(pts)),
+                    None => self.started.elapsed().as_micros() as u64,
+                };
+                // 90kHz 时间戳，从 1 秒开始，避免播放器处理接近 0 的 PTS
+                Bytes::from(muxer.push_nal(nal, 90_000 + micros * 9 / 100))
+            }
+        }
+    }
+
+    /// 下一块要输出的数据，广播关闭时返回 None
+    async fn next_chunk(&mut self) -> Option<Bytes> {
+        loop {
+            let BroadcastFrame { nal, pts } = if self.prefix.is_empty() {
+                match self.rx.recv().await {
+                    Ok(frame) => frame,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        debug!("📼 /raw consumer lagged by {} frames, waiting for next keyframe", n);
+                        self.waiting_keyframe = true;
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            } else {
+                self.prefix.remove(0).into()
+            };
+
+            match nal_type(&nal) {
+                5 => self.waiting_keyframe = false,
+                7 | 8 => {}
+                _ if self.waiting_keyframe => continue,
+                _ => {}
+            }
+            let nal = with_start_code_len(nal, self.start_code_len);
+            // 参数集没有 PTS，缓存到下一个 slice 一起输出
+            let chunk = self.encode(&nal, pts.filter(|_| matches!(nal_type(&nal), 1 | 5)));
+            if !chunk.is_empty() {
+                return Some(chunk);
+            }
+        }
+    }
+}
+
+/// 输出视频流
+pub async fn serve_raw(
+    params: RawParams,
+    tx: broadcast::Sender<BroadcastFrame>,
+    video_config: Arc<RwLock<VideoConfig>>,
+    idr_request_tx: mpsc::Sender<()>,
+    start_code_len: u8,
+) -> Response {
+    // 先订阅再读取缓存，避免漏掉之后到达的参数集
+    let rx = tx.subscribe();
+    let prefix = {
+        let config = video_config.read().await;
+        [config.sps.clone(), config.pps.clone()].into_iter().flatten().collect()
+    };
+    let _ = idr_request_tx.try_send(());
+    info!("📼 New /raw consumer ({:?})", params.fmt);
+
+    let consumer = RawConsumer {
+        rx,
+        muxer: (params.fmt == RawFormat::Ts).then(TsMuxer::new),
+        started: Instant::now(),
+        first_pts: None,
+        waiting_keyframe: true,
+        prefix,
+        start_code_len,
+    };
+    let stream = futures_util::stream::unfold(consumer, |mut consumer| async move {
+        let chunk = consumer.next_chunk().await?;
+        Some((Ok::<_, Infallible>(chunk), consumer))
+    });
+
+    (
+        [(header::CONTENT_TYPE, params.fmt.content_type()), (header::CACHE_CONTROL, "no-store")],
+        Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nal(nal_type: u8) -> Bytes {
+        Bytes::from(vec![0x00, 0x00, 0x00, 0x01, nal_type, 0xAA, 0xBB])
+    }
+
+    #[tokio::test]
+    async fn test_consumer_starts_at_keyframe() {
+        let (tx, rx) = broadcast::channel(16);
+        let mut consumer = RawConsumer {
+            rx,
+            muxer: None,
+            started: Instant::now(),
+            first_pts: None,
+            waiting_keyframe: true,
+            prefix: vec![nal(0x67), nal(0x68)],
+            start_code_len: 3,
+        };
+        tx.send(nal(0x41).into()).unwrap();  // IDR 之前的 P 帧被跳过
+        tx.send(nal(0x65).into()).unwrap();
+        tx.send(nal(0x41).into()).unwrap();
+
+        let mut types = Vec::new();
+        for _ in 0..4 {
+            let chunk = consumer.next_chunk().await.unwrap();
+            assert_eq!(&chunk[..3], &[0x00, 0x00, 0x01]);
+            types.push(chunk[3] & 0x1F);
+        }
+        assert_eq!(types, vec![7, 8, 5, 1]);
+
+        drop(tx);
+        assert!(consumer.next_chunk().await.is_none());
+    }
+}
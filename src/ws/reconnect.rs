@@ -0,0 +1,96 @@
+//! 重连宽限模块 - scrcpy server 重启（调整分辨率、视频流中断后的重连）期间向客户端广播流状态
+//!
+//! 重启期间 WebSocket 连接保持不变，客户端只收到 `stream_status` 消息，不会看到"连接断开"：
+//! - `{"type":"stream_status","state":"reconnecting","grace_ms":10000}`：开始重连，网页显示加载动画，保留最后一帧
+//! - `{"type":"stream_status","state":"live"}`：重连后的第一帧已发出（新编码器总是先输出 SPS/PPS + IDR）
+//! - `{"type":"stream_status","state":"stalled"}`：超过宽限时间仍没有画面，网页改为提示中断（连接仍保持）
+
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// 默认的重连宽限时间
+pub const DEFAULT_RECONNECT_GRACE: Duration = Duration::from_secs(10);
+
+/// 主循环中的重连状态
+#[derive(Debug)]
+pub struct ReconnectGrace {
+    grace: Duration,
+    started: Option<Instant>,  // 本次重连开始的时间，None 表示视频流正常
+    stalled: bool,             // 已超过宽限时间并通知客户端
+}
+
+impl ReconnectGrace {
+    pub fn new(grace: Duration) -> Self {
+        Self { grace, started: None, stalled: false }
+    }
+
+    /// 是否正在重连（尚未收到重连后的第一帧）
+    pub fn is_reconnecting(&self) -> bool {
+        self.started.is_some()
+    }
+
+    /// 开始重连，返回要广播的状态消息（连续失败的重连只通知一次）
+    pub fn begin(&mut self, now: Instant) -> Option<String> {
+        if self.started.is_some() {
+            return None;
+        }
+        self.started = Some(now);
+        self.stalled = false;
+        Some(json!({ "type": "stream_status", "state": "reconnecting", "grace_ms": self.grace.as_millis() as u64 }).to_string())
+    }
+
+    /// 广播了一个 NAL 单元；重连后的第一个视频帧结束重连状态
+    pub fn on_frame(&mut self, nal_header: u8, now: Instant) -> Option<String> {
+        let started = self.started?;
+        if !matches!(nal_header & 0x1F, 1 | 5) {
+            return None;
+        }
+        self.started = None;
+        info!("🔄 Stream resumed after {} ms", now.saturating_duration_since(started).as_millis());
+        Some(json!({ "type": "stream_status", "state": "live" }).to_string())
+    }
+
+    /// 定期检查：超过宽限时间仍没有画面时返回 `stalled` 消息（只返回一次）
+    pub fn poll(&mut self, now: Instant) -> Option<String> {
+        let started = self.started?;
+        if self.stalled || now.saturating_duration_since(started) < self.grace {
+            return None;
+        }
+        self.stalled = true;
+        warn!("🔄 No video {}s after reconnect started", self.grace.as_secs());
+        Some(json!({ "type": "stream_status", "state": "stalled" }).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(msg: Option<String>) -> Option<String> {
+        msg.map(|msg| serde_json::from_str::<serde_json::Value>(&msg).unwrap()["state"].as_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_reconnect_grace() {
+        let start = Instant::now();
+        let mut grace = ReconnectGrace::new(Duration::from_secs(5));
+        assert_eq!(grace.on_frame(0x65, start), None);  // 未重连时不发消息
+
+        assert_eq!(state(grace.begin(start)), Some("reconnecting".to_string()));
+        assert_eq!(grace.begin(start + Duration::from_secs(1)), None);  // 重连失败后重试不重复通知
+        assert!(grace.is_reconnecting());
+
+        // SPS/PPS 不结束重连，第一个视频帧结束
+        assert_eq!(grace.on_frame(0x67, start + Duration::from_secs(2)), None);
+        assert_eq!(grace.poll(start + Duration::from_secs(2)), None);
+        assert_eq!(state(grace.on_frame(0x65, start + Duration::from_secs(2))), Some("live".to_string()));
+        assert!(!grace.is_reconnecting());
+
+        // 超过宽限时间：只通知一次 stalled，之后的第一帧仍恢复为 live
+        grace.begin(start);
+        assert_eq!(state(grace.poll(start + Duration::from_secs(5))), Some("stalled".to_string()));
+        assert_eq!(grace.poll(start + Duration::from_secs(6)), None);
+        assert_eq!(state(grace.on_frame(0x41, start + Duration::from_secs(7))), Some("live".to_string()));
+    }
+}
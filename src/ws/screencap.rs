@@ -0,0 +1,286 @@
+//! /screencap 路由 - 通过 adb screencap 获取设备截图，可缩放和重新编码
+//!
+//! - `/screencap.png`、`/screencap.jpg`、`/screenshot`（`?format=png|jpg`，默认 PNG）
+//! - `?w=320`：缩放到指定宽度（保持宽高比，不放大），限制在 16 ~ 4096
+//! - `?quality=60`：JPEG 质量，限制在 1 ~ 100，默认 80（PNG 忽略）
+//!
+//! 一面墙的缩略图同时轮询时，1 秒内只执行一次 screencap，
+//! 同一次截图按 (格式, 宽度, 质量) 缓存编码结果。
+//!
+//! screencap 不带 `-d` 时截取默认显示屏。`-d` 需要 SurfaceFlinger 的物理显示屏 id，与 scrcpy 使用的逻辑 id 不同，
+//! 因此镜像其他显示屏（`--display-id` / `--display-ids`）的会话直接返回 501，而不是返回默认显示屏的画面。
+//!
+//! 带 FLAG_SECURE 的窗口（银行、支付、DRM 视频等）在截图中是纯黑的，screencap 本身不会报错。
+//! 每次截图检查是否整幅全黑，是则照常返回图片，但带上 `X-Screencap-Blank: 1` 响应头并输出一次警告，
+//! 调用方可以据此显示占位图，而不是把黑屏当作正常画面。
+
+use crate::adb::AdbClient;
+use crate::ws::server::{PrivacyGate, PRIVACY_PAUSED_MESSAGE};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+/// 会话镜像的不是默认显示屏时截图请求的错误信息
+pub const NON_DEFAULT_DISPLAY_MESSAGE: &str = "screencap only supports the default display (display 0)";
+
+/// 同一次截图的复用时间
+pub const SCREENCAP_CACHE_TTL: Duration = Duration::from_secs(1);
+pub const MIN_SCREENCAP_WIDTH: u32 = 16;
+pub const MAX_SCREENCAP_WIDTH: u32 = 4096;
+pub const DEFAULT_JPEG_QUALITY: u8 = 80;
+
+/// 截图全黑时的响应头
+pub const BLANK_HEADER: &str = "x-screencap-blank";
+/// 每个通道不超过该值的像素视为黑色（容忍硬件合成的轻微噪声）
+const BLANK_THRESHOLD: u8 = 4;
+
+/// 输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScreencapFormat {
+    Png,
+    #[serde(alias = "jpg")]
+    Jpeg,
+}
+
+impl ScreencapFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ScreencapFormat::Png => "image/png",
+            ScreencapFormat::Jpeg => "image/jpeg",
+        }
+    }
+}
+
+/// /screencap 查询参数
+#[derive(Debug, Default, Deserialize)]
+pub struct ScreencapParams {
+    pub w: Option<u32>,
+    pub quality: Option<u8>,
+    pub format: Option<ScreencapFormat>,  // 只用于 /screenshot
+}
+
+/// 限制后的输出参数（也是缓存键）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Variant {
+    format: ScreencapFormat,
+    width: Option<u32>,   // None 表示原始宽度
+    quality: u8,          // PNG 固定为 0
+}
+
+impl Variant {
+    fn new(format: ScreencapFormat, params: &ScreencapParams) -> Self {
+        let quality = match format {
+            ScreencapFormat::Png => 0,
+            ScreencapFormat::Jpeg => params.quality.unwrap_or(DEFAULT_JPEG_QUALITY).clamp(1, 100),
+        };
+        Self {
+            format,
+            width: params.w.map(|w| w.clamp(MIN_SCREENCAP_WIDTH, MAX_SCREENCAP_WIDTH)),
+            quality,
+        }
+    }
+}
+
+/// 最近一次截图及其编码结果
+#[derive(Default)]
+struct CachedCapture {
+    captured_at: Option<Instant>,
+    png: Bytes,
+    blank: bool,  // 整幅全黑（安全窗口或屏幕关闭）
+    decoded: Option<Arc<DynamicImage>>,
+    variants: HashMap<Variant, Bytes>,
+}
+
+/// 截图缓存（所有请求共享）
+pub struct ScreencapCache {
+    device: Option<(AdbClient, String, u32)>,  // (adb, 序列号, 显示屏)
+    privacy: PrivacyGate,
+    capture: Mutex<CachedCapture>,
+}
+
+impl ScreencapCache {
+    pub fn new(device: Option<(AdbClient, String, u32)>, privacy: PrivacyGate) -> Self {
+        Self { device, privacy, capture: Mutex::new(CachedCapture::default()) }
+    }
+
+    /// 获取指定格式的截图（持有锁期间截图，并发请求等待同一次截图），同时返回截图是否全黑
+    async fn get(&self, variant: Variant) -> Result<(Bytes, bool), (StatusCode, String)> {
+        // --only-app：目标应用不在前台时不截图，也不返回之前缓存的截图
+        if self.privacy.is_hidden() {
+            return Err((StatusCode::FORBIDDEN, PRIVACY_PAUSED_MESSAGE.to_string()));
+        }
+        let Some((adb, device_id, display_id)) = &self.device else {
+            return Err((StatusCode::SERVICE_UNAVAILABLE, "no device".to_string()));
+        };
+        if *display_id != 0 {
+            return Err((StatusCode::NOT_IMPLEMENTED, NON_DEFAULT_DISPLAY_MESSAGE.to_string()));
+        }
+
+        let mut capture = self.capture.lock().await;
+        let fresh = capture.captured_at.is_some_and(|t| t.elapsed() < SCREENCAP_CACHE_TTL);
+        if !fresh {
+            let png = Bytes::from(adb.screencap_png(device_id).await.map_err(|e| (StatusCode::BAD_GATEWAY, e.to_string()))?);
+            // 每次截图都要解码一次才能检查全黑，解码结果留给后面的缩放复用
+            let (decoded, blank) = tokio::task::spawn_blocking({
+                let png = png.clone();
+                move || image::load_from_memory_with_format(&png, ImageFormat::Png).map(|image| {
+                    let blank = is_blank(&image);
+                    (image, blank)
+                })
+            })
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(|e| (StatusCode::BAD_GATEWAY, format!("invalid screencap PNG: {}", e)))?;
+            if blank && !capture.blank {
+                warn!("📷 Screencap is completely black: the screen is off or a secure window (FLAG_SECURE) hides its content");
+            }
+            *capture = CachedCapture {
+                captured_at: Some(Instant::now()),
+                png,
+                blank,
+                decoded: Some(Arc::new(decoded)),
+                variants: HashMap::new(),
+            };
+        }
+        let blank = capture.blank;
+
+        // 原始 PNG 直接返回，不重新编码
+        if variant.format == ScreencapFormat::Png && variant.width.is_none() {
+            return Ok((capture.png.clone(), blank));
+        }
+        if let Some(bytes) = capture.variants.get(&variant) {
+            return Ok((bytes.clone(), blank));
+        }
+
+        let decoded = capture.decoded.clone().expect("decoded with every capture");
+
+        let bytes = tokio::task::spawn_blocking(move || encode(&decoded, variant))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        debug!("📷 Encoded screencap {:?}: {} bytes", variant, bytes.len());
+        capture.variants.insert(variant, bytes.clone());
+        Ok((bytes, blank))
+    }
+}
+
+/// 所有像素都接近纯黑（忽略透明通道）
+fn is_blank(image: &DynamicImage) -> bool {
+    match image.as_rgba8() {
+        Some(rgba) => rgba.pixels().all(|p| p.0[..3].iter().all(|&c| c <= BLANK_THRESHOLD)),
+        None => image.to_rgb8().pixels().all(|p| p.0.iter().all(|&c| c <= BLANK_THRESHOLD)),
+    }
+}
+
+/// 缩放（不放大）并编码
+fn encode(image: &DynamicImage, variant: Variant) -> image::ImageResult<Bytes> {
+    let scaled;
+    let image = match variant.width {
+        Some(width) if width < image.width() => {
+            let height = ((image.height() as u64 * width as u64) / image.width() as u64).max(1) as u32;
+            scaled = image.resize_exact(width, height, FilterType::Triangle);
+            &scaled
+        }
+        _ => image,
+    };
+
+    let mut out = Vec::new();
+    match variant.format {
+        ScreencapFormat::Png => image.write_to(&mut std::io::Cursor::new(&mut out), ImageFormat::Png)?,
+        // JPEG 不支持透明通道
+        ScreencapFormat::Jpeg => JpegEncoder::new_with_quality(&mut out, variant.quality).encode_image(&image.to_rgb8())?,
+    }
+    Ok(Bytes::from(out))
+}
+
+/// 返回截图
+pub async fn serve_screencap(format: ScreencapFormat, params: ScreencapParams, cache: Arc<ScreencapCache>) -> Response {
+    match cache.get(Variant::new(format, &params)).await {
+        Ok((bytes, blank)) => {
+            let mut response = (
+                [(header::CONTENT_TYPE, format.content_type()), (header::CACHE_CONTROL, "no-store")],
+                bytes,
+            )
+                .into_response();
+            if blank {
+                response.headers_mut().insert(BLANK_HEADER, header::HeaderValue::from_static("1"));
+            }
+            response
+        }
+        Err((status, message)) => {
+            warn!("📷 Screencap failed: {}", message);
+            (status, axum::Json(serde_json::json!({ "error": message }))).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variant_clamps_and_scales() {
+        let params = ScreencapParams { w: Some(100_000), quality: Some(0), format: None };
+        let variant = Variant::new(ScreencapFormat::Jpeg, &params);
+        assert_eq!((variant.width, variant.quality), (Some(MAX_SCREENCAP_WIDTH), 1));
+        assert_eq!(Variant::new(ScreencapFormat::Png, &params).quality, 0);
+        assert_eq!(Variant::new(ScreencapFormat::Jpeg, &ScreencapParams::default()).quality, DEFAULT_JPEG_QUALITY);
+
+        let image = DynamicImage::new_rgba8(1080, 2400);
+        let thumb = encode(&image, Variant { format: ScreencapFormat::Jpeg, width: Some(320), quality: 60 }).unwrap();
+        let decoded = image::load_from_memory(&thumb).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (320, 711));
+
+        // 不放大
+        let png = encode(&image, Variant { format: ScreencapFormat::Png, width: Some(4096), quality: 0 }).unwrap();
+        assert_eq!(image::load_from_memory(&png).unwrap().width(), 1080);
+    }
+
+    #[test]
+    fn test_blank_detection_and_format() {
+        let mut image = image::RgbaImage::new(64, 64);
+        assert!(is_blank(&DynamicImage::ImageRgba8(image.clone())));
+        image.put_pixel(10, 10, image::Rgba([3, 2, 4, 255]));
+        assert!(is_blank(&DynamicImage::ImageRgba8(image.clone())));
+        image.put_pixel(20, 20, image::Rgba([200, 0, 0, 255]));
+        assert!(!is_blank(&DynamicImage::ImageRgba8(image)));
+
+        let parse = |uri: &str| axum::extract::Query::<ScreencapParams>::try_from_uri(&uri.parse().unwrap()).map(|q| q.0.format);
+        assert_eq!(parse("/screenshot?format=jpg&w=320").unwrap(), Some(ScreencapFormat::Jpeg));
+        assert_eq!(parse("/screenshot?format=png").unwrap(), Some(ScreencapFormat::Png));
+        assert_eq!(parse("/screenshot").unwrap(), None);
+        assert!(parse("/screenshot?format=gif").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_privacy_gate_blocks_capture() {
+        let (visible_tx, visible_rx) = tokio::sync::watch::channel(false);
+        let cache = Arc::new(ScreencapCache::new(None, PrivacyGate::new(visible_rx)));
+        let response = serve_screencap(ScreencapFormat::Png, ScreencapParams::default(), cache.clone()).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // 目标应用回到前台后照常截图（这里没有设备）
+        visible_tx.send(true).unwrap();
+        let response = serve_screencap(ScreencapFormat::Png, ScreencapParams::default(), cache).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+    #[tokio::test]
+    async fn test_non_default_display_rejected() {
+        // 不执行 adb：在截图之前就拒绝
+        let device = (AdbClient::new("/nonexistent/adb".into()), "serial".to_string(), 1);
+        let cache = Arc::new(ScreencapCache::new(Some(device), PrivacyGate::default()));
+        let response = serve_screencap(ScreencapFormat::Jpeg, ScreencapParams::default(), cache).await;
+        assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+    }
+}
@@ -1,17 +1,37 @@
+use crate::adb::client::{AdbClient, BrightnessState, RotationState, MAX_BRIGHTNESS, MIN_BRIGHTNESS};
 use crate::error::{Result, ScrcpyError};
 use crate::scrcpy::control::ControlEvent;
+use crate::scrcpy::control_queue::ControlSender;
+use crate::scrcpy::server_log::ServerLogStats;
+use crate::scrcpy::video::is_discardable_nal;
 use crate::utils::find_available_port;
+use crate::utils::frame_sinks::{BroadcastFrame, SinkStats};
+use crate::ws::assets;
+use crate::ws::audio::handle_audio_socket;
+use crate::ws::auth::{self, AuthToken};
+use crate::ws::clients::{client_addr, ClientGuard, ClientKind, ClientRegistry, KickTarget};
+use crate::ws::health::Liveness;
+use crate::ws::hub;
+use crate::ws::raw::{serve_raw, RawParams};
+use crate::ws::stats::StreamStats;
+use crate::ws::screencap::{serve_screencap, ScreencapCache, ScreencapFormat, ScreencapParams, NON_DEFAULT_DISPLAY_MESSAGE};
 use axum::{
-    extract::ws::{WebSocket, WebSocketUpgrade, Message},
+    extract::ws::{close_code, CloseFrame, WebSocket, WebSocketUpgrade, Message},
+    extract::{ConnectInfo, NestedPath, Query},
+    http::{header, HeaderMap, StatusCode},
+    middleware,
     response::IntoResponse,
-    routing::get,
+    routing::{get, post},
     Router,
 };
+use axum_server::tls_rustls::RustlsConfig;
 use bytes::Bytes;
-use tokio::sync::{broadcast, RwLock, mpsc};
+use tokio::sync::{broadcast, Mutex, RwLock, mpsc, watch};
 use tracing::{info, warn, debug};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::Duration;
+use tower_http::compression::CompressionLayer;
 
 /// 视频配置信息
 #[derive(Clone)]
@@ -23,23 +43,131 @@ pub struct VideoConfig {
     pub device_width: u32,    // 设备物理屏幕宽度（用于触控）
     pub device_height: u32,   // 设备物理屏幕高度（用于触控）
     pub is_landscape: bool,   // 是否为横屏模式（width > height）
+    pub target_aspect: Option<(u32, u32)>,  // 目标宽高比（--target-aspect），用于信箱黑边
+    pub profile: Option<H264Profile>,       // 从 SPS 解析出的 H.264 profile
+    pub inline_sps_on_idr: bool,            // 发给浏览器的 IDR 前拼接 SPS/PPS（--inline-sps-on-idr）
+    pub uhid_keyboard: bool,                // 键盘按物理键位经 UHID 键盘注入（--uhid）
+    pub frame_meta: bool,                   // 每条二进制视频消息前有 8 字节 PTS（--frame-meta）
+}
+
+impl VideoConfig {
+    /// 生成发送给客户端的 config 消息
+    pub fn config_message(&self) -> String {
+        serde_json::json!({
+            "type": "config",
+            "width": self.width,
+            "height": self.height,
+            "device_width": self.device_width,
+            "device_height": self.device_height,
+            "is_landscape": self.is_landscape,
+            "letterbox": self.target_aspect.map(|aspect| Letterbox::fit(self.width, self.height, aspect)),
+            "profile": self.profile,
+            "uhid_keyboard": self.uhid_keyboard,
+            "frame_meta": self.frame_meta,
+        })
+        .to_string()
+    }
+}
+
+/// H.264 profile（SPS 中的 profile_idc）
+///
+/// Broadway.js 只能解码 Baseline profile，客户端据此禁用 Broadway 选项。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct H264Profile {
+    pub idc: u8,
+    pub name: &'static str,
+    pub broadway_compatible: bool,
+}
+
+impl H264Profile {
+    pub fn from_idc(idc: u8) -> Self {
+        let name = match idc {
+            66 => "Baseline",
+            77 => "Main",
+            88 => "Extended",
+            100 => "High",
+            110 => "High 10",
+            122 => "High 4:2:2",
+            244 => "High 4:4:4",
+            _ => "Unknown",
+        };
+        Self { idc, name, broadway_compatible: idc == 66 }
+    }
+}
+
+/// 信箱（letterbox）布局：视频居中放在目标宽高比的画框中，其余部分为黑边
+///
+/// 所有尺寸都以视频像素为单位，客户端按画框整体缩放显示。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct Letterbox {
+    pub box_width: u32,
+    pub box_height: u32,
+    pub offset_x: u32,    // 视频左侧黑边宽度
+    pub offset_y: u32,    // 视频上方黑边高度
+}
+
+impl Letterbox {
+    /// 计算能完整容纳 width x height 视频、且宽高比为 aspect 的最小画框
+    pub fn fit(width: u32, height: u32, aspect: (u32, u32)) -> Self {
+        let (aw, ah) = (aspect.0 as u64, aspect.1 as u64);
+        let (w, h) = (width as u64, height as u64);
+        let (box_width, box_height) = if w * ah >= h * aw {
+            // 视频比目标更宽：上下加黑边
+            (w, (w * ah).div_ceil(aw))
+        } else {
+            // 视频比目标更高：左右加黑边
+            ((h * aw).div_ceil(ah), h)
+        };
+        Self {
+            box_width: box_width as u32,
+            box_height: box_height as u32,
+            offset_x: ((box_width - w) / 2) as u32,
+            offset_y: ((box_height - h) / 2) as u32,
+        }
+    }
 }
 
 /// WebSocket 服务器
 pub struct WebSocketServer {
-    port: u16,
     actual_port: u16,  // 实际使用的端口（可能与请求的端口不同）
-    public: bool,      // 是否监听所有接口（局域网可访问）
+    bind_addr: IpAddr, // 监听地址（--bind，--public 为 0.0.0.0）
     // 使用 broadcast channel 向所有连接的客户端广播视频帧
-    tx: broadcast::Sender<Bytes>,
+    tx: broadcast::Sender<BroadcastFrame>,
     // 使用 broadcast channel 向所有连接的客户端广播配置变化
     config_tx: broadcast::Sender<String>,
+    // 向 /audio 客户端广播 Opus 音频的 broadcast channel（--audio），None 时不提供 /audio
+    audio_tx: Option<broadcast::Sender<Bytes>>,
     // 缓存 SPS/PPS 配置帧
     video_config: Arc<RwLock<VideoConfig>>,
     // 用于请求IDR帧的通道
     idr_request_tx: mpsc::Sender<()>,
     // 用于发送控制事件的通道
-    control_tx: mpsc::Sender<ControlEvent>,
+    control_tx: ControlSender,
+    // 关闭通知：Some(原因) 时所有客户端以该原因关闭连接
+    shutdown_tx: watch::Sender<Option<String>>,
+    // 管线存活状态（/healthz）
+    liveness: Arc<Liveness>,
+    // 广播的帧数和关键帧数（/stats）
+    stream_stats: Arc<StreamStats>,
+    // 超过该时长未收到视频帧即视为不健康
+    health_stale_after: Duration,
+    // 当前连接的客户端
+    clients: ClientRegistry,
+    // 是否信任 X-Forwarded-For（位于反向代理之后时开启）
+    trust_proxy: bool,
+    device: Option<DeviceAccess>,  // 用于 device_screenshot 等直接通过 ADB 执行的请求
+    privacy: PrivacyGate,  // --only-app：目标应用不在前台时截图和音频也暂停
+    saved_brightness: SavedBrightness,  // 首次 set_brightness 前的亮度设置（关闭时恢复）
+    saved_rotation: SavedRotation,  // 首次 set_rotation_lock 前的旋转设置（关闭时恢复）
+    debug_config: Option<Arc<serde_json::Value>>,  // /debug/config 内容，None 时不提供该路由
+    sink_stats: SinkStats,  // --raw-output 写入端状态（/stats）
+    server_log_stats: Option<ServerLogStats>,  // 从 scrcpy-server 输出解析的编码器信息（/stats）
+    start_code_len: u8,     // /raw 输出的起始码长度（--start-code）
+    lag_resync_threshold: u32,  // 客户端窗口内积压达到该次数时重发参数集（0 = 关闭）
+    max_message_bytes: usize,   // 客户端发来的单条消息上限，超过时关闭连接
+    tls: Option<RustlsConfig>,  // --tls-cert / --tls-key，None 时提供明文 HTTP/WS
+    auth_token: Option<AuthToken>,  // --auth-token，None 时不要求认证
+    hub_authenticated: bool,  // 挂在 hub 下且 hub 已校验 --auth-token（本服务不再单独校验）
 }
 
 impl WebSocketServer {
@@ -47,10 +175,10 @@ impl WebSocketServer {
     ///
     /// # Arguments
     /// * `port` - 期望的端口号，如果被占用会自动向后寻找
-    /// * `public` - 是否监听所有接口（true: 0.0.0.0，false: 127.0.0.1）
-    pub fn new(port: u16, idr_request_tx: mpsc::Sender<()>, control_tx: mpsc::Sender<ControlEvent>, device_width: u32, device_height: u32, public: bool) -> Result<Self> {
-        // 自动寻找可用端口
-        let actual_port = find_available_port(port, 100)?;
+    /// * `bind_addr` - 监听地址（IPv4 或 IPv6，0.0.0.0 / :: 为所有接口）
+    pub fn new(port: u16, idr_request_tx: mpsc::Sender<()>, control_tx: ControlSender, device_width: u32, device_height: u32, bind_addr: IpAddr) -> Result<Self> {
+        // 在监听地址上自动寻找可用端口
+        let actual_port = find_available_port(bind_addr, port, 100)?;
 
         let (tx, _rx) = broadcast::channel(2); // 极小缓冲：只保留1-2帧，最小化延迟
         let (config_tx, _) = broadcast::channel(16); // 配置变化广播通道
@@ -63,9 +191,44 @@ impl WebSocketServer {
             device_width,   // 设备物理屏幕尺寸
             device_height,  // 设备物理屏幕尺寸
             is_landscape: device_width > device_height,  // 初始横屏状态
+            target_aspect: None,
+            profile: None,
+            inline_sps_on_idr: false,
+            uhid_keyboard: false,
+            frame_meta: false,
         }));
 
-        Ok(Self { port, actual_port, public, tx, config_tx, video_config, idr_request_tx, control_tx })
+        let (shutdown_tx, _) = watch::channel(None);
+
+        Ok(Self {
+            actual_port,
+            bind_addr,
+            tx,
+            config_tx: config_tx.clone(),
+            audio_tx: None,
+            video_config,
+            idr_request_tx,
+            control_tx,
+            shutdown_tx,
+            liveness: Arc::new(Liveness::new()),
+            stream_stats: Arc::new(StreamStats::new()),
+            health_stale_after: Duration::from_secs(5),
+            clients: ClientRegistry::with_events(config_tx.clone()),
+            trust_proxy: false,
+            device: None,
+            privacy: PrivacyGate::default(),
+            saved_brightness: SavedBrightness::default(),
+            saved_rotation: SavedRotation::default(),
+            debug_config: None,
+            sink_stats: SinkStats::default(),
+            server_log_stats: None,
+            start_code_len: 4,
+            lag_resync_threshold: 0,
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            tls: None,
+            auth_token: None,
+            hub_authenticated: false,
+        })
     }
 
     /// 获取实际使用的端口
@@ -74,7 +237,7 @@ impl WebSocketServer {
     }
 
     /// 获取视频帧发送器的克隆
-    pub fn get_sender(&self) -> broadcast::Sender<Bytes> {
+    pub fn get_sender(&self) -> broadcast::Sender<BroadcastFrame> {
         self.tx.clone()
     }
 
@@ -83,80 +246,589 @@ impl WebSocketServer {
         self.config_tx.clone()
     }
 
+    /// 以 HTTPS/WSS 提供服务（证书由 `ws::tls::load_tls_config` 加载）
+    pub fn set_tls(&mut self, tls: RustlsConfig) {
+        self.tls = Some(tls);
+    }
+
+    /// 要求客户端携带访问令牌（--auth-token），/healthz 除外
+    pub fn set_auth_token(&mut self, token: &str) {
+        self.auth_token = Some(AuthToken::new(token));
+    }
+
+    /// 多设备模式：Router 挂在 hub 下，由 hub 校验令牌。告知本服务请求是否已通过令牌认证，
+    /// /admin/kick 等依赖认证状态的路由与单设备模式行为一致
+    pub fn set_hub_authenticated(&mut self, authenticated: bool) {
+        self.hub_authenticated = authenticated;
+    }
+
+    /// 启用 /audio 路由，向客户端转发该通道中的 Opus 音频（--audio）
+    pub fn set_audio_sender(&mut self, audio_tx: broadcast::Sender<Bytes>) {
+        self.audio_tx = Some(audio_tx);
+    }
+
     /// 获取视频配置的克隆
     pub fn get_video_config(&self) -> Arc<RwLock<VideoConfig>> {
         self.video_config.clone()
     }
 
-    /// 启动 WebSocket 服务器
-    pub async fn start(self) -> Result<()> {
-        // 根据 public 参数选择监听地址
-        let bind_addr: [u8; 4] = if self.public {
-            [0, 0, 0, 0]      // 监听所有接口，局域网可访问
-        } else {
-            [127, 0, 0, 1]    // 仅本地访问
-        };
-        let addr = SocketAddr::from((bind_addr, self.actual_port));
-        info!("🌐 Starting WebSocket server on {}", addr);
+    /// 获取管线存活状态的克隆（主循环在此记录帧、设备和 server 进程状态）
+    pub fn get_liveness(&self) -> Arc<Liveness> {
+        self.liveness.clone()
+    }
+
+    /// 获取视频流计数的克隆（主循环在此记录广播的帧和关键帧）
+    pub fn get_stream_stats(&self) -> Arc<StreamStats> {
+        self.stream_stats.clone()
+    }
 
+    /// 设置 /healthz 判定“帧停止”的时长
+    pub fn set_health_stale_after(&mut self, stale_after: Duration) {
+        self.health_stale_after = stale_after;
+    }
+
+    /// 设置是否信任 X-Forwarded-For 作为客户端地址
+    pub fn set_trust_proxy(&mut self, trust_proxy: bool) {
+        self.trust_proxy = trust_proxy;
+    }
+
+    /// 设置 ADB 设备，启用 device_screenshot 等直接通过 ADB 执行的请求
+    ///
+    /// `display_id` 是本会话镜像的显示屏：screencap 只能截取默认显示屏，其他显示屏上的截图请求直接报错。
+    pub fn set_device(&mut self, adb: AdbClient, device_id: String, display_id: u32) {
+        self.device = Some(DeviceAccess {
+            adb,
+            device_id,
+            display_id,
+            saved_brightness: self.saved_brightness.clone(),
+            saved_rotation: self.saved_rotation.clone(),
+            privacy: PrivacyGate::default(),
+        });
+    }
+
+    /// --only-app：目标应用不在前台时（`app_visible` 为 false）拒绝截图、暂停 /audio
+    pub fn set_privacy_gate(&mut self, app_visible: watch::Receiver<bool>) {
+        self.privacy = PrivacyGate::new(app_visible);
+    }
+
+    /// 获取 set_brightness 保存的原始亮度设置（关闭时恢复）
+    pub fn get_saved_brightness(&self) -> SavedBrightness {
+        self.saved_brightness.clone()
+    }
+
+    /// 获取 set_rotation_lock 保存的原始旋转设置（关闭时恢复）
+    pub fn get_saved_rotation(&self) -> SavedRotation {
+        self.saved_rotation.clone()
+    }
+
+    /// 设置 /stats 中显示的写入端状态
+    pub fn set_sink_stats(&mut self, stats: SinkStats) {
+        self.sink_stats = stats;
+    }
+
+    /// 设置 /stats 中显示的编码器信息（设备实际选择的编码器、尺寸等）
+    pub fn set_server_log_stats(&mut self, stats: ServerLogStats) {
+        self.server_log_stats = Some(stats);
+    }
+
+    /// 设置积压恢复阈值：客户端在 [`LAG_RESYNC_WINDOW`] 内积压（`Lagged`）达到该次数时，
+    /// 在下一个 IDR 之前单独给它重发 SPS/PPS（0 = 关闭）
+    pub fn set_lag_resync_threshold(&mut self, threshold: u32) {
+        self.lag_resync_threshold = threshold;
+    }
+
+    /// 设置客户端发来的单条 WebSocket 消息的字节数上限（/ws 和 /control）
+    pub fn set_max_message_bytes(&mut self, max_message_bytes: usize) {
+        self.max_message_bytes = max_message_bytes;
+    }
+
+    /// 设置 /raw 输出的起始码长度（3 或 4）
+    pub fn set_start_code_len(&mut self, len: u8) {
+        self.start_code_len = len;
+    }
+
+    /// 启用 /debug/config，返回给定的配置快照（调用方负责隐藏密钥）
+    pub fn set_debug_config(&mut self, config: serde_json::Value) {
+        self.debug_config = Some(Arc::new(config));
+    }
+
+    /// 获取关闭通知发送器的克隆，发送 Some(原因) 会断开所有客户端
+    pub fn get_shutdown_sender(&self) -> watch::Sender<Option<String>> {
+        self.shutdown_tx.clone()
+    }
+
+    /// 构建包含全部路由（`/`、`/ws`、`/control`、`/decoder/*`、`/stats` 等）的 Router
+    ///
+    /// 嵌入到其他 Axum 应用时可以挂载到子路径（如 `.nest("/mirror", server.build_router())`），
+    /// 监听地址和 TLS 由调用方负责。路由依赖 `ConnectInfo<SocketAddr>` 获取客户端地址，
+    /// 调用方需要使用 `into_make_service_with_connect_info::<SocketAddr>()` 提供服务。
+    pub fn build_router(&self) -> Router {
         let tx = self.tx.clone();
         let config_tx = self.config_tx.clone();
         let video_config = self.video_config.clone();
         let idr_request_tx = self.idr_request_tx.clone();
-        let control_tx = self.control_tx.clone();
-
-        // 创建 Axum 路由
-        let app = Router::new()
+        let control = ControlContext {
+            control_tx: self.control_tx.clone(),
+            device: self.device.clone().map(|device| DeviceAccess { privacy: self.privacy.clone(), ..device }),
+            max_message_bytes: self.max_message_bytes,
+        };
+        let shutdown_tx = self.shutdown_tx.clone();
+        let liveness = self.liveness.clone();
+        let health_stale_after = self.health_stale_after;
+        let clients = self.clients.clone();
+        let trust_proxy = self.trust_proxy;
+        let debug_config = self.debug_config.clone();
+        let start_code_len = self.start_code_len;
+        let lag_resync_threshold = self.lag_resync_threshold;
+        let screencap = Arc::new(ScreencapCache::new(self.device.clone().map(|d| (d.adb, d.device_id, d.display_id)), self.privacy.clone()));
+
+        // WebSocket 视频流不经过压缩层
+        let router = Router::new()
             .route("/ws", get({
                 let tx = tx.clone();
                 let config_tx = config_tx.clone();
                 let video_config = video_config.clone();
                 let idr_request_tx = idr_request_tx.clone();
-                let control_tx = control_tx.clone();
-                move |ws| handle_socket(ws, tx, config_tx, video_config, idr_request_tx, control_tx)
+                let control = control.clone();
+                let shutdown_tx = shutdown_tx.clone();
+                let clients = clients.clone();
+                move |ws: WebSocketUpgrade, ConnectInfo(peer): ConnectInfo<SocketAddr>, headers: HeaderMap, Query(params): Query<StreamParams>| {
+                    let addr = client_addr(peer, &headers, trust_proxy);
+                    let codec = check_codec(params.codec.as_deref());
+                    async move {
+                        if let Err(message) = codec {
+                            warn!("🎞️  Rejected viewer {}: {}", addr, message);
+                            return (StatusCode::BAD_REQUEST, axum::Json(serde_json::json!({ "error": message }))).into_response();
+                        }
+                        handle_socket(ws, clients.clone(), addr, tx, config_tx, video_config, idr_request_tx, control, lag_resync_threshold, shutdown_tx.subscribe())
+                            .await
+                            .into_response()
+                    }
+                }
+            }))
+            .route("/control", get({
+                let control = control.clone();
+                let shutdown_tx = shutdown_tx.clone();
+                let clients = clients.clone();
+                move |ws: WebSocketUpgrade, ConnectInfo(peer): ConnectInfo<SocketAddr>, headers: HeaderMap| {
+                    let addr = client_addr(peer, &headers, trust_proxy);
+                    handle_control_socket(ws, clients.clone(), addr, control, shutdown_tx.subscribe())
+                }
+            }))
+            .route("/stats", get({
+                let clients = clients.clone();
+                let tx = tx.clone();
+                let video_config = video_config.clone();
+                let stream_stats = self.stream_stats.clone();
+                let sink_stats = self.sink_stats.clone();
+                let server_log_stats = self.server_log_stats.clone();
+                move || serve_stats(clients, tx, video_config, stream_stats, sink_stats, server_log_stats)
+            }))
+            .route("/admin/kick", post({
+                let token_checked = self.auth_token.is_some() || self.hub_authenticated;
+                move |ConnectInfo(peer): ConnectInfo<SocketAddr>, headers: HeaderMap, Query(params): Query<KickParams>| {
+                    serve_kick(clients, peer, headers, token_checked, params)
+                }
+            }))
+            .route("/debug/config", get(move || serve_debug_config(debug_config)))
+            .route("/raw", get({
+                let tx = tx.clone();
+                let video_config = video_config.clone();
+                let idr_request_tx = idr_request_tx.clone();
+                move |Query(params): Query<RawParams>| serve_raw(params, tx, video_config, idr_request_tx, start_code_len)
+            }))
+            .route("/screencap.png", get({
+                let screencap = screencap.clone();
+                move |Query(params): Query<ScreencapParams>| serve_screencap(ScreencapFormat::Png, params, screencap)
             }))
-            .route("/", get(serve_html))
-            .route("/decoder/Decoder.min.js", get(serve_broadway_decoder))
-            .route("/decoder/jmuxer.min.js", get(serve_jmuxer));
+            .route("/screencap.jpg", get({
+                let screencap = screencap.clone();
+                move |Query(params): Query<ScreencapParams>| serve_screencap(ScreencapFormat::Jpeg, params, screencap)
+            }))
+            .route("/screenshot", get(move |Query(params): Query<ScreencapParams>| {
+                serve_screencap(params.format.unwrap_or(ScreencapFormat::Png), params, screencap)
+            }))
+            .route("/raw.h264", get(move || serve_raw(RawParams::default(), tx, video_config, idr_request_tx, start_code_len)))
+            .merge(static_routes());
+
+        // 未启用音频时不提供 /audio，只看视频的部署不受影响
+        let router = match self.audio_tx.clone() {
+            Some(audio_tx) => {
+                let clients = self.clients.clone();
+                let privacy = self.privacy.clone();
+                router.route("/audio", get(move |ws: WebSocketUpgrade, ConnectInfo(peer): ConnectInfo<SocketAddr>, headers: HeaderMap| {
+                    let addr = client_addr(peer, &headers, trust_proxy);
+                    handle_audio_socket(ws, clients.clone(), addr, audio_tx, privacy.clone(), shutdown_tx.subscribe())
+                }))
+            }
+            None => router,
+        };
+
+        // 令牌检查只覆盖之前添加的路由，/healthz 在之后添加，存活探针不需要令牌
+        let router = match self.auth_token.clone() {
+            Some(token) => router.layer(middleware::from_fn(move |request, next| auth::require_token(token.clone(), request, next))),
+            None => router,
+        };
+        router.route("/healthz", get(move || serve_healthz(liveness, health_stale_after)))
+    }
+
+    /// 绑定监听地址
+    ///
+    /// 与 [`serve`](Self::serve) 分开调用时，绑定失败可以在提供服务之前返回给调用方，
+    /// 由调用方停止设备端 server 后退出，而不是留下一个无法访问的会话。
+    pub async fn bind(&self) -> Result<tokio::net::TcpListener> {
+        let addr = SocketAddr::new(self.bind_addr, self.actual_port);
+        info!("🌐 Starting WebSocket server on {}", addr);
 
-        // 启动服务器
         let listener = tokio::net::TcpListener::bind(&addr)
             .await
-            .map_err(|e| ScrcpyError::Network(format!("Failed to bind: {}", e)))?;
+            .map_err(|e| ScrcpyError::Network(format!("Failed to bind {}: {}", addr, e)))?;
 
-        info!("✅ WebSocket server ready at ws://{}/ws", addr);
-        info!("📱 Open http://{} in your browser", addr);
+        let (ws_scheme, http_scheme) = if self.tls.is_some() { ("wss", "https") } else { ("ws", "http") };
+        info!("✅ WebSocket server ready at {}://{}/ws", ws_scheme, addr);
+        info!("📱 Open {}://{} in your browser", http_scheme, addr);
+        Ok(listener)
+    }
 
-        axum::serve(listener, app)
-            .await
-            .map_err(|e| ScrcpyError::Network(format!("Server error: {}", e)))?;
+    /// 在已绑定的监听器上提供服务，直到出错
+    pub async fn serve(mut self, listener: tokio::net::TcpListener) -> Result<()> {
+        let tls = self.tls.take();
+        let app = self.build_router().into_make_service_with_connect_info::<SocketAddr>();
+        match tls {
+            Some(tls) => {
+                axum_server::from_tcp_rustls(listener.into_std()?, tls)
+                    .serve(app)
+                    .await
+                    .map_err(|e| ScrcpyError::Network(format!("Server error: {}", e)))?;
+            }
+            None => {
+                axum::serve(listener, app)
+                    .await
+                    .map_err(|e| ScrcpyError::Network(format!("Server error: {}", e)))?;
+            }
+        }
 
         Ok(())
     }
 }
 
 /// 处理 WebSocket 连接
+#[allow(clippy::too_many_arguments)]
 async fn handle_socket(
     ws: WebSocketUpgrade,
-    tx: broadcast::Sender<Bytes>,
+    clients: ClientRegistry,
+    addr: String,
+    tx: broadcast::Sender<BroadcastFrame>,
     config_tx: broadcast::Sender<String>,
     video_config: Arc<RwLock<VideoConfig>>,
     idr_request_tx: mpsc::Sender<()>,
-    control_tx: mpsc::Sender<ControlEvent>,
+    control: ControlContext,
+    lag_resync_threshold: u32,
+    shutdown_rx: watch::Receiver<Option<String>>,
+) -> impl IntoResponse {
+    ws.max_message_size(control.max_message_bytes).max_frame_size(control.max_message_bytes).on_upgrade(move |socket| {
+        let client = clients.register(addr, ClientKind::Viewer);
+        handle_client(socket, client, tx, config_tx, video_config, idr_request_tx, control, lag_resync_threshold, shutdown_rx)
+    })
+}
+
+/// ADB 设备句柄（WebSocket 层直接执行的设备操作）
+#[derive(Clone)]
+struct DeviceAccess {
+    adb: AdbClient,
+    device_id: String,
+    display_id: u32,  // 本会话镜像的显示屏（--display-id / --display-ids）
+    saved_brightness: SavedBrightness,
+    saved_rotation: SavedRotation,
+    privacy: PrivacyGate,
+}
+
+/// --only-app 的隐私开关：视频广播之外直接读取设备画面或声音的路径（截图、/audio）共用
+///
+/// 未启用 --only-app 时总是放行。
+#[derive(Clone, Default)]
+pub struct PrivacyGate(Option<watch::Receiver<bool>>);
+
+impl PrivacyGate {
+    pub fn new(app_visible: watch::Receiver<bool>) -> Self {
+        Self(Some(app_visible))
+    }
+
+    /// 目标应用当前不在前台
+    pub fn is_hidden(&self) -> bool {
+        self.0.as_ref().is_some_and(|app_visible| !*app_visible.borrow())
+    }
+}
+
+/// 隐私模式下拒绝请求时的说明
+pub const PRIVACY_PAUSED_MESSAGE: &str = "paused: the --only-app target app is not in the foreground";
+
+/// 首次调整亮度前的设置，None 表示未调整过
+pub type SavedBrightness = Arc<Mutex<Option<BrightnessState>>>;
+
+/// 首次锁定方向前的旋转设置，None 表示未调整过
+pub type SavedRotation = Arc<Mutex<Option<RotationState>>>;
+
+/// 每个客户端处理控制消息所需的上下文
+#[derive(Clone)]
+struct ControlContext {
+    control_tx: ControlSender,
+    device: Option<DeviceAccess>,
+    max_message_bytes: usize,
+}
+
+/// 不经过 scrcpy 控制通道、需要回复请求者的设备请求
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum DeviceRequest {
+    DeviceScreenshot,      // 在设备上截图并保存到 /sdcard/Pictures
+    ListImes,              // 列出已启用的输入法和当前输入法
+    SetIme { id: String }, // 切换输入法
+    SetBrightness { value: i64 },  // 调整屏幕亮度（0 ~ 255，关闭自动亮度）
+    GetRotation,                   // 读取自动旋转和锁定方向
+    SetRotationLock { rotation: Option<u8> },  // 锁定方向（0 ~ 3），null 恢复自动旋转
+}
+
+fn error_message(message: impl std::fmt::Display) -> String {
+    serde_json::json!({ "type": "error", "message": message.to_string() }).to_string()
+}
+
+/// 在后台执行设备请求，结果通过 reply_tx 发回请求者（不阻塞该客户端的视频发送）
+fn spawn_device_request(request: DeviceRequest, device: Option<DeviceAccess>, reply_tx: mpsc::Sender<String>) {
+    tokio::spawn(async move {
+        let Some(device) = device else {
+            let _ = reply_tx.send(error_message("device requests are not available")).await;
+            return;
+        };
+        let reply = match request {
+            DeviceRequest::DeviceScreenshot if device.privacy.is_hidden() => error_message(PRIVACY_PAUSED_MESSAGE),
+            DeviceRequest::DeviceScreenshot if device.display_id != 0 => error_message(NON_DEFAULT_DISPLAY_MESSAGE),
+            DeviceRequest::DeviceScreenshot => match device.adb.device_screenshot(&device.device_id).await {
+                Ok(path) => {
+                    info!("📸 Device screenshot saved: {}", path);
+                    serde_json::json!({ "type": "device_screenshot", "path": path }).to_string()
+                }
+                Err(e) => {
+                    warn!("❌ Device screenshot failed: {}", e);
+                    error_message(format!("device screenshot failed: {}", e))
+                }
+            },
+            DeviceRequest::ListImes => {
+                let imes = device.adb.list_imes(&device.device_id).await;
+                let current = device.adb.current_ime(&device.device_id).await;
+                match (imes, current) {
+                    (Ok(imes), Ok(current)) => serde_json::json!({ "type": "imes", "imes": imes, "current": current }).to_string(),
+                    (Err(e), _) | (_, Err(e)) => error_message(format!("failed to list IMEs: {}", e)),
+                }
+            }
+            DeviceRequest::SetIme { id } => match device.adb.set_ime(&device.device_id, &id).await {
+                Ok(()) => {
+                    info!("⌨️  Switched IME to {}", id);
+                    serde_json::json!({ "type": "ime_changed", "id": id }).to_string()
+                }
+                Err(e) => {
+                    warn!("❌ Failed to switch IME: {}", e);
+                    error_message(format!("set_ime failed: {}", e))
+                }
+            },
+            DeviceRequest::SetBrightness { value } => match set_brightness(&device, value).await {
+                Ok(()) => {
+                    info!("🔆 Set brightness to {}", value);
+                    serde_json::json!({ "type": "brightness_changed", "value": value }).to_string()
+                }
+                Err(e) => {
+                    warn!("❌ Failed to set brightness: {}", e);
+                    error_message(format!("set_brightness failed: {}", e))
+                }
+            },
+            DeviceRequest::GetRotation => match device.adb.get_rotation(&device.device_id).await {
+                Ok(state) => rotation_message(state),
+                Err(e) => error_message(format!("get_rotation failed: {}", e)),
+            },
+            DeviceRequest::SetRotationLock { rotation } => match set_rotation_lock(&device, rotation).await {
+                Ok(state) => {
+                    match rotation {
+                        Some(rotation) => info!("🔒 Locked rotation to {}", rotation),
+                        None => info!("🔓 Auto-rotate enabled"),
+                    }
+                    rotation_message(state)
+                }
+                Err(e) => {
+                    warn!("❌ Failed to set rotation lock: {}", e);
+                    error_message(format!("set_rotation_lock failed: {}", e))
+                }
+            },
+        };
+        let _ = reply_tx.send(reply).await;
+    });
+}
+
+/// 调整亮度；首次调整前保存原始设置（持有锁，并发请求不会把已调整的值当作原始值）
+async fn set_brightness(device: &DeviceAccess, value: i64) -> Result<()> {
+    let mut saved = device.saved_brightness.lock().await;
+    if saved.is_none() && (MIN_BRIGHTNESS..=MAX_BRIGHTNESS).contains(&value) {
+        *saved = Some(device.adb.get_brightness(&device.device_id).await?);
+    }
+    device.adb.set_brightness(&device.device_id, value).await
+}
+
+/// 锁定/解锁方向；首次调整前保存原始设置
+async fn set_rotation_lock(device: &DeviceAccess, rotation: Option<u8>) -> Result<RotationState> {
+    let mut saved = device.saved_rotation.lock().await;
+    if saved.is_none() {
+        *saved = Some(device.adb.get_rotation(&device.device_id).await?);
+    }
+    device.adb.set_rotation_lock(&device.device_id, rotation).await
+}
+
+fn rotation_message(state: RotationState) -> String {
+    serde_json::json!({ "type": "rotation", "locked": state.locked, "user_rotation": state.user_rotation }).to_string()
+}
+
+/// 解析控制事件 JSON 并转发给控制通道
+///
+/// 解析失败（未知类型、未知系统按键名等）时返回发给客户端的错误消息；
+/// 设备请求在后台执行，结果稍后通过 reply_tx 回复。
+async fn forward_control_message(text: &str, control: &ControlContext, reply_tx: &mpsc::Sender<String>) -> Option<String> {
+    debug!("📥 Received control message: {}", text);
+    if let Ok(request) = serde_json::from_str::<DeviceRequest>(text) {
+        spawn_device_request(request, control.device.clone(), reply_tx.clone());
+        return None;
+    }
+    match serde_json::from_str::<ControlEvent>(text) {
+        Ok(ControlEvent::Unknown) => {
+            // 新版客户端的消息：只记 debug 日志，回复结构化错误便于客户端检测服务端支持的功能
+            let message_type = serde_json::from_str::<serde_json::Value>(text)
+                .ok()
+                .and_then(|value| value.get("type")?.as_str().map(str::to_string))
+                .unwrap_or_default();
+            debug!("Unknown control message type '{}'", message_type);
+            Some(serde_json::json!({
+                "type": "error",
+                "reason": "unknown_type",
+                "message_type": message_type,
+                "message": format!("unknown message type '{}'", message_type),
+            }).to_string())
+        }
+        Ok(control_event) => {
+            if let Err(e) = control_event.validate() {
+                warn!("Rejected control event: {}", e);
+                return Some(error_message(e));
+            }
+            debug!("✅ Parsed control event: {:?}", control_event);
+            if let Err(e) = control.control_tx.send(control_event).await {
+                warn!("Failed to forward control event: {}", e);
+            }
+            None
+        }
+        Err(e) => {
+            warn!("Failed to parse control event '{}': {}", text, e);
+            Some(error_message(e))
+        }
+    }
+}
+
+/// 仅控制的 WebSocket 升级处理（/control）
+async fn handle_control_socket(
+    ws: WebSocketUpgrade,
+    clients: ClientRegistry,
+    addr: String,
+    control: ControlContext,
+    shutdown_rx: watch::Receiver<Option<String>>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_client(socket, tx, config_tx, video_config, idr_request_tx, control_tx))
+    ws.max_message_size(control.max_message_bytes).max_frame_size(control.max_message_bytes).on_upgrade(move |socket| {
+        let client = clients.register(addr, ClientKind::ControlOnly);
+        handle_control_client(socket, client, control, shutdown_rx)
+    })
+}
+
+/// 处理仅控制的客户端连接
+///
+/// 视频在其他地方渲染的客户端只需要发送控制事件：
+/// 不订阅视频广播、不发送 SPS/PPS、也不请求 IDR 帧。
+async fn handle_control_client(
+    mut socket: WebSocket,
+    mut client: ClientGuard,
+    control: ControlContext,
+    mut shutdown_rx: watch::Receiver<Option<String>>,
+) {
+    let addr = client.info().addr.clone();
+    info!("🎮 New control-only client connected: {} (id {})", addr, client.info().id);
+    let (reply_tx, mut reply_rx) = mpsc::channel::<String>(8);
+
+    loop {
+        tokio::select! {
+            // 设备请求的回复
+            Some(reply) = reply_rx.recv() => {
+                let _ = socket.send(Message::Text(reply)).await;
+            }
+            // 管理员踢出该客户端
+            reason = client.kicked() => {
+                info!("🚫 Kicking control client {}: {}", addr, reason);
+                let _ = socket.send(Message::Close(Some(CloseFrame {
+                    code: close_code::POLICY,
+                    reason: reason.into(),
+                }))).await;
+                break;
+            }
+            Ok(()) = shutdown_rx.changed() => {
+                let reason = shutdown_rx.borrow_and_update().clone();
+                if let Some(reason) = reason {
+                    info!("🛑 Closing control client {}: {}", addr, reason);
+                    let _ = socket.send(Message::Close(Some(CloseFrame {
+                        code: close_code::AWAY,
+                        reason: reason.into(),
+                    }))).await;
+                    break;
+                }
+            }
+            msg = socket.recv() => {
+                match msg {
+                    Some(Ok(Message::Text(text))) => {
+                        if let Some(error_msg) = forward_control_message(&text, &control, &reply_tx).await {
+                            let _ = socket.send(Message::Text(error_msg)).await;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | None => {
+                        info!("👋 Control client {} disconnected", addr);
+                        break;
+                    }
+                    Some(Err(e)) => {
+                        warn!("❌ Control client {} disconnected (recv error): {}", addr, e);
+                        break;
+                    }
+                    _ => {
+                        // 忽略二进制等其他消息类型
+                    }
+                }
+            }
+        }
+    }
+
+    info!("👋 Control-only client handler exiting: {}", addr);
 }
 
 /// 处理单个客户端连接
+#[allow(clippy::too_many_arguments)]
 async fn handle_client(
     mut socket: WebSocket,
-    tx: broadcast::Sender<Bytes>,
+    mut client: ClientGuard,
+    tx: broadcast::Sender<BroadcastFrame>,
     config_tx: broadcast::Sender<String>,
     video_config: Arc<RwLock<VideoConfig>>,
     idr_request_tx: mpsc::Sender<()>,
-    control_tx: mpsc::Sender<ControlEvent>,
+    control: ControlContext,
+    lag_resync_threshold: u32,
+    mut shutdown_rx: watch::Receiver<Option<String>>,
 ) {
-    info!("📱 New WebSocket client connected");
+    let addr = client.info().addr.clone();
+    info!("📱 New WebSocket client connected: {} (id {})", addr, client.info().id);
+
+    // 先订阅广播频道，再请求 IDR 和读取缓存：
+    // 这样之后才到达（或被重新广播）的 SPS/PPS 一定会出现在订阅流中，不会漏掉
+    let mut rx = tx.subscribe();
+    let mut config_rx = config_tx.subscribe();
+    let (reply_tx, mut reply_rx) = mpsc::channel::<String>(8);
 
     // 🔥 关键：新客户端连接时，立即请求IDR帧
     info!("🎬 Requesting IDR frame for new client...");
@@ -166,42 +838,70 @@ async fn handle_client(
 
     // 立即发送视频配置信息（视频流分辨率 + 设备物理分辨率 + 横屏状态）
     let config = video_config.read().await;
-    let config_msg = format!("{{\"type\":\"config\",\"width\":{},\"height\":{},\"device_width\":{},\"device_height\":{},\"is_landscape\":{}}}",
-        config.width, config.height, config.device_width, config.device_height, config.is_landscape);
+    let config_msg = config.config_message();
     if socket.send(Message::Text(config_msg)).await.is_err() {
         warn!("Failed to send config to client");
         return;
     }
 
     // 立即发送缓存的 SPS/PPS 给新客户端
+    let mut param_sets = ParamSetState { frame_meta: config.frame_meta, ..Default::default() };
     if let Some(sps) = &config.sps {
         info!("📤 Sending cached SPS to new client ({} bytes)", sps.len());
-        if socket.send(Message::Binary(sps.to_vec())).await.is_err() {
+        if socket.send(Message::Binary(video_message(&[sps], None, param_sets.frame_meta))).await.is_err() {
             warn!("Failed to send SPS to client");
             return;
         }
+        param_sets.sps_sent = true;
     } else {
         info!("⚠️  No SPS cached yet");
     }
     if let Some(pps) = &config.pps {
         info!("📤 Sending cached PPS to new client ({} bytes)", pps.len());
-        if socket.send(Message::Binary(pps.to_vec())).await.is_err() {
+        if socket.send(Message::Binary(video_message(&[pps], None, param_sets.frame_meta))).await.is_err() {
             warn!("Failed to send PPS to client");
             return;
         }
+        param_sets.pps_sent = true;
     } else {
         info!("⚠️  No PPS cached yet");
     }
 
     drop(config); // 释放读锁
-
-    // 订阅广播频道
-    let mut rx = tx.subscribe();
-    let mut config_rx = config_tx.subscribe();
+    let mut lag_tracker = LagTracker::new(lag_resync_threshold);
 
     // 持续接收并转发视频帧，同时监听客户端消息和配置变化
     loop {
         tokio::select! {
+            // 设备请求的回复
+            Some(reply) = reply_rx.recv() => {
+                let _ = socket.send(Message::Text(reply)).await;
+            }
+            // 管理员踢出该客户端
+            reason = client.kicked() => {
+                info!("🚫 Kicking client {}: {}", addr, reason);
+                let _ = socket.send(Message::Close(Some(CloseFrame {
+                    code: close_code::POLICY,
+                    reason: reason.into(),
+                }))).await;
+                break;
+            }
+            // 服务端关闭：以给定原因关闭连接
+            Ok(()) = shutdown_rx.changed() => {
+                let reason = shutdown_rx.borrow_and_update().clone();
+                if let Some(reason) = reason {
+                    info!("🛑 Closing client {}: {}", addr, reason);
+                    // 先把尚未发出的状态消息（如 session_ending）发给客户端
+                    while let Ok(msg) = config_rx.try_recv() {
+                        let _ = socket.send(Message::Text(msg)).await;
+                    }
+                    let _ = socket.send(Message::Close(Some(CloseFrame {
+                        code: close_code::AWAY,
+                        reason: reason.into(),
+                    }))).await;
+                    break;
+                }
+            }
             // 接收配置变化并发送给客户端
             config_result = config_rx.recv() => {
                 match config_result {
@@ -226,19 +926,39 @@ async fn handle_client(
             frame_result = rx.recv() => {
                 match frame_result {
                     Ok(frame_data) => {
+                        // 连接时还没有 SPS/PPS 的客户端，在第一个视频帧前补发
+                        if !param_sets.ensure(&mut socket, &frame_data.nal, &video_config).await {
+                            warn!("❌ Client disconnected (param set send failed)");
+                            break;
+                        }
                         // 发送二进制数据到客户端
-                        if socket.send(Message::Binary(frame_data.to_vec())).await.is_err() {
+                        if socket.send(Message::Binary(viewer_frame(&frame_data, &video_config, param_sets.frame_meta).await)).await.is_err() {
                             warn!("❌ Client disconnected (send failed)");
                             break;
                         }
                     }
                     Err(broadcast::error::RecvError::Lagged(_skipped)) => {
+                        // 反复积压：MSE 解码器可能已经丢失参数集，在下一个 IDR 前单独重发 SPS/PPS
+                        if lag_tracker.on_lag(std::time::Instant::now()) {
+                            info!("🩹 Client {} keeps lagging, re-sending SPS/PPS before the next IDR", addr);
+                            param_sets.resync_at_next_idr();
+                            client.record_lag_recovery();
+                        }
                         // 🔥 追帧策略：清空积压的旧帧，直接跳到最新
+                        // 积压中的非参考 P 帧直接丢弃（不会被后续帧参考，不会花屏），
+                        // 参考帧仍需发送以保证解码正确
                         loop {
                             match rx.try_recv() {
+                                Ok(latest_frame) if latest_frame.nal.get(4).is_some_and(|&h| is_discardable_nal(h)) => {
+                                    continue;
+                                }
                                 Ok(latest_frame) => {
+                                    if !param_sets.ensure(&mut socket, &latest_frame.nal, &video_config).await {
+                                        warn!("❌ Client disconnected during flush");
+                                        break;
+                                    }
                                     // 尝试发送最新帧
-                                    if socket.send(Message::Binary(latest_frame.to_vec())).await.is_err() {
+                                    if socket.send(Message::Binary(viewer_frame(&latest_frame, &video_config, param_sets.frame_meta).await)).await.is_err() {
                                         warn!("❌ Client disconnected during flush");
                                         break;
                                     }
@@ -270,18 +990,8 @@ async fn handle_client(
             msg = socket.recv() => {
                 match msg {
                     Some(Ok(Message::Text(text))) => {
-                        // 解析控制事件JSON
-                        debug!("📥 Received control message: {}", text);
-                        match serde_json::from_str::<ControlEvent>(&text) {
-                            Ok(control_event) => {
-                                debug!("✅ Parsed control event: {:?}", control_event);
-                                if let Err(e) = control_tx.send(control_event).await {
-                                    warn!("Failed to forward control event: {}", e);
-                                }
-                            }
-                            Err(e) => {
-                                warn!("Failed to parse control event '{}': {}", text, e);
-                            }
+                        if let Some(error_msg) = forward_control_message(&text, &control, &reply_tx).await {
+                            let _ = socket.send(Message::Text(error_msg)).await;
                         }
                     }
                     Some(Ok(Message::Close(_))) => {
@@ -307,12 +1017,219 @@ async fn handle_client(
         }
     }
 
-    info!("👋 WebSocket client disconnected");
+    info!("👋 WebSocket client disconnected: {}", addr);
+}
+
+/// 发给浏览器的一条视频消息
+///
+/// `--inline-sps-on-idr` 时在 IDR 前拼接缓存的 SPS/PPS，作为一条消息发送，WebCodecs 每个关键帧都自带参数集。
+/// 只在发给浏览器时拼接：广播通道还供 /raw 和写入端使用，它们按每条消息第一个 NAL 的类型处理。
+async fn viewer_frame(frame: &BroadcastFrame, video_config: &Arc<RwLock<VideoConfig>>, frame_meta: bool) -> Vec<u8> {
+    if frame.nal.get(4).map(|b| b & 0x1F) == Some(5) {
+        let config = video_config.read().await;
+        if let (Some(sps), Some(pps), true) = (&config.sps, &config.pps, config.inline_sps_on_idr) {
+            return video_message(&[sps, pps, &frame.nal], frame.pts, frame_meta);
+        }
+    }
+    video_message(&[&frame.nal], frame.pts, frame_meta)
+}
+
+/// 拼接一条二进制视频消息
+///
+/// `--frame-meta` 时消息以 8 字节大端 PTS（微秒）开头，参数集等没有 PTS 的数据为 0。
+fn video_message(parts: &[&Bytes], pts: Option<u64>, frame_meta: bool) -> Vec<u8> {
+    let header_len = if frame_meta { 8 } else { 0 };
+    let mut message = Vec::with_capacity(header_len + parts.iter().map(|part| part.len()).sum::<usize>());
+    if frame_meta {
+        message.extend_from_slice(&pts.unwrap_or(0).to_be_bytes());
+    }
+    for part in parts {
+        message.extend_from_slice(part);
+    }
+    message
+}
+
+/// 记录某个客户端是否已经收到 SPS/PPS
+///
+/// 客户端可能在 SPS 到达之前连接（部分设备会先发送 AUD/填充 NAL），
+/// 此时缓存为空。在它看到参数集之前，每个视频帧发送前都会重新检查缓存，
+/// 一旦主循环缓存了 SPS/PPS 就立即补发，客户端无需刷新页面即可开始解码。
+#[derive(Default)]
+struct ParamSetState {
+    sps_sent: bool,
+    pps_sent: bool,
+    wait_for_idr: bool,  // 积压恢复：缓存的参数集推迟到下一个 IDR 之前发送
+    frame_meta: bool,    // 消息带 PTS 前缀（--frame-meta）
+}
+
+impl ParamSetState {
+    /// 积压恢复：在下一个 IDR 之前重新发送缓存的 SPS/PPS（流中先到达新的参数集时不再重复发送）
+    fn resync_at_next_idr(&mut self) {
+        self.sps_sent = false;
+        self.pps_sent = false;
+        self.wait_for_idr = true;
+    }
+
+    /// 在发送 `frame` 之前确保客户端已收到 SPS/PPS，返回 false 表示发送失败
+    async fn ensure(
+        &mut self,
+        socket: &mut WebSocket,
+        frame: &Bytes,
+        video_config: &Arc<RwLock<VideoConfig>>,
+    ) -> bool {
+        if self.sps_sent && self.pps_sent {
+            return true;
+        }
+
+        // 广播的帧都带有 4 字节起始码，NAL 类型在第 5 个字节
+        match frame.get(4).map(|b| b & 0x1F) {
+            Some(7) => {
+                self.sps_sent = true;
+                return true;
+            }
+            Some(8) => {
+                self.pps_sent = true;
+                return true;
+            }
+            Some(5) => self.wait_for_idr = false,
+            _ if self.wait_for_idr => return true,
+            _ => {}
+        }
+
+        let config = video_config.read().await;
+        if !self.sps_sent {
+            if let Some(sps) = &config.sps {
+                info!("📤 Sending late SPS to client ({} bytes)", sps.len());
+                if socket.send(Message::Binary(video_message(&[sps], None, self.frame_meta))).await.is_err() {
+                    return false;
+                }
+                self.sps_sent = true;
+            }
+        }
+        if !self.pps_sent {
+            if let Some(pps) = &config.pps {
+                info!("📤 Sending late PPS to client ({} bytes)", pps.len());
+                if socket.send(Message::Binary(video_message(&[pps], None, self.frame_meta))).await.is_err() {
+                    return false;
+                }
+                self.pps_sent = true;
+            }
+        }
+        true
+    }
+}
+
+/// 客户端单条消息的默认上限：能容纳 scrcpy 允许的最大剪贴板文本（256KB）及其 JSON 转义
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 512 * 1024;
+
+/// 积压恢复的统计窗口：窗口内没有新的积压时计数清零
+pub const LAG_RESYNC_WINDOW: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// 统计单个客户端的积压（`Lagged`）次数，判断是否需要重发参数集
+struct LagTracker {
+    threshold: u32,
+    count: u32,
+    last_lag: Option<std::time::Instant>,
+}
+
+impl LagTracker {
+    fn new(threshold: u32) -> Self {
+        Self { threshold, count: 0, last_lag: None }
+    }
+
+    /// 记录一次积压，达到阈值时返回 true 并重新计数
+    fn on_lag(&mut self, now: std::time::Instant) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+        if self.last_lag.is_some_and(|last| now.duration_since(last) > LAG_RESYNC_WINDOW) {
+            self.count = 0;
+        }
+        self.last_lag = Some(now);
+        self.count += 1;
+        if self.count >= self.threshold {
+            self.count = 0;
+            return true;
+        }
+        false
+    }
+}
+
+/// 静态资源路由：HTML 和解码器 JS 体积较大，按 Accept-Encoding 压缩
+fn static_routes() -> Router {
+    Router::new()
+        .route("/", get(serve_html))
+        .route("/decoder/Decoder.min.js", get(serve_broadway_decoder))
+        .route("/decoder/jmuxer.min.js", get(serve_jmuxer))
+        .layer(CompressionLayer::new())
+}
+
+/// WebCodecs 硬件加速偏好（对应 VideoDecoderConfig.hardwareAcceleration）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum HardwareAcceleration {
+    NoPreference,
+    #[default]
+    PreferHardware,
+    PreferSoftware,
+}
+
+impl HardwareAcceleration {
+    fn as_str(self) -> &'static str {
+        match self {
+            HardwareAcceleration::NoPreference => "no-preference",
+            HardwareAcceleration::PreferHardware => "prefer-hardware",
+            HardwareAcceleration::PreferSoftware => "prefer-software",
+        }
+    }
+}
+
+/// 每个会话只运行一路编码，/ws 可选的编码只有 H.264（按客户端选择编码未实现，见 README）
+pub const AVAILABLE_CODECS: &[&str] = &["h264"];
+
+/// /ws 查询参数：`/ws?codec=h264`
+#[derive(Debug, Default, serde::Deserialize)]
+struct StreamParams {
+    codec: Option<String>,
+}
+
+/// 检查客户端请求的编码，不可用时返回错误信息（升级前以 400 拒绝，而不是发送无法解码的流）
+fn check_codec(codec: Option<&str>) -> std::result::Result<(), String> {
+    match codec {
+        None => Ok(()),
+        Some(codec) if AVAILABLE_CODECS.iter().any(|c| c.eq_ignore_ascii_case(codec)) => Ok(()),
+        Some(codec) => Err(format!("codec '{}' is not available (available: {})", codec, AVAILABLE_CODECS.join(", "))),
+    }
+}
+
+/// 页面查询参数：`/?hwaccel=prefer-software&low_latency=false&smooth_rotate=1`
+///
+/// 部分机器的硬件解码有驱动问题，可以不改源码直接切换解码配置。
+#[derive(Debug, Default, serde::Deserialize)]
+struct ViewerParams {
+    #[serde(default)]
+    hwaccel: HardwareAcceleration,
+    low_latency: Option<bool>,
+    smooth_rotate: Option<u8>,  // 1：旋转时保留最后一帧，直到新分辨率的第一个 IDR
+    device: Option<String>,     // --all-devices：页面的 /ws、/audio 连接带上同样的 ?device=
 }
 
 /// 提供简单的 HTML 页面
-async fn serve_html() -> impl IntoResponse {
-    let html = r#"
+async fn serve_html(nested_path: Option<NestedPath>, Query(params): Query<ViewerParams>) -> impl IntoResponse {
+    // 挂载在子路径下时（build_router + nest），页面中的资源和 WebSocket 地址加上前缀
+    let base_path = nested_path.as_ref().map(|p| p.as_str().trim_end_matches('/')).unwrap_or("");
+    let html = VIEWER_HTML
+        .replace("__BASE_PATH__", base_path)
+        .replace("__DEVICE_QUERY__", &params.device.as_deref().map(hub::device_query).unwrap_or_default())
+        .replace("__HW_ACCEL__", params.hwaccel.as_str())
+        .replace("__OPTIMIZE_FOR_LATENCY__", if params.low_latency.unwrap_or(true) { "true" } else { "false" })
+        .replace("__SMOOTH_ROTATE__", if params.smooth_rotate.unwrap_or(0) != 0 { "true" } else { "false" });
+
+    ([("content-type", "text/html; charset=utf-8")], html)
+}
+
+/// Web 查看器页面模板（`__HW_ACCEL__` 等占位符由 serve_html 按查询参数和挂载路径替换）
+const VIEWER_HTML: &str = r#"
 <!DOCTYPE html>
 <html>
 <head>
@@ -320,9 +1237,9 @@ async fn serve_html() -> impl IntoResponse {
     <meta name="viewport" content="width=device-width, initial-scale=1.0, maximum-scale=1.0, user-scalable=no">
     <title>Rust-Scrcpy Web Viewer</title>
     <!-- Broadway.js H.264 解码器 (本地文件) -->
-    <script src="/decoder/Decoder.min.js"></script>
+    <script src="__BASE_PATH__/decoder/Decoder.min.js"></script>
     <!-- JMuxer MSE 播放器 (本地文件) -->
-    <script src="/decoder/jmuxer.min.js"></script>
+    <script src="__BASE_PATH__/decoder/jmuxer.min.js"></script>
     <style>
         * {
             margin: 0;
@@ -426,6 +1343,19 @@ async fn serve_html() -> impl IntoResponse {
             animation: pulse 1s infinite;
         }
 
+        /* 重连期间：圆点变为旋转的圆环，画面保留最后一帧 */
+        #decoderStatus.reconnecting .dot {
+            background: transparent;
+            border: 2px solid rgba(255, 193, 7, 0.3);
+            border-top-color: #FFC107;
+            box-sizing: border-box;
+            animation: spin 0.8s linear infinite;
+        }
+
+        @keyframes spin {
+            to { transform: rotate(360deg); }
+        }
+
         @keyframes pulse {
             0%, 100% { opacity: 1; }
             50% { opacity: 0.4; }
@@ -494,6 +1424,72 @@ async fn serve_html() -> impl IntoResponse {
             gap: 10px;
             justify-content: center;
         }
+
+        /* 相对鼠标模式按钮（指针锁定，用于 FPS 游戏） */
+        #mouseModeButton {
+            position: fixed;
+            right: 10px;
+            bottom: 10px;
+            padding: 8px 14px;
+            border: none;
+            border-radius: 20px;
+            font-size: 12px;
+            color: white;
+            background: rgba(0, 0, 0, 0.7);
+            z-index: 1000;
+            cursor: pointer;
+            user-select: none;
+            -webkit-user-select: none;
+        }
+
+        #mouseModeButton.active {
+            background: rgba(76, 175, 80, 0.8);
+        }
+
+        /* 导航按键（返回 / 主页 / 最近任务） */
+        #navButtons {
+            position: fixed;
+            left: 50%;
+            bottom: 10px;
+            transform: translateX(-50%);
+            display: flex;
+            gap: 6px;
+            padding: 4px;
+            border-radius: 20px;
+            background: rgba(0, 0, 0, 0.7);
+            z-index: 1000;
+        }
+
+        #navButtons button {
+            width: 40px;
+            height: 28px;
+            border: none;
+            border-radius: 14px;
+            font-size: 14px;
+            color: white;
+            background: transparent;
+            cursor: pointer;
+            user-select: none;
+            -webkit-user-select: none;
+        }
+
+        #navButtons button:hover {
+            background: rgba(255, 255, 255, 0.2);
+        }
+
+        /* 接收输入法组合输入的隐藏输入框（点击画面后获得焦点） */
+        #imeInput {
+            position: fixed;
+            left: 0;
+            bottom: 0;
+            width: 1px;
+            height: 1px;
+            padding: 0;
+            border: 0;
+            opacity: 0;
+            resize: none;
+            pointer-events: none;
+        }
     </style>
 </head>
 <body>
@@ -508,6 +1504,19 @@ async fn serve_html() -> impl IntoResponse {
         </div>
     </div>
 
+    <!-- 输入法（中日韩、阿拉伯文、组合字符）的文本经此输入框组合后整体发送 -->
+    <textarea id="imeInput" autocomplete="off" autocorrect="off" autocapitalize="off" spellcheck="false" aria-hidden="true"></textarea>
+
+    <!-- 相对鼠标模式：点击后锁定指针，按 Esc 退出 -->
+    <button id="mouseModeButton" title="锁定鼠标指针，以相对位移控制 UHID 鼠标（Esc 退出）">🎯 相对鼠标</button>
+
+    <!-- 导航按键：发送 syskey 事件 -->
+    <div id="navButtons">
+        <button data-key="back" title="返回">◀</button>
+        <button data-key="home" title="主页">●</button>
+        <button data-key="recents" title="最近任务">■</button>
+    </div>
+
     <!-- 解码器选择面板 -->
     <div id="decoderPanel">
         <div class="option" data-decoder="webcodecs">
@@ -538,6 +1547,11 @@ async fn serve_html() -> impl IntoResponse {
         let deviceWidth = 0;
         let deviceHeight = 0;
         let isLandscape = false;
+        let letterbox = null;  // 目标宽高比画框（--target-aspect），null 表示不加黑边
+        let streamProfile = null;  // 视频流的 H.264 profile（服务端从 SPS 解析）
+        const smoothRotate = __SMOOTH_ROTATE__;  // ?smooth_rotate=1：旋转时不黑屏
+        let streamReconnecting = false;  // server 重启中（stream_status），收到重连后的第一帧前为 true
+        let pendingReinit = false;  // 旋转后等待新分辨率的第一个 IDR 再重建解码器
 
         // 解码器可用性状态
         const decoderSupport = {
@@ -549,6 +1563,16 @@ async fn serve_html() -> impl IntoResponse {
         // 当前使用的解码器类型
         let currentDecoderType = null;
 
+        // Broadway 只能解码 Baseline profile
+        function isProfileIncompatible(type) {
+            return type === 'broadway' && streamProfile !== null && !streamProfile.broadway_compatible;
+        }
+
+        // 解码器可用：浏览器支持且兼容当前视频流
+        function isDecoderUsable(type) {
+            return decoderSupport[type] && !isProfileIncompatible(type);
+        }
+
         // ========== 解码器抽象接口 ==========
         class BaseDecoder {
             constructor(canvas) {
@@ -617,15 +1641,15 @@ async fn serve_html() -> impl IntoResponse {
 
                 this.decoder.configure({
                     codec: 'avc1.42001E',
-                    optimizeForLatency: true,
-                    hardwareAcceleration: 'prefer-hardware',
+                    optimizeForLatency: __OPTIMIZE_FOR_LATENCY__,
+                    hardwareAcceleration: '__HW_ACCEL__',
                 });
 
                 this.ready = true;
                 console.log('✅ WebCodecs decoder initialized');
             }
 
-            decode(nalData, isKeyFrame) {
+            decode(nalData, isKeyFrame, pts) {
                 if (!this.decoder || !this.ready) return;
 
                 try {
@@ -640,7 +1664,7 @@ async fn serve_html() -> impl IntoResponse {
 
                     const chunk = new EncodedVideoChunk({
                         type: isKeyFrame ? 'key' : 'delta',
-                        timestamp: performance.now() * 1000,
+                        timestamp: pts ?? performance.now() * 1000,
                         data: nalData
                     });
                     this.decoder.decode(chunk);
@@ -828,14 +1852,20 @@ async fn serve_html() -> impl IntoResponse {
                 requestAnimationFrame(render);
             }
 
-            decode(nalData, isKeyFrame) {
+            decode(nalData, isKeyFrame, pts) {
                 if (!this.player || !this.ready) return;
 
                 try {
+                    // --frame-meta 时按相邻两帧的设备 PTS（微秒）计算时长，否则假设 60fps
+                    let duration = 1000 / 60;
+                    if (pts != null && this.lastPts != null && pts > this.lastPts) {
+                        duration = (pts - this.lastPts) / 1000;
+                    }
+                    if (pts != null) this.lastPts = pts;
                     // JMuxer 需要特定的数据格式
                     this.player.feed({
                         video: nalData,
-                        duration: 1000 / 60  // 假设 60fps
+                        duration: duration
                     });
                 } catch (e) {
                     console.error('JMuxer decode error:', e);
@@ -880,14 +1910,15 @@ async fn serve_html() -> impl IntoResponse {
                 document.getElementById('webcodecs-status').textContent =
                     decoderSupport.webcodecs ? '✓ 可用 (硬件加速)' : '✗ 不支持';
                 document.getElementById('broadway-status').textContent =
-                    decoderSupport.broadway ? '✓ 可用 (软解码)' : '✗ 未加载';
+                    !decoderSupport.broadway ? '✗ 未加载' :
+                    isProfileIncompatible('broadway') ? `✗ 不兼容 (${streamProfile.name} Profile)` : '✓ 可用 (软解码)';
                 document.getElementById('jmuxer-status').textContent =
                     decoderSupport.jmuxer ? '✓ 可用 (MSE)' : '✗ 不支持';
 
                 // 标记不可用的选项
                 document.querySelectorAll('#decoderPanel .option').forEach(option => {
                     const decoder = option.dataset.decoder;
-                    if (!decoderSupport[decoder]) {
+                    if (!isDecoderUsable(decoder)) {
                         option.classList.add('unavailable');
                     } else {
                         option.classList.remove('unavailable');
@@ -899,7 +1930,7 @@ async fn serve_html() -> impl IntoResponse {
             getBestDecoder() {
                 if (decoderSupport.webcodecs) return 'webcodecs';
                 if (decoderSupport.jmuxer) return 'jmuxer';
-                if (decoderSupport.broadway) return 'broadway';
+                if (isDecoderUsable('broadway')) return 'broadway';
                 return null;
             },
 
@@ -910,7 +1941,7 @@ async fn serve_html() -> impl IntoResponse {
                     throw new Error(`Unknown decoder type: ${type}`);
                 }
 
-                if (!decoderSupport[type]) {
+                if (!isDecoderUsable(type)) {
                     throw new Error(`Decoder ${type} is not supported`);
                 }
 
@@ -993,7 +2024,7 @@ async fn serve_html() -> impl IntoResponse {
         }
 
         async function switchDecoder(type) {
-            if (!decoderSupport[type]) {
+            if (!isDecoderUsable(type)) {
                 console.warn(`Decoder ${type} is not supported`);
                 return;
             }
@@ -1018,6 +2049,7 @@ async fn serve_html() -> impl IntoResponse {
 
                 currentDecoderType = type;
                 frameCount = 0;
+                pendingReinit = false;  // 新解码器已按当前分辨率初始化
 
                 updateDecoderStatus(type, currentDecoder.getName());
                 console.log(`✅ Switched to ${type} decoder`);
@@ -1244,26 +2276,32 @@ async fn serve_html() -> impl IntoResponse {
         // ========== Canvas 尺寸管理 ==========
         function resizeCanvas() {
             if (videoWidth > 0 && videoHeight > 0) {
-                const videoRatio = videoWidth / videoHeight;
+                // 有信箱画框时按画框比例适配窗口，否则按视频比例
+                const boxWidth = letterbox ? letterbox.box_width : videoWidth;
+                const boxHeight = letterbox ? letterbox.box_height : videoHeight;
+                const boxRatio = boxWidth / boxHeight;
                 const windowWidth = window.innerWidth;
                 const windowHeight = window.innerHeight;
                 const windowRatio = windowWidth / windowHeight;
 
-                let canvasStyleWidth, canvasStyleHeight;
-                if (videoRatio > windowRatio) {
-                    canvasStyleWidth = windowWidth;
-                    canvasStyleHeight = windowWidth / videoRatio;
+                let boxStyleWidth, boxStyleHeight;
+                if (boxRatio > windowRatio) {
+                    boxStyleWidth = windowWidth;
+                    boxStyleHeight = windowWidth / boxRatio;
                 } else {
-                    canvasStyleHeight = windowHeight;
-                    canvasStyleWidth = windowHeight * videoRatio;
+                    boxStyleHeight = windowHeight;
+                    boxStyleWidth = windowHeight * boxRatio;
                 }
 
-                canvas.style.width = canvasStyleWidth + 'px';
-                canvas.style.height = canvasStyleHeight + 'px';
+                // 视频在画框中居中（容器 flex 居中），黑边由容器背景提供
+                const scale = boxStyleWidth / boxWidth;
+                canvas.style.width = (videoWidth * scale) + 'px';
+                canvas.style.height = (videoHeight * scale) + 'px';
 
                 // 同步设置容器尺寸
-                container.style.width = canvasStyleWidth + 'px';
-                container.style.height = canvasStyleHeight + 'px';
+                container.style.width = boxStyleWidth + 'px';
+                container.style.height = boxStyleHeight + 'px';
+                container.style.background = letterbox ? '#000' : '';
 
                 // 重新加载位置以适应新尺寸
                 loadStatusPosition();
@@ -1282,7 +2320,17 @@ async fn serve_html() -> impl IntoResponse {
         });
 
         // ========== 解码处理 ==========
-        function handleVideoFrame(data) {
+        // 消息中是否还有 IDR NAL（SPS/PPS 经过防竞争处理，不会出现 00 00 01）
+        function hasInlineIdr(data) {
+            for (let i = 4; i + 3 < data.length; i++) {
+                if (data[i] === 0 && data[i + 1] === 0 && data[i + 2] === 1 && (data[i + 3] & 0x1F) === 5) {
+                    return true;
+                }
+            }
+            return false;
+        }
+
+        function handleVideoFrame(data, pts) {
             if (!currentDecoder || !currentDecoder.ready) return;
 
             // 检查 NAL 单元类型
@@ -1291,6 +2339,10 @@ async fn serve_html() -> impl IntoResponse {
                 nalType = data[4] & 0x1F;
             }
 
+            // --inline-sps-on-idr：服务端已在 IDR 前拼接 SPS/PPS，整条消息作为关键帧解码，不再拼接缓存的参数集
+            const inlineIdr = nalType === 7 && hasInlineIdr(data);
+            if (inlineIdr) nalType = 5;
+
             // 缓存 SPS/PPS
             if (nalType === 7) {
                 cachedSPS = data;
@@ -1300,11 +2352,18 @@ async fn serve_html() -> impl IntoResponse {
                 return;
             }
 
+            // smooth_rotate：画面停在缩放后的最后一帧，新分辨率的 IDR 到达后才重建解码器并切换
+            if (pendingReinit) {
+                if (nalType !== 5) return;
+                pendingReinit = false;
+                currentDecoder.init(videoWidth, videoHeight);
+            }
+
             // IDR 帧处理
             if (nalType === 5) {
                 let combinedData = data;
 
-                if (cachedSPS && cachedPPS) {
+                if (!inlineIdr && cachedSPS && cachedPPS) {
                     const totalLength = cachedSPS.length + cachedPPS.length + data.length;
                     combinedData = new Uint8Array(totalLength);
 
@@ -1316,14 +2375,14 @@ async fn serve_html() -> impl IntoResponse {
                     combinedData.set(data, offset);
                 }
 
-                currentDecoder.decode(combinedData, true);
+                currentDecoder.decode(combinedData, true, pts);
                 frameCount++;
                 return;
             }
 
             // P 帧处理
             if (frameCount > 0) {
-                currentDecoder.decode(data, false);
+                currentDecoder.decode(data, false, pts);
             }
         }
 
@@ -1332,7 +2391,7 @@ async fn serve_html() -> impl IntoResponse {
             updateDecoderStatus('loading', '连接中...');
 
             const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
-            const wsUrl = `${protocol}//${window.location.host}/ws`;
+            const wsUrl = `${protocol}//${window.location.host}__BASE_PATH__/ws__DEVICE_QUERY__`;
 
             ws = new WebSocket(wsUrl);
             ws.binaryType = 'arraybuffer';
@@ -1387,18 +2446,103 @@ async fn serve_html() -> impl IntoResponse {
                             deviceWidth = msg.device_width;
                             deviceHeight = msg.device_height;
                             isLandscape = msg.is_landscape || false;
+                            letterbox = msg.letterbox || null;
+                            uhidKeyboard = !!msg.uhid_keyboard;
+                            frameMeta = !!msg.frame_meta;
 
                             console.log('📐 Video resolution:', videoWidth, 'x', videoHeight);
                             console.log('📱 Device resolution:', deviceWidth, 'x', deviceHeight);
 
-                            canvas.width = msg.width;
-                            canvas.height = msg.height;
+                            const sizeChanged = canvas.width !== msg.width || canvas.height !== msg.height;
+                            // 重连（如调整画质）期间同样保留最后一帧，避免黑屏闪烁
+                            const smoothResize = (smoothRotate || streamReconnecting) && sizeChanged && frameCount > 0;
+                            if (smoothResize) {
+                                // 修改 canvas 尺寸会清空画面：先保存最后一帧，缩放到新尺寸后重新绘制
+                                const snapshot = document.createElement('canvas');
+                                snapshot.width = canvas.width;
+                                snapshot.height = canvas.height;
+                                snapshot.getContext('2d').drawImage(canvas, 0, 0);
+                                canvas.width = msg.width;
+                                canvas.height = msg.height;
+                                ctx.drawImage(snapshot, 0, 0, canvas.width, canvas.height);
+                            } else {
+                                canvas.width = msg.width;
+                                canvas.height = msg.height;
+                            }
                             resizeCanvas();
 
-                            // 重新初始化解码器
-                            if (currentDecoder) {
+                            streamProfile = msg.profile || null;
+                            DecoderManager.updateSupportUI();
+
+                            // 当前解码器无法解码该 profile：切换到其他可用解码器
+                            if (isProfileIncompatible(currentDecoderType)) {
+                                console.warn(`⚠️ Broadway cannot decode ${streamProfile.name} profile`);
+                                const fallback = DecoderManager.getBestDecoder();
+                                if (fallback) {
+                                    switchDecoder(fallback);
+                                } else {
+                                    updateDecoderStatus('error', `Broadway 不支持 ${streamProfile.name} Profile`);
+                                }
+                            } else if (smoothResize) {
+                                // 旧解码器继续工作，新分辨率的第一个 IDR 到达时再重建（见 handleVideoFrame）
+                                pendingReinit = true;
+                            } else if (currentDecoder) {
+                                // 重新初始化解码器
                                 currentDecoder.init(videoWidth, videoHeight);
                             }
+                        } else if (msg.type === 'privacy') {
+                            // 目标应用不在前台：清空画面
+                            if (msg.paused) {
+                                clearCanvas();
+                                updateDecoderStatus('error', '已暂停（应用不在前台）');
+                            } else if (currentDecoder) {
+                                updateDecoderStatus(currentDecoderType, currentDecoder.getName());
+                            }
+                        } else if (msg.type === 'session_event') {
+                            console.log('👥 Client', msg.event + ':', msg.client.addr);
+                        } else if (msg.type === 'imes') {
+                            console.log('⌨️ Enabled IMEs (current: ' + msg.current + '):', msg.imes);
+                        } else if (msg.type === 'ime_changed') {
+                            console.log('⌨️ IME switched to', msg.id);
+                        } else if (msg.type === 'brightness_changed') {
+                            console.log('🔆 Brightness set to', msg.value);
+                        } else if (msg.type === 'rotation') {
+                            console.log(msg.locked ? '🔒 Rotation locked to ' + msg.user_rotation : '🔓 Auto-rotate on');
+                        } else if (msg.type === 'device_screenshot') {
+                            console.log('📸 Screenshot saved on device:', msg.path);
+                        } else if (msg.type === 'error') {
+                            console.warn('⚠️ Server rejected control message:', msg.message);
+                        } else if (msg.type === 'device_locked') {
+                            // 设备锁屏时编码器停止输出，画面停在最后一帧
+                            if (msg.locked) {
+                                updateDecoderStatus('error', '设备已锁屏（画面已暂停）');
+                            } else if (currentDecoder) {
+                                updateDecoderStatus(currentDecoderType, currentDecoder.getName());
+                            }
+                        } else if (msg.type === 'uhid_output') {
+                            // UHID 键盘的输出报告：第一个字节是 LED 位（Num/Caps/Scroll Lock）
+                            const leds = msg.data[0] || 0;
+                            console.log('⌨️ Device keyboard LEDs:', { num: !!(leds & 1), caps: !!(leds & 2), scroll: !!(leds & 4) });
+                        } else if (msg.type === 'stream_status') {
+                            // server 重启期间 WebSocket 保持连接，解码器保留最后一帧，等待新编码器的 SPS/PPS + IDR
+                            streamReconnecting = msg.state !== 'live';
+                            if (msg.state === 'reconnecting') {
+                                updateDecoderStatus('reconnecting', '重新连接中…');
+                            } else if (msg.state === 'stalled') {
+                                updateDecoderStatus('error', '画面中断，等待设备…');
+                            } else if (currentDecoder) {
+                                updateDecoderStatus(currentDecoderType, currentDecoder.getName());
+                            }
+                        } else if (msg.type === 'control_status') {
+                            // 控制流断开后服务端会完整重连（画面按 stream_status 保留最后一帧）
+                            if (msg.state === 'connected') {
+                                console.log('🎮 Control channel restored');
+                            } else {
+                                console.warn('🎮 Control channel', msg.state);
+                            }
+                        } else if (msg.type === 'session_ending') {
+                            console.log('⏰ Session time limit reached');
+                            updateDecoderStatus('error', '会话已到期');
                         }
                     } catch (e) {
                         console.error('Failed to parse config:', e);
@@ -1408,7 +2552,14 @@ async fn serve_html() -> impl IntoResponse {
 
                 // 处理二进制消息（视频帧）
                 if (event.data instanceof ArrayBuffer) {
-                    handleVideoFrame(new Uint8Array(event.data));
+                    // --frame-meta：消息以 8 字节大端 PTS（微秒）开头，参数集为 0
+                    if (frameMeta && event.data.byteLength > 8) {
+                        const view = new DataView(event.data);
+                        const pts = view.getUint32(0) * 4294967296 + view.getUint32(4);
+                        handleVideoFrame(new Uint8Array(event.data, 8), pts || null);
+                    } else {
+                        handleVideoFrame(new Uint8Array(event.data), null);
+                    }
                 }
             };
 
@@ -1418,9 +2569,9 @@ async fn serve_html() -> impl IntoResponse {
                 clearCanvas();
             };
 
-            ws.onclose = () => {
-                console.log('WebSocket closed');
-                updateDecoderStatus('error', '连接断开');
+            ws.onclose = (event) => {
+                console.log('WebSocket closed', event.reason);
+                updateDecoderStatus('error', event.reason || '连接断开');
                 clearCanvas();
                 if (currentDecoder) {
                     currentDecoder.close();
@@ -1444,6 +2595,7 @@ async fn serve_html() -> impl IntoResponse {
                 currentDecoder = null;
             }
             frameCount = 0;
+            pendingReinit = false;
             cachedSPS = null;
             cachedPPS = null;
             clearCanvas();
@@ -1465,9 +2617,14 @@ async fn serve_html() -> impl IntoResponse {
         }
 
         function normalizeCoords(canvasX, canvasY) {
-            const rect = canvas.getBoundingClientRect();
-            const x = (canvasX - rect.left) / rect.width;
-            const y = (canvasY - rect.top) / rect.height;
+            // 相对信箱画框计算，再扣除黑边偏移，得到视频区域内的归一化坐标
+            const rect = container.getBoundingClientRect();
+            const boxWidth = letterbox ? letterbox.box_width : videoWidth;
+            const boxHeight = letterbox ? letterbox.box_height : videoHeight;
+            const offsetX = letterbox ? letterbox.offset_x : 0;
+            const offsetY = letterbox ? letterbox.offset_y : 0;
+            const x = ((canvasX - rect.left) / rect.width * boxWidth - offsetX) / videoWidth;
+            const y = ((canvasY - rect.top) / rect.height * boxHeight - offsetY) / videoHeight;
             return { x: Math.max(0, Math.min(1, x)), y: Math.max(0, Math.min(1, y)) };
         }
 
@@ -1504,34 +2661,44 @@ async fn serve_html() -> impl IntoResponse {
             ws.send(JSON.stringify(event));
         }
 
+        // touch.identifier 可能为负数，与鼠标的 -1 冲突：每次按下分配一个新的非负 id
+        // （服务端再把按下的手指映射到 0..9 的槽位）
+        const touchIds = new Map();
+        let nextTouchId = 0;
+
         function handleTouchStart(e) {
             e.preventDefault();
             for (let touch of e.changedTouches) {
+                const id = nextTouchId++;
+                touchIds.set(touch.identifier, id);
                 const coords = normalizeCoords(touch.clientX, touch.clientY);
-                activeTouches.set(touch.identifier, coords);
+                activeTouches.set(id, coords);
                 const action = activeTouches.size === 1 ? 0 : 5;
-                sendTouchEvent(action, touch.identifier, coords.x, coords.y, touch.force || 1.0);
+                sendTouchEvent(action, id, coords.x, coords.y, touch.force || 1.0);
             }
         }
 
         function handleTouchMove(e) {
             e.preventDefault();
             for (let touch of e.changedTouches) {
-                if (!activeTouches.has(touch.identifier)) continue;
+                const id = touchIds.get(touch.identifier);
+                if (id === undefined || !activeTouches.has(id)) continue;
                 const coords = normalizeCoords(touch.clientX, touch.clientY);
-                activeTouches.set(touch.identifier, coords);
-                sendTouchEvent(2, touch.identifier, coords.x, coords.y, touch.force || 1.0);
+                activeTouches.set(id, coords);
+                sendTouchEvent(2, id, coords.x, coords.y, touch.force || 1.0);
             }
         }
 
         function handleTouchEnd(e) {
             e.preventDefault();
             for (let touch of e.changedTouches) {
-                if (!activeTouches.has(touch.identifier)) continue;
-                const coords = activeTouches.get(touch.identifier);
-                activeTouches.delete(touch.identifier);
+                const id = touchIds.get(touch.identifier);
+                touchIds.delete(touch.identifier);
+                if (id === undefined || !activeTouches.has(id)) continue;
+                const coords = activeTouches.get(id);
+                activeTouches.delete(id);
                 const action = activeTouches.size === 0 ? 1 : 6;
-                sendTouchEvent(action, touch.identifier, coords.x, coords.y, 1.0);
+                sendTouchEvent(action, id, coords.x, coords.y, 1.0);
             }
         }
 
@@ -1539,6 +2706,10 @@ async fn serve_html() -> impl IntoResponse {
         const MOUSE_POINTER_ID = -1;
 
         function handleMouseDown(e) {
+            if (relativeMouse) {
+                sendMouseRel(0, 0, e.buttons, 0, 0);
+                return;
+            }
             mouseDown = true;
             const coords = normalizeCoords(e.clientX, e.clientY);
             activeTouches.set(MOUSE_POINTER_ID, coords);
@@ -1546,6 +2717,10 @@ async fn serve_html() -> impl IntoResponse {
         }
 
         function handleMouseMove(e) {
+            if (relativeMouse) {
+                sendMouseRel(e.movementX, e.movementY, e.buttons, 0, 0);
+                return;
+            }
             const coords = normalizeCoords(e.clientX, e.clientY);
             if (mouseDown) {
                 activeTouches.set(MOUSE_POINTER_ID, coords);
@@ -1554,6 +2729,10 @@ async fn serve_html() -> impl IntoResponse {
         }
 
         function handleMouseUp(e) {
+            if (relativeMouse) {
+                if (e.type === 'mouseup') sendMouseRel(0, 0, e.buttons, 0, 0);
+                return;
+            }
             if (!mouseDown) return;
             mouseDown = false;
             const coords = activeTouches.get(MOUSE_POINTER_ID) || normalizeCoords(e.clientX, e.clientY);
@@ -1590,45 +2769,242 @@ async fn serve_html() -> impl IntoResponse {
             return meta;
         }
 
-        function sendKeyEvent(action, keycode, metastate) {
+        // 浏览器自动重复的 keydown 次数（对应 Android KeyEvent 的 repeat）
+        const keyRepeats = {};
+
+        // --uhid：按物理键位发送给设备上的 UHID 键盘，修饰键和长按由设备处理（浏览器的自动重复由服务端忽略）
+        let uhidKeyboard = false;
+        let frameMeta = false;  // 二进制视频消息带 PTS 前缀（服务端 --frame-meta）
+        const uhidHeldKeys = new Set();
+
+        function sendUhidKey(code, down) {
+            if (!ws || ws.readyState !== WebSocket.OPEN) return;
+            if (down) uhidHeldKeys.add(code); else uhidHeldKeys.delete(code);
+            ws.send(JSON.stringify({ type: 'uhid_key', code: code, down: down }));
+        }
+
+        // 页面失去焦点时收不到 keyup，抬起所有按键避免设备上的按键卡住
+        function releaseUhidKeys() {
+            if (uhidHeldKeys.size === 0 || !ws || ws.readyState !== WebSocket.OPEN) return;
+            uhidHeldKeys.clear();
+            ws.send(JSON.stringify({ type: 'uhid_key_release_all' }));
+        }
+
+        function sendKeyEvent(action, keycode, metastate, repeat = 0) {
             if (!ws || ws.readyState !== WebSocket.OPEN) return;
             ws.send(JSON.stringify({
                 type: 'key',
                 action: action,
                 keycode: keycode,
-                repeat: 0,
+                repeat: repeat,
                 metastate: metastate
             }));
         }
 
         function handleKeyDown(e) {
+            // 输入法组合中的按键由输入法处理，最终文本在 compositionend 时发送
+            if (e.isComposing || e.keyCode === 229) return;
+            // Ctrl+Shift+S：在设备上保存原始分辨率截图
+            if (e.ctrlKey && e.shiftKey && e.code === 'KeyS') {
+                e.preventDefault();
+                requestDeviceScreenshot();
+                return;
+            }
             if (e.ctrlKey && e.code === 'KeyV') {
                 e.preventDefault();
                 handlePaste();
                 return;
             }
+            if (uhidKeyboard) {
+                e.preventDefault();
+                if (!e.repeat) sendUhidKey(e.code, true);
+                return;
+            }
+            if (isTextKey(e)) return;
             const keycode = KEY_MAP[e.code];
             if (keycode !== undefined) {
                 e.preventDefault();
-                sendKeyEvent(0, keycode, getMetaState(e));
+                keyRepeats[e.code] = e.repeat ? (keyRepeats[e.code] || 0) + 1 : 0;
+                sendKeyEvent(0, keycode, getMetaState(e), keyRepeats[e.code]);
             }
         }
 
         function handleKeyUp(e) {
+            if (e.isComposing || e.keyCode === 229) return;
+            if (uhidKeyboard) {
+                e.preventDefault();
+                if (uhidHeldKeys.has(e.code)) sendUhidKey(e.code, false);
+                return;
+            }
+            if (isTextKey(e)) return;
             const keycode = KEY_MAP[e.code];
             if (keycode !== undefined) {
                 e.preventDefault();
+                delete keyRepeats[e.code];
                 sendKeyEvent(1, keycode, getMetaState(e));
             }
         }
 
+        // ========== 输入法组合输入 ==========
+        // 阿拉伯文、带组合符号的文字和中日韩输入法在组合过程中会触发多次 input 事件，
+        // 组合期间只缓冲，compositionend 时把最终字符串作为一个 text 消息发送（不逐键发送）
+        const imeInput = document.getElementById('imeInput');
+        let composing = false;
+        let lastComposed = null;  // Safari 在 compositionend 之后还会触发一次相同内容的 input
+
+        // 非 ASCII 的可打印字符（阿拉伯文等键盘布局、死键组合的结果）不按物理键位映射，由 input 事件按文本发送
+        function isTextKey(e) {
+            return !e.ctrlKey && !e.altKey && !e.metaKey && [...e.key].length === 1 && e.key.codePointAt(0) > 0x7F;
+        }
+
+        function setupImeInput() {
+            imeInput.addEventListener('compositionstart', () => {
+                composing = true;
+            });
+            imeInput.addEventListener('compositionend', (e) => {
+                composing = false;
+                if (e.data) {
+                    sendText(e.data);
+                    lastComposed = e.data;
+                }
+                imeInput.value = '';
+            });
+            imeInput.addEventListener('input', (e) => {
+                if (composing || e.isComposing) return;
+                const composed = lastComposed;
+                lastComposed = null;
+                if (e.data && e.data !== composed && (e.inputType === 'insertText' || e.inputType === 'insertReplacementText')) {
+                    sendText(e.data);
+                }
+                imeInput.value = '';
+            });
+            // 输入法只对获得焦点的输入框生效：点击画面后把焦点交给隐藏输入框（触屏不聚焦，避免弹出软键盘）
+            canvas.addEventListener('mouseup', () => imeInput.focus({ preventScroll: true }));
+        }
+
         // ========== 文本输入和粘贴 ==========
         function sendText(text) {
             if (!ws || ws.readyState !== WebSocket.OPEN) return;
+            // scrcpy 一次最多注入 300 字节文本，更长的文本经剪贴板粘贴
+            if (new TextEncoder().encode(text).length > 300) {
+                setClipboard(text, true);
+                console.log('📝 Pasted long text via clipboard:', text.length, 'chars');
+                return;
+            }
             ws.send(JSON.stringify({ type: 'text', text: text }));
             console.log('📝 Sent text:', text.length, 'chars');
         }
 
+        function requestDeviceScreenshot() {
+            if (!ws || ws.readyState !== WebSocket.OPEN) return;
+            ws.send(JSON.stringify({ type: 'device_screenshot' }));
+        }
+
+        // 输入法切换：在控制台调用 listImes() 查看可用输入法，setIme(id) 切换
+        function listImes() {
+            if (!ws || ws.readyState !== WebSocket.OPEN) return;
+            ws.send(JSON.stringify({ type: 'list_imes' }));
+        }
+
+        function setIme(id) {
+            if (!ws || ws.readyState !== WebSocket.OPEN) return;
+            ws.send(JSON.stringify({ type: 'set_ime', id: id }));
+        }
+
+        // 屏幕亮度：在控制台调用 setBrightness(0-255)，关闭服务时恢复原亮度
+        function setBrightness(value) {
+            if (!ws || ws.readyState !== WebSocket.OPEN) return;
+            ws.send(JSON.stringify({ type: 'set_brightness', value: value }));
+        }
+
+        // 旋转设备：在控制台调用 rotateDevice()，新画面到达前服务端暂停转发触摸
+        function rotateDevice() {
+            if (!ws || ws.readyState !== WebSocket.OPEN) return;
+            ws.send(JSON.stringify({ type: 'rotate' }));
+        }
+
+        // 方向锁定：getRotation() 查询，setRotationLock(0-3) 锁定方向，setRotationLock(null) 恢复自动旋转
+        function getRotation() {
+            if (!ws || ws.readyState !== WebSocket.OPEN) return;
+            ws.send(JSON.stringify({ type: 'get_rotation' }));
+        }
+
+        function setRotationLock(rotation) {
+            if (!ws || ws.readyState !== WebSocket.OPEN) return;
+            ws.send(JSON.stringify({ type: 'set_rotation_lock', rotation: rotation }));
+        }
+
+        // 设备声音（服务端以 --audio 启动时可用）：在控制台调用 enableAudio()，disableAudio() 关闭
+        // /audio 推送 Opus 包，用 WebCodecs AudioDecoder 解码后按顺序排入 WebAudio 播放
+        let audioWs = null;
+        let audioCtx = null;
+        let audioDecoder = null;
+        let audioNextTime = 0;
+
+        function playAudioData(data) {
+            const buffer = audioCtx.createBuffer(data.numberOfChannels, data.numberOfFrames, data.sampleRate);
+            for (let c = 0; c < data.numberOfChannels; c++) {
+                data.copyTo(buffer.getChannelData(c), { planeIndex: c, format: 'f32-planar' });
+            }
+            data.close();
+            const source = audioCtx.createBufferSource();
+            source.buffer = buffer;
+            source.connect(audioCtx.destination);
+
+            // 断音或积压过多时从当前时间重新排队，保留 50ms 缓冲吸收网络抖动
+            const now = audioCtx.currentTime;
+            if (audioNextTime < now || audioNextTime > now + 0.5) audioNextTime = now + 0.05;
+            source.start(audioNextTime);
+            audioNextTime += buffer.duration;
+        }
+
+        function enableAudio() {
+            if (audioWs) return;
+            if (typeof AudioDecoder === 'undefined') {
+                console.warn('🔇 This browser has no WebCodecs AudioDecoder, cannot play Opus audio');
+                return;
+            }
+            const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
+            audioWs = new WebSocket(`${protocol}//${window.location.host}__BASE_PATH__/audio__DEVICE_QUERY__`);
+            audioWs.binaryType = 'arraybuffer';
+            let timestamp = 0;
+
+            audioWs.onmessage = (event) => {
+                if (typeof event.data === 'string') {
+                    const msg = JSON.parse(event.data);
+                    if (msg.type === 'audio_config') {
+                        audioCtx = new AudioContext({ sampleRate: msg.sample_rate });
+                        audioDecoder = new AudioDecoder({
+                            output: (data) => audioCtx ? playAudioData(data) : data.close(),
+                            error: (e) => console.warn('🔇 Audio decoder error:', e),
+                        });
+                        audioDecoder.configure({ codec: msg.codec, sampleRate: msg.sample_rate, numberOfChannels: msg.channels });
+                        audioNextTime = 0;
+                        console.log(`🔊 Audio: ${msg.codec} ${msg.sample_rate}Hz x${msg.channels}`);
+                    }
+                    return;
+                }
+                if (!audioDecoder || audioDecoder.state !== 'configured') return;
+
+                // 每个 Opus 包都可以独立解码；时间戳只用于解码器内部排序，播放时间由 playAudioData 安排
+                audioDecoder.decode(new EncodedAudioChunk({ type: 'key', timestamp: timestamp, data: event.data }));
+                timestamp += 20000;
+            };
+
+            audioWs.onclose = () => {
+                console.log('🔇 Audio disconnected');
+                audioWs = null;
+                if (audioDecoder && audioDecoder.state !== 'closed') audioDecoder.close();
+                audioDecoder = null;
+                if (audioCtx) audioCtx.close();
+                audioCtx = null;
+            };
+        }
+
+        function disableAudio() {
+            if (audioWs) audioWs.close();
+        }
+
         function setClipboard(text, paste) {
             if (!ws || ws.readyState !== WebSocket.OPEN) return;
             ws.send(JSON.stringify({ type: 'clipboard', text: text, paste: paste }));
@@ -1646,6 +3022,7 @@ async fn serve_html() -> impl IntoResponse {
         function setupKeyboardEvents() {
             document.addEventListener('keydown', handleKeyDown);
             document.addEventListener('keyup', handleKeyUp);
+            window.addEventListener('blur', releaseUhidKeys);
             document.addEventListener('paste', async (e) => {
                 e.preventDefault();
                 const text = e.clipboardData.getData('text');
@@ -1667,6 +3044,10 @@ async fn serve_html() -> impl IntoResponse {
 
         function handleWheel(e) {
             e.preventDefault();
+            if (relativeMouse) {
+                sendMouseRel(0, 0, e.buttons, -Math.sign(e.deltaY), Math.sign(e.deltaX));
+                return;
+            }
             const coords = normalizeCoords(e.clientX, e.clientY);
             const vscroll = e.deltaY > 0 ? -1 : (e.deltaY < 0 ? 1 : 0);
             const hscroll = e.deltaX > 0 ? -1 : (e.deltaX < 0 ? 1 : 0);
@@ -1679,27 +3060,511 @@ async fn serve_html() -> impl IntoResponse {
             canvas.addEventListener('wheel', handleWheel, { passive: false });
         }
 
+        // ========== 相对鼠标（指针锁定 + UHID 鼠标） ==========
+        // 触摸模拟的鼠标只有绝对坐标，FPS 游戏的视角控制需要相对位移：
+        // 锁定指针后把 movementX/Y 发给服务端，由设备上的 UHID 鼠标注入
+        let relativeMouse = false;
+        const mouseModeButton = document.getElementById('mouseModeButton');
+
+        function sendMouseRel(dx, dy, buttons, wheel, hwheel) {
+            if (!ws || ws.readyState !== WebSocket.OPEN) return;
+            ws.send(JSON.stringify({
+                type: 'uhid_mouse_rel',
+                dx: Math.round(dx), dy: Math.round(dy),
+                buttons: buttons & 0x1F,
+                wheel: wheel, hwheel: hwheel
+            }));
+        }
+
+        function setupRelativeMouse() {
+            mouseModeButton.addEventListener('click', async () => {
+                // 优先使用未经系统加速的原始位移（Chrome），不支持时退回普通指针锁定
+                try {
+                    await canvas.requestPointerLock({ unadjustedMovement: true });
+                } catch (e) {
+                    canvas.requestPointerLock();
+                }
+            });
+            canvas.addEventListener('contextmenu', (e) => {
+                if (relativeMouse) e.preventDefault();
+            });
+            document.addEventListener('pointerlockchange', () => {
+                relativeMouse = document.pointerLockElement === canvas;
+                mouseModeButton.classList.toggle('active', relativeMouse);
+                console.log(relativeMouse ? '🎯 Relative mouse mode on (Esc to exit)' : '🎯 Relative mouse mode off');
+                // 退出后销毁 UHID 鼠标，设备上的鼠标指针随之消失
+                if (!relativeMouse && ws && ws.readyState === WebSocket.OPEN) {
+                    ws.send(JSON.stringify({ type: 'uhid_mouse_close' }));
+                }
+            });
+        }
+
+        // ========== 导航按键 ==========
+        function sendSysKey(key) {
+            if (!ws || ws.readyState !== WebSocket.OPEN) return;
+            ws.send(JSON.stringify({ type: 'syskey', key: key }));
+        }
+
+        function setupNavButtons() {
+            document.querySelectorAll('#navButtons button').forEach((button) => {
+                button.addEventListener('click', () => sendSysKey(button.dataset.key));
+            });
+        }
+
         // ========== 初始化 ==========
         setupTouchEvents();
         setupKeyboardEvents();
+        setupImeInput();
         setupScrollEvents();
+        setupRelativeMouse();
+        setupNavButtons();
         connect();
     </script>
 </body>
 </html>
     "#;
 
-    ([("content-type", "text/html; charset=utf-8")], html)
+/// 启动参数快照（--enable-debug-endpoints），未启用时返回 404
+async fn serve_debug_config(config: Option<Arc<serde_json::Value>>) -> impl IntoResponse {
+    match config {
+        Some(config) => (StatusCode::OK, axum::Json((*config).clone())),
+        None => (StatusCode::NOT_FOUND, axum::Json(serde_json::json!({ "error": "debug endpoints are disabled (use --enable-debug-endpoints)" }))),
+    }
+}
+
+/// /admin/kick 查询参数：`?id=3` 或 `?addr=192.168.1.20`
+#[derive(Debug, serde::Deserialize)]
+struct KickParams {
+    id: Option<u64>,
+    addr: Option<String>,
+}
+
+/// 管理接口要求的请求头：浏览器跨域发送自定义请求头前必须先预检，本服务不响应预检，
+/// 其他网页（包括同一台机器上其他端口的页面）无法借用户的浏览器伪造管理请求
+pub const ADMIN_HEADER: &str = "x-scrcpy-admin";
+
+/// 检查管理请求的来源，拒绝时返回原因
+///
+/// 总是要求 [`ADMIN_HEADER`]。设置了 `--auth-token` 时令牌已由中间件检查；
+/// 否则只接受本机回环地址且没有经过代理的请求（经过本机反向代理时对端是代理本身，回环检查没有意义）。
+fn check_admin_request(peer: SocketAddr, headers: &HeaderMap, token_checked: bool) -> std::result::Result<(), &'static str> {
+    if !headers.contains_key(ADMIN_HEADER) {
+        return Err("admin requests must carry the X-Scrcpy-Admin header");
+    }
+    if token_checked {
+        return Ok(());
+    }
+    if !peer.ip().is_loopback() {
+        return Err("admin routes are only available from localhost (or with --auth-token)");
+    }
+    if headers.contains_key("x-forwarded-for") || headers.contains_key(header::FORWARDED) {
+        return Err("admin routes are not available through a proxy without --auth-token");
+    }
+    Ok(())
+}
+
+/// 踢出指定客户端（来源检查见 [`check_admin_request`]）
+async fn serve_kick(clients: ClientRegistry, peer: SocketAddr, headers: HeaderMap, token_checked: bool, params: KickParams) -> impl IntoResponse {
+    if let Err(reason) = check_admin_request(peer, &headers, token_checked) {
+        warn!("🚫 Rejected /admin/kick from {}: {}", peer, reason);
+        return (StatusCode::FORBIDDEN, axum::Json(serde_json::json!({ "error": reason })));
+    }
+    let target = match (params.id, params.addr) {
+        (Some(id), None) => KickTarget::Id(id),
+        (None, Some(addr)) => KickTarget::Addr(addr),
+        _ => {
+            return (StatusCode::BAD_REQUEST, axum::Json(serde_json::json!({ "error": "specify exactly one of id or addr" })));
+        }
+    };
+
+    let kicked = clients.kick(&target, "Disconnected by administrator");
+    info!("🚫 Kick {:?}: {} client(s)", target, kicked.len());
+    let status = if kicked.is_empty() { StatusCode::NOT_FOUND } else { StatusCode::OK };
+    (status, axum::Json(serde_json::json!({ "kicked": kicked })))
+}
+
+/// 健康检查：管线健康时返回 200，否则返回 503
+async fn serve_healthz(liveness: Arc<Liveness>, stale_after: Duration) -> impl IntoResponse {
+    let report = liveness.report(stale_after);
+    let status = if report.healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, axum::Json(report))
 }
 
 /// 提供 Broadway Decoder.min.js
 async fn serve_broadway_decoder() -> impl IntoResponse {
-    let js = include_str!("../decoder/Decoder.min.js");
-    ([("content-type", "application/javascript; charset=utf-8")], js)
+    ([("content-type", "application/javascript; charset=utf-8")], assets::BROADWAY_JS)
 }
 
 /// 提供 JMuxer jmuxer.min.js
 async fn serve_jmuxer() -> impl IntoResponse {
-    let js = include_str!("../decoder/jmuxer.min.js");
-    ([("content-type", "application/javascript; charset=utf-8")], js)
+    ([("content-type", "application/javascript; charset=utf-8")], assets::JMUXER_JS)
+}
+
+/// 提供运行状态信息（JSON）
+async fn serve_stats(
+    clients: ClientRegistry,
+    tx: broadcast::Sender<BroadcastFrame>,
+    video_config: Arc<RwLock<VideoConfig>>,
+    stream_stats: Arc<StreamStats>,
+    sink_stats: SinkStats,
+    server_log_stats: Option<ServerLogStats>,
+) -> impl IntoResponse {
+    let stream = {
+        let config = video_config.read().await;
+        stream_stats.report(tx.receiver_count(), config.width, config.height, config.is_landscape)
+    };
+    let mut stats = serde_json::json!({
+        "stream": stream,
+        "decoders": assets::decoder_assets(),
+        "viewers": clients.list(),
+        "sinks": sink_stats.snapshot(),
+    });
+    if let Some(server_log_stats) = server_log_stats {
+        stats["encoder"] = serde_json::json!(server_log_stats.snapshot());
+    }
+    axum::Json(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{header, Request};
+    use std::net::Ipv4Addr;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_check_admin_request() {
+        let local: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let remote: SocketAddr = "192.168.1.20:50000".parse().unwrap();
+        let mut headers = HeaderMap::new();
+
+        // 没有自定义请求头：跨站表单或 fetch 无法通过，即使来自本机或带着令牌
+        assert!(check_admin_request(local, &headers, false).is_err());
+        assert!(check_admin_request(local, &headers, true).is_err());
+
+        headers.insert(ADMIN_HEADER, "1".parse().unwrap());
+        assert!(check_admin_request(local, &headers, false).is_ok());
+        assert!(check_admin_request(remote, &headers, false).is_err());
+        assert!(check_admin_request(remote, &headers, true).is_ok());
+
+        // 经过本机反向代理的请求看起来来自回环地址，没有令牌时拒绝
+        headers.insert("x-forwarded-for", "203.0.113.7".parse().unwrap());
+        assert!(check_admin_request(local, &headers, false).is_err());
+        assert!(check_admin_request(local, &headers, true).is_ok());
+    }
+
+    #[test]
+    fn test_check_codec() {
+        assert!(check_codec(None).is_ok());
+        assert!(check_codec(Some("H264")).is_ok());
+        assert_eq!(check_codec(Some("hevc")).unwrap_err(), "codec 'hevc' is not available (available: h264)");
+    }
+
+    #[tokio::test]
+    async fn test_device_request_replies_to_sender() {
+        let (control_tx, _control_rx) = crate::scrcpy::control_queue::control_queue(4);
+        let control = ControlContext { control_tx, device: None, max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES };
+        let (reply_tx, mut reply_rx) = mpsc::channel(1);
+
+        // 设备请求不会作为控制事件解析，结果通过 reply_tx 回复
+        assert!(forward_control_message(r#"{"type":"device_screenshot"}"#, &control, &reply_tx).await.is_none());
+        let reply: serde_json::Value = serde_json::from_str(&reply_rx.recv().await.unwrap()).unwrap();
+        assert_eq!(reply["type"], "error");
+        assert!(forward_control_message(r#"{"type":"set_brightness","value":128}"#, &control, &reply_tx).await.is_none());
+        let reply: serde_json::Value = serde_json::from_str(&reply_rx.recv().await.unwrap()).unwrap();
+        assert_eq!(reply["type"], "error");
+        assert!(matches!(serde_json::from_str(r#"{"type":"set_rotation_lock","rotation":null}"#), Ok(DeviceRequest::SetRotationLock { rotation: None })));
+        assert!(forward_control_message(r#"{"type":"set_rotation_lock","rotation":1}"#, &control, &reply_tx).await.is_none());
+        let reply: serde_json::Value = serde_json::from_str(&reply_rx.recv().await.unwrap()).unwrap();
+        assert_eq!(reply["type"], "error");
+
+        let error: serde_json::Value = serde_json::from_str(&forward_control_message(r#"{"type":"bogus","x":1}"#, &control, &reply_tx).await.unwrap()).unwrap();
+        assert_eq!((error["type"].as_str(), error["reason"].as_str(), error["message_type"].as_str()), (Some("error"), Some("unknown_type"), Some("bogus")));
+
+        // 已知类型仍严格校验字段，返回普通的解析错误
+        let error: serde_json::Value = serde_json::from_str(&forward_control_message(r#"{"type":"touch","action":0}"#, &control, &reply_tx).await.unwrap()).unwrap();
+        assert_eq!(error["type"], "error");
+        assert!(error.get("reason").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_inline_sps_on_idr() {
+        let (idr_request_tx, _) = mpsc::channel(1);
+        let (control_tx, _control_rx) = crate::scrcpy::control_queue::control_queue(4);
+        let server = WebSocketServer::new(0, idr_request_tx, control_tx, 1080, 2400, Ipv4Addr::LOCALHOST.into()).unwrap();
+        let video_config = server.get_video_config();
+        let nal = |header: u8| Bytes::from(vec![0, 0, 0, 1, header, 0xAA]);
+        {
+            let mut config = video_config.write().await;
+            config.sps = Some(nal(0x67));
+            config.pps = Some(nal(0x68));
+        }
+
+        let frame = |header: u8| BroadcastFrame { nal: nal(header), pts: Some(0x0102) };
+
+        // 默认不拼接
+        assert_eq!(viewer_frame(&frame(0x65), &video_config, false).await, nal(0x65).to_vec());
+
+        // 开启后只有 IDR 前拼接参数集，P 帧和参数集本身不变
+        video_config.write().await.inline_sps_on_idr = true;
+        assert_eq!(viewer_frame(&frame(0x65), &video_config, false).await, [nal(0x67), nal(0x68), nal(0x65)].concat());
+        assert_eq!(viewer_frame(&frame(0x41), &video_config, false).await, nal(0x41).to_vec());
+        assert_eq!(viewer_frame(&frame(0x67), &video_config, false).await, nal(0x67).to_vec());
+
+        // --frame-meta：整条消息前加 8 字节 PTS，没有 PTS 时为 0
+        let pts = [0, 0, 0, 0, 0, 0, 0x01, 0x02];
+        assert_eq!(viewer_frame(&frame(0x65), &video_config, true).await, [&pts[..], &nal(0x67), &nal(0x68), &nal(0x65)].concat());
+        assert_eq!(viewer_frame(&nal(0x41).into(), &video_config, true).await, [&[0; 8][..], &nal(0x41)].concat());
+    }
+
+    #[test]
+    fn test_profile_in_config_message() {
+        let mut config = VideoConfig {
+            sps: None,
+            pps: None,
+            width: 1080,
+            height: 2400,
+            device_width: 1080,
+            device_height: 2400,
+            is_landscape: false,
+            target_aspect: None,
+            profile: None,
+            inline_sps_on_idr: false,
+            uhid_keyboard: false,
+            frame_meta: false,
+        };
+        let msg: serde_json::Value = serde_json::from_str(&config.config_message()).unwrap();
+        assert!(msg["profile"].is_null());
+
+        config.profile = Some(H264Profile::from_idc(100));
+        let msg: serde_json::Value = serde_json::from_str(&config.config_message()).unwrap();
+        assert_eq!(msg["profile"]["name"], "High");
+        assert_eq!(msg["profile"]["broadway_compatible"], false);
+        assert!(H264Profile::from_idc(66).broadway_compatible);
+    }
+
+    #[test]
+    fn test_letterbox_fit() {
+        // 竖屏视频放进 16:9 画框：左右黑边
+        let lb = Letterbox::fit(1080, 1920, (16, 9));
+        assert_eq!((lb.box_width, lb.box_height), (3414, 1920));
+        assert_eq!((lb.offset_x, lb.offset_y), (1167, 0));
+
+        // 横屏视频放进 1:1 画框：上下黑边
+        let lb = Letterbox::fit(1920, 1080, (1, 1));
+        assert_eq!((lb.box_width, lb.box_height), (1920, 1920));
+        assert_eq!((lb.offset_x, lb.offset_y), (0, 420));
+
+        // 比例一致：没有黑边
+        let lb = Letterbox::fit(1280, 720, (16, 9));
+        assert_eq!(lb, Letterbox { box_width: 1280, box_height: 720, offset_x: 0, offset_y: 0 });
+    }
+
+    #[tokio::test]
+    async fn test_static_routes_compressed() {
+        for path in ["/", "/decoder/jmuxer.min.js", "/decoder/Decoder.min.js"] {
+            let response = static_routes()
+                .oneshot(
+                    Request::builder()
+                        .uri(path)
+                        .header(header::ACCEPT_ENCODING, "gzip")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip", "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_kick_behind_authenticated_hub() {
+        let kick = |hub_authenticated: bool| async move {
+            let (idr_request_tx, _idr_request_rx) = mpsc::channel(1);
+            let (control_tx, _control_rx) = crate::scrcpy::control_queue::control_queue(8);
+            let mut server = WebSocketServer::new(38080, idr_request_tx, control_tx, 1080, 1920, Ipv4Addr::LOCALHOST.into()).unwrap();
+            server.set_hub_authenticated(hub_authenticated);
+            let remote: SocketAddr = "192.168.1.20:50000".parse().unwrap();
+            let app = server.build_router().layer(axum::extract::connect_info::MockConnectInfo(remote));
+            let request = Request::post("/admin/kick?id=99").header(ADMIN_HEADER, "1").body(Body::empty()).unwrap();
+            app.oneshot(request).await.unwrap().status()
+        };
+        // hub 已校验令牌：远程请求与单设备模式带令牌时一样被接受（没有匹配的客户端）
+        assert_eq!(kick(true).await, StatusCode::NOT_FOUND);
+        assert_eq!(kick(false).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_router_requires_auth_token() {
+        let (idr_request_tx, _idr_request_rx) = mpsc::channel(1);
+        let (control_tx, _control_rx) = crate::scrcpy::control_queue::control_queue(8);
+        let mut server = WebSocketServer::new(38080, idr_request_tx, control_tx, 1080, 1920, Ipv4Addr::LOCALHOST.into()).unwrap();
+        server.set_auth_token("s3cret");
+        let app = server.build_router();
+
+        for (path, status) in [
+            ("/", StatusCode::UNAUTHORIZED),
+            ("/ws", StatusCode::UNAUTHORIZED),
+            ("/decoder/jmuxer.min.js", StatusCode::UNAUTHORIZED),
+            ("/stats?token=wrong", StatusCode::UNAUTHORIZED),
+            ("/healthz", StatusCode::SERVICE_UNAVAILABLE),  // 存活探针不需要令牌
+        ] {
+            let response = app.clone().oneshot(Request::builder().uri(path).body(Body::empty()).unwrap()).await.unwrap();
+            assert_eq!(response.status(), status, "{}", path);
+        }
+
+        // 带令牌打开页面后，Cookie 可以用于加载解码器
+        let response = app.clone().oneshot(Request::builder().uri("/?token=s3cret").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let cookie = response.headers().get(header::SET_COOKIE).unwrap().to_str().unwrap();
+        let cookie = cookie.split(';').next().unwrap().to_string();
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/decoder/jmuxer.min.js").header(header::COOKIE, cookie).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(Request::builder().uri("/stats").header(header::AUTHORIZATION, "Bearer s3cret").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!((stats["stream"]["width"].as_u64(), stats["stream"]["frames_broadcast"].as_u64()), (Some(1080), Some(0)));
+    }
+
+    #[tokio::test]
+    async fn test_router_nested_under_prefix() {
+        let (idr_request_tx, _idr_request_rx) = mpsc::channel(1);
+        let (control_tx, _control_rx) = crate::scrcpy::control_queue::control_queue(8);
+        let server = WebSocketServer::new(38080, idr_request_tx, control_tx, 1080, 1920, Ipv4Addr::LOCALHOST.into()).unwrap();
+        let app = Router::new().nest("/mirror", server.build_router());
+
+        let response = app.clone().oneshot(Request::builder().uri("/mirror").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let html = String::from_utf8(body.to_vec()).unwrap();
+        assert!(html.contains(r#"src="/mirror/decoder/Decoder.min.js""#));
+        assert!(html.contains("${window.location.host}/mirror/ws"));
+
+        for (path, status) in [
+            ("/mirror/decoder/jmuxer.min.js", StatusCode::OK),
+            ("/mirror/stats", StatusCode::OK),
+            ("/mirror/healthz", StatusCode::SERVICE_UNAVAILABLE),  // 还没有收到视频帧
+        ] {
+            let response = app.clone().oneshot(Request::builder().uri(path).body(Body::empty()).unwrap()).await.unwrap();
+            assert_eq!(response.status(), status, "{}", path);
+        }
+
+        // 独立运行时路径不变
+        let response = static_routes().oneshot(Request::builder().uri("/").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8(body.to_vec()).unwrap().contains(r#"src="/decoder/Decoder.min.js""#));
+    }
+
+    #[test]
+    fn test_lag_tracker_threshold_and_window() {
+        let start = std::time::Instant::now();
+        let mut disabled = LagTracker::new(0);
+        assert!(!(0..10).any(|_| disabled.on_lag(start)));
+
+        let mut tracker = LagTracker::new(3);
+        assert!(!tracker.on_lag(start));
+        assert!(!tracker.on_lag(start + std::time::Duration::from_secs(1)));
+        assert!(tracker.on_lag(start + std::time::Duration::from_secs(2)));
+
+        // 间隔超过窗口的积压重新计数
+        assert!(!tracker.on_lag(start + std::time::Duration::from_secs(3)));
+        assert!(!tracker.on_lag(start + std::time::Duration::from_secs(4)));
+        assert!(!tracker.on_lag(start + std::time::Duration::from_secs(20)));
+    }
+
+    #[tokio::test]
+    async fn test_bind_failure_is_returned() {
+        let (idr_request_tx, _idr_request_rx) = mpsc::channel(1);
+        let (control_tx, _control_rx) = crate::scrcpy::control_queue::control_queue(8);
+        let server = WebSocketServer::new(38180, idr_request_tx, control_tx, 1080, 1920, Ipv4Addr::LOCALHOST.into()).unwrap();
+
+        // 端口在 new() 之后被其他程序占用
+        let _occupied = std::net::TcpListener::bind(("127.0.0.1", server.get_actual_port())).unwrap();
+        assert!(matches!(server.bind().await, Err(ScrcpyError::Network(_))));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_messages_rejected() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message as ClientMessage;
+
+        let (idr_request_tx, _idr_request_rx) = mpsc::channel(1);
+        let (control_tx, mut control_rx) = crate::scrcpy::control_queue::control_queue(8);
+        let mut server = WebSocketServer::new(38200, idr_request_tx, control_tx, 1080, 1920, Ipv4Addr::LOCALHOST.into()).unwrap();
+        server.set_max_message_bytes(1024);
+        let port = server.get_actual_port();
+        let listener = server.bind().await.unwrap();
+        tokio::spawn(server.serve(listener));
+
+        let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}/control", port)).await.unwrap();
+
+        // 消息大小合法，但文本超过 scrcpy 的注入上限：回复错误，不转发
+        let long_text = serde_json::json!({ "type": "text", "text": "a".repeat(400) }).to_string();
+        ws.send(ClientMessage::Text(long_text)).await.unwrap();
+        let reply = ws.next().await.unwrap().unwrap();
+        assert!(reply.to_text().unwrap().contains("text too long"));
+
+        // 超过消息上限：服务端关闭连接
+        let oversized = serde_json::json!({ "type": "clipboard", "text": "a".repeat(4096) }).to_string();
+        ws.send(ClientMessage::Text(oversized)).await.unwrap();
+        let closed = tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap();
+        assert!(matches!(closed, Some(Ok(ClientMessage::Close(_))) | Some(Err(_)) | None), "unexpected message: {:?}", closed);
+        // 两条消息都没有进入控制队列
+        assert!(tokio::time::timeout(Duration::from_millis(50), control_rx.recv()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_html_decoder_config_from_query() {
+        let response = static_routes()
+            .oneshot(Request::builder().uri("/?hwaccel=prefer-software&low_latency=false").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let html = String::from_utf8(body.to_vec()).unwrap();
+        assert!(html.contains("hardwareAcceleration: 'prefer-software'"));
+        assert!(html.contains("optimizeForLatency: false"));
+        assert!(html.contains("const smoothRotate = false;"));
+
+        let response = static_routes()
+            .oneshot(Request::builder().uri("/?smooth_rotate=1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8(body.to_vec()).unwrap().contains("const smoothRotate = true;"));
+
+        // 默认值保持原来的行为
+        let response = static_routes()
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let html = String::from_utf8(body.to_vec()).unwrap();
+        assert!(html.contains("hardwareAcceleration: 'prefer-hardware'"));
+        assert!(html.contains("optimizeForLatency: true"));
+
+        // 非法值返回 400
+        let response = static_routes()
+            .oneshot(Request::builder().uri("/?hwaccel=gpu").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_static_routes_identity_without_accept_encoding() {
+        let response = static_routes()
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    }
 }
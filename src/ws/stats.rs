@@ -0,0 +1,91 @@
+//! 视频流计数 - 主循环记录广播的帧数和关键帧数，/stats 读取
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// 视频流计数（主循环更新，/stats 读取）
+#[derive(Debug)]
+pub struct StreamStats {
+    started: Instant,
+    frames: AtomicU64,     // 已广播的帧数（不含被限帧丢弃的帧）
+    keyframes: AtomicU64,  // 收到的关键帧数（IDR 和 SPS/PPS）
+}
+
+/// /stats 的 `stream` 字段
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct StreamStatsReport {
+    pub uptime_secs: u64,
+    pub frames_broadcast: u64,
+    pub keyframes: u64,
+    pub clients: usize,  // 视频广播通道的订阅者数量
+    pub width: u32,
+    pub height: u32,
+    pub orientation: &'static str,  // "landscape" / "portrait"
+}
+
+impl Default for StreamStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamStats {
+    pub fn new() -> Self {
+        Self { started: Instant::now(), frames: AtomicU64::new(0), keyframes: AtomicU64::new(0) }
+    }
+
+    /// 广播了一帧，返回累计帧数
+    pub fn record_frame(&self) -> u64 {
+        self.frames.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// 收到一个关键帧，返回累计关键帧数
+    pub fn record_keyframe(&self) -> u64 {
+        self.keyframes.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames.load(Ordering::Relaxed)
+    }
+
+    pub fn keyframes(&self) -> u64 {
+        self.keyframes.load(Ordering::Relaxed)
+    }
+
+    /// 生成报告，分辨率和方向取自当前的 VideoConfig
+    pub fn report(&self, clients: usize, width: u32, height: u32, is_landscape: bool) -> StreamStatsReport {
+        StreamStatsReport {
+            uptime_secs: self.started.elapsed().as_secs(),
+            frames_broadcast: self.frames(),
+            keyframes: self.keyframes(),
+            clients,
+            width,
+            height,
+            orientation: if is_landscape { "landscape" } else { "portrait" },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_stats() {
+        let stats = StreamStats::new();
+        assert_eq!((stats.record_frame(), stats.record_frame()), (1, 2));
+        assert_eq!(stats.record_keyframe(), 1);
+
+        let report = stats.report(3, 1920, 1080, true);
+        assert_eq!(report, StreamStatsReport {
+            uptime_secs: 0,
+            frames_broadcast: 2,
+            keyframes: 1,
+            clients: 3,
+            width: 1920,
+            height: 1080,
+            orientation: "landscape",
+        });
+    }
+}